@@ -1,10 +1,25 @@
-use rusqlite::params;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension};
+use std::collections::HashMap;
 use tauri_plugin_dialog::{DialogExt, FilePath};
 
 use crate::db::database;
-use crate::db::model::{Video, Setting};
-use std::path::Path;
+use crate::hashing::{self, HashProgress};
+use crate::library::{self, FileEntry, ScanProgress};
+use crate::playback::{self, CodecSupport, Playability};
+use crate::probe::{self, MediaInfo, MediaKind};
+use crate::remux::{self, RemuxProgress};
+use crate::scope;
+use crate::subtitles::{self, timing, SubtitleTrack};
+use crate::watcher;
+use crate::db::model::{
+    CoverArt, DuplicateGroup, Library, MissingReport, PlayabilityReport, Playlist, PlaylistItem, RelinkReport, Remux, SearchHit, Setting,
+    SmartPlaylist, SubtitleTiming, Tag, TagSummary, UnplayableCause, UnplayableVideo, Video, VideoPage, VIDEO_COLUMNS, VIDEO_COLUMN_COUNT,
+};
+use crate::db::query::{self, Cursor, SortDirection, SortField, SortSpec, VideoFilter};
+use crate::db::smart::{self, SmartRules};
+use std::path::{Path, PathBuf};
 use std::fs;
+use tauri::ipc::Channel;
 
 #[tauri::command]
 pub fn open_file_dialog(app: tauri::AppHandle) -> Option<FilePath> {
@@ -20,30 +35,12 @@ pub fn greet(name: String) -> String {
 }
 
 #[tauri::command]
-pub fn list_videos() -> Result<Vec<Video>, String> {
+pub fn list_videos(with_tags: Option<bool>, media_kind: Option<MediaKind>) -> Result<Vec<Video>, String> {
     let conn = database::get_connection().lock().map_err(|e| e.to_string())?;
-    let mut stmt = conn
-        .prepare("SELECT id, uuid, path, title, duration, rating, watch_count, favorite FROM videos ORDER BY COALESCE(last_watched, added_at) DESC")
-        .map_err(|e| e.to_string())?;
-
-    let video_iter = stmt
-        .query_map([], |row| {
-            Ok(Video {
-                id: row.get(0)?,
-                uuid: row.get(1)?,
-                path: row.get(2)?,
-                title: row.get(3)?,
-                duration: row.get(4)?,
-                rating: row.get(5)?,
-                watch_count: row.get(6)?,
-                favorite: row.get(7)?,
-            })
-        })
-        .map_err(|e| e.to_string())?;
-
-    let mut videos = Vec::new();
-    for vid in video_iter {
-        videos.push(vid.map_err(|e| e.to_string())?);
+    let filter = VideoFilter { media_kind, ..Default::default() };
+    let mut videos = query_page(&conn, &filter, &SortSpec::default(), None, None, None, false)?.items;
+    if with_tags.unwrap_or(false) {
+        attach_tags(&conn, &mut videos).map_err(|e| e.to_string())?;
     }
     Ok(videos)
 }
@@ -59,32 +56,29 @@ pub fn pick_folder(app: tauri::AppHandle) -> Result<Option<String>, String> {
 #[tauri::command]
 pub fn read_dir_recursive(path: String) -> Result<Vec<String>, String> {
     let mut result: Vec<String> = Vec::new();
-    let start = Path::new(&path);
-    if !start.exists() {
+    if !Path::new(&path).exists() {
         return Err("Path does not exist".to_string());
     }
+    // only folders inside a library; the frontend may not list the rest of the disk
+    let start = scope::check_in_library(Path::new(&path))?;
 
-    let exts = [".mp4", ".mkv", ".mov", ".webm", ".avi", ".flv", ".m4v", ".ts", ".m2ts", ".wmv", ".mp3"];
-
-    fn walk_dir(p: &Path, exts: &[&str], out: &mut Vec<String>) -> std::io::Result<()> {
+    fn walk_dir(p: &Path, out: &mut Vec<String>) -> std::io::Result<()> {
         for entry in fs::read_dir(p)? {
             let e = entry?;
             let path = e.path();
-            if path.is_dir() {
-                let _ = walk_dir(&path, exts, out);
-            } else if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-                let lower = name.to_lowercase();
-                if exts.iter().any(|ext| lower.ends_with(ext)) {
-                    if let Some(s) = path.to_str() {
-                        out.push(s.to_string());
-                    }
+            // symlinked folders are not followed, they could lead out of the library
+            if e.file_type()?.is_dir() {
+                let _ = walk_dir(&path, out);
+            } else if library::is_media_file(&path) {
+                if let Some(s) = path.to_str() {
+                    out.push(s.to_string());
                 }
             }
         }
         Ok(())
     }
 
-    match walk_dir(start, &exts, &mut result) {
+    match walk_dir(&start, &mut result) {
         Ok(_) => Ok(result),
         Err(e) => Err(e.to_string()),
     }
@@ -93,223 +87,132 @@ pub fn read_dir_recursive(path: String) -> Result<Vec<String>, String> {
 #[tauri::command]
 pub fn list_recent(limit: Option<i64>, offset: Option<i64>) -> Result<Vec<Video>, String> {
     let conn = database::get_connection().lock().map_err(|e| e.to_string())?;
-
-    let mut videos: Vec<Video> = Vec::new();
-
-    match (limit, offset) {
-        (Some(l), Some(o)) => {
-            let mut stmt = conn
-                .prepare("SELECT id, uuid, path, title, duration, rating, watch_count, favorite FROM videos WHERE last_watched IS NOT NULL ORDER BY last_watched DESC LIMIT ?1 OFFSET ?2")
-                .map_err(|e| e.to_string())?;
-            let iter = stmt
-                .query_map(params![l, o], |row| {
-                    Ok(Video {
-                        id: row.get(0)?,
-                        uuid: row.get(1)?,
-                        path: row.get(2)?,
-                        title: row.get(3)?,
-                        duration: row.get(4)?,
-                        rating: row.get(5)?,
-                        watch_count: row.get(6)?,
-                        favorite: row.get(7)?,
-                    })
-                })
-                .map_err(|e| e.to_string())?;
-            for v in iter {
-                videos.push(v.map_err(|e| e.to_string())?);
-            }
-        }
-        (Some(l), None) => {
-            let mut stmt = conn
-                .prepare("SELECT id, uuid, path, title, duration, rating, watch_count, favorite FROM videos WHERE last_watched IS NOT NULL ORDER BY last_watched DESC LIMIT ?1")
-                .map_err(|e| e.to_string())?;
-            let iter = stmt
-                .query_map(params![l], |row| {
-                    Ok(Video {
-                        id: row.get(0)?,
-                        uuid: row.get(1)?,
-                        path: row.get(2)?,
-                        title: row.get(3)?,
-                        duration: row.get(4)?,
-                        rating: row.get(5)?,
-                        watch_count: row.get(6)?,
-                        favorite: row.get(7)?,
-                    })
-                })
-                .map_err(|e| e.to_string())?;
-            for v in iter {
-                videos.push(v.map_err(|e| e.to_string())?);
-            }
-        }
-        (None, Some(o)) => {
-            let mut stmt = conn
-                .prepare("SELECT id, uuid, path, title, duration, rating, watch_count, favorite FROM videos WHERE last_watched IS NOT NULL ORDER BY last_watched DESC LIMIT -1 OFFSET ?1")
-                .map_err(|e| e.to_string())?;
-            let iter = stmt
-                .query_map(params![o], |row| {
-                    Ok(Video {
-                        id: row.get(0)?,
-                        uuid: row.get(1)?,
-                        path: row.get(2)?,
-                        title: row.get(3)?,
-                        duration: row.get(4)?,
-                        rating: row.get(5)?,
-                        watch_count: row.get(6)?,
-                        favorite: row.get(7)?,
-                    })
-                })
-                .map_err(|e| e.to_string())?;
-            for v in iter {
-                videos.push(v.map_err(|e| e.to_string())?);
-            }
-        }
-        (None, None) => {
-            let mut stmt = conn
-                .prepare("SELECT id, uuid, path, title, duration, rating, watch_count, favorite FROM videos WHERE last_watched IS NOT NULL ORDER BY last_watched DESC")
-                .map_err(|e| e.to_string())?;
-            let iter = stmt
-                .query_map([], |row| {
-                    Ok(Video {
-                        id: row.get(0)?,
-                        uuid: row.get(1)?,
-                        path: row.get(2)?,
-                        title: row.get(3)?,
-                        duration: row.get(4)?,
-                        rating: row.get(5)?,
-                        watch_count: row.get(6)?,
-                        favorite: row.get(7)?,
-                    })
-                })
-                .map_err(|e| e.to_string())?;
-            for v in iter {
-                videos.push(v.map_err(|e| e.to_string())?);
-            }
-        }
-    }
-
-    Ok(videos)
+    let filter = VideoFilter { watched: Some(true), ..Default::default() };
+    let sort = SortSpec { field: SortField::LastWatched, direction: SortDirection::Desc };
+    Ok(query_page(&conn, &filter, &sort, limit, offset, None, false)?.items)
 }
 
 #[tauri::command]
 pub fn list_favorites(limit: Option<i64>, offset: Option<i64>) -> Result<Vec<Video>, String> {
     let conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+    let filter = VideoFilter { favorite: Some(true), ..Default::default() };
+    Ok(query_page(&conn, &filter, &SortSpec::default(), limit, offset, None, false)?.items)
+}
 
-    let mut videos: Vec<Video> = Vec::new();
+// Shared SELECT behind the library listings. With a `cursor` the page starts after the
+// cursor row (keyset paging) and `offset` is ignored.
+fn query_page(
+    conn: &Connection,
+    filter: &VideoFilter,
+    sort: &SortSpec,
+    limit: Option<i64>,
+    offset: Option<i64>,
+    cursor: Option<&str>,
+    with_total: bool,
+) -> Result<VideoPage, String> {
+    let mut conditions = Vec::new();
+    let mut filter_params = Vec::new();
+    query::filter_sql(filter, &mut conditions, &mut filter_params);
 
-    match (limit, offset) {
-        (Some(l), Some(o)) => {
-            let mut stmt = conn
-                .prepare("SELECT id, uuid, path, title, duration, rating, watch_count, favorite FROM videos WHERE favorite = 1 ORDER BY COALESCE(last_watched, added_at) DESC LIMIT ?1 OFFSET ?2")
-                .map_err(|e| e.to_string())?;
-            let iter = stmt
-                .query_map(params![l, o], |row| {
-                    Ok(Video {
-                        id: row.get(0)?,
-                        uuid: row.get(1)?,
-                        path: row.get(2)?,
-                        title: row.get(3)?,
-                        duration: row.get(4)?,
-                        rating: row.get(5)?,
-                        watch_count: row.get(6)?,
-                        favorite: row.get(7)?,
-                    })
-                })
-                .map_err(|e| e.to_string())?;
-            for v in iter {
-                videos.push(v.map_err(|e| e.to_string())?);
-            }
-        }
-        (Some(l), None) => {
-            let mut stmt = conn
-                .prepare("SELECT id, uuid, path, title, duration, rating, watch_count, favorite FROM videos WHERE favorite = 1 ORDER BY COALESCE(last_watched, added_at) DESC LIMIT ?1")
-                .map_err(|e| e.to_string())?;
-            let iter = stmt
-                .query_map(params![l], |row| {
-                    Ok(Video {
-                        id: row.get(0)?,
-                        uuid: row.get(1)?,
-                        path: row.get(2)?,
-                        title: row.get(3)?,
-                        duration: row.get(4)?,
-                        rating: row.get(5)?,
-                        watch_count: row.get(6)?,
-                        favorite: row.get(7)?,
-                    })
-                })
-                .map_err(|e| e.to_string())?;
-            for v in iter {
-                videos.push(v.map_err(|e| e.to_string())?);
-            }
-        }
-        (None, Some(o)) => {
-            let mut stmt = conn
-                .prepare("SELECT id, uuid, path, title, duration, rating, watch_count, favorite FROM videos WHERE favorite = 1 ORDER BY COALESCE(last_watched, added_at) DESC LIMIT -1 OFFSET ?1")
-                .map_err(|e| e.to_string())?;
-            let iter = stmt
-                .query_map(params![o], |row| {
-                    Ok(Video {
-                        id: row.get(0)?,
-                        uuid: row.get(1)?,
-                        path: row.get(2)?,
-                        title: row.get(3)?,
-                        duration: row.get(4)?,
-                        rating: row.get(5)?,
-                        watch_count: row.get(6)?,
-                        favorite: row.get(7)?,
-                    })
-                })
-                .map_err(|e| e.to_string())?;
-            for v in iter {
-                videos.push(v.map_err(|e| e.to_string())?);
-            }
-        }
-        (None, None) => {
-            let mut stmt = conn
-                .prepare("SELECT id, uuid, path, title, duration, rating, watch_count, favorite FROM videos WHERE favorite = 1 ORDER BY COALESCE(last_watched, added_at) DESC")
-                .map_err(|e| e.to_string())?;
-            let iter = stmt
-                .query_map([], |row| {
-                    Ok(Video {
-                        id: row.get(0)?,
-                        uuid: row.get(1)?,
-                        path: row.get(2)?,
-                        title: row.get(3)?,
-                        duration: row.get(4)?,
-                        rating: row.get(5)?,
-                        watch_count: row.get(6)?,
-                        favorite: row.get(7)?,
-                    })
-                })
-                .map_err(|e| e.to_string())?;
-            for v in iter {
-                videos.push(v.map_err(|e| e.to_string())?);
-            }
+    let total = if with_total {
+        let where_sql = if conditions.is_empty() { String::new() } else { format!("WHERE {}", conditions.join(" AND ")) };
+        conn.query_row(
+            &format!("SELECT COUNT(*) FROM videos {}", where_sql),
+            params_from_iter(filter_params.iter()),
+            |row| row.get(0),
+        )
+        .map_err(|e| e.to_string())?
+    } else {
+        0
+    };
+
+    let mut page_params = filter_params;
+    let offset = match cursor {
+        Some(c) => {
+            conditions.push(Cursor::decode(c)?.condition(sort, &mut page_params)?);
+            0
         }
+        None => offset.unwrap_or(0),
+    };
+    let where_sql = if conditions.is_empty() { String::new() } else { format!("WHERE {}", conditions.join(" AND ")) };
+    let key_sql = query::sort_key(sort.field).unwrap_or("NULL");
+    let sql = format!(
+        "SELECT {}, {} FROM videos {} ORDER BY {} LIMIT {} OFFSET {}",
+        VIDEO_COLUMNS,
+        key_sql,
+        where_sql,
+        query::sort_sql(sort),
+        limit.unwrap_or(-1),
+        offset
+    );
+
+    let mut stmt = conn.prepare(&sql).map_err(|e| e.to_string())?;
+    let iter = stmt
+        .query_map(params_from_iter(page_params.iter()), |row| {
+            Ok((Video::from_row(row)?, row.get::<_, rusqlite::types::Value>(VIDEO_COLUMN_COUNT)?))
+        })
+        .map_err(|e| e.to_string())?;
+
+    let mut items = Vec::new();
+    let mut last_key = None;
+    for r in iter {
+        let (video, key) = r.map_err(|e| e.to_string())?;
+        last_key = Some((key, video.id));
+        items.push(video);
     }
 
-    Ok(videos)
+    let full_page = limit.map(|l| l > 0 && items.len() as i64 == l).unwrap_or(false);
+    let next_cursor = match last_key {
+        Some((key, id)) if full_page && query::sort_key(sort.field).is_some() => {
+            let key = match key {
+                rusqlite::types::Value::Integer(i) => serde_json::Value::from(i),
+                rusqlite::types::Value::Real(f) => serde_json::Value::from(f),
+                rusqlite::types::Value::Text(t) => serde_json::Value::from(t),
+                _ => serde_json::Value::Null,
+            };
+            Some(Cursor { key, id }.encode())
+        }
+        _ => None,
+    };
+
+    Ok(VideoPage { items, total, next_cursor })
+}
+
+// One entry point for the library views: filter, sort and page (offset or cursor).
+#[tauri::command]
+pub fn query_videos(
+    filter: Option<VideoFilter>,
+    sort: Option<SortSpec>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+    cursor: Option<String>,
+    with_tags: Option<bool>,
+) -> Result<VideoPage, String> {
+    let conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+    let mut page = query_page(
+        &conn,
+        &filter.unwrap_or_default(),
+        &sort.unwrap_or_default(),
+        limit,
+        offset,
+        cursor.as_deref(),
+        true,
+    )?;
+    if with_tags.unwrap_or(false) {
+        attach_tags(&conn, &mut page.items).map_err(|e| e.to_string())?;
+    }
+    Ok(page)
 }
 
 #[tauri::command]
 pub fn get_video(id: i64) -> Result<Option<Video>, String> {
     let conn = database::get_connection().lock().map_err(|e| e.to_string())?;
     let mut stmt = conn
-        .prepare("SELECT id, uuid, path, title, duration, rating, watch_count, favorite FROM videos WHERE id = ?1")
+        .prepare(&format!("SELECT {} FROM videos WHERE id = ?1", VIDEO_COLUMNS))
         .map_err(|e| e.to_string())?;
 
     let mut rows = stmt
-        .query_map(params![id], |row| {
-            Ok(Video {
-                id: row.get(0)?,
-                uuid: row.get(1)?,
-                path: row.get(2)?,
-                title: row.get(3)?,
-                duration: row.get(4)?,
-                rating: row.get(5)?,
-                watch_count: row.get(6)?,
-                favorite: row.get(7)?,
-            })
-        })
+        .query_map(params![id], Video::from_row)
         .map_err(|e| e.to_string())?;
 
     if let Some(result) = rows.next() {
@@ -320,29 +223,58 @@ pub fn get_video(id: i64) -> Result<Option<Video>, String> {
     }
 }
 
+// A file that matches a missing row (moved outside the app) takes over that row, so
+// its history is kept instead of starting over as a new video. Only media files inside a
+// library folder can be added; the `video://` protocol serves whatever is in the table.
 #[tauri::command]
 pub fn add_video(path: String, title: Option<String>, duration: Option<i64>) -> Result<Video, String> {
+    if !library::is_media_file(Path::new(&path)) {
+        return Err(format!("{} is not a media file", path));
+    }
+    scope::check_in_library(Path::new(&path))?;
+    let entry = FileEntry::from_path(Path::new(&path)).ok().map(|e| e.with_quick_hash().with_media_info());
     let conn = database::get_connection().lock().map_err(|e| e.to_string())?;
-    let uuid = uuid::Uuid::new_v4().to_string();
 
-    conn.execute(
-        "INSERT INTO videos (uuid, path, title, duration) VALUES (?1, ?2, ?3, ?4)",
-        params![uuid, path, title, duration],
-    )
-    .map_err(|e| e.to_string())?;
+    let moved = match &entry {
+        Some(entry) => library::find_moved_row(&conn, entry).map_err(|e| e.to_string())?,
+        None => None,
+    };
 
-    let id = conn.last_insert_rowid();
-
-    Ok(Video {
-        id,
-        uuid,
-        path,
-        title,
-        duration,
-        rating: None,
-        watch_count: 0,
-        favorite: 0,
-    })
+    let id = match (moved, &entry) {
+        (Some(id), Some(entry)) => {
+            library::relink_row(&conn, id, None, entry).map_err(|e| e.to_string())?;
+            let roots = library::load_roots(&conn).map_err(|e| e.to_string())?;
+            // what the caller passed wins over the old row, as it would for a new one
+            conn.execute(
+                "UPDATE videos SET library_id = ?1, title = COALESCE(?2, title), duration = COALESCE(?3, duration) WHERE id = ?4",
+                params![library::library_for(&roots, Path::new(&entry.path)), title, duration, id],
+            )
+            .map_err(|e| e.to_string())?;
+            id
+        }
+        _ => {
+            let uuid = uuid::Uuid::new_v4().to_string();
+            conn.execute(
+                "INSERT INTO videos (uuid, path, title, duration, file_size, file_mtime, quick_hash) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    uuid,
+                    path,
+                    title,
+                    duration,
+                    entry.as_ref().map(|e| e.size),
+                    entry.as_ref().map(|e| e.mtime),
+                    entry.as_ref().and_then(|e| e.quick_hash.clone())
+                ],
+            )
+            .map_err(|e| e.to_string())?;
+            conn.last_insert_rowid()
+        }
+    };
+    if let Some(entry) = &entry {
+        library::store_media_info(&conn, entry, &playback::codec_support(&conn)).map_err(|e| e.to_string())?;
+    }
+    conn.query_row(&format!("SELECT {} FROM videos WHERE id = ?1", VIDEO_COLUMNS), params![id], Video::from_row)
+        .map_err(|e| e.to_string())
 }
 
 #[tauri::command]
@@ -380,9 +312,68 @@ pub fn update_video_duration_by_path(path: String, duration: i64) -> Result<(),
     Ok(())
 }
 
+// Called periodically by the player while a video is playing.
+#[tauri::command]
+pub fn save_position(id: i64, position: f64) -> Result<(), String> {
+    let conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+    store_position(&conn, id, position).map_err(|e| e.to_string())
+}
+
+fn store_position(conn: &Connection, id: i64, position: f64) -> rusqlite::Result<()> {
+    conn.execute(
+        "UPDATE videos SET position = ?1, position_updated_at = datetime('now') WHERE id = ?2",
+        params![position.max(0.0), id],
+    )?;
+    Ok(())
+}
+
+#[tauri::command]
+pub fn clear_position(id: i64) -> Result<(), String> {
+    let conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+    conn.execute(
+        "UPDATE videos SET position = NULL, position_updated_at = NULL WHERE id = ?1",
+        params![id],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+// Setting key for the fraction of the duration after which a video counts as finished
+// and drops out of "continue watching". Values above 1 are read as a percentage.
+pub const FINISHED_THRESHOLD_KEY: &str = "resume_finished_threshold";
+const DEFAULT_FINISHED_THRESHOLD: f64 = 0.95;
+
+fn finished_threshold(conn: &rusqlite::Connection) -> f64 {
+    let value: Option<String> = conn
+        .query_row("SELECT value FROM settings WHERE key = ?1", params![FINISHED_THRESHOLD_KEY], |row| row.get(0))
+        .ok();
+    match value.and_then(|v| v.trim().parse::<f64>().ok()) {
+        Some(t) if t > 1.0 && t <= 100.0 => t / 100.0,
+        Some(t) if t > 0.0 && t <= 1.0 => t,
+        _ => DEFAULT_FINISHED_THRESHOLD,
+    }
+}
+
+#[tauri::command]
+pub fn list_continue_watching(limit: Option<i64>, offset: Option<i64>) -> Result<Vec<Video>, String> {
+    let conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+    continue_watching(&conn, limit, offset).map_err(|e| e.to_string())
+}
+
+fn continue_watching(conn: &Connection, limit: Option<i64>, offset: Option<i64>) -> rusqlite::Result<Vec<Video>> {
+    let threshold = finished_threshold(conn);
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM videos WHERE position > 0 AND (duration IS NULL OR duration <= 0 OR position < duration * ?1) ORDER BY position_updated_at DESC LIMIT ?2 OFFSET ?3",
+        VIDEO_COLUMNS
+    ))?;
+    let rows = stmt.query_map(params![threshold, limit.unwrap_or(-1), offset.unwrap_or(0)], Video::from_row)?;
+    rows.collect()
+}
+
 #[tauri::command]
 pub fn delete_video(id: i64) -> Result<(), String> {
     let conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+    remux::delete_remux(&conn, id).map_err(|e| e.to_string())?;
     conn.execute("DELETE FROM videos WHERE id = ?1", params![id])
         .map_err(|e| e.to_string())?;
     Ok(())
@@ -401,22 +392,11 @@ pub fn set_favorite(id: i64, favorite: bool) -> Result<(), String> {
 pub fn get_video_by_path(path: String) -> Result<Option<Video>, String> {
     let conn = database::get_connection().lock().map_err(|e| e.to_string())?;
     let mut stmt = conn
-        .prepare("SELECT id, uuid, path, title, duration, rating, watch_count, favorite FROM videos WHERE path = ?1")
+        .prepare(&format!("SELECT {} FROM videos WHERE path = ?1", VIDEO_COLUMNS))
         .map_err(|e| e.to_string())?;
 
     let mut rows = stmt
-        .query_map(params![path], |row| {
-            Ok(Video {
-                id: row.get(0)?,
-                uuid: row.get(1)?,
-                path: row.get(2)?,
-                title: row.get(3)?,
-                duration: row.get(4)?,
-                rating: row.get(5)?,
-                watch_count: row.get(6)?,
-                favorite: row.get(7)?,
-            })
-        })
+        .query_map(params![path], Video::from_row)
         .map_err(|e| e.to_string())?;
 
     if let Some(result) = rows.next() {
@@ -477,3 +457,1579 @@ pub fn list_settings() -> Result<Vec<Setting>, String> {
     }
     Ok(out)
 }
+
+// "?, ?, ?" for dynamic IN (...) lists
+fn placeholders(n: usize) -> String {
+    vec!["?"; n].join(", ")
+}
+
+// Loads the tags of all given videos with one query per chunk instead of one per video.
+fn attach_tags(conn: &Connection, videos: &mut [Video]) -> rusqlite::Result<()> {
+    let mut by_video: HashMap<i64, Vec<Tag>> = HashMap::new();
+    let ids: Vec<i64> = videos.iter().map(|v| v.id).collect();
+    for chunk in ids.chunks(500) {
+        let sql = format!(
+            "SELECT vt.video_id, t.id, t.name FROM video_tags vt JOIN tags t ON t.id = vt.tag_id WHERE vt.video_id IN ({}) ORDER BY t.name COLLATE NOCASE",
+            placeholders(chunk.len())
+        );
+        let mut stmt = conn.prepare(&sql)?;
+        let rows = stmt.query_map(params_from_iter(chunk.iter()), |row| {
+            Ok((row.get::<_, i64>(0)?, Tag { id: row.get(1)?, name: row.get(2)? }))
+        })?;
+        for r in rows {
+            let (video_id, tag) = r?;
+            by_video.entry(video_id).or_default().push(tag);
+        }
+    }
+    for v in videos.iter_mut() {
+        v.tags = Some(by_video.remove(&v.id).unwrap_or_default());
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub fn list_tags() -> Result<Vec<TagSummary>, String> {
+    let conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+    let mut stmt = conn
+        .prepare("SELECT t.id, t.name, COUNT(vt.video_id) FROM tags t LEFT JOIN video_tags vt ON vt.tag_id = t.id GROUP BY t.id ORDER BY t.name COLLATE NOCASE")
+        .map_err(|e| e.to_string())?;
+    let iter = stmt
+        .query_map([], |row| Ok(TagSummary { id: row.get(0)?, name: row.get(1)?, video_count: row.get(2)? }))
+        .map_err(|e| e.to_string())?;
+
+    let mut out = Vec::new();
+    for t in iter {
+        out.push(t.map_err(|e| e.to_string())?);
+    }
+    Ok(out)
+}
+
+#[tauri::command]
+pub fn list_video_tags(video_id: i64) -> Result<Vec<Tag>, String> {
+    let conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+    let mut stmt = conn
+        .prepare("SELECT t.id, t.name FROM tags t JOIN video_tags vt ON vt.tag_id = t.id WHERE vt.video_id = ?1 ORDER BY t.name COLLATE NOCASE")
+        .map_err(|e| e.to_string())?;
+    let iter = stmt
+        .query_map(params![video_id], |row| Ok(Tag { id: row.get(0)?, name: row.get(1)? }))
+        .map_err(|e| e.to_string())?;
+
+    let mut out = Vec::new();
+    for t in iter {
+        out.push(t.map_err(|e| e.to_string())?);
+    }
+    Ok(out)
+}
+
+#[tauri::command]
+pub fn create_tag(name: String) -> Result<Tag, String> {
+    let name = name.trim().to_string();
+    if name.is_empty() {
+        return Err("Tag name must not be empty".to_string());
+    }
+    let conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+    conn.execute("INSERT INTO tags (name) VALUES (?1)", params![name])
+        .map_err(|e| match e {
+            rusqlite::Error::SqliteFailure(f, _) if f.code == rusqlite::ErrorCode::ConstraintViolation => {
+                format!("Tag \"{}\" already exists", name)
+            }
+            e => e.to_string(),
+        })?;
+    Ok(Tag { id: conn.last_insert_rowid(), name })
+}
+
+#[tauri::command]
+pub fn rename_tag(id: i64, name: String) -> Result<(), String> {
+    let name = name.trim().to_string();
+    if name.is_empty() {
+        return Err("Tag name must not be empty".to_string());
+    }
+    let conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+    conn.execute("UPDATE tags SET name = ?1 WHERE id = ?2", params![name, id])
+        .map_err(|e| match e {
+            rusqlite::Error::SqliteFailure(f, _) if f.code == rusqlite::ErrorCode::ConstraintViolation => {
+                format!("Tag \"{}\" already exists, merge the tags instead", name)
+            }
+            e => e.to_string(),
+        })?;
+    Ok(())
+}
+
+// Moves every video of the source tags onto the target tag and removes the sources.
+#[tauri::command]
+pub fn merge_tags(source_ids: Vec<i64>, target_id: i64) -> Result<(), String> {
+    let mut conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+    merge_tags_into(&mut conn, &source_ids, target_id)
+}
+
+fn merge_tags_into(conn: &mut Connection, source_ids: &[i64], target_id: i64) -> Result<(), String> {
+    let sources: Vec<i64> = source_ids.iter().copied().filter(|id| *id != target_id).collect();
+    if sources.is_empty() {
+        return Ok(());
+    }
+
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    let exists: bool = tx
+        .query_row("SELECT EXISTS(SELECT 1 FROM tags WHERE id = ?1)", params![target_id], |row| row.get(0))
+        .map_err(|e| e.to_string())?;
+    if !exists {
+        return Err("Target tag does not exist".to_string());
+    }
+    let list = placeholders(sources.len());
+    tx.execute(
+        &format!("INSERT OR IGNORE INTO video_tags (video_id, tag_id) SELECT video_id, {} FROM video_tags WHERE tag_id IN ({})", target_id, list),
+        params_from_iter(sources.iter()),
+    )
+    .map_err(|e| e.to_string())?;
+    tx.execute(&format!("DELETE FROM tags WHERE id IN ({})", list), params_from_iter(sources.iter()))
+        .map_err(|e| e.to_string())?;
+    tx.commit().map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[tauri::command]
+pub fn delete_tag(id: i64) -> Result<(), String> {
+    let conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+    conn.execute("DELETE FROM tags WHERE id = ?1", params![id])
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[tauri::command]
+pub fn tag_videos(video_ids: Vec<i64>, tag_ids: Vec<i64>) -> Result<(), String> {
+    let mut conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+    assign_tags(&mut conn, &video_ids, &tag_ids).map_err(|e| e.to_string())
+}
+
+fn assign_tags(conn: &mut Connection, video_ids: &[i64], tag_ids: &[i64]) -> rusqlite::Result<()> {
+    let tx = conn.transaction()?;
+    {
+        let mut stmt = tx.prepare("INSERT OR IGNORE INTO video_tags (video_id, tag_id) VALUES (?1, ?2)")?;
+        for video_id in video_ids {
+            for tag_id in tag_ids {
+                stmt.execute(params![video_id, tag_id])?;
+            }
+        }
+    }
+    tx.commit()
+}
+
+#[tauri::command]
+pub fn untag_videos(video_ids: Vec<i64>, tag_ids: Vec<i64>) -> Result<(), String> {
+    let mut conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    {
+        let mut stmt = tx
+            .prepare("DELETE FROM video_tags WHERE video_id = ?1 AND tag_id = ?2")
+            .map_err(|e| e.to_string())?;
+        for video_id in &video_ids {
+            for tag_id in &tag_ids {
+                stmt.execute(params![video_id, tag_id]).map_err(|e| e.to_string())?;
+            }
+        }
+    }
+    tx.commit().map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+// match_all = true: videos carrying every given tag (AND), otherwise any of them (OR).
+#[tauri::command]
+pub fn list_videos_by_tags(
+    tag_ids: Vec<i64>,
+    match_all: Option<bool>,
+    with_tags: Option<bool>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+) -> Result<Vec<Video>, String> {
+    if tag_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+    let conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+
+    let mut tag_ids = tag_ids;
+    tag_ids.sort_unstable();
+    tag_ids.dedup();
+    let having = if match_all.unwrap_or(false) {
+        format!("HAVING COUNT(DISTINCT tag_id) = {}", tag_ids.len())
+    } else {
+        String::new()
+    };
+    let sql = format!(
+        "SELECT {} FROM videos WHERE id IN (SELECT video_id FROM video_tags WHERE tag_id IN ({}) GROUP BY video_id {}) ORDER BY COALESCE(last_watched, added_at) DESC LIMIT {} OFFSET {}",
+        VIDEO_COLUMNS,
+        placeholders(tag_ids.len()),
+        having,
+        limit.unwrap_or(-1),
+        offset.unwrap_or(0)
+    );
+    let mut stmt = conn.prepare(&sql).map_err(|e| e.to_string())?;
+    let iter = stmt
+        .query_map(params_from_iter(tag_ids.iter()), Video::from_row)
+        .map_err(|e| e.to_string())?;
+
+    let mut videos = Vec::new();
+    for v in iter {
+        videos.push(v.map_err(|e| e.to_string())?);
+    }
+    if with_tags.unwrap_or(false) {
+        attach_tags(&conn, &mut videos).map_err(|e| e.to_string())?;
+    }
+    Ok(videos)
+}
+
+fn load_videos_by_ids(conn: &Connection, ids: &[i64]) -> rusqlite::Result<HashMap<i64, Video>> {
+    let mut out = HashMap::new();
+    for chunk in ids.chunks(500) {
+        let sql = format!("SELECT {} FROM videos WHERE id IN ({})", VIDEO_COLUMNS, placeholders(chunk.len()));
+        let mut stmt = conn.prepare(&sql)?;
+        let rows = stmt.query_map(params_from_iter(chunk.iter()), Video::from_row)?;
+        for r in rows {
+            let v = r?;
+            out.insert(v.id, v);
+        }
+    }
+    Ok(out)
+}
+
+fn get_playlist_by_id(conn: &Connection, id: i64) -> rusqlite::Result<Playlist> {
+    conn.query_row(
+        "SELECT p.id, p.name, (SELECT COUNT(*) FROM playlist_items pi WHERE pi.playlist_id = p.id), p.created_at, p.updated_at FROM playlists p WHERE p.id = ?1",
+        params![id],
+        |row| {
+            Ok(Playlist {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                item_count: row.get(2)?,
+                created_at: row.get(3)?,
+                updated_at: row.get(4)?,
+            })
+        },
+    )
+}
+
+// Rewrites positions as 0..n keeping the current order, after removals or inserts.
+fn renumber_playlist(conn: &Connection, playlist_id: i64) -> rusqlite::Result<()> {
+    let ids: Vec<i64> = {
+        let mut stmt = conn.prepare("SELECT id FROM playlist_items WHERE playlist_id = ?1 ORDER BY position, id")?;
+        let rows = stmt.query_map(params![playlist_id], |row| row.get(0))?;
+        rows.collect::<rusqlite::Result<_>>()?
+    };
+    let mut stmt = conn.prepare("UPDATE playlist_items SET position = ?1 WHERE id = ?2")?;
+    for (i, id) in ids.iter().enumerate() {
+        stmt.execute(params![i as i64, id])?;
+    }
+    conn.execute("UPDATE playlists SET updated_at = datetime('now') WHERE id = ?1", params![playlist_id])?;
+    Ok(())
+}
+
+#[tauri::command]
+pub fn list_playlists() -> Result<Vec<Playlist>, String> {
+    let conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+    let mut stmt = conn
+        .prepare("SELECT p.id, p.name, COUNT(pi.id), p.created_at, p.updated_at FROM playlists p LEFT JOIN playlist_items pi ON pi.playlist_id = p.id GROUP BY p.id ORDER BY p.name COLLATE NOCASE")
+        .map_err(|e| e.to_string())?;
+    let iter = stmt
+        .query_map([], |row| {
+            Ok(Playlist {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                item_count: row.get(2)?,
+                created_at: row.get(3)?,
+                updated_at: row.get(4)?,
+            })
+        })
+        .map_err(|e| e.to_string())?;
+
+    let mut out = Vec::new();
+    for p in iter {
+        out.push(p.map_err(|e| e.to_string())?);
+    }
+    Ok(out)
+}
+
+#[tauri::command]
+pub fn create_playlist(name: String) -> Result<Playlist, String> {
+    let name = name.trim().to_string();
+    if name.is_empty() {
+        return Err("Playlist name must not be empty".to_string());
+    }
+    let conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+    conn.execute("INSERT INTO playlists (name) VALUES (?1)", params![name])
+        .map_err(|e| e.to_string())?;
+    get_playlist_by_id(&conn, conn.last_insert_rowid()).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn rename_playlist(id: i64, name: String) -> Result<(), String> {
+    let name = name.trim().to_string();
+    if name.is_empty() {
+        return Err("Playlist name must not be empty".to_string());
+    }
+    let conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+    conn.execute(
+        "UPDATE playlists SET name = ?1, updated_at = datetime('now') WHERE id = ?2",
+        params![name, id],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[tauri::command]
+pub fn delete_playlist(id: i64) -> Result<(), String> {
+    let conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+    conn.execute("DELETE FROM playlists WHERE id = ?1", params![id])
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[tauri::command]
+pub fn duplicate_playlist(id: i64, name: Option<String>) -> Result<Playlist, String> {
+    let mut conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    let source = get_playlist_by_id(&tx, id).map_err(|e| e.to_string())?;
+    let name = name
+        .map(|n| n.trim().to_string())
+        .filter(|n| !n.is_empty())
+        .unwrap_or_else(|| format!("{} (copy)", source.name));
+
+    tx.execute("INSERT INTO playlists (name) VALUES (?1)", params![name])
+        .map_err(|e| e.to_string())?;
+    let new_id = tx.last_insert_rowid();
+    tx.execute(
+        "INSERT INTO playlist_items (playlist_id, video_id, video_path, position) SELECT ?1, video_id, video_path, position FROM playlist_items WHERE playlist_id = ?2 ORDER BY position",
+        params![new_id, id],
+    )
+    .map_err(|e| e.to_string())?;
+    let playlist = get_playlist_by_id(&tx, new_id).map_err(|e| e.to_string())?;
+    tx.commit().map_err(|e| e.to_string())?;
+    Ok(playlist)
+}
+
+#[tauri::command]
+pub fn get_playlist_items(playlist_id: i64) -> Result<Vec<PlaylistItem>, String> {
+    let conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+    let mut stmt = conn
+        .prepare("SELECT id, position, video_path, video_id FROM playlist_items WHERE playlist_id = ?1 ORDER BY position")
+        .map_err(|e| e.to_string())?;
+    let iter = stmt
+        .query_map(params![playlist_id], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?, row.get::<_, String>(2)?, row.get::<_, Option<i64>>(3)?))
+        })
+        .map_err(|e| e.to_string())?;
+
+    let mut rows = Vec::new();
+    for r in iter {
+        rows.push(r.map_err(|e| e.to_string())?);
+    }
+    let ids: Vec<i64> = rows.iter().filter_map(|r| r.3).collect();
+    let videos = load_videos_by_ids(&conn, &ids).map_err(|e| e.to_string())?;
+
+    Ok(rows
+        .into_iter()
+        .map(|(id, position, video_path, video_id)| PlaylistItem {
+            id,
+            position,
+            video_path,
+            // the same video may appear several times in one playlist
+            video: video_id.and_then(|vid| videos.get(&vid).cloned()),
+        })
+        .collect())
+}
+
+// Inserts the videos at `position` (0-based), or appends them when no position is given.
+#[tauri::command]
+pub fn add_to_playlist(playlist_id: i64, video_ids: Vec<i64>, position: Option<i64>) -> Result<(), String> {
+    let mut conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+    insert_playlist_items(&mut conn, playlist_id, &video_ids, position)
+}
+
+fn insert_playlist_items(conn: &mut Connection, playlist_id: i64, video_ids: &[i64], position: Option<i64>) -> Result<(), String> {
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+
+    let count: i64 = tx
+        .query_row("SELECT COUNT(*) FROM playlist_items WHERE playlist_id = ?1", params![playlist_id], |row| row.get(0))
+        .map_err(|e| e.to_string())?;
+    let start = position.unwrap_or(count).clamp(0, count);
+    let n = video_ids.len() as i64;
+
+    tx.execute(
+        "UPDATE playlist_items SET position = position + ?1 WHERE playlist_id = ?2 AND position >= ?3",
+        params![n, playlist_id, start],
+    )
+    .map_err(|e| e.to_string())?;
+    {
+        let mut stmt = tx
+            .prepare("INSERT INTO playlist_items (playlist_id, video_id, video_path, position) SELECT ?1, id, path, ?2 FROM videos WHERE id = ?3")
+            .map_err(|e| e.to_string())?;
+        for (i, video_id) in video_ids.iter().enumerate() {
+            let inserted = stmt
+                .execute(params![playlist_id, start + i as i64, video_id])
+                .map_err(|e| e.to_string())?;
+            if inserted == 0 {
+                return Err(format!("Video {} does not exist", video_id));
+            }
+        }
+    }
+    renumber_playlist(&tx, playlist_id).map_err(|e| e.to_string())?;
+    tx.commit().map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[tauri::command]
+pub fn remove_from_playlist(playlist_id: i64, item_ids: Vec<i64>) -> Result<(), String> {
+    let mut conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    {
+        let mut stmt = tx
+            .prepare("DELETE FROM playlist_items WHERE playlist_id = ?1 AND id = ?2")
+            .map_err(|e| e.to_string())?;
+        for item_id in &item_ids {
+            stmt.execute(params![playlist_id, item_id]).map_err(|e| e.to_string())?;
+        }
+    }
+    renumber_playlist(&tx, playlist_id).map_err(|e| e.to_string())?;
+    tx.commit().map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+// `item_ids` is the complete new order of the playlist's items.
+#[tauri::command]
+pub fn reorder_playlist(playlist_id: i64, item_ids: Vec<i64>) -> Result<(), String> {
+    let mut conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+    reorder_playlist_items(&mut conn, playlist_id, &item_ids)
+}
+
+fn reorder_playlist_items(conn: &mut Connection, playlist_id: i64, item_ids: &[i64]) -> Result<(), String> {
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+
+    let mut current: Vec<i64> = {
+        let mut stmt = tx
+            .prepare("SELECT id FROM playlist_items WHERE playlist_id = ?1")
+            .map_err(|e| e.to_string())?;
+        let iter = stmt
+            .query_map(params![playlist_id], |row| row.get(0))
+            .map_err(|e| e.to_string())?;
+        let mut ids = Vec::new();
+        for id in iter {
+            ids.push(id.map_err(|e| e.to_string())?);
+        }
+        ids
+    };
+    let mut requested = item_ids.to_vec();
+    current.sort_unstable();
+    requested.sort_unstable();
+    if current != requested {
+        return Err("Item list does not match the playlist contents".to_string());
+    }
+
+    {
+        let mut stmt = tx
+            .prepare("UPDATE playlist_items SET position = ?1 WHERE id = ?2")
+            .map_err(|e| e.to_string())?;
+        for (i, item_id) in item_ids.iter().enumerate() {
+            stmt.execute(params![i as i64, item_id]).map_err(|e| e.to_string())?;
+        }
+    }
+    tx.execute("UPDATE playlists SET updated_at = datetime('now') WHERE id = ?1", params![playlist_id])
+        .map_err(|e| e.to_string())?;
+    tx.commit().map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+fn smart_playlist_from_row(row: &rusqlite::Row) -> rusqlite::Result<SmartPlaylist> {
+    let json: String = row.get(2)?;
+    let rules = serde_json::from_str(&json)
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(2, rusqlite::types::Type::Text, Box::new(e)))?;
+    Ok(SmartPlaylist {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        rules,
+        created_at: row.get(3)?,
+        updated_at: row.get(4)?,
+    })
+}
+
+fn evaluate_rules(conn: &Connection, rules: &SmartRules) -> Result<Vec<Video>, String> {
+    let compiled = smart::compile(rules)?;
+    let sql = format!(
+        "SELECT {} FROM videos WHERE {} ORDER BY {} LIMIT {}",
+        VIDEO_COLUMNS,
+        compiled.where_sql,
+        compiled.order_sql,
+        compiled.limit.unwrap_or(-1)
+    );
+    let mut stmt = conn.prepare(&sql).map_err(|e| e.to_string())?;
+    let iter = stmt
+        .query_map(params_from_iter(compiled.params.iter()), Video::from_row)
+        .map_err(|e| e.to_string())?;
+
+    let mut videos = Vec::new();
+    for v in iter {
+        videos.push(v.map_err(|e| e.to_string())?);
+    }
+    Ok(videos)
+}
+
+#[tauri::command]
+pub fn list_smart_playlists() -> Result<Vec<SmartPlaylist>, String> {
+    let conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+    let mut stmt = conn
+        .prepare("SELECT id, name, rules, created_at, updated_at FROM smart_playlists ORDER BY name COLLATE NOCASE")
+        .map_err(|e| e.to_string())?;
+    let iter = stmt
+        .query_map([], smart_playlist_from_row)
+        .map_err(|e| e.to_string())?;
+
+    let mut out = Vec::new();
+    for p in iter {
+        out.push(p.map_err(|e| e.to_string())?);
+    }
+    Ok(out)
+}
+
+#[tauri::command]
+pub fn create_smart_playlist(name: String, rules: SmartRules) -> Result<SmartPlaylist, String> {
+    let name = name.trim().to_string();
+    if name.is_empty() {
+        return Err("Playlist name must not be empty".to_string());
+    }
+    // reject rule sets that cannot be turned into SQL before storing them
+    smart::compile(&rules)?;
+    let json = serde_json::to_string(&rules).map_err(|e| e.to_string())?;
+
+    let conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+    conn.execute("INSERT INTO smart_playlists (name, rules) VALUES (?1, ?2)", params![name, json])
+        .map_err(|e| e.to_string())?;
+    conn.query_row(
+        "SELECT id, name, rules, created_at, updated_at FROM smart_playlists WHERE id = ?1",
+        params![conn.last_insert_rowid()],
+        smart_playlist_from_row,
+    )
+    .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn update_smart_playlist(id: i64, name: Option<String>, rules: Option<SmartRules>) -> Result<(), String> {
+    let conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+    if let Some(name) = name {
+        let name = name.trim().to_string();
+        if name.is_empty() {
+            return Err("Playlist name must not be empty".to_string());
+        }
+        conn.execute(
+            "UPDATE smart_playlists SET name = ?1, updated_at = datetime('now') WHERE id = ?2",
+            params![name, id],
+        )
+        .map_err(|e| e.to_string())?;
+    }
+    if let Some(rules) = rules {
+        smart::compile(&rules)?;
+        let json = serde_json::to_string(&rules).map_err(|e| e.to_string())?;
+        conn.execute(
+            "UPDATE smart_playlists SET rules = ?1, updated_at = datetime('now') WHERE id = ?2",
+            params![json, id],
+        )
+        .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub fn delete_smart_playlist(id: i64) -> Result<(), String> {
+    let conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+    conn.execute("DELETE FROM smart_playlists WHERE id = ?1", params![id])
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[tauri::command]
+pub fn evaluate_smart_playlist(id: i64, with_tags: Option<bool>) -> Result<Vec<Video>, String> {
+    let conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+    let playlist = conn
+        .query_row(
+            "SELECT id, name, rules, created_at, updated_at FROM smart_playlists WHERE id = ?1",
+            params![id],
+            smart_playlist_from_row,
+        )
+        .map_err(|e| e.to_string())?;
+
+    let mut videos = evaluate_rules(&conn, &playlist.rules)?;
+    if with_tags.unwrap_or(false) {
+        attach_tags(&conn, &mut videos).map_err(|e| e.to_string())?;
+    }
+    Ok(videos)
+}
+
+// Evaluates unsaved rules, used by the editor to preview the result.
+#[tauri::command]
+pub fn preview_smart_playlist(rules: SmartRules) -> Result<Vec<Video>, String> {
+    let conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+    evaluate_rules(&conn, &rules)
+}
+
+// Turns free text into an FTS5 query: every word is quoted (so operators and punctuation
+// typed by the user are literal) and prefix-matched, all words must match.
+fn fts_query(input: &str) -> String {
+    input
+        .split_whitespace()
+        .map(|word| format!("\"{}\"*", word.replace('"', "\"\"")))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[tauri::command]
+pub fn search_videos(query: String, limit: Option<i64>, offset: Option<i64>) -> Result<Vec<SearchHit>, String> {
+    let match_expr = fts_query(&query);
+    if match_expr.is_empty() {
+        return Ok(Vec::new());
+    }
+    let conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+    search_hits(&conn, &match_expr, limit, offset).map_err(|e| e.to_string())
+}
+
+fn search_hits(conn: &Connection, match_expr: &str, limit: Option<i64>, offset: Option<i64>) -> rusqlite::Result<Vec<SearchHit>> {
+    // title and file name weigh more than tags, folder names the least
+    let sql = format!(
+        "SELECT {}, m.rank, m.title_hl, m.file_name_hl, m.path_snippet, m.tags_hl FROM (
+            SELECT rowid AS video_id,
+                   bm25(videos_fts, 10.0, 5.0, 1.0, 3.0) AS rank,
+                   highlight(videos_fts, 0, '<mark>', '</mark>') AS title_hl,
+                   highlight(videos_fts, 1, '<mark>', '</mark>') AS file_name_hl,
+                   snippet(videos_fts, 2, '<mark>', '</mark>', '…', 12) AS path_snippet,
+                   highlight(videos_fts, 3, '<mark>', '</mark>') AS tags_hl
+            FROM videos_fts WHERE videos_fts MATCH ?1 ORDER BY rank LIMIT ?2 OFFSET ?3
+        ) m JOIN videos ON videos.id = m.video_id ORDER BY m.rank",
+        VIDEO_COLUMNS
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let rows = stmt.query_map(params![match_expr, limit.unwrap_or(50), offset.unwrap_or(0)], |row| {
+        Ok(SearchHit {
+            video: Video::from_row(row)?,
+            rank: row.get(VIDEO_COLUMN_COUNT)?,
+            title_highlight: row.get(VIDEO_COLUMN_COUNT + 1)?,
+            file_name_highlight: row.get(VIDEO_COLUMN_COUNT + 2)?,
+            path_snippet: row.get(VIDEO_COLUMN_COUNT + 3)?,
+            tags_highlight: row.get(VIDEO_COLUMN_COUNT + 4)?,
+        })
+    })?;
+    rows.collect()
+}
+
+fn library_from_row(row: &rusqlite::Row) -> rusqlite::Result<Library> {
+    Ok(Library {
+        id: row.get(0)?,
+        path: row.get(1)?,
+        name: row.get(2)?,
+        added_at: row.get(3)?,
+        last_scanned_at: row.get(4)?,
+        video_count: row.get(5)?,
+    })
+}
+
+const LIBRARY_SELECT: &str = "SELECT l.id, l.path, l.name, l.added_at, l.last_scanned_at, (SELECT COUNT(*) FROM videos v WHERE v.library_id = l.id) FROM libraries l";
+
+#[tauri::command]
+pub fn list_libraries() -> Result<Vec<Library>, String> {
+    let conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+    let mut stmt = conn
+        .prepare(&format!("{} ORDER BY l.path", LIBRARY_SELECT))
+        .map_err(|e| e.to_string())?;
+    let iter = stmt.query_map([], library_from_row).map_err(|e| e.to_string())?;
+
+    let mut out = Vec::new();
+    for l in iter {
+        out.push(l.map_err(|e| e.to_string())?);
+    }
+    Ok(out)
+}
+
+#[tauri::command]
+pub fn add_library(app: tauri::AppHandle, path: String, name: Option<String>) -> Result<Library, String> {
+    let root = fs::canonicalize(&path).map_err(|e| e.to_string())?;
+    if !root.is_dir() {
+        return Err("Path is not a folder".to_string());
+    }
+    let root_str = root.to_string_lossy().to_string();
+
+    let conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+    // a file may only belong to one library, so roots must not be nested
+    let existing: Vec<String> = {
+        let mut stmt = conn.prepare("SELECT path FROM libraries").map_err(|e| e.to_string())?;
+        let iter = stmt.query_map([], |row| row.get(0)).map_err(|e| e.to_string())?;
+        iter.collect::<rusqlite::Result<_>>().map_err(|e| e.to_string())?
+    };
+    for other in &existing {
+        let other = Path::new(other);
+        if root.starts_with(other) || other.starts_with(&root) {
+            return Err(format!("Folder overlaps with the existing library {}", other.display()));
+        }
+    }
+
+    let name = name.map(|n| n.trim().to_string()).filter(|n| !n.is_empty());
+    conn.execute("INSERT INTO libraries (path, name) VALUES (?1, ?2)", params![root_str, name])
+        .map_err(|e| e.to_string())?;
+    let library = conn
+        .query_row(&format!("{} WHERE l.id = ?1", LIBRARY_SELECT), params![conn.last_insert_rowid()], library_from_row)
+        .map_err(|e| e.to_string())?;
+    drop(conn);
+
+    // the library change itself succeeded; a root that cannot be watched is only reported
+    if let Err(e) = watcher::sync_roots() {
+        watcher::report_error(&app, e);
+    }
+    scope::sync_roots(&app)?;
+    Ok(library)
+}
+
+// Without `remove_videos` the videos stay in the library view, detached from the root.
+#[tauri::command]
+pub fn remove_library(app: tauri::AppHandle, id: i64, remove_videos: Option<bool>) -> Result<(), String> {
+    library::cancel_scan(id);
+    let mut conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    if remove_videos.unwrap_or(false) {
+        let remuxed: Vec<i64> = {
+            let mut stmt = tx
+                .prepare("SELECT r.video_id FROM remuxes r JOIN videos v ON v.id = r.video_id WHERE v.library_id = ?1")
+                .map_err(|e| e.to_string())?;
+            let ids = stmt.query_map(params![id], |row| row.get(0)).map_err(|e| e.to_string())?;
+            ids.collect::<rusqlite::Result<_>>().map_err(|e| e.to_string())?
+        };
+        for video_id in remuxed {
+            remux::delete_remux(&tx, video_id).map_err(|e| e.to_string())?;
+        }
+        tx.execute("DELETE FROM videos WHERE library_id = ?1", params![id])
+            .map_err(|e| e.to_string())?;
+    }
+    tx.execute("DELETE FROM libraries WHERE id = ?1", params![id])
+        .map_err(|e| e.to_string())?;
+    tx.commit().map_err(|e| e.to_string())?;
+    drop(conn);
+
+    if let Err(e) = watcher::sync_roots() {
+        watcher::report_error(&app, e);
+    }
+    scope::sync_roots(&app)?;
+    Ok(())
+}
+
+// Walks the library folder on a worker thread and streams progress to `on_progress`.
+// Resolves with the final counters once the scan finished or was cancelled.
+#[tauri::command]
+pub async fn scan_library(library_id: i64, on_progress: Channel<ScanProgress>) -> Result<ScanProgress, String> {
+    let root: String = {
+        let conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+        conn.query_row("SELECT path FROM libraries WHERE id = ?1", params![library_id], |row| row.get(0))
+            .map_err(|e| e.to_string())?
+    };
+    let guard = library::begin_scan(library_id)?;
+
+    tauri::async_runtime::spawn_blocking(move || {
+        library::scan_library(database::get_connection(), library_id, Path::new(&root), &guard.cancel, |p| {
+            let _ = on_progress.send(p.clone());
+        })
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+#[tauri::command]
+pub fn cancel_scan(library_id: i64) -> Result<bool, String> {
+    Ok(library::cancel_scan(library_id))
+}
+
+// Flags rows whose file is gone and clears the flag on rows whose file is back.
+// Without `library_id` every video is checked, including ones added outside a library.
+#[tauri::command]
+pub async fn check_missing_files(library_id: Option<i64>) -> Result<MissingReport, String> {
+    let check = tauri::async_runtime::spawn_blocking(move || library::check_missing(database::get_connection(), library_id))
+        .await
+        .map_err(|e| e.to_string())??;
+
+    let conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT {} FROM videos WHERE missing = 1 AND (?1 IS NULL OR library_id = ?1) ORDER BY path",
+            VIDEO_COLUMNS
+        ))
+        .map_err(|e| e.to_string())?;
+    let iter = stmt.query_map(params![library_id], Video::from_row).map_err(|e| e.to_string())?;
+    let mut missing = Vec::new();
+    for v in iter {
+        missing.push(v.map_err(|e| e.to_string())?);
+    }
+    Ok(MissingReport { checked: check.checked, newly_missing: check.newly_missing, found_again: check.found_again, missing })
+}
+
+// Searches `folders` (default: all library roots) for the files of missing rows and
+// moves the rows to the new paths, keeping uuid and history. Folders outside the
+// libraries are refused, a relink never points a row outside them.
+#[tauri::command]
+pub async fn relink_missing(folders: Option<Vec<String>>) -> Result<RelinkReport, String> {
+    let folders: Vec<PathBuf> = match folders {
+        Some(folders) => {
+            for folder in &folders {
+                scope::check_in_library(Path::new(folder))?;
+            }
+            folders.into_iter().map(PathBuf::from).collect()
+        }
+        None => {
+            let conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+            library::load_roots(&conn).map_err(|e| e.to_string())?.into_values().collect()
+        }
+    };
+    tauri::async_runtime::spawn_blocking(move || library::relink_missing(database::get_connection(), &folders))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+// Manual relink of one video to a file the user picked. Refuses a file whose size or
+// fingerprint differs from what was recorded unless `force` is set. A row that already
+// exists for the new path is merged into this one. Like `add_video`, only media files
+// inside a library folder are accepted.
+#[tauri::command]
+pub fn relink_video(id: i64, path: String, force: Option<bool>) -> Result<Video, String> {
+    if !library::is_media_file(Path::new(&path)) {
+        return Err(format!("{} is not a media file", path));
+    }
+    scope::check_in_library(Path::new(&path))?;
+    let entry = FileEntry::from_path(Path::new(&path)).map_err(|e| e.to_string())?.with_quick_hash().with_media_info();
+
+    let mut conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+    let (size, hash): (Option<i64>, Option<String>) = conn
+        .query_row("SELECT file_size, quick_hash FROM videos WHERE id = ?1", params![id], |row| Ok((row.get(0)?, row.get(1)?)))
+        .map_err(|e| e.to_string())?;
+    let differs = size.is_some_and(|s| s != entry.size) || (hash.is_some() && entry.quick_hash.is_some() && hash != entry.quick_hash);
+    if differs && !force.unwrap_or(false) {
+        return Err("The file does not match the recorded video".to_string());
+    }
+
+    let roots = library::load_roots(&conn).map_err(|e| e.to_string())?;
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    let existing: Option<i64> = tx
+        .query_row("SELECT id FROM videos WHERE path = ?1", params![entry.path], |row| row.get(0))
+        .optional()
+        .map_err(|e| e.to_string())?;
+    if let Some(existing) = existing {
+        library::merge_rows(&tx, id, existing).map_err(|e| e.to_string())?;
+    }
+    library::relink_row(&tx, id, None, &entry).map_err(|e| e.to_string())?;
+    // a forced relink replaces the recorded fingerprint instead of keeping the old one
+    tx.execute(
+        "UPDATE videos SET library_id = ?1, quick_hash = ?2 WHERE id = ?3",
+        params![library::library_for(&roots, Path::new(&entry.path)), entry.quick_hash, id],
+    )
+    .map_err(|e| e.to_string())?;
+    library::store_media_info(&tx, &entry, &playback::codec_support(&tx)).map_err(|e| e.to_string())?;
+    let video = tx
+        .query_row(&format!("SELECT {} FROM videos WHERE id = ?1", VIDEO_COLUMNS), params![id], Video::from_row)
+        .map_err(|e| e.to_string())?;
+    tx.commit().map_err(|e| e.to_string())?;
+    Ok(video)
+}
+
+// Hashes every video that has no fingerprint yet (and with `full` no full hash) on a
+// worker thread, streaming progress like `scan_library`.
+#[tauri::command]
+pub async fn hash_videos(full: Option<bool>, on_progress: Channel<HashProgress>) -> Result<HashProgress, String> {
+    let guard = hashing::begin_hashing()?;
+    tauri::async_runtime::spawn_blocking(move || {
+        hashing::hash_videos(database::get_connection(), full.unwrap_or(false), &guard.cancel, |p| {
+            let _ = on_progress.send(p.clone());
+        })
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+#[tauri::command]
+pub fn cancel_hashing() -> Result<bool, String> {
+    Ok(hashing::cancel_hashing())
+}
+
+// Most history first: watched most, rated, favorite, watched last.
+const HISTORY_ORDER: &str = "COALESCE(watch_count, 0) DESC, rating IS NULL, COALESCE(favorite, 0) DESC, COALESCE(last_watched, '') DESC, id";
+
+// Copies of one file with their position in the query order.
+type Copies = Vec<(usize, Video)>;
+
+// Groups existing videos by content. Copies with a full hash that differs from the
+// others are split off; copies without one join the group when it is unambiguous.
+#[tauri::command]
+pub fn find_duplicates() -> Result<Vec<DuplicateGroup>, String> {
+    let conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+    duplicate_groups(&conn)
+}
+
+fn duplicate_groups(conn: &Connection) -> Result<Vec<DuplicateGroup>, String> {
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT {}, quick_hash, full_hash FROM videos v
+             WHERE COALESCE(missing, 0) = 0 AND quick_hash IS NOT NULL AND EXISTS (
+                SELECT 1 FROM videos d WHERE d.id <> v.id AND d.file_size IS v.file_size
+                    AND d.quick_hash = v.quick_hash AND COALESCE(d.missing, 0) = 0)
+             ORDER BY file_size DESC, quick_hash, {}",
+            VIDEO_COLUMNS, HISTORY_ORDER
+        ))
+        .map_err(|e| e.to_string())?;
+    let iter = stmt
+        .query_map([], |row| Ok((Video::from_row(row)?, row.get::<_, String>(VIDEO_COLUMN_COUNT)?, row.get::<_, Option<String>>(VIDEO_COLUMN_COUNT + 1)?)))
+        .map_err(|e| e.to_string())?;
+
+    // rows arrive sorted by (size, quick hash), so each run is one candidate group
+    let mut runs: Vec<Vec<(Video, String, Option<String>)>> = Vec::new();
+    for r in iter {
+        let r = r.map_err(|e| e.to_string())?;
+        match runs.last_mut() {
+            Some(run) if run[0].0.file_size == r.0.file_size && run[0].1 == r.1 => run.push(r),
+            _ => runs.push(vec![r]),
+        }
+    }
+
+    let mut groups = Vec::new();
+    for run in runs {
+        let quick_hash = run[0].1.clone();
+        let mut by_full: Vec<(Option<String>, Copies)> = Vec::new();
+        let mut unhashed = Vec::new();
+        for (i, (video, _, full)) in run.into_iter().enumerate() {
+            match full {
+                Some(full) => match by_full.iter_mut().find(|(f, _)| f.as_deref() == Some(full.as_str())) {
+                    Some((_, videos)) => videos.push((i, video)),
+                    None => by_full.push((Some(full), vec![(i, video)])),
+                },
+                None => unhashed.push((i, video)),
+            }
+        }
+        // copies without full hash make the group unverified when they join it
+        let joined = by_full.len() == 1 && !unhashed.is_empty();
+        if by_full.len() == 1 {
+            by_full[0].1.append(&mut unhashed);
+        } else if !unhashed.is_empty() {
+            by_full.push((None, unhashed));
+        }
+
+        for (full_hash, mut videos) in by_full {
+            if videos.len() < 2 {
+                continue;
+            }
+            videos.sort_by_key(|(i, _)| *i);
+            let videos: Vec<Video> = videos.into_iter().map(|(_, v)| v).collect();
+            let file_size = videos[0].file_size;
+            groups.push(DuplicateGroup {
+                file_size,
+                quick_hash: quick_hash.clone(),
+                verified: full_hash.is_some() && !joined,
+                full_hash,
+                keep_id: videos[0].id,
+                reclaimable: file_size.unwrap_or(0) * (videos.len() as i64 - 1),
+                videos,
+            });
+        }
+    }
+    Ok(groups)
+}
+
+// Folds the history of `drop_ids` into `keep_id` (see `library::merge_rows`) and
+// removes their rows. All of them must have the same content as `keep_id`. The files
+// are left alone, so a copy inside a library folder comes back as a new video on the
+// next scan.
+#[tauri::command]
+pub fn merge_duplicates(keep_id: i64, drop_ids: Vec<i64>) -> Result<Video, String> {
+    let mut conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    let fingerprint = |id: i64| {
+        tx.query_row("SELECT path, file_size, quick_hash, full_hash FROM videos WHERE id = ?1", params![id], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, Option<i64>>(1)?, row.get::<_, Option<String>>(2)?, row.get::<_, Option<String>>(3)?))
+        })
+        .map_err(|e| e.to_string())
+    };
+    let (_, size, hash, full) = fingerprint(keep_id)?;
+    if hash.is_none() {
+        return Err("The video has not been hashed yet".to_string());
+    }
+
+    for &id in drop_ids.iter().filter(|&&id| id != keep_id) {
+        let (path, other_size, other_hash, other_full) = fingerprint(id)?;
+        let full_differs = full.is_some() && other_full.is_some() && full != other_full;
+        if other_size != size || other_hash != hash || full_differs {
+            return Err(format!("{} is not a copy of the same video", path));
+        }
+        library::merge_rows(&tx, keep_id, id).map_err(|e| e.to_string())?;
+    }
+    let video = tx
+        .query_row(&format!("SELECT {} FROM videos WHERE id = ?1", VIDEO_COLUMNS), params![keep_id], Video::from_row)
+        .map_err(|e| e.to_string())?;
+    tx.commit().map_err(|e| e.to_string())?;
+    Ok(video)
+}
+
+// Reads the container again, e.g. after the probe learned a new format.
+#[tauri::command]
+pub fn probe_video(id: i64) -> Result<Video, String> {
+    let path: String = {
+        let conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+        conn.query_row("SELECT path FROM videos WHERE id = ?1", params![id], |row| row.get(0))
+            .map_err(|e| e.to_string())?
+    };
+    let entry = FileEntry::from_path(Path::new(&path)).map_err(|e| e.to_string())?.with_media_info();
+
+    let conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+    library::store_media_info(&conn, &entry, &playback::codec_support(&conn)).map_err(|e| e.to_string())?;
+    conn.query_row(&format!("SELECT {} FROM videos WHERE id = ?1", VIDEO_COLUMNS), params![id], Video::from_row)
+        .map_err(|e| e.to_string())
+}
+
+// Full probe result with every stream; None when the file was not probed (yet).
+#[tauri::command]
+pub fn get_media_info(id: i64) -> Result<Option<MediaInfo>, String> {
+    let conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+    let json: Option<String> = conn
+        .query_row("SELECT media_info FROM videos WHERE id = ?1", params![id], |row| row.get(0))
+        .map_err(|e| e.to_string())?;
+    match json {
+        Some(json) => serde_json::from_str(&json).map(Some).map_err(|e| e.to_string()),
+        None => Ok(None),
+    }
+}
+
+// Front cover (or first picture) from the ID3 tag of the file; None when it has none.
+#[tauri::command]
+pub fn get_cover_art(id: i64) -> Result<Option<CoverArt>, String> {
+    let path: String = {
+        let conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+        conn.query_row("SELECT path FROM videos WHERE id = ?1", params![id], |row| row.get(0))
+            .map_err(|e| e.to_string())?
+    };
+    let mut file = std::io::BufReader::new(fs::File::open(&path).map_err(|e| e.to_string())?);
+    let tags = probe::id3::read_v2(&mut file).map_err(|e| e.to_string())?;
+    Ok(tags.and_then(|t| t.into_cover()).map(|p| CoverArt { mime_type: p.mime_type, data: p.data }))
+}
+
+// Whether the webview can play the video, stream by stream, with a reason for the UI.
+#[tauri::command]
+pub fn check_playability(id: i64) -> Result<Playability, String> {
+    let conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+    let (json, probed): (Option<String>, bool) = conn
+        .query_row("SELECT media_info, probed_at IS NOT NULL FROM videos WHERE id = ?1", params![id], |row| Ok((row.get(0)?, row.get(1)?)))
+        .map_err(|e| e.to_string())?;
+    let support = playback::codec_support(&conn);
+    playback::check_stored(json.as_deref(), probed, &support).map_err(|e| e.to_string())
+}
+
+// Every video the webview cannot play, optionally limited to one library.
+#[tauri::command]
+pub fn playability_report(library_id: Option<i64>) -> Result<PlayabilityReport, String> {
+    let conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+    let support = playback::codec_support(&conn);
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT {}, media_info, probed_at IS NOT NULL FROM videos WHERE (?1 IS NULL OR library_id = ?1) ORDER BY path",
+            VIDEO_COLUMNS
+        ))
+        .map_err(|e| e.to_string())?;
+    let iter = stmt
+        .query_map(params![library_id], |row| {
+            Ok((Video::from_row(row)?, row.get::<_, Option<String>>(VIDEO_COLUMN_COUNT)?, row.get::<_, bool>(VIDEO_COLUMN_COUNT + 1)?))
+        })
+        .map_err(|e| e.to_string())?;
+
+    let mut report = PlayabilityReport { checked: 0, unknown: 0, unplayable: Vec::new(), causes: Vec::new() };
+    let mut causes: HashMap<(&'static str, String), i64> = HashMap::new();
+    for r in iter {
+        let (video, json, probed) = r.map_err(|e| e.to_string())?;
+        let playability = match playback::check_stored(json.as_deref(), probed, &support) {
+            Ok(p) => p,
+            // unreadable probe result, same as an unknown format
+            Err(_) => {
+                report.unknown += 1;
+                continue;
+            }
+        };
+        match playability.playable {
+            None => report.unknown += 1,
+            Some(true) => report.checked += 1,
+            Some(false) => {
+                report.checked += 1;
+                for cause in playability.blockers() {
+                    *causes.entry(cause).or_insert(0) += 1;
+                }
+                report.unplayable.push(UnplayableVideo { video, reason: playability.reason });
+            }
+        }
+    }
+    report.causes = causes
+        .into_iter()
+        .map(|((kind, name), video_count)| {
+            let display_name = if kind == "container" { playback::container_name(&name) } else { playback::codec_name(&name) };
+            UnplayableCause { kind: kind.to_string(), name, display_name, video_count }
+        })
+        .collect();
+    report.causes.sort_by(|a, b| b.video_count.cmp(&a.video_count).then_with(|| a.name.cmp(&b.name)));
+    Ok(report)
+}
+
+// Containers and codecs treated as playable by `check_playability`.
+#[tauri::command]
+pub fn get_codec_support() -> Result<CodecSupport, String> {
+    let conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+    Ok(playback::codec_support(&conn))
+}
+
+// Replaces the support matrix (None restores the default) and updates the stored
+// `playable_in_webview` flags to match it.
+#[tauri::command]
+pub fn set_codec_support(support: Option<CodecSupport>) -> Result<CodecSupport, String> {
+    let mut conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    playback::save_codec_support(&tx, support.as_ref()).map_err(|e| e.to_string())?;
+    let support = playback::codec_support(&tx);
+    playback::refresh_playable(&tx, &support).map_err(|e| e.to_string())?;
+    tx.commit().map_err(|e| e.to_string())?;
+    Ok(support)
+}
+
+// Copies a Matroska video losslessly into an MP4 in the remux cache, for webviews that
+// cannot play .mkv or its codecs in that container. Resolves with the final progress,
+// whose `path` is the cached file (None when cancelled).
+#[tauri::command]
+pub async fn remux_video(id: i64, on_progress: Channel<RemuxProgress>) -> Result<RemuxProgress, String> {
+    let guard = remux::begin_remux(id)?;
+    tauri::async_runtime::spawn_blocking(move || {
+        remux::remux_video(database::get_connection(), id, &guard.cancel, |p| {
+            let _ = on_progress.send(p.clone());
+        })
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+#[tauri::command]
+pub fn cancel_remux(id: i64) -> Result<bool, String> {
+    Ok(remux::cancel_remux(id))
+}
+
+// The cached MP4 of the video; None when there is none or the source changed since.
+#[tauri::command]
+pub fn get_remux(id: i64) -> Result<Option<Remux>, String> {
+    let conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+    remux::cached_remux(&conn, id).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn delete_remux(id: i64) -> Result<bool, String> {
+    let conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+    remux::delete_remux(&conn, id).map_err(|e| e.to_string())
+}
+
+fn video_uuid_and_path(id: i64) -> Result<(String, String), String> {
+    let conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+    conn.query_row("SELECT uuid, path FROM videos WHERE id = ?1", params![id], |row| Ok((row.get(0)?, row.get(1)?)))
+        .map_err(|e| e.to_string())
+}
+
+// Subtitle files found next to the video (see `subtitles::discover`) and its embedded
+// tracks, each with the WebVTT URL its `<track>` element loads and, for ASS files, the
+// styling left out.
+#[tauri::command]
+pub fn list_subtitles(id: i64) -> Result<Vec<SubtitleTrack>, String> {
+    let (uuid, path) = video_uuid_and_path(id)?;
+    Ok(subtitles::list(Path::new(&path), &uuid))
+}
+
+// Text subtitle tracks inside the video's Matroska file, with languages; `url` is set for
+// the ones already extracted.
+#[tauri::command]
+pub fn list_embedded_subtitles(id: i64) -> Result<Vec<SubtitleTrack>, String> {
+    let (uuid, path) = video_uuid_and_path(id)?;
+    subtitles::embedded::list(Path::new(&path), &uuid)
+}
+
+// Reads the sidecar subtitle with the given encoding (any WHATWG label, e.g. "latin2" or
+// "shift_jis") from now on; None goes back to detection. Returns the updated track.
+#[tauri::command]
+pub fn set_subtitle_encoding(id: i64, subtitle_id: String, encoding: Option<String>) -> Result<SubtitleTrack, String> {
+    let (uuid, path) = video_uuid_and_path(id)?;
+    let video_path = Path::new(&path);
+    let track = subtitles::discover(video_path, &uuid)
+        .into_iter()
+        .find(|t| t.id == subtitle_id)
+        .ok_or_else(|| format!("No subtitle file {} for this video", subtitle_id))?;
+    {
+        let conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+        subtitles::encoding::save_override(&conn, &track.path, encoding.as_deref())?;
+    }
+    subtitles::list(video_path, &uuid).into_iter().find(|t| t.id == subtitle_id).ok_or_else(|| format!("No subtitle file {} for this video", subtitle_id))
+}
+
+// Subtitle sync correction of the video; the neutral one when none was saved.
+#[tauri::command]
+pub fn get_subtitle_timing(id: i64) -> Result<SubtitleTiming, String> {
+    let conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+    timing::load(&conn, id).map_err(|e| e.to_string())
+}
+
+// Saves the correction applied to every subtitle of the video from now on: cue times are
+// stretched by source_fps/target_fps, then moved by offset_ms (positive is later).
+#[tauri::command]
+pub fn set_subtitle_timing(id: i64, offset_ms: i64, source_fps: Option<f64>, target_fps: Option<f64>) -> Result<SubtitleTiming, String> {
+    let conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+    timing::save(&conn, id, offset_ms, source_fps, target_fps)
+}
+
+// Writes a copy of the subtitle with the video's correction applied next to the video
+// (see `subtitles::save_corrected`) and returns its path.
+#[tauri::command]
+pub fn save_corrected_subtitle(id: i64, subtitle_id: String) -> Result<String, String> {
+    let (uuid, path) = video_uuid_and_path(id)?;
+    let correction = {
+        let conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+        timing::load(&conn, id).map_err(|e| e.to_string())?
+    };
+    match subtitles::save_corrected(Path::new(&path), &uuid, &subtitle_id, &correction)? {
+        Some(target) => Ok(target.to_string_lossy().to_string()),
+        None => Err(format!("No subtitle {} for this video; embedded tracks have to be extracted first", subtitle_id)),
+    }
+}
+
+// Extracts an embedded track into the subtitle cache as WebVTT. Reads through the whole
+// file, so it runs off the main thread.
+#[tauri::command]
+pub async fn extract_subtitle(id: i64, track: u64) -> Result<SubtitleTrack, String> {
+    let (uuid, path) = video_uuid_and_path(id)?;
+    tauri::async_runtime::spawn_blocking(move || subtitles::embedded::extract(Path::new(&path), &uuid, track))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn db() -> Connection {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.pragma_update(None, "foreign_keys", "ON").unwrap();
+        crate::db::schema::init_schema(&mut conn).unwrap();
+        conn
+    }
+
+    fn insert(conn: &Connection, path: &str, duration: Option<i64>) -> i64 {
+        conn.execute("INSERT INTO videos (uuid, path, duration) VALUES (?1, ?2, ?3)", params![path, path, duration]).unwrap();
+        conn.last_insert_rowid()
+    }
+
+    fn paths(videos: &[Video]) -> Vec<&str> {
+        videos.iter().map(|v| v.path.as_str()).collect()
+    }
+
+    #[test]
+    fn positions_are_saved_and_finished_videos_drop_out() {
+        let conn = db();
+        let started = insert(&conn, "/started.mp4", Some(100));
+        let finished = insert(&conn, "/finished.mp4", Some(100));
+        let unknown = insert(&conn, "/unknown.mp4", None);
+        let untouched = insert(&conn, "/untouched.mp4", Some(100));
+        store_position(&conn, started, 30.0).unwrap();
+        store_position(&conn, finished, 96.0).unwrap();
+        store_position(&conn, unknown, 5000.0).unwrap();
+        store_position(&conn, untouched, -3.0).unwrap();
+        let position: Option<f64> = conn.query_row("SELECT position FROM videos WHERE id = ?1", params![untouched], |row| row.get(0)).unwrap();
+        assert_eq!(position, Some(0.0));
+
+        // most recently watched first
+        conn.execute("UPDATE videos SET position_updated_at = datetime('now', '-' || id || ' minutes')", []).unwrap();
+        assert_eq!(paths(&continue_watching(&conn, None, None).unwrap()), vec!["/started.mp4", "/unknown.mp4"]);
+        assert_eq!(paths(&continue_watching(&conn, Some(1), Some(1)).unwrap()), vec!["/unknown.mp4"]);
+
+        // a threshold above 1 is a percentage
+        conn.execute("INSERT INTO settings (key, value) VALUES (?1, '99')", params![FINISHED_THRESHOLD_KEY]).unwrap();
+        assert_eq!(paths(&continue_watching(&conn, None, None).unwrap()), vec!["/started.mp4", "/finished.mp4", "/unknown.mp4"]);
+        conn.execute("UPDATE settings SET value = '0.25' WHERE key = ?1", params![FINISHED_THRESHOLD_KEY]).unwrap();
+        assert_eq!(paths(&continue_watching(&conn, None, None).unwrap()), vec!["/unknown.mp4"]);
+        // anything else falls back to the default
+        conn.execute("UPDATE settings SET value = 'soon' WHERE key = ?1", params![FINISHED_THRESHOLD_KEY]).unwrap();
+        assert_eq!(finished_threshold(&conn), DEFAULT_FINISHED_THRESHOLD);
+    }
+
+    fn tag(conn: &Connection, name: &str) -> i64 {
+        conn.execute("INSERT INTO tags (name) VALUES (?1)", params![name]).unwrap();
+        conn.last_insert_rowid()
+    }
+
+    fn indexed_tags(conn: &Connection, id: i64) -> Option<String> {
+        conn.query_row("SELECT tags FROM videos_fts WHERE rowid = ?1", params![id], |row| row.get(0)).unwrap()
+    }
+
+    fn fts_match(conn: &Connection, query: &str) -> Vec<i64> {
+        let mut stmt = conn.prepare("SELECT rowid FROM videos_fts WHERE videos_fts MATCH ?1 ORDER BY rowid").unwrap();
+        let rows = stmt.query_map(params![query], |row| row.get(0)).unwrap();
+        rows.collect::<rusqlite::Result<_>>().unwrap()
+    }
+
+    #[test]
+    fn tags_are_assigned_and_merged_with_the_index_in_sync() {
+        let mut conn = db();
+        let a = insert(&conn, "/a.mp4", None);
+        let b = insert(&conn, "/b.mp4", None);
+        let (holiday, summer, beach) = (tag(&conn, "Holiday"), tag(&conn, "Summer"), tag(&conn, "beach"));
+        assign_tags(&mut conn, &[a, b], &[holiday]).unwrap();
+        // assigning twice is a no-op
+        assign_tags(&mut conn, &[a], &[holiday, summer, beach]).unwrap();
+        assign_tags(&mut conn, &[b], &[beach]).unwrap();
+        assert_eq!(indexed_tags(&conn, a).as_deref(), Some("Holiday Summer beach"));
+        assert_eq!(fts_match(&conn, "tags:summer"), vec![a]);
+
+        conn.execute("UPDATE tags SET name = 'Vacation' WHERE id = ?1", params![holiday]).unwrap();
+        assert_eq!(fts_match(&conn, "tags:vacation"), vec![a, b]);
+        assert!(fts_match(&conn, "tags:holiday").is_empty());
+
+        // a has every tag already, b only gets the merged ones it lacked
+        merge_tags_into(&mut conn, &[summer, beach, holiday], holiday).unwrap();
+        let tags: Vec<(i64, i64)> = {
+            let mut stmt = conn.prepare("SELECT video_id, tag_id FROM video_tags ORDER BY video_id, tag_id").unwrap();
+            let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?))).unwrap();
+            rows.collect::<rusqlite::Result<_>>().unwrap()
+        };
+        assert_eq!(tags, vec![(a, holiday), (b, holiday)]);
+        let names: i64 = conn.query_row("SELECT COUNT(*) FROM tags", [], |row| row.get(0)).unwrap();
+        assert_eq!(names, 1);
+        assert!(fts_match(&conn, "tags:beach").is_empty());
+        assert_eq!(indexed_tags(&conn, b).as_deref(), Some("Vacation"));
+
+        assert_eq!(merge_tags_into(&mut conn, &[holiday], 999), Err("Target tag does not exist".to_string()));
+        // merging a tag into itself does nothing
+        merge_tags_into(&mut conn, &[holiday], holiday).unwrap();
+        conn.execute("DELETE FROM video_tags WHERE video_id = ?1", params![a]).unwrap();
+        assert_eq!(indexed_tags(&conn, a), None);
+    }
+
+    fn playlist(conn: &Connection) -> i64 {
+        conn.execute("INSERT INTO playlists (name) VALUES ('p')", []).unwrap();
+        conn.last_insert_rowid()
+    }
+
+    // (item id, video path, video id) in playlist order
+    fn items(conn: &Connection, playlist_id: i64) -> Vec<(i64, String, Option<i64>)> {
+        let mut stmt = conn.prepare("SELECT id, video_path, video_id FROM playlist_items WHERE playlist_id = ?1 ORDER BY position").unwrap();
+        let rows = stmt.query_map(params![playlist_id], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?))).unwrap();
+        rows.collect::<rusqlite::Result<_>>().unwrap()
+    }
+
+    fn item_paths(conn: &Connection, playlist_id: i64) -> Vec<String> {
+        items(conn, playlist_id).into_iter().map(|(_, path, _)| path).collect()
+    }
+
+    #[test]
+    fn playlist_items_are_inserted_and_reordered() {
+        let mut conn = db();
+        let (a, b, c) = (insert(&conn, "/a.mp4", None), insert(&conn, "/b.mp4", None), insert(&conn, "/c.mp4", None));
+        let list = playlist(&conn);
+        insert_playlist_items(&mut conn, list, &[a, b], None).unwrap();
+        insert_playlist_items(&mut conn, list, &[c, a], Some(1)).unwrap();
+        // positions past the end append
+        insert_playlist_items(&mut conn, list, &[c], Some(99)).unwrap();
+        assert_eq!(item_paths(&conn, list), vec!["/a.mp4", "/c.mp4", "/a.mp4", "/b.mp4", "/c.mp4"]);
+        let positions: Vec<i64> = {
+            let mut stmt = conn.prepare("SELECT position FROM playlist_items ORDER BY position").unwrap();
+            let rows = stmt.query_map([], |row| row.get(0)).unwrap();
+            rows.collect::<rusqlite::Result<_>>().unwrap()
+        };
+        assert_eq!(positions, vec![0, 1, 2, 3, 4]);
+        // an unknown video rolls back the whole insert
+        assert_eq!(insert_playlist_items(&mut conn, list, &[b, 999], None), Err("Video 999 does not exist".to_string()));
+        assert_eq!(items(&conn, list).len(), 5);
+
+        let mut order: Vec<i64> = items(&conn, list).into_iter().map(|(id, _, _)| id).collect();
+        order.reverse();
+        reorder_playlist_items(&mut conn, list, &order).unwrap();
+        assert_eq!(item_paths(&conn, list), vec!["/c.mp4", "/b.mp4", "/a.mp4", "/c.mp4", "/a.mp4"]);
+        // the new order has to name every item exactly once
+        order.pop();
+        assert!(reorder_playlist_items(&mut conn, list, &order).is_err());
+        order.push(order[0]);
+        assert!(reorder_playlist_items(&mut conn, list, &order).is_err());
+    }
+
+    #[test]
+    fn playlist_items_follow_their_video_by_path() {
+        let mut conn = db();
+        let a = insert(&conn, "/a.mp4", None);
+        let list = playlist(&conn);
+        insert_playlist_items(&mut conn, list, &[a], None).unwrap();
+
+        conn.execute("UPDATE videos SET path = '/moved/a.mp4' WHERE id = ?1", params![a]).unwrap();
+        assert_eq!(items(&conn, list)[0].1, "/moved/a.mp4");
+
+        // removing the video keeps the entry, adding the path again reattaches it
+        conn.execute("DELETE FROM videos WHERE id = ?1", params![a]).unwrap();
+        assert_eq!(items(&conn, list)[0].2, None);
+        insert(&conn, "/other.mp4", None);
+        assert_eq!(items(&conn, list)[0].2, None);
+        let again = insert(&conn, "/moved/a.mp4", None);
+        assert_eq!(items(&conn, list)[0].2, Some(again));
+    }
+
+    fn hits(conn: &Connection, query: &str) -> Vec<SearchHit> {
+        search_hits(conn, &fts_query(query), None, None).unwrap()
+    }
+
+    #[test]
+    fn user_input_becomes_quoted_prefix_terms() {
+        assert_eq!(fts_query("  holi  beach "), "\"holi\"* \"beach\"*");
+        assert_eq!(fts_query("title:x OR \"y"), "\"title:x\"* \"OR\"* \"\"\"y\"*");
+        assert_eq!(fts_query(" "), "");
+    }
+
+    #[test]
+    fn search_ranks_titles_over_folders_and_highlights_matches() {
+        let mut conn = db();
+        let in_title = insert(&conn, "/media/x/Clip.mp4", None);
+        conn.execute("UPDATE videos SET title = 'Beach Day' WHERE id = ?1", params![in_title]).unwrap();
+        let in_folder = insert(&conn, "/media/beach/Clip.mp4", None);
+        let in_file_name = insert(&conn, "/media/y/beach.mp4", None);
+        let in_tags = insert(&conn, "/media/z/Clip.mp4", None);
+        let beach = tag(&conn, "beaches");
+        assign_tags(&mut conn, &[in_tags], &[beach]).unwrap();
+        insert(&conn, "/media/Other.mp4", None);
+
+        let found = hits(&conn, "BEA");
+        let ids: Vec<i64> = found.iter().map(|h| h.video.id).collect();
+        assert_eq!(ids, vec![in_title, in_file_name, in_tags, in_folder]);
+        assert!(found.windows(2).all(|w| w[0].rank <= w[1].rank));
+
+        assert_eq!(found[0].title_highlight.as_deref(), Some("<mark>Beach</mark> Day"));
+        assert_eq!(found[1].file_name_highlight.as_deref(), Some("<mark>beach</mark>.mp4"));
+        assert_eq!(found[2].tags_highlight.as_deref(), Some("<mark>beaches</mark>"));
+        assert_eq!(found[3].path_snippet.as_deref(), Some("/media/<mark>beach</mark>/Clip.mp4"));
+        assert_eq!(found[3].title_highlight, None);
+
+        // every word has to match, diacritics do not matter
+        conn.execute("UPDATE videos SET title = 'Café Crème' WHERE id = ?1", params![in_folder]).unwrap();
+        let ids: Vec<i64> = hits(&conn, "beach creme").iter().map(|h| h.video.id).collect();
+        assert_eq!(ids, vec![in_folder]);
+        // quoted input is not an FTS query
+        assert!(hits(&conn, "beach NOT").is_empty());
+        assert_eq!(search_hits(&conn, &fts_query("clip"), Some(2), Some(1)).unwrap().len(), 2);
+    }
+
+    // Follows next_cursor until the last page, checking the total on every page.
+    fn all_pages(conn: &Connection, filter: &VideoFilter, sort: &SortSpec, limit: i64) -> Vec<i64> {
+        let mut ids = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = query_page(conn, filter, sort, Some(limit), None, cursor.as_deref(), true).unwrap();
+            assert!(page.items.len() as i64 <= limit);
+            ids.extend(page.items.iter().map(|v| v.id));
+            assert!(page.total >= ids.len() as i64);
+            match page.next_cursor {
+                Some(next) => cursor = Some(next),
+                None => return ids,
+            }
+        }
+    }
+
+    #[test]
+    fn cursor_pages_break_ties_by_id_without_gaps() {
+        let conn = db();
+        let ratings = [Some(3.0), Some(5.0), Some(3.0), None, Some(3.0), Some(5.0), Some(1.0)];
+        for (i, rating) in ratings.iter().enumerate() {
+            let id = insert(&conn, &format!("/v{}.mp4", i), Some(60 * (i as i64 % 2)));
+            // equal titles and dates too, so every sort field has ties
+            conn.execute(
+                "UPDATE videos SET rating = ?1, title = 'Same', added_at = '2024-01-01 00:00:00', favorite = ?2 WHERE id = ?3",
+                params![rating, i % 3 == 0, id],
+            )
+            .unwrap();
+        }
+
+        let by_rating = |direction| SortSpec { field: SortField::Rating, direction };
+        let unfiltered = VideoFilter::default();
+        assert_eq!(all_pages(&conn, &unfiltered, &by_rating(SortDirection::Asc), 2), vec![4, 7, 1, 3, 5, 2, 6]);
+        assert_eq!(all_pages(&conn, &unfiltered, &by_rating(SortDirection::Desc), 2), vec![6, 2, 5, 3, 1, 7, 4]);
+
+        let fields = [SortField::Recent, SortField::Title, SortField::AddedAt, SortField::LastWatched, SortField::Rating, SortField::WatchCount, SortField::Duration];
+        let favorites = VideoFilter { favorite: Some(true), ..Default::default() };
+        for field in fields {
+            for direction in [SortDirection::Asc, SortDirection::Desc] {
+                let sort = SortSpec { field, direction };
+                for filter in [&unfiltered, &favorites] {
+                    let everything = query_page(&conn, filter, &sort, None, None, None, true).unwrap();
+                    let expected: Vec<i64> = everything.items.iter().map(|v| v.id).collect();
+                    assert_eq!(everything.total, expected.len() as i64);
+                    for limit in [1, 2, 3, 7] {
+                        assert_eq!(all_pages(&conn, filter, &sort, limit), expected, "{:?} {:?} limit {}", field, direction, limit);
+                    }
+                }
+            }
+        }
+        let favorites_total = query_page(&conn, &favorites, &SortSpec::default(), Some(1), None, None, true).unwrap().total;
+        assert_eq!(favorites_total, 3);
+    }
+
+    #[test]
+    fn offsets_and_invalid_cursors() {
+        let conn = db();
+        for i in 0..5 {
+            insert(&conn, &format!("/v{}.mp4", i), Some(i));
+        }
+        let sort = SortSpec { field: SortField::Duration, direction: SortDirection::Asc };
+        let page = query_page(&conn, &VideoFilter::default(), &sort, Some(2), Some(2), None, true).unwrap();
+        assert_eq!((page.items.iter().map(|v| v.id).collect::<Vec<_>>(), page.total), (vec![3, 4], 5));
+        // a short page is the last one
+        let last = query_page(&conn, &VideoFilter::default(), &sort, Some(2), Some(4), None, false).unwrap();
+        assert_eq!((last.items.len(), last.next_cursor), (1, None));
+
+        let random = SortSpec { field: SortField::Random, direction: SortDirection::Asc };
+        let cursor = Cursor { key: serde_json::Value::from(1), id: 1 }.encode();
+        assert_eq!(query_page(&conn, &VideoFilter::default(), &random, Some(2), None, Some(&cursor), false).unwrap_err(), "Cursor paging is not possible with random order");
+        assert!(query_page(&conn, &VideoFilter::default(), &random, Some(2), None, None, false).unwrap().next_cursor.is_none());
+        assert_eq!(query_page(&conn, &VideoFilter::default(), &sort, Some(2), None, Some("{"), false).unwrap_err(), "Invalid cursor");
+        let null_key = Cursor { key: serde_json::Value::Null, id: 1 }.encode();
+        assert_eq!(query_page(&conn, &VideoFilter::default(), &sort, Some(2), None, Some(&null_key), false).unwrap_err(), "Invalid cursor");
+    }
+
+    #[test]
+    fn duplicates_are_grouped_by_size_and_hash_and_split_by_full_hash() {
+        let conn = db();
+        let copies = [
+            ("/a.mp4", 100, "q1", Some("f1")),
+            ("/b.mp4", 100, "q1", Some("f1")),
+            ("/c.mp4", 100, "q1", None),
+            ("/d.mp4", 50, "q2", Some("f2")),
+            ("/e.mp4", 50, "q2", Some("f3")),
+            ("/f.mp4", 50, "q2", None),
+            ("/g.mp4", 50, "q2", None),
+            ("/h.mp4", 100, "q1", None),
+            ("/i.mp4", 200, "q1", None),
+            ("/j.mp4", 100, "q9", None),
+            ("/k.mp4", 10, "q3", Some("f4")),
+            ("/l.mp4", 10, "q3", Some("f4")),
+        ];
+        for (path, size, quick, full) in copies {
+            let id = insert(&conn, path, None);
+            conn.execute("UPDATE videos SET file_size = ?1, quick_hash = ?2, full_hash = ?3 WHERE id = ?4", params![size, quick, full, id])
+                .unwrap();
+        }
+        conn.execute("UPDATE videos SET watch_count = 5 WHERE path = '/b.mp4'", []).unwrap();
+        conn.execute("UPDATE videos SET favorite = 1 WHERE path = '/l.mp4'", []).unwrap();
+        conn.execute("UPDATE videos SET missing = 1 WHERE path = '/h.mp4'", []).unwrap();
+
+        let groups = duplicate_groups(&conn).unwrap();
+        let summary: Vec<_> = groups
+            .iter()
+            .map(|g| {
+                let keep = g.videos.iter().find(|v| v.id == g.keep_id).unwrap().path.as_str();
+                (g.quick_hash.as_str(), g.full_hash.as_deref(), g.verified, keep, g.reclaimable, paths(&g.videos))
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                // a copy without full hash joins the group, which is then unverified
+                ("q1", Some("f1"), false, "/b.mp4", 200, vec!["/b.mp4", "/a.mp4", "/c.mp4"]),
+                // different full hashes split the group; the unhashed ones are left together
+                ("q2", None, false, "/f.mp4", 50, vec!["/f.mp4", "/g.mp4"]),
+                ("q3", Some("f4"), true, "/l.mp4", 10, vec!["/l.mp4", "/k.mp4"]),
+            ]
+        );
+    }
+}
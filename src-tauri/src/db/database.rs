@@ -1,33 +1,46 @@
 use std::fs;
 use std::path::PathBuf;
 use std::sync::Mutex;
-use rusqlite::{Connection, Result};
+use rusqlite::Connection;
 use once_cell::sync::OnceCell;
+use crate::db::migrations::MigrationError;
 use crate::db::schema::init_schema;
 
 // Global singleton for the SQLite connection. Wrapped in a Mutex to allow interior
 // mutability across threads. We initialize it lazily on first access.
 static DB_CONN: OnceCell<Mutex<Connection>> = OnceCell::new();
 
-pub fn init_db() -> Result<Connection> {
-    // Backwards-compatible: open a new connection (same as before)
-    let db_path = get_db_path();
-    let conn = Connection::open(&db_path)?;
-    init_schema(&conn)?;
-    Ok(conn)
+pub fn init_db() -> Result<Connection, MigrationError> {
+    // Backwards-compatible: open a new connection (same as before). Called from `setup`,
+    // so pending migrations run (and a too-new database is rejected) before any command.
+    open_connection()
 }
 
 pub fn get_connection() -> &'static Mutex<Connection> {
     DB_CONN.get_or_init(|| {
-        let db_path = get_db_path();
-        let conn = Connection::open(&db_path).expect("Failed to open database");
-        // ensure schema exists
-        init_schema(&conn).expect("Failed to initialize DB schema");
+        let conn = open_connection().expect("Failed to initialize DB schema");
         Mutex::new(conn)
     })
 }
 
+fn open_connection() -> Result<Connection, MigrationError> {
+    let db_path = get_db_path();
+    let mut conn = Connection::open(&db_path)?;
+    // needed for ON DELETE CASCADE on the join tables; SQLite defaults to off per connection
+    conn.pragma_update(None, "foreign_keys", "ON")?;
+    // bring the schema up to date
+    init_schema(&mut conn)?;
+    Ok(conn)
+}
+
 pub fn get_db_path() -> PathBuf {
+    let mut path = get_app_dir();
+    path.push("videos.db");
+    path
+}
+
+// Per-user folder for the database and caches; created on first use.
+pub fn get_app_dir() -> PathBuf {
     let mut path = if cfg!(target_os = "windows") {
         std::env::var("APPDATA").unwrap().into()
     } else {
@@ -38,6 +51,5 @@ pub fn get_db_path() -> PathBuf {
     };
     path.push("tauri-react-videoplayer");
     let _ = fs::create_dir_all(&path);
-    path.push("videos.db");
     path
 }
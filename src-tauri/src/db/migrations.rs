@@ -0,0 +1,274 @@
+use std::fmt;
+
+use rusqlite::Connection;
+
+use crate::db::schema::{
+    ADD_FULL_HASH,
+    ADD_MEDIA_INFO,
+    ADD_MEDIA_KIND,
+    ADD_PLAYABLE_IN_WEBVIEW,
+    ADD_PLAYBACK_POSITION,
+    ADD_QUICK_HASH,
+    CREATE_LIBRARIES_TABLE,
+    CREATE_PLAYLISTS_TABLES,
+    CREATE_REMUXES_TABLE,
+    CREATE_SETTINGS_TABLE,
+    CREATE_SMART_PLAYLISTS_TABLE,
+    CREATE_SUBTITLE_ENCODINGS_TABLE,
+    CREATE_SUBTITLE_TIMINGS_TABLE,
+    CREATE_TAGS_TABLES,
+    CREATE_VIDEOS_FTS,
+    CREATE_VIDEOS_TABLE,
+    REPROBE_UNKNOWN,
+};
+
+// A single forward-only schema step. Migrations are applied in order of `version`,
+// each inside its own transaction, and the applied version is tracked through
+// `PRAGMA user_version` so existing `videos.db` files pick up new columns on startup.
+pub struct Migration {
+    pub version: i64,
+    pub description: &'static str,
+    pub statements: &'static [&'static str],
+}
+
+// Append new migrations at the end, never edit or reorder shipped ones.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "initial videos and settings tables",
+        statements: &[CREATE_VIDEOS_TABLE, CREATE_SETTINGS_TABLE],
+    },
+    Migration {
+        version: 2,
+        description: "playback position per video",
+        statements: &[ADD_PLAYBACK_POSITION],
+    },
+    Migration {
+        version: 3,
+        description: "tags and video_tags",
+        statements: &[CREATE_TAGS_TABLES],
+    },
+    Migration {
+        version: 4,
+        description: "playlists and playlist_items",
+        statements: &[CREATE_PLAYLISTS_TABLES],
+    },
+    Migration {
+        version: 5,
+        description: "smart playlists",
+        statements: &[CREATE_SMART_PLAYLISTS_TABLE],
+    },
+    Migration {
+        version: 6,
+        description: "full-text search index",
+        statements: &[CREATE_VIDEOS_FTS],
+    },
+    Migration {
+        version: 7,
+        description: "library roots and file state",
+        statements: &[CREATE_LIBRARIES_TABLE],
+    },
+    Migration {
+        version: 8,
+        description: "quick content hash",
+        statements: &[ADD_QUICK_HASH],
+    },
+    Migration {
+        version: 9,
+        description: "full content hash",
+        statements: &[ADD_FULL_HASH],
+    },
+    Migration {
+        version: 10,
+        description: "probed container and stream info",
+        statements: &[ADD_MEDIA_INFO],
+    },
+    Migration {
+        version: 11,
+        description: "re-probe files after adding Matroska support",
+        statements: &[REPROBE_UNKNOWN],
+    },
+    Migration {
+        version: 12,
+        description: "re-probe files after adding MPEG-TS support",
+        statements: &[REPROBE_UNKNOWN],
+    },
+    Migration {
+        version: 13,
+        description: "AVI, FLV and ASF support and the webview playability flag",
+        statements: &[ADD_PLAYABLE_IN_WEBVIEW],
+    },
+    Migration {
+        version: 14,
+        description: "media kind and MP3 support",
+        statements: &[ADD_MEDIA_KIND, REPROBE_UNKNOWN],
+    },
+    Migration {
+        version: 15,
+        description: "remuxed MP4 copies of Matroska videos",
+        statements: &[CREATE_REMUXES_TABLE],
+    },
+    Migration {
+        version: 16,
+        description: "per-video subtitle timing correction",
+        statements: &[CREATE_SUBTITLE_TIMINGS_TABLE],
+    },
+    Migration {
+        version: 17,
+        description: "subtitle encoding overrides",
+        statements: &[CREATE_SUBTITLE_ENCODINGS_TABLE],
+    },
+];
+
+#[derive(Debug)]
+pub enum MigrationError {
+    Sqlite(rusqlite::Error),
+    // The database was written by a newer build of the app; we refuse to touch it
+    // instead of running old queries against a schema we do not know.
+    NewerSchema { found: i64, supported: i64 },
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MigrationError::Sqlite(e) => write!(f, "database migration failed: {}", e),
+            MigrationError::NewerSchema { found, supported } => write!(
+                f,
+                "database schema version {} is newer than the supported version {}; please update the app",
+                found, supported
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MigrationError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MigrationError::Sqlite(e) => Some(e),
+            MigrationError::NewerSchema { .. } => None,
+        }
+    }
+}
+
+impl From<rusqlite::Error> for MigrationError {
+    fn from(e: rusqlite::Error) -> Self {
+        MigrationError::Sqlite(e)
+    }
+}
+
+pub fn latest_version() -> i64 {
+    MIGRATIONS.last().map(|m| m.version).unwrap_or(0)
+}
+
+pub fn current_version(conn: &Connection) -> rusqlite::Result<i64> {
+    conn.query_row("PRAGMA user_version", [], |row| row.get(0))
+}
+
+pub fn run_migrations(conn: &mut Connection) -> Result<(), MigrationError> {
+    let found = current_version(conn)?;
+    let supported = latest_version();
+    if found > supported {
+        return Err(MigrationError::NewerSchema { found, supported });
+    }
+
+    for migration in MIGRATIONS.iter().filter(|m| m.version > found) {
+        let tx = conn.transaction()?;
+        for sql in migration.statements {
+            tx.execute_batch(sql)?;
+        }
+        // user_version lives in the database header and is covered by the transaction,
+        // so a failed step leaves both the schema and the version untouched.
+        tx.pragma_update(None, "user_version", migration.version)?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A videos.db as written before migrations existed: the baseline tables, no user_version.
+    fn baseline() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(&format!("{}\n{}", CREATE_VIDEOS_TABLE, CREATE_SETTINGS_TABLE)).unwrap();
+        conn.execute_batch(
+            "INSERT INTO videos (uuid, path, title, duration, watch_count, rating, favorite) VALUES
+                 ('a', '/videos/Holiday.mp4', 'Holiday', 120, 3, 4.5, 1),
+                 ('b', '/videos/Song.mp3', NULL, 200, 0, NULL, 0);
+             INSERT INTO settings (key, value) VALUES ('theme', 'dark');",
+        )
+        .unwrap();
+        conn
+    }
+
+    #[test]
+    fn baseline_rows_survive_every_migration() {
+        let mut conn = baseline();
+        assert_eq!(current_version(&conn).unwrap(), 0);
+        run_migrations(&mut conn).unwrap();
+        assert_eq!(current_version(&conn).unwrap(), latest_version());
+
+        type Row = (String, Option<String>, i64, Option<f64>, bool, Option<f64>, String);
+        let rows: Vec<Row> = conn
+            .prepare("SELECT uuid, title, watch_count, rating, favorite, position, media_kind FROM videos ORDER BY id")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?, row.get(5)?, row.get(6)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            rows,
+            vec![
+                ("a".to_string(), Some("Holiday".to_string()), 3, Some(4.5), true, None, "video".to_string()),
+                ("b".to_string(), None, 0, None, false, None, "audio".to_string()),
+            ]
+        );
+        let theme: String = conn.query_row("SELECT value FROM settings WHERE key = 'theme'", [], |row| row.get(0)).unwrap();
+        assert_eq!(theme, "dark");
+        // rows that existed before the search index are indexed by the migration that adds it
+        let found: i64 = conn.query_row("SELECT rowid FROM videos_fts WHERE videos_fts MATCH 'holiday'", [], |row| row.get(0)).unwrap();
+        assert_eq!(found, 1);
+    }
+
+    #[test]
+    fn second_run_does_nothing() {
+        let mut conn = baseline();
+        run_migrations(&mut conn).unwrap();
+        let schema = |conn: &Connection| -> Vec<String> {
+            conn.prepare("SELECT sql FROM sqlite_master WHERE sql IS NOT NULL ORDER BY name")
+                .unwrap()
+                .query_map([], |row| row.get(0))
+                .unwrap()
+                .collect::<Result<_, _>>()
+                .unwrap()
+        };
+        let before = schema(&conn);
+        // a step that ran again would clear probed_at through REPROBE_UNKNOWN
+        conn.execute("UPDATE videos SET probed_at = '2024-01-01', container = NULL", []).unwrap();
+
+        run_migrations(&mut conn).unwrap();
+        assert_eq!(current_version(&conn).unwrap(), latest_version());
+        assert_eq!(schema(&conn), before);
+        let probed: i64 = conn.query_row("SELECT COUNT(*) FROM videos WHERE probed_at IS NOT NULL", [], |row| row.get(0)).unwrap();
+        assert_eq!(probed, 2);
+    }
+
+    #[test]
+    fn newer_schema_is_refused() {
+        let mut conn = baseline();
+        conn.pragma_update(None, "user_version", latest_version() + 1).unwrap();
+        match run_migrations(&mut conn) {
+            Err(MigrationError::NewerSchema { found, supported }) => assert_eq!((found, supported), (latest_version() + 1, latest_version())),
+            other => panic!("expected NewerSchema, got {:?}", other),
+        }
+        // nothing was applied
+        assert!(conn.prepare("SELECT position FROM videos").is_err());
+    }
+
+    #[test]
+    fn versions_are_increasing() {
+        assert!(MIGRATIONS.windows(2).all(|w| w[0].version + 1 == w[1].version));
+        assert_eq!(MIGRATIONS[0].version, 1);
+    }
+}
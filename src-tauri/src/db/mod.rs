@@ -1,3 +1,6 @@
 pub mod model;
 pub mod schema;
-pub mod database;
\ No newline at end of file
+pub mod migrations;
+pub mod database;
+pub mod query;
+pub mod smart;
\ No newline at end of file
@@ -1,6 +1,30 @@
+use rusqlite::Row;
 use serde::Serialize;
 
-#[derive(Debug, Serialize)]
+use crate::db::smart::SmartRules;
+
+// Column list matching `Video::from_row`.
+pub const VIDEO_COLUMNS: &str =
+    "id, uuid, path, title, duration, rating, watch_count, favorite, position, position_updated_at, library_id, missing, file_size, \
+     container, width, height, frame_rate, video_codec, audio_codec, audio_channels, rotation, playable_in_webview, media_kind";
+
+// Number of columns in VIDEO_COLUMNS; extra columns selected after them start here.
+pub const VIDEO_COLUMN_COUNT: usize = column_count(VIDEO_COLUMNS);
+
+const fn column_count(columns: &str) -> usize {
+    let bytes = columns.as_bytes();
+    let mut count = 1;
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b',' {
+            count += 1;
+        }
+        i += 1;
+    }
+    count
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct Video {
     pub id: i64,
     pub uuid: String,
@@ -10,6 +34,64 @@ pub struct Video {
     pub rating: Option<f32>,
     pub watch_count: i64,
     pub favorite: i64,
+    // last saved playback position in seconds, if the video was started
+    pub position: Option<f64>,
+    pub position_updated_at: Option<String>,
+    pub library_id: Option<i64>,
+    // 1 when the last library scan did not find the file anymore
+    pub missing: i64,
+    pub file_size: Option<i64>,
+    // filled by the container probe on import, see `probe::MediaInfo`
+    pub container: Option<String>,
+    pub width: Option<i64>,
+    pub height: Option<i64>,
+    pub frame_rate: Option<f64>,
+    pub video_codec: Option<String>,
+    pub audio_codec: Option<String>,
+    pub audio_channels: Option<i64>,
+    pub rotation: Option<i64>,
+    // false when the container or a codec is missing from the support matrix (see
+    // `playback::check`), so the UI can warn before trying; None until the file was
+    // probed or when the format is unknown
+    pub playable_in_webview: Option<bool>,
+    // "video" or "audio", see `probe::MediaKind`
+    pub media_kind: Option<String>,
+    // only filled when the caller asks for tags, to avoid one request per video in the UI
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<Tag>>,
+}
+
+impl Video {
+    // Reads the columns by name; extra columns selected after VIDEO_COLUMNS may repeat a
+    // name, the first one counts.
+    pub fn from_row(row: &Row) -> rusqlite::Result<Video> {
+        Ok(Video {
+            id: row.get("id")?,
+            uuid: row.get("uuid")?,
+            path: row.get("path")?,
+            title: row.get("title")?,
+            duration: row.get("duration")?,
+            rating: row.get("rating")?,
+            watch_count: row.get("watch_count")?,
+            favorite: row.get("favorite")?,
+            position: row.get("position")?,
+            position_updated_at: row.get("position_updated_at")?,
+            library_id: row.get("library_id")?,
+            missing: row.get::<_, Option<i64>>("missing")?.unwrap_or(0),
+            file_size: row.get("file_size")?,
+            container: row.get("container")?,
+            width: row.get("width")?,
+            height: row.get("height")?,
+            frame_rate: row.get("frame_rate")?,
+            video_codec: row.get("video_codec")?,
+            audio_codec: row.get("audio_codec")?,
+            audio_channels: row.get("audio_channels")?,
+            rotation: row.get("rotation")?,
+            playable_in_webview: row.get("playable_in_webview")?,
+            media_kind: row.get("media_kind")?,
+            tags: None,
+        })
+    }
 }
 
 #[derive(Debug, Serialize)]
@@ -17,3 +99,186 @@ pub struct Setting {
     pub key: String,
     pub value: String,
 }
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Tag {
+    pub id: i64,
+    pub name: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TagSummary {
+    pub id: i64,
+    pub name: String,
+    pub video_count: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Playlist {
+    pub id: i64,
+    pub name: String,
+    pub item_count: i64,
+    pub created_at: Option<String>,
+    pub updated_at: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PlaylistItem {
+    pub id: i64,
+    pub position: i64,
+    pub video_path: String,
+    // None while the referenced video is not in the library
+    pub video: Option<Video>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SmartPlaylist {
+    pub id: i64,
+    pub name: String,
+    pub rules: SmartRules,
+    pub created_at: Option<String>,
+    pub updated_at: Option<String>,
+}
+
+// Highlighted fragments wrap matches in <mark></mark>; the surrounding text is not escaped.
+#[derive(Debug, Serialize)]
+pub struct SearchHit {
+    pub video: Video,
+    pub rank: f64,
+    pub title_highlight: Option<String>,
+    pub file_name_highlight: Option<String>,
+    pub path_snippet: Option<String>,
+    pub tags_highlight: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VideoPage {
+    pub items: Vec<Video>,
+    // number of videos matching the filter, independent of paging
+    pub total: i64,
+    // pass back as `cursor` to get the next page; None on the last page
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Library {
+    pub id: i64,
+    pub path: String,
+    pub name: Option<String>,
+    pub added_at: Option<String>,
+    pub last_scanned_at: Option<String>,
+    pub video_count: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MissingReport {
+    pub checked: u64,
+    // rows flagged by this check
+    pub newly_missing: u64,
+    // rows that were flagged before but whose file exists again
+    pub found_again: u64,
+    pub missing: Vec<Video>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RelinkedVideo {
+    pub id: i64,
+    pub old_path: String,
+    pub new_path: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RelinkReport {
+    pub relinked: Vec<RelinkedVideo>,
+    // several files matched equally well; left alone for a manual `relink_video`
+    pub ambiguous: u64,
+    pub unmatched: u64,
+}
+
+// Videos with the same content. `verified` is set when every copy has the same full
+// hash; otherwise the group only shares size and sampled fingerprint.
+#[derive(Debug, Serialize)]
+pub struct DuplicateGroup {
+    pub file_size: Option<i64>,
+    pub quick_hash: String,
+    pub full_hash: Option<String>,
+    pub verified: bool,
+    // copy with the most history, the suggested target for `merge_duplicates`
+    pub keep_id: i64,
+    // bytes freed by deleting all other copies
+    pub reclaimable: i64,
+    pub videos: Vec<Video>,
+}
+
+// Embedded cover image of an audio file, read from the file by `get_cover_art`.
+#[derive(Debug, Serialize)]
+pub struct CoverArt {
+    pub mime_type: String,
+    pub data: Vec<u8>,
+}
+
+// Library-wide result of `playability_report`.
+#[derive(Debug, Serialize)]
+pub struct PlayabilityReport {
+    // probed videos whose format is known
+    pub checked: u64,
+    // not probed yet, or in a format the probe does not know
+    pub unknown: u64,
+    pub unplayable: Vec<UnplayableVideo>,
+    // what keeps the unplayable videos from playing, most frequent first
+    pub causes: Vec<UnplayableCause>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UnplayableVideo {
+    pub video: Video,
+    pub reason: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UnplayableCause {
+    // "container", "video" or "audio"
+    pub kind: String,
+    // container or codec family, see `probe::codec_family`
+    pub name: String,
+    pub display_name: String,
+    pub video_count: i64,
+}
+
+// Fragmented MP4 copy of a Matroska video in the remux cache, see `remux_video`.
+#[derive(Debug, Serialize)]
+pub struct Remux {
+    pub video_id: i64,
+    pub path: String,
+    pub file_size: Option<i64>,
+    pub created_at: Option<String>,
+}
+
+// Subtitle sync correction of a video, see `subtitles::timing`. Videos without a row get
+// the neutral one (no offset, no stretching).
+#[derive(Debug, Clone, Serialize)]
+pub struct SubtitleTiming {
+    pub video_id: i64,
+    pub offset_ms: i64,
+    // frame rate the subtitles were timed for, and the video's
+    pub source_fps: Option<f64>,
+    pub target_fps: Option<f64>,
+    pub updated_at: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::Connection;
+
+    #[test]
+    fn video_columns_match_from_row() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        crate::db::schema::init_schema(&mut conn).unwrap();
+        conn.execute("INSERT INTO videos (uuid, path, missing) VALUES ('u', '/v.mp4', 1)", []).unwrap();
+        let mut stmt = conn.prepare(&format!("SELECT {}, 'extra' AS path FROM videos", VIDEO_COLUMNS)).unwrap();
+        assert_eq!(stmt.column_count(), VIDEO_COLUMN_COUNT + 1);
+        let (video, extra) = stmt.query_row([], |row| Ok((Video::from_row(row)?, row.get::<_, String>(VIDEO_COLUMN_COUNT)?))).unwrap();
+        assert_eq!((video.path.as_str(), video.missing, extra.as_str()), ("/v.mp4", 1, "extra"));
+    }
+}
@@ -0,0 +1,183 @@
+use rusqlite::types::Value;
+use serde::{Deserialize, Serialize};
+
+use crate::probe::MediaKind;
+
+// Filter for `query_videos`. Every field is optional, unset fields do not restrict.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VideoFilter {
+    pub favorite: Option<bool>,
+    // true: has a rating, false: not rated yet
+    pub rated: Option<bool>,
+    pub min_rating: Option<f32>,
+    // true: watched at least once, false: never watched
+    pub watched: Option<bool>,
+    pub min_duration: Option<i64>,
+    pub max_duration: Option<i64>,
+    // only videos below this folder
+    pub folder: Option<String>,
+    pub library_id: Option<i64>,
+    // true: only files the last scan did not find, false: hide them
+    pub missing: Option<bool>,
+    pub media_kind: Option<MediaKind>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SortSpec {
+    pub field: SortField,
+    #[serde(default)]
+    pub direction: SortDirection,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SortField {
+    // last watched, falling back to the date added
+    Recent,
+    Title,
+    AddedAt,
+    LastWatched,
+    Rating,
+    WatchCount,
+    Duration,
+    Random,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SortDirection {
+    Asc,
+    #[default]
+    Desc,
+}
+
+impl Default for SortSpec {
+    fn default() -> Self {
+        SortSpec { field: SortField::Recent, direction: SortDirection::Desc }
+    }
+}
+
+// Sort expression per field. NULLs are folded into a sentinel so that the expression
+// can be compared in keyset pagination; `None` means the order is not stable (random).
+pub fn sort_key(field: SortField) -> Option<&'static str> {
+    match field {
+        SortField::Recent => Some("COALESCE(last_watched, added_at, '')"),
+        SortField::Title => Some("COALESCE(title, path) COLLATE NOCASE"),
+        SortField::AddedAt => Some("COALESCE(added_at, '')"),
+        SortField::LastWatched => Some("COALESCE(last_watched, '')"),
+        SortField::Rating => Some("COALESCE(rating, -1)"),
+        SortField::WatchCount => Some("COALESCE(watch_count, 0)"),
+        SortField::Duration => Some("COALESCE(duration, -1)"),
+        SortField::Random => None,
+    }
+}
+
+pub fn sort_sql(sort: &SortSpec) -> String {
+    let dir = direction_sql(sort.direction);
+    match sort_key(sort.field) {
+        // id as tie breaker keeps paging stable
+        Some(key) => format!("{} {}, id {}", key, dir, dir),
+        None => "RANDOM()".to_string(),
+    }
+}
+
+fn direction_sql(direction: SortDirection) -> &'static str {
+    match direction {
+        SortDirection::Asc => "ASC",
+        SortDirection::Desc => "DESC",
+    }
+}
+
+// Appends the WHERE conditions for `filter` to `conditions`/`params`.
+pub fn filter_sql(filter: &VideoFilter, conditions: &mut Vec<String>, params: &mut Vec<Value>) {
+    if let Some(favorite) = filter.favorite {
+        conditions.push(if favorite { "favorite = 1".to_string() } else { "COALESCE(favorite, 0) = 0".to_string() });
+    }
+    if let Some(rated) = filter.rated {
+        conditions.push(if rated { "rating IS NOT NULL".to_string() } else { "rating IS NULL".to_string() });
+    }
+    if let Some(min) = filter.min_rating {
+        conditions.push("rating >= ?".to_string());
+        params.push(Value::Real(min as f64));
+    }
+    if let Some(watched) = filter.watched {
+        conditions.push(if watched { "last_watched IS NOT NULL".to_string() } else { "last_watched IS NULL".to_string() });
+    }
+    if let Some(min) = filter.min_duration {
+        conditions.push("duration >= ?".to_string());
+        params.push(Value::Integer(min));
+    }
+    if let Some(max) = filter.max_duration {
+        conditions.push("duration <= ?".to_string());
+        params.push(Value::Integer(max));
+    }
+    if let Some(library_id) = filter.library_id {
+        conditions.push("library_id = ?".to_string());
+        params.push(Value::Integer(library_id));
+    }
+    if let Some(missing) = filter.missing {
+        conditions.push(if missing { "missing = 1".to_string() } else { "COALESCE(missing, 0) = 0".to_string() });
+    }
+    if let Some(kind) = filter.media_kind {
+        // rows added before the kind was known are videos
+        conditions.push("COALESCE(media_kind, 'video') = ?".to_string());
+        params.push(Value::Text(kind.as_str().to_string()));
+    }
+    if let Some(folder) = filter.folder.as_deref().filter(|f| !f.is_empty()) {
+        conditions.push("path LIKE ? ESCAPE '\\'".to_string());
+        params.push(Value::Text(format!("{}%", escape_like(&folder_prefix(folder)))));
+    }
+}
+
+// "/videos/a" must not match "/videos/ab/x.mp4", so make sure the prefix ends in a separator.
+fn folder_prefix(folder: &str) -> String {
+    if folder.ends_with('/') || folder.ends_with('\\') {
+        folder.to_string()
+    } else if folder.contains('\\') && !folder.contains('/') {
+        format!("{}\\", folder)
+    } else {
+        format!("{}/", folder)
+    }
+}
+
+pub fn escape_like(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+// Keyset cursor: sort key and id of the last row of the previous page, serialized as
+// JSON. The frontend treats it as an opaque string.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Cursor {
+    pub key: serde_json::Value,
+    pub id: i64,
+}
+
+impl Cursor {
+    pub fn encode(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+
+    pub fn decode(s: &str) -> Result<Cursor, String> {
+        serde_json::from_str(s).map_err(|_| "Invalid cursor".to_string())
+    }
+
+    // Condition selecting the rows after this cursor in the given sort order.
+    pub fn condition(&self, sort: &SortSpec, params: &mut Vec<Value>) -> Result<String, String> {
+        let key = sort_key(sort.field).ok_or_else(|| "Cursor paging is not possible with random order".to_string())?;
+        let cmp = match sort.direction {
+            SortDirection::Asc => ">",
+            SortDirection::Desc => "<",
+        };
+        let value = match &self.key {
+            serde_json::Value::String(s) => Value::Text(s.clone()),
+            serde_json::Value::Number(n) if n.is_i64() => Value::Integer(n.as_i64().unwrap_or_default()),
+            serde_json::Value::Number(n) => Value::Real(n.as_f64().unwrap_or_default()),
+            _ => return Err("Invalid cursor".to_string()),
+        };
+        params.push(value.clone());
+        params.push(value);
+        params.push(Value::Integer(self.id));
+        Ok(format!("({0} {1} ? OR ({0} = ? AND id {1} ?))", key, cmp))
+    }
+}
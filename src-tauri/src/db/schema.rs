@@ -1,5 +1,6 @@
 use rusqlite::Connection;
-use rusqlite::Result;
+
+use crate::db::migrations::{run_migrations, MigrationError};
 
 
 pub const CREATE_SETTINGS_TABLE: &str = r#"
@@ -29,7 +30,235 @@ CREATE INDEX IF NOT EXISTS idx_title ON videos(title);
 CREATE INDEX IF NOT EXISTS idx_rating ON videos(rating);
 "#;
 
-pub fn init_schema(conn: &Connection) -> Result<()> {
-    conn.execute_batch(&format!("{}\n{}", CREATE_VIDEOS_TABLE, CREATE_SETTINGS_TABLE))?;
-    Ok(())
+// Migration 2: resume position per video, saved by the player on a heartbeat.
+pub const ADD_PLAYBACK_POSITION: &str = r#"
+ALTER TABLE videos ADD COLUMN position REAL;
+ALTER TABLE videos ADD COLUMN position_updated_at DATETIME;
+
+CREATE INDEX IF NOT EXISTS idx_position_updated_at ON videos(position_updated_at);
+"#;
+
+// Migration 3: free-form tags attached to videos (many-to-many).
+pub const CREATE_TAGS_TABLES: &str = r#"
+CREATE TABLE IF NOT EXISTS tags (
+    id            INTEGER PRIMARY KEY AUTOINCREMENT,
+    name          TEXT NOT NULL UNIQUE COLLATE NOCASE,
+    created_at    DATETIME DEFAULT (datetime('now'))
+);
+
+CREATE TABLE IF NOT EXISTS video_tags (
+    video_id      INTEGER NOT NULL REFERENCES videos(id) ON DELETE CASCADE,
+    tag_id        INTEGER NOT NULL REFERENCES tags(id) ON DELETE CASCADE,
+    PRIMARY KEY (video_id, tag_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_video_tags_tag ON video_tags(tag_id);
+"#;
+
+// Migration 4: user playlists. Items keep the video path next to the id so that an
+// entry whose video was deleted (video_id set to NULL) reattaches when the same path
+// is added to the library again.
+pub const CREATE_PLAYLISTS_TABLES: &str = r#"
+CREATE TABLE IF NOT EXISTS playlists (
+    id            INTEGER PRIMARY KEY AUTOINCREMENT,
+    name          TEXT NOT NULL,
+    created_at    DATETIME DEFAULT (datetime('now')),
+    updated_at    DATETIME DEFAULT (datetime('now'))
+);
+
+CREATE TABLE IF NOT EXISTS playlist_items (
+    id            INTEGER PRIMARY KEY AUTOINCREMENT,
+    playlist_id   INTEGER NOT NULL REFERENCES playlists(id) ON DELETE CASCADE,
+    video_id      INTEGER REFERENCES videos(id) ON DELETE SET NULL,
+    video_path    TEXT NOT NULL,
+    position      INTEGER NOT NULL,
+    added_at      DATETIME DEFAULT (datetime('now'))
+);
+
+CREATE INDEX IF NOT EXISTS idx_playlist_items_playlist ON playlist_items(playlist_id, position);
+CREATE INDEX IF NOT EXISTS idx_playlist_items_video ON playlist_items(video_id);
+CREATE INDEX IF NOT EXISTS idx_playlist_items_path ON playlist_items(video_path);
+
+CREATE TRIGGER IF NOT EXISTS trg_playlist_items_relink AFTER INSERT ON videos
+BEGIN
+    UPDATE playlist_items SET video_id = NEW.id WHERE video_id IS NULL AND video_path = NEW.path;
+END;
+
+CREATE TRIGGER IF NOT EXISTS trg_playlist_items_path AFTER UPDATE OF path ON videos
+BEGIN
+    UPDATE playlist_items SET video_path = NEW.path WHERE video_id = NEW.id;
+END;
+"#;
+
+// Migration 5: smart playlists, the rule tree is stored as JSON (see `db::smart`).
+pub const CREATE_SMART_PLAYLISTS_TABLE: &str = r#"
+CREATE TABLE IF NOT EXISTS smart_playlists (
+    id            INTEGER PRIMARY KEY AUTOINCREMENT,
+    name          TEXT NOT NULL,
+    rules         TEXT NOT NULL,
+    created_at    DATETIME DEFAULT (datetime('now')),
+    updated_at    DATETIME DEFAULT (datetime('now'))
+);
+"#;
+
+// Migration 6: full-text index over title, file name, path and tag names. The unicode61
+// tokenizer splits paths on separators, so folder names are searchable words. The file
+// name is cut out of the path in SQL (everything after the last '/' or '\').
+pub const CREATE_VIDEOS_FTS: &str = r#"
+CREATE VIRTUAL TABLE IF NOT EXISTS videos_fts USING fts5(
+    title,
+    file_name,
+    path,
+    tags,
+    tokenize = 'unicode61 remove_diacritics 2'
+);
+
+INSERT INTO videos_fts (rowid, title, file_name, path, tags)
+SELECT id, title, substr(replace(path, '\', '/'), length(rtrim(replace(path, '\', '/'), replace(replace(path, '\', '/'), '/', ''))) + 1), path, (SELECT group_concat(t.name, ' ') FROM video_tags vt JOIN tags t ON t.id = vt.tag_id WHERE vt.video_id = videos.id) FROM videos;
+
+CREATE TRIGGER IF NOT EXISTS trg_videos_fts_insert AFTER INSERT ON videos
+BEGIN
+    INSERT INTO videos_fts (rowid, title, file_name, path, tags)
+    VALUES (NEW.id, NEW.title, substr(replace(NEW.path, '\', '/'), length(rtrim(replace(NEW.path, '\', '/'), replace(replace(NEW.path, '\', '/'), '/', ''))) + 1), NEW.path, (SELECT group_concat(t.name, ' ') FROM video_tags vt JOIN tags t ON t.id = vt.tag_id WHERE vt.video_id = NEW.id));
+END;
+
+CREATE TRIGGER IF NOT EXISTS trg_videos_fts_update AFTER UPDATE OF title, path ON videos
+BEGIN
+    UPDATE videos_fts SET title = NEW.title, file_name = substr(replace(NEW.path, '\', '/'), length(rtrim(replace(NEW.path, '\', '/'), replace(replace(NEW.path, '\', '/'), '/', ''))) + 1), path = NEW.path WHERE rowid = NEW.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS trg_videos_fts_delete AFTER DELETE ON videos
+BEGIN
+    DELETE FROM videos_fts WHERE rowid = OLD.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS trg_video_tags_fts_insert AFTER INSERT ON video_tags
+BEGIN
+    UPDATE videos_fts SET tags = (SELECT group_concat(t.name, ' ') FROM video_tags vt JOIN tags t ON t.id = vt.tag_id WHERE vt.video_id = NEW.video_id) WHERE rowid = NEW.video_id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS trg_video_tags_fts_delete AFTER DELETE ON video_tags
+BEGIN
+    UPDATE videos_fts SET tags = (SELECT group_concat(t.name, ' ') FROM video_tags vt JOIN tags t ON t.id = vt.tag_id WHERE vt.video_id = OLD.video_id) WHERE rowid = OLD.video_id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS trg_tags_fts_rename AFTER UPDATE OF name ON tags
+BEGIN
+    UPDATE videos_fts SET tags = (SELECT group_concat(t.name, ' ') FROM video_tags vt JOIN tags t ON t.id = vt.tag_id WHERE vt.video_id = videos_fts.rowid)
+    WHERE rowid IN (SELECT video_id FROM video_tags WHERE tag_id = NEW.id);
+END;
+"#;
+
+// Migration 7: registered library folders and per-file state maintained by the scanner.
+pub const CREATE_LIBRARIES_TABLE: &str = r#"
+CREATE TABLE IF NOT EXISTS libraries (
+    id              INTEGER PRIMARY KEY AUTOINCREMENT,
+    path            TEXT NOT NULL UNIQUE,
+    name            TEXT,
+    added_at        DATETIME DEFAULT (datetime('now')),
+    last_scanned_at DATETIME
+);
+
+ALTER TABLE videos ADD COLUMN library_id INTEGER REFERENCES libraries(id) ON DELETE SET NULL;
+ALTER TABLE videos ADD COLUMN missing INTEGER DEFAULT 0;
+ALTER TABLE videos ADD COLUMN file_size INTEGER;
+ALTER TABLE videos ADD COLUMN file_mtime INTEGER;
+
+CREATE INDEX IF NOT EXISTS idx_videos_library ON videos(library_id);
+"#;
+
+// Migration 8: sampled content fingerprint used to recognise moved files.
+pub const ADD_QUICK_HASH: &str = r#"
+ALTER TABLE videos ADD COLUMN quick_hash TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_videos_size_hash ON videos(file_size, quick_hash);
+"#;
+
+// Migration 9: optional BLAKE3 hash of the whole file, filled by the hashing job. Cleared
+// whenever the file changes, unlike quick_hash it proves two files are identical.
+pub const ADD_FULL_HASH: &str = r#"
+ALTER TABLE videos ADD COLUMN full_hash TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_videos_full_hash ON videos(full_hash);
+"#;
+
+// Migration 10: container and stream details read by `probe` on import. `media_info` holds
+// the full probe result as JSON, `probed_at` marks rows the scanner does not probe again.
+pub const ADD_MEDIA_INFO: &str = r#"
+ALTER TABLE videos ADD COLUMN container TEXT;
+ALTER TABLE videos ADD COLUMN width INTEGER;
+ALTER TABLE videos ADD COLUMN height INTEGER;
+ALTER TABLE videos ADD COLUMN frame_rate REAL;
+ALTER TABLE videos ADD COLUMN video_codec TEXT;
+ALTER TABLE videos ADD COLUMN audio_codec TEXT;
+ALTER TABLE videos ADD COLUMN audio_channels INTEGER;
+ALTER TABLE videos ADD COLUMN rotation INTEGER;
+ALTER TABLE videos ADD COLUMN media_info TEXT;
+ALTER TABLE videos ADD COLUMN probed_at TEXT;
+"#;
+
+// Clears probed_at on rows the probe did not recognise, so the next scan tries them again.
+// Used by every migration that ships support for another container format.
+pub const REPROBE_UNKNOWN: &str = r#"
+UPDATE videos SET probed_at = NULL WHERE probed_at IS NOT NULL AND container IS NULL;
+"#;
+
+// Migration 13: whether the webview can play the file, see `playback::check`.
+// Every row is probed again so files imported before get the flag too.
+pub const ADD_PLAYABLE_IN_WEBVIEW: &str = r#"
+ALTER TABLE videos ADD COLUMN playable_in_webview INTEGER;
+
+UPDATE videos SET probed_at = NULL;
+"#;
+
+// Migration 14: audio files next to videos. Rows are classified by extension here and by
+// their streams once probed, see `MediaInfo::media_kind`.
+pub const ADD_MEDIA_KIND: &str = r#"
+ALTER TABLE videos ADD COLUMN media_kind TEXT;
+
+UPDATE videos SET media_kind = CASE WHEN lower(path) LIKE '%.mp3' THEN 'audio' ELSE 'video' END;
+
+CREATE INDEX IF NOT EXISTS idx_videos_media_kind ON videos(media_kind);
+"#;
+
+// Migration 15: fragmented MP4 copies of Matroska videos made by `remux`. The source
+// size and mtime at remux time tell when the copy went stale.
+pub const CREATE_REMUXES_TABLE: &str = r#"
+CREATE TABLE IF NOT EXISTS remuxes (
+    video_id      INTEGER PRIMARY KEY REFERENCES videos(id) ON DELETE CASCADE,
+    path          TEXT NOT NULL,
+    file_size     INTEGER,
+    source_size   INTEGER,
+    source_mtime  INTEGER,
+    created_at    DATETIME DEFAULT (datetime('now'))
+);
+"#;
+
+// Migration 16: subtitle sync correction per video, applied to every subtitle the video
+// serves (see `subtitles::timing`). `source_fps`/`target_fps` stretch cue times for
+// subtitles made for another frame rate (23.976 <-> 25); both NULL means no stretching.
+pub const CREATE_SUBTITLE_TIMINGS_TABLE: &str = r#"
+CREATE TABLE IF NOT EXISTS subtitle_timings (
+    video_id    INTEGER PRIMARY KEY REFERENCES videos(id) ON DELETE CASCADE,
+    offset_ms   INTEGER NOT NULL DEFAULT 0,
+    source_fps  REAL,
+    target_fps  REAL,
+    updated_at  DATETIME DEFAULT (datetime('now'))
+);
+"#;
+
+// Migration 17: character encodings chosen by hand for subtitle files whose detected
+// encoding is wrong, by file path (see `subtitles::encoding`).
+pub const CREATE_SUBTITLE_ENCODINGS_TABLE: &str = r#"
+CREATE TABLE IF NOT EXISTS subtitle_encodings (
+    path        TEXT PRIMARY KEY,
+    encoding    TEXT NOT NULL,
+    updated_at  DATETIME DEFAULT (datetime('now'))
+);
+"#;
+
+// CREATE_VIDEOS_TABLE and CREATE_SETTINGS_TABLE are the baseline schema (migration 1).
+// Later changes go into `db::migrations::MIGRATIONS` so they also reach existing databases.
+pub fn init_schema(conn: &mut Connection) -> Result<(), MigrationError> {
+    run_migrations(conn)
 }
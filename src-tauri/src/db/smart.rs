@@ -0,0 +1,427 @@
+use rusqlite::types::Value;
+use serde::{Deserialize, Serialize};
+
+use crate::db::query::{escape_like, sort_sql, SortSpec};
+
+// Rule set of a smart playlist, stored as JSON in `smart_playlists.rules`, e.g.
+// { "match": "all", "rules": [ { "field": "rating", "op": "gte", "value": 4 },
+//   { "field": "last_watched", "op": "not_in_last_days", "value": 30 },
+//   { "field": "tag", "op": "is", "value": "lecture" } ],
+//   "sort": { "field": "added_at", "direction": "desc" }, "limit": 50 }
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SmartRules {
+    #[serde(flatten)]
+    pub root: RuleGroup,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sort: Option<SortSpec>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RuleGroup {
+    #[serde(rename = "match")]
+    pub combinator: Combinator,
+    pub rules: Vec<RuleNode>,
+    #[serde(default)]
+    pub negate: bool,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Combinator {
+    All,
+    Any,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum RuleNode {
+    Group(RuleGroup),
+    Rule(Rule),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rule {
+    pub field: RuleField,
+    pub op: RuleOp,
+    #[serde(default)]
+    pub value: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleField {
+    Title,
+    Path,
+    Rating,
+    WatchCount,
+    Duration,
+    Favorite,
+    AddedAt,
+    LastWatched,
+    Tag,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleOp {
+    Eq,
+    Ne,
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+    Contains,
+    NotContains,
+    StartsWith,
+    InLastDays,
+    NotInLastDays,
+    IsSet,
+    IsNotSet,
+    Is,
+    IsNot,
+}
+
+// SQL pieces for `SELECT ... FROM videos WHERE {where_sql} ORDER BY {order_sql}`.
+pub struct CompiledRules {
+    pub where_sql: String,
+    pub order_sql: String,
+    pub limit: Option<i64>,
+    pub params: Vec<Value>,
+}
+
+pub fn compile(rules: &SmartRules) -> Result<CompiledRules, String> {
+    let mut params = Vec::new();
+    let where_sql = compile_group(&rules.root, &mut params)?;
+    let order_sql = sort_sql(&rules.sort.clone().unwrap_or_default());
+    if let Some(limit) = rules.limit {
+        if limit < 0 {
+            return Err("limit must not be negative".to_string());
+        }
+    }
+    Ok(CompiledRules { where_sql, order_sql, limit: rules.limit, params })
+}
+
+fn compile_group(group: &RuleGroup, params: &mut Vec<Value>) -> Result<String, String> {
+    let parts = group
+        .rules
+        .iter()
+        .map(|node| match node {
+            RuleNode::Group(g) => compile_group(g, params),
+            RuleNode::Rule(r) => compile_rule(r, params),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let sql = if parts.is_empty() {
+        // an empty "all" matches everything, an empty "any" matches nothing
+        match group.combinator {
+            Combinator::All => "1".to_string(),
+            Combinator::Any => "0".to_string(),
+        }
+    } else {
+        let joiner = match group.combinator {
+            Combinator::All => " AND ",
+            Combinator::Any => " OR ",
+        };
+        format!("({})", parts.join(joiner))
+    };
+    Ok(if group.negate { format!("NOT {}", sql) } else { sql })
+}
+
+fn compile_rule(rule: &Rule, params: &mut Vec<Value>) -> Result<String, String> {
+    use RuleField as F;
+    use RuleOp as O;
+
+    let bad_op = || format!("operator {:?} is not supported for field {:?}", rule.op, rule.field);
+
+    match rule.field {
+        F::Title | F::Path => {
+            let column = if rule.field == F::Title { "title" } else { "path" };
+            match rule.op {
+                O::IsSet => return Ok(format!("({0} IS NOT NULL AND {0} <> '')", column)),
+                O::IsNotSet => return Ok(format!("({0} IS NULL OR {0} = '')", column)),
+                _ => {}
+            }
+            let text = text_value(rule)?;
+            let (sql, value) = match rule.op {
+                O::Eq => (format!("{} = ? COLLATE NOCASE", column), text),
+                O::Ne => (format!("({0} IS NULL OR {0} <> ? COLLATE NOCASE)", column), text),
+                O::Contains => (format!("{} LIKE ? ESCAPE '\\'", column), format!("%{}%", escape_like(&text))),
+                O::NotContains => (
+                    format!("({0} IS NULL OR {0} NOT LIKE ? ESCAPE '\\')", column),
+                    format!("%{}%", escape_like(&text)),
+                ),
+                O::StartsWith => (format!("{} LIKE ? ESCAPE '\\'", column), format!("{}%", escape_like(&text))),
+                _ => return Err(bad_op()),
+            };
+            params.push(Value::Text(value));
+            Ok(sql)
+        }
+        F::Rating | F::WatchCount | F::Duration => {
+            let column = match rule.field {
+                F::Rating => "rating",
+                F::WatchCount => "watch_count",
+                _ => "duration",
+            };
+            match rule.op {
+                O::IsSet => return Ok(format!("{} IS NOT NULL", column)),
+                O::IsNotSet => return Ok(format!("{} IS NULL", column)),
+                _ => {}
+            }
+            let cmp = match rule.op {
+                O::Eq => "=",
+                O::Ne => "<>",
+                O::Gt => ">",
+                O::Gte => ">=",
+                O::Lt => "<",
+                O::Lte => "<=",
+                _ => return Err(bad_op()),
+            };
+            params.push(Value::Real(number_value(rule)?));
+            Ok(format!("{} {} ?", column, cmp))
+        }
+        F::Favorite => {
+            let wanted = match (rule.op, &rule.value) {
+                (O::IsSet, _) => true,
+                (O::IsNotSet, _) => false,
+                (O::Is, serde_json::Value::Bool(b)) | (O::Eq, serde_json::Value::Bool(b)) => *b,
+                (O::IsNot, serde_json::Value::Bool(b)) | (O::Ne, serde_json::Value::Bool(b)) => !*b,
+                (O::Is, serde_json::Value::Null) => true,
+                _ => return Err(bad_op()),
+            };
+            Ok(if wanted { "favorite = 1".to_string() } else { "COALESCE(favorite, 0) = 0".to_string() })
+        }
+        F::AddedAt | F::LastWatched => {
+            let column = if rule.field == F::AddedAt { "added_at" } else { "last_watched" };
+            match rule.op {
+                O::IsSet => Ok(format!("{} IS NOT NULL", column)),
+                O::IsNotSet => Ok(format!("{} IS NULL", column)),
+                O::InLastDays => {
+                    params.push(Value::Text(format!("-{} days", days_value(rule)?)));
+                    Ok(format!("{} >= datetime('now', ?)", column))
+                }
+                // "not watched in 30 days" includes videos that were never watched
+                O::NotInLastDays => {
+                    params.push(Value::Text(format!("-{} days", days_value(rule)?)));
+                    Ok(format!("({0} IS NULL OR {0} < datetime('now', ?))", column))
+                }
+                O::Gt | O::Gte | O::Lt | O::Lte => {
+                    let cmp = match rule.op {
+                        O::Gt => ">",
+                        O::Gte => ">=",
+                        O::Lt => "<",
+                        _ => "<=",
+                    };
+                    params.push(Value::Text(text_value(rule)?));
+                    Ok(format!("{} {} datetime(?)", column, cmp))
+                }
+                _ => Err(bad_op()),
+            }
+        }
+        F::Tag => {
+            let exists = "EXISTS (SELECT 1 FROM video_tags vt JOIN tags t ON t.id = vt.tag_id WHERE vt.video_id = videos.id AND t.name = ? COLLATE NOCASE)";
+            match rule.op {
+                O::Is | O::Eq => {
+                    params.push(Value::Text(text_value(rule)?));
+                    Ok(exists.to_string())
+                }
+                O::IsNot | O::Ne => {
+                    params.push(Value::Text(text_value(rule)?));
+                    Ok(format!("NOT {}", exists))
+                }
+                O::IsSet => Ok("EXISTS (SELECT 1 FROM video_tags vt WHERE vt.video_id = videos.id)".to_string()),
+                O::IsNotSet => Ok("NOT EXISTS (SELECT 1 FROM video_tags vt WHERE vt.video_id = videos.id)".to_string()),
+                _ => Err(bad_op()),
+            }
+        }
+    }
+}
+
+fn text_value(rule: &Rule) -> Result<String, String> {
+    match &rule.value {
+        serde_json::Value::String(s) => Ok(s.clone()),
+        serde_json::Value::Number(n) => Ok(n.to_string()),
+        _ => Err(format!("field {:?} expects a text value", rule.field)),
+    }
+}
+
+fn number_value(rule: &Rule) -> Result<f64, String> {
+    match &rule.value {
+        serde_json::Value::Number(n) => n.as_f64().ok_or_else(|| "invalid number".to_string()),
+        serde_json::Value::String(s) => s.trim().parse::<f64>().map_err(|_| format!("field {:?} expects a number", rule.field)),
+        _ => Err(format!("field {:?} expects a number", rule.field)),
+    }
+}
+
+fn days_value(rule: &Rule) -> Result<i64, String> {
+    let days = number_value(rule)?;
+    if days < 0.0 {
+        return Err("number of days must not be negative".to_string());
+    }
+    Ok(days.round() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::{params_from_iter, Connection};
+    use serde_json::json;
+
+    // 1: a favourite lecture, 2: a film never watched, 3: an untitled song from 2020,
+    // 4: a favourite lecture with an empty title, tagged as film too
+    fn db() -> Connection {
+        let mut conn = Connection::open_in_memory().unwrap();
+        crate::db::schema::init_schema(&mut conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO videos (uuid, path, title, rating, watch_count, duration, favorite, added_at, last_watched) VALUES
+                 ('1', '/videos/Lecture One.mp4', 'Lecture One', 4.5, 3, 3600, 1, datetime('now', '-2 days'), datetime('now', '-1 days')),
+                 ('2', '/videos/Movie_100%.mkv', 'Movie 100%', 2, 0, 5400, 0, datetime('now', '-40 days'), NULL),
+                 ('3', '/music/Song.mp3', NULL, NULL, 1, NULL, NULL, '2020-05-01 12:00:00', datetime('now', '-60 days')),
+                 ('4', '/videos/lecture two.mp4', '', 5, 10, 1800, 1, datetime('now'), datetime('now', '-10 days'));
+             INSERT INTO tags (id, name) VALUES (1, 'lecture'), (2, 'Film');
+             INSERT INTO video_tags (video_id, tag_id) VALUES (1, 1), (2, 2), (4, 1), (4, 2);",
+        )
+        .unwrap();
+        conn
+    }
+
+    fn matching(conn: &Connection, rules: serde_json::Value) -> Result<Vec<i64>, String> {
+        let rules: SmartRules = serde_json::from_value(rules).map_err(|e| e.to_string())?;
+        let compiled = compile(&rules)?;
+        let sql = format!("SELECT id FROM videos WHERE {} ORDER BY {} LIMIT {}", compiled.where_sql, compiled.order_sql, compiled.limit.unwrap_or(-1));
+        let mut stmt = conn.prepare(&sql).map_err(|e| e.to_string())?;
+        let rows = stmt.query_map(params_from_iter(compiled.params.iter()), |row| row.get(0)).map_err(|e| e.to_string())?;
+        let mut ids = rows.collect::<rusqlite::Result<Vec<i64>>>().map_err(|e| e.to_string())?;
+        if rules.sort.is_none() {
+            ids.sort_unstable();
+        }
+        Ok(ids)
+    }
+
+    fn rule(conn: &Connection, field: &str, op: &str, value: serde_json::Value) -> Result<Vec<i64>, String> {
+        matching(conn, json!({ "match": "all", "rules": [{ "field": field, "op": op, "value": value }] }))
+    }
+
+    #[test]
+    fn text_rules() {
+        let conn = db();
+        let cases = [
+            ("title", "eq", json!("lecture one"), vec![1]),
+            // NULL and empty titles are not equal to anything
+            ("title", "ne", json!("Lecture One"), vec![2, 3, 4]),
+            ("title", "contains", json!("100%"), vec![2]),
+            ("title", "contains", json!("e%"), vec![]),
+            ("path", "contains", json!("_"), vec![2]),
+            ("title", "not_contains", json!("LECTURE"), vec![2, 3, 4]),
+            ("path", "starts_with", json!("/videos/"), vec![1, 2, 4]),
+            ("title", "is_set", json!(null), vec![1, 2]),
+            ("title", "is_not_set", json!(null), vec![3, 4]),
+        ];
+        for (field, op, value, expected) in cases {
+            assert_eq!(rule(&conn, field, op, value.clone()).unwrap(), expected, "{} {} {}", field, op, value);
+        }
+    }
+
+    #[test]
+    fn number_and_favorite_rules() {
+        let conn = db();
+        let cases = [
+            ("rating", "gte", json!(4.5), vec![1, 4]),
+            ("rating", "lt", json!("3"), vec![2]),
+            ("rating", "ne", json!(2), vec![1, 4]),
+            ("rating", "is_not_set", json!(null), vec![3]),
+            ("watch_count", "eq", json!(0), vec![2]),
+            ("watch_count", "gt", json!(1), vec![1, 4]),
+            ("watch_count", "lte", json!(1), vec![2, 3]),
+            ("duration", "is_set", json!(null), vec![1, 2, 4]),
+            ("favorite", "is", json!(true), vec![1, 4]),
+            ("favorite", "is", json!(null), vec![1, 4]),
+            ("favorite", "is_not", json!(false), vec![1, 4]),
+            ("favorite", "eq", json!(false), vec![2, 3]),
+            ("favorite", "is_not_set", json!(null), vec![2, 3]),
+        ];
+        for (field, op, value, expected) in cases {
+            assert_eq!(rule(&conn, field, op, value.clone()).unwrap(), expected, "{} {} {}", field, op, value);
+        }
+    }
+
+    #[test]
+    fn date_and_tag_rules() {
+        let conn = db();
+        let cases = [
+            ("added_at", "in_last_days", json!(7), vec![1, 4]),
+            ("added_at", "not_in_last_days", json!(30), vec![2, 3]),
+            // never watched counts as not watched recently
+            ("last_watched", "not_in_last_days", json!(30), vec![2, 3]),
+            ("last_watched", "in_last_days", json!("30"), vec![1, 4]),
+            ("last_watched", "is_not_set", json!(null), vec![2]),
+            ("added_at", "lt", json!("2021-01-01"), vec![3]),
+            ("added_at", "gte", json!("2021-01-01"), vec![1, 2, 4]),
+            ("tag", "is", json!("LECTURE"), vec![1, 4]),
+            ("tag", "is_not", json!("film"), vec![1, 3]),
+            ("tag", "is_set", json!(null), vec![1, 2, 4]),
+            ("tag", "is_not_set", json!(null), vec![3]),
+        ];
+        for (field, op, value, expected) in cases {
+            assert_eq!(rule(&conn, field, op, value.clone()).unwrap(), expected, "{} {} {}", field, op, value);
+        }
+    }
+
+    #[test]
+    fn nested_and_negated_groups() {
+        let conn = db();
+        let nested = json!({ "match": "any", "rules": [
+            { "field": "rating", "op": "gte", "value": 5 },
+            { "match": "all", "rules": [
+                { "field": "tag", "op": "is", "value": "film" },
+                { "field": "watch_count", "op": "eq", "value": 0 },
+            ] },
+        ] });
+        assert_eq!(matching(&conn, nested).unwrap(), vec![2, 4]);
+
+        let negated = json!({ "match": "all", "rules": [
+            { "field": "path", "op": "starts_with", "value": "/videos/" },
+            { "match": "any", "negate": true, "rules": [
+                { "field": "tag", "op": "is", "value": "lecture" },
+                { "field": "favorite", "op": "is", "value": true },
+            ] },
+        ] });
+        assert_eq!(matching(&conn, negated).unwrap(), vec![2]);
+        assert_eq!(matching(&conn, json!({ "match": "all", "negate": true, "rules": [{ "field": "tag", "op": "is_set" }] })).unwrap(), vec![3]);
+
+        // empty groups: "all" matches everything, "any" nothing
+        assert_eq!(matching(&conn, json!({ "match": "all", "rules": [] })).unwrap(), vec![1, 2, 3, 4]);
+        assert!(matching(&conn, json!({ "match": "any", "rules": [] })).unwrap().is_empty());
+        assert_eq!(matching(&conn, json!({ "match": "any", "negate": true, "rules": [] })).unwrap(), vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn sort_and_limit() {
+        let conn = db();
+        let rules = json!({ "match": "all", "rules": [], "sort": { "field": "rating", "direction": "desc" }, "limit": 3 });
+        assert_eq!(matching(&conn, rules).unwrap(), vec![4, 1, 2]);
+        let rules = json!({ "match": "all", "rules": [], "sort": { "field": "title", "direction": "asc" } });
+        // a NULL title sorts by the path, an empty one first
+        assert_eq!(matching(&conn, rules).unwrap(), vec![4, 3, 1, 2]);
+    }
+
+    #[test]
+    fn invalid_rules_are_errors() {
+        let conn = db();
+        assert_eq!(rule(&conn, "rating", "contains", json!("x")).unwrap_err(), "operator Contains is not supported for field Rating");
+        assert_eq!(rule(&conn, "title", "gt", json!("x")).unwrap_err(), "operator Gt is not supported for field Title");
+        assert_eq!(rule(&conn, "favorite", "gt", json!(true)).unwrap_err(), "operator Gt is not supported for field Favorite");
+        assert_eq!(rule(&conn, "tag", "contains", json!("a")).unwrap_err(), "operator Contains is not supported for field Tag");
+        assert_eq!(rule(&conn, "added_at", "eq", json!("2020-01-01")).unwrap_err(), "operator Eq is not supported for field AddedAt");
+        assert_eq!(rule(&conn, "title", "eq", json!(true)).unwrap_err(), "field Title expects a text value");
+        assert_eq!(rule(&conn, "rating", "gt", json!("high")).unwrap_err(), "field Rating expects a number");
+        assert_eq!(rule(&conn, "last_watched", "in_last_days", json!(-1)).unwrap_err(), "number of days must not be negative");
+        let nested = json!({ "match": "any", "rules": [{ "match": "all", "rules": [{ "field": "duration", "op": "is" }] }] });
+        assert_eq!(matching(&conn, nested).unwrap_err(), "operator Is is not supported for field Duration");
+        assert_eq!(matching(&conn, json!({ "match": "all", "rules": [], "limit": -1 })).unwrap_err(), "limit must not be negative");
+        // unknown fields and operators are rejected when the JSON is read
+        assert!(rule(&conn, "size", "eq", json!(1)).is_err());
+        assert!(rule(&conn, "title", "like", json!("a")).is_err());
+    }
+}
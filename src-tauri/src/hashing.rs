@@ -0,0 +1,334 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+use rusqlite::{params, Connection};
+use serde::Serialize;
+
+use crate::library::FileEntry;
+
+// Size of each sample read by `quick_hash`.
+const SAMPLE_SIZE: u64 = 64 * 1024;
+
+// Read size for `full_hash`.
+const CHUNK_SIZE: usize = 1024 * 1024;
+
+// Fingerprint of a file from its size and three samples (start, middle, end). Reads at
+// most 192 KiB, so it is cheap enough to compute for every file during a scan, and is
+// stable when the file is moved or renamed. Not a proof of identity on its own.
+pub fn quick_hash(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let size = file.metadata()?.len();
+
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&size.to_le_bytes());
+
+    if size <= SAMPLE_SIZE * 3 {
+        let mut buf = Vec::with_capacity(size as usize);
+        file.read_to_end(&mut buf)?;
+        hasher.update(&buf);
+    } else {
+        let mut buf = vec![0u8; SAMPLE_SIZE as usize];
+        for offset in [0, size / 2 - SAMPLE_SIZE / 2, size - SAMPLE_SIZE] {
+            file.seek(SeekFrom::Start(offset))?;
+            file.read_exact(&mut buf)?;
+            hasher.update(&buf);
+        }
+    }
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+// BLAKE3 of the whole file. Reads everything, so only the hashing job calls it. Fails
+// with `ErrorKind::Interrupted` when `cancel` is set.
+pub fn full_hash(path: &Path, cancel: &AtomicBool) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = blake3::Hasher::new();
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    loop {
+        if cancel.load(Ordering::Relaxed) {
+            return Err(io::Error::new(io::ErrorKind::Interrupted, "cancelled"));
+        }
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum HashPhase {
+    Hashing,
+    Finished,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HashProgress {
+    pub phase: HashPhase,
+    // videos that needed hashing when the job started
+    pub total: u64,
+    pub done: u64,
+    pub quick_hashed: u64,
+    pub full_hashed: u64,
+    pub errors: u64,
+    pub current: Option<String>,
+}
+
+// Cancellation flag of the running hashing job; there is at most one.
+static HASH_JOB: Lazy<Mutex<Option<Arc<AtomicBool>>>> = Lazy::new(|| Mutex::new(None));
+
+pub struct HashGuard {
+    pub cancel: Arc<AtomicBool>,
+}
+
+impl Drop for HashGuard {
+    fn drop(&mut self) {
+        if let Ok(mut job) = HASH_JOB.lock() {
+            *job = None;
+        }
+    }
+}
+
+pub fn begin_hashing() -> Result<HashGuard, String> {
+    let mut job = HASH_JOB.lock().map_err(|e| e.to_string())?;
+    if job.is_some() {
+        return Err("Hashing is already running".to_string());
+    }
+    let cancel = Arc::new(AtomicBool::new(false));
+    *job = Some(cancel.clone());
+    Ok(HashGuard { cancel })
+}
+
+// Returns false when no hashing job is running.
+pub fn cancel_hashing() -> bool {
+    match HASH_JOB.lock() {
+        Ok(job) => match job.as_ref() {
+            Some(flag) => {
+                flag.store(true, Ordering::SeqCst);
+                true
+            }
+            None => false,
+        },
+        Err(_) => false,
+    }
+}
+
+struct PendingVideo {
+    id: i64,
+    path: String,
+    size: Option<i64>,
+    mtime: Option<i64>,
+    has_quick: bool,
+}
+
+// Fills in the fingerprints of every video that is not missing: `quick_hash` where it
+// is absent and, with `full`, `full_hash` as well. Files are read without holding the
+// database lock and each result is written on its own, so a cancelled job keeps what
+// it finished. Files that cannot be read are counted as errors and left for
+// `check_missing_files`.
+pub fn hash_videos(
+    db: &Mutex<Connection>,
+    full: bool,
+    cancel: &AtomicBool,
+    mut report: impl FnMut(&HashProgress),
+) -> Result<HashProgress, String> {
+    let pending: Vec<PendingVideo> = {
+        let conn = db.lock().map_err(|e| e.to_string())?;
+        let mut stmt = conn
+            .prepare(
+                "SELECT id, path, file_size, file_mtime, quick_hash IS NOT NULL FROM videos
+                 WHERE COALESCE(missing, 0) = 0 AND (quick_hash IS NULL OR (?1 AND full_hash IS NULL)) ORDER BY id",
+            )
+            .map_err(|e| e.to_string())?;
+        let rows = stmt
+            .query_map(params![full], |row| {
+                Ok(PendingVideo {
+                    id: row.get(0)?,
+                    path: row.get(1)?,
+                    size: row.get(2)?,
+                    mtime: row.get(3)?,
+                    has_quick: row.get(4)?,
+                })
+            })
+            .map_err(|e| e.to_string())?;
+        rows.collect::<rusqlite::Result<_>>().map_err(|e| e.to_string())?
+    };
+
+    let mut progress = HashProgress {
+        phase: HashPhase::Hashing,
+        total: pending.len() as u64,
+        done: 0,
+        quick_hashed: 0,
+        full_hashed: 0,
+        errors: 0,
+        current: None,
+    };
+    report(&progress);
+    let mut last_report = Instant::now();
+
+    for video in pending {
+        if cancel.load(Ordering::SeqCst) {
+            break;
+        }
+        if last_report.elapsed() > Duration::from_millis(200) {
+            progress.current = Some(video.path.clone());
+            report(&progress);
+            last_report = Instant::now();
+        }
+
+        let Ok(entry) = FileEntry::from_path(Path::new(&video.path)) else {
+            progress.errors += 1;
+            progress.done += 1;
+            continue;
+        };
+        // a file changed since the last scan needs a new fingerprint too
+        let changed = video.size != Some(entry.size) || video.mtime != Some(entry.mtime);
+        let quick = if !video.has_quick || changed { quick_hash(Path::new(&entry.path)).ok() } else { None };
+        let whole = if full {
+            match full_hash(Path::new(&entry.path), cancel) {
+                Ok(hash) => Some(hash),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => break,
+                Err(_) => None,
+            }
+        } else {
+            None
+        };
+
+        let conn = db.lock().map_err(|e| e.to_string())?;
+        // the path check skips rows that were relinked or removed in the meantime
+        conn.execute(
+            "UPDATE videos SET
+                full_hash = COALESCE(?4, CASE WHEN file_size IS ?1 AND file_mtime IS ?2 THEN full_hash ELSE NULL END),
+                file_size = ?1, file_mtime = ?2, quick_hash = COALESCE(?3, quick_hash)
+             WHERE id = ?5 AND path = ?6",
+            params![entry.size, entry.mtime, quick, whole, video.id, video.path],
+        )
+        .map_err(|e| e.to_string())?;
+        drop(conn);
+
+        if quick.is_some() {
+            progress.quick_hashed += 1;
+        }
+        if whole.is_some() {
+            progress.full_hashed += 1;
+        }
+        if (quick.is_none() && (!video.has_quick || changed)) || (full && whole.is_none()) {
+            progress.errors += 1;
+        }
+        progress.done += 1;
+    }
+
+    progress.phase = if cancel.load(Ordering::SeqCst) { HashPhase::Cancelled } else { HashPhase::Finished };
+    progress.current = None;
+    report(&progress);
+    Ok(progress)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn quick_hash_samples_large_files() {
+        let dir = temp_dir("hashing-quick");
+        let large = vec![7u8; (SAMPLE_SIZE * 4) as usize];
+        fs::write(dir.join("a"), &large).unwrap();
+        fs::write(dir.join("b"), &large).unwrap();
+        let hash = quick_hash(&dir.join("a")).unwrap();
+        assert_eq!(quick_hash(&dir.join("b")).unwrap(), hash);
+
+        // a change between the samples goes unnoticed, one inside a sample does not
+        let mut changed = large.clone();
+        changed[SAMPLE_SIZE as usize + 10] = 0;
+        fs::write(dir.join("b"), &changed).unwrap();
+        assert_eq!(quick_hash(&dir.join("b")).unwrap(), hash);
+        let no_cancel = AtomicBool::new(false);
+        assert_ne!(full_hash(&dir.join("b"), &no_cancel).unwrap(), full_hash(&dir.join("a"), &no_cancel).unwrap());
+        changed[10] = 0;
+        fs::write(dir.join("b"), &changed).unwrap();
+        assert_ne!(quick_hash(&dir.join("b")).unwrap(), hash);
+
+        // small files are read whole and the size is part of the fingerprint
+        fs::write(dir.join("c"), "x").unwrap();
+        fs::write(dir.join("d"), "xx").unwrap();
+        assert_ne!(quick_hash(&dir.join("c")).unwrap(), quick_hash(&dir.join("d")).unwrap());
+        assert!(full_hash(&dir.join("c"), &AtomicBool::new(true)).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn hashing_job_fills_in_missing_fingerprints() {
+        let dir = temp_dir("hashing-job");
+        for file in ["new.mp4", "changed.mp4", "known.mp4", "missing.mp4"] {
+            fs::write(dir.join(file), file).unwrap();
+        }
+        let mut conn = Connection::open_in_memory().unwrap();
+        crate::db::schema::init_schema(&mut conn).unwrap();
+        let path = |file: &str| dir.join(file).to_string_lossy().to_string();
+        let known = FileEntry::from_path(&dir.join("known.mp4")).unwrap();
+        conn.execute_batch(&format!(
+            "INSERT INTO videos (uuid, path, file_size, file_mtime, quick_hash, full_hash, missing) VALUES
+                ('1', '{new}', NULL, NULL, NULL, NULL, 0),
+                ('2', '{changed}', 1, 1, 'old', NULL, 0),
+                ('3', '{known}', {size}, {mtime}, 'kept', NULL, 0),
+                ('4', '{missing}', NULL, NULL, NULL, NULL, 1),
+                ('5', '{gone}', NULL, NULL, NULL, NULL, 0);",
+            new = path("new.mp4"),
+            changed = path("changed.mp4"),
+            known = path("known.mp4"),
+            size = known.size,
+            mtime = known.mtime,
+            missing = path("missing.mp4"),
+            gone = path("gone.mp4"),
+        ))
+        .unwrap();
+        let db = Mutex::new(conn);
+        let hashes = || -> Vec<(Option<String>, Option<String>)> {
+            let conn = db.lock().unwrap();
+            let mut stmt = conn.prepare("SELECT quick_hash, full_hash FROM videos ORDER BY uuid").unwrap();
+            let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?))).unwrap();
+            rows.collect::<rusqlite::Result<_>>().unwrap()
+        };
+        let quick = |file: &str| Some(quick_hash(&dir.join(file)).unwrap());
+        let full = |file: &str| Some(full_hash(&dir.join(file), &AtomicBool::new(false)).unwrap());
+
+        let progress = hash_videos(&db, false, &AtomicBool::new(false), |_| {}).unwrap();
+        assert_eq!((progress.phase, progress.total, progress.done), (HashPhase::Finished, 2, 2));
+        assert_eq!((progress.quick_hashed, progress.full_hashed, progress.errors), (1, 0, 1));
+        let after_quick = hashes();
+        assert_eq!(after_quick[0], (quick("new.mp4"), None));
+        // not picked up: it already has a fingerprint, even if a stale one
+        assert_eq!(after_quick[1], (Some("old".to_string()), None));
+        assert_eq!(after_quick[2], (Some("kept".to_string()), None));
+
+        let progress = hash_videos(&db, true, &AtomicBool::new(false), |_| {}).unwrap();
+        assert_eq!((progress.total, progress.quick_hashed, progress.full_hashed, progress.errors), (4, 1, 3, 1));
+        let after_full = hashes();
+        assert_eq!(after_full[0], (quick("new.mp4"), full("new.mp4")));
+        // a file changed since it was fingerprinted gets a new one
+        assert_eq!(after_full[1], (quick("changed.mp4"), full("changed.mp4")));
+        assert_eq!(after_full[2], (Some("kept".to_string()), full("known.mp4")));
+        assert_eq!(after_full[3], (None, None));
+
+        // only the unreadable file is left, and a cancelled job does not get to it
+        let progress = hash_videos(&db, true, &AtomicBool::new(true), |_| {}).unwrap();
+        assert_eq!((progress.phase, progress.done), (HashPhase::Cancelled, 0));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,5 +1,14 @@
 mod commands;
 mod db;
+mod hashing;
+mod library;
+mod playback;
+mod probe;
+mod remux;
+mod scope;
+mod stream;
+mod subtitles;
+mod watcher;
 
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
@@ -8,8 +17,25 @@ pub fn run() {
         .plugin(tauri_plugin_fs::init())
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_shell::init())
+        .register_asynchronous_uri_scheme_protocol(stream::SCHEME, |_ctx, request, responder| {
+            // file reads stay off the thread that handles webview requests
+            tauri::async_runtime::spawn_blocking(move || responder.respond(stream::handle(&request)));
+        })
+        .register_asynchronous_uri_scheme_protocol(stream::ASSET_SCHEME, |_ctx, request, responder| {
+            tauri::async_runtime::spawn_blocking(move || responder.respond(stream::handle_asset(&request)));
+        })
         .setup(| app | {
             db::database::init_db()?;
+            {
+                let conn = db::database::get_connection().lock().map_err(|e| e.to_string())?;
+                remux::sweep_cache(&conn)?;
+            }
+            // new files in library folders show up without a rescan; not fatal if unavailable
+            if let Err(e) = watcher::start(app.handle().clone()) {
+                watcher::report_error(app.handle(), e);
+            }
+            // the asset protocol reads nothing until the library roots are allowed
+            scope::sync_roots(app.handle())?;
             Ok(())
         })
             .invoke_handler(tauri::generate_handler![
@@ -27,12 +53,71 @@ pub fn run() {
             commands::add_like,
             commands::add_rating,
             commands::update_video_duration_by_path,
+            commands::save_position,
+            commands::clear_position,
+            commands::list_continue_watching,
             commands::delete_video,
             commands::set_favorite,
             commands::confirm_dialog,
             commands::get_setting,
             commands::set_setting,
-            commands::list_settings
+            commands::list_settings,
+            commands::list_tags,
+            commands::list_video_tags,
+            commands::create_tag,
+            commands::rename_tag,
+            commands::merge_tags,
+            commands::delete_tag,
+            commands::tag_videos,
+            commands::untag_videos,
+            commands::list_videos_by_tags,
+            commands::list_playlists,
+            commands::create_playlist,
+            commands::rename_playlist,
+            commands::delete_playlist,
+            commands::duplicate_playlist,
+            commands::get_playlist_items,
+            commands::add_to_playlist,
+            commands::remove_from_playlist,
+            commands::reorder_playlist,
+            commands::list_smart_playlists,
+            commands::create_smart_playlist,
+            commands::update_smart_playlist,
+            commands::delete_smart_playlist,
+            commands::evaluate_smart_playlist,
+            commands::preview_smart_playlist,
+            commands::search_videos,
+            commands::query_videos,
+            commands::list_libraries,
+            commands::add_library,
+            commands::remove_library,
+            commands::scan_library,
+            commands::cancel_scan,
+            commands::check_missing_files,
+            commands::relink_missing,
+            commands::relink_video,
+            commands::hash_videos,
+            commands::cancel_hashing,
+            commands::find_duplicates,
+            commands::merge_duplicates,
+            commands::probe_video,
+            commands::get_media_info,
+            commands::get_cover_art,
+            commands::check_playability,
+            commands::playability_report,
+            commands::get_codec_support,
+            commands::set_codec_support,
+            commands::remux_video,
+            commands::cancel_remux,
+            commands::get_remux,
+            commands::delete_remux,
+            commands::list_subtitles,
+            commands::list_embedded_subtitles,
+            commands::extract_subtitle,
+            commands::get_subtitle_timing,
+            commands::set_subtitle_timing,
+            commands::save_corrected_subtitle,
+            commands::set_subtitle_encoding
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
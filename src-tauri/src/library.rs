@@ -0,0 +1,1078 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use once_cell::sync::Lazy;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+
+use crate::db::model::{RelinkReport, RelinkedVideo};
+use crate::db::query::escape_like;
+use crate::hashing;
+use crate::playback::{self, CodecSupport};
+use crate::probe::{self, MediaInfo, MediaKind};
+use crate::remux;
+
+// File types picked up by the folder browser and the library scanner.
+pub const MEDIA_EXTENSIONS: &[&str] = &[
+    ".mp4", ".mkv", ".mov", ".webm", ".avi", ".flv", ".m4v", ".ts", ".m2ts", ".mts", ".wmv", ".mp3",
+];
+
+pub fn is_media_file(path: &Path) -> bool {
+    match path.file_name().and_then(|n| n.to_str()) {
+        Some(name) => {
+            let lower = name.to_lowercase();
+            MEDIA_EXTENSIONS.iter().any(|ext| lower.ends_with(ext))
+        }
+        None => false,
+    }
+}
+
+// Files that are audio even when the probe cannot tell, see `media_kind`.
+const AUDIO_EXTENSIONS: &[&str] = &[".mp3"];
+
+// Title used for files the scanner adds, same as the frontend: the file name.
+pub fn default_title(path: &Path) -> Option<String> {
+    path.file_name().map(|n| n.to_string_lossy().to_string())
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ScanPhase {
+    Walking,
+    Importing,
+    Finished,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ScanProgress {
+    pub library_id: i64,
+    pub phase: ScanPhase,
+    pub files_seen: u64,
+    pub added: u64,
+    pub updated: u64,
+    // already in the library and unchanged
+    pub skipped: u64,
+    pub missing: u64,
+    // missing rows whose file was found again under a new path
+    pub relinked: u64,
+    pub errors: u64,
+    pub current: Option<String>,
+}
+
+impl ScanProgress {
+    fn new(library_id: i64) -> Self {
+        ScanProgress {
+            library_id,
+            phase: ScanPhase::Walking,
+            files_seen: 0,
+            added: 0,
+            updated: 0,
+            skipped: 0,
+            missing: 0,
+            relinked: 0,
+            errors: 0,
+            current: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FileEntry {
+    pub path: String,
+    pub size: i64,
+    pub mtime: i64,
+    // filled lazily, see `hashing::quick_hash`
+    pub quick_hash: Option<String>,
+    // set by `with_media_info`; `media` stays None for formats the probe does not know
+    pub probed: bool,
+    pub media: Option<MediaInfo>,
+}
+
+impl FileEntry {
+    pub fn from_path(path: &Path) -> std::io::Result<FileEntry> {
+        let meta = fs::metadata(path)?;
+        let mtime = meta
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or(0);
+        Ok(FileEntry {
+            path: path.to_string_lossy().to_string(),
+            size: meta.len() as i64,
+            mtime,
+            quick_hash: None,
+            probed: false,
+            media: None,
+        })
+    }
+
+    pub fn with_quick_hash(mut self) -> FileEntry {
+        if self.quick_hash.is_none() {
+            self.quick_hash = hashing::quick_hash(Path::new(&self.path)).ok();
+        }
+        self
+    }
+
+    pub fn with_media_info(mut self) -> FileEntry {
+        if !self.probed {
+            self.media = probe::probe_file(Path::new(&self.path)).ok().flatten();
+            self.probed = true;
+        }
+        self
+    }
+}
+
+// Cancellation flags of the scans currently running, by library id.
+static RUNNING_SCANS: Lazy<Mutex<HashMap<i64, Arc<AtomicBool>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+// Removes the library from RUNNING_SCANS when the scan ends, however it ends.
+pub struct ScanGuard {
+    library_id: i64,
+    pub cancel: Arc<AtomicBool>,
+}
+
+impl Drop for ScanGuard {
+    fn drop(&mut self) {
+        if let Ok(mut scans) = RUNNING_SCANS.lock() {
+            scans.remove(&self.library_id);
+        }
+    }
+}
+
+pub fn begin_scan(library_id: i64) -> Result<ScanGuard, String> {
+    let mut scans = RUNNING_SCANS.lock().map_err(|e| e.to_string())?;
+    if scans.contains_key(&library_id) {
+        return Err("A scan of this library is already running".to_string());
+    }
+    let cancel = Arc::new(AtomicBool::new(false));
+    scans.insert(library_id, cancel.clone());
+    Ok(ScanGuard { library_id, cancel })
+}
+
+// Returns false when no scan of the library is running.
+pub fn cancel_scan(library_id: i64) -> bool {
+    match RUNNING_SCANS.lock() {
+        Ok(scans) => match scans.get(&library_id) {
+            Some(flag) => {
+                flag.store(true, Ordering::SeqCst);
+                true
+            }
+            None => false,
+        },
+        Err(_) => false,
+    }
+}
+
+// Depth-first walk calling `on_entry` for every media file. Symlinked directories are
+// not followed to avoid cycles. Stops early when `cancel` is set. Returns the folders
+// that could not be listed completely, whose files may or may not still be there.
+pub fn walk(root: &Path, cancel: &AtomicBool, mut on_entry: impl FnMut(std::io::Result<FileEntry>)) -> Vec<PathBuf> {
+    let mut unreadable = Vec::new();
+    let mut stack: Vec<PathBuf> = vec![root.to_path_buf()];
+    while let Some(dir) = stack.pop() {
+        if cancel.load(Ordering::Relaxed) {
+            break;
+        }
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                on_entry(Err(e));
+                unreadable.push(dir);
+                continue;
+            }
+        };
+        for entry in entries {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    on_entry(Err(e));
+                    if unreadable.last() != Some(&dir) {
+                        unreadable.push(dir.clone());
+                    }
+                    continue;
+                }
+            };
+            let path = entry.path();
+            let is_dir = entry.file_type().map(|t| t.is_dir()).unwrap_or(false);
+            if is_dir {
+                stack.push(path);
+            } else if is_media_file(&path) {
+                on_entry(FileEntry::from_path(&path));
+            }
+        }
+    }
+    unreadable
+}
+
+// LIKE pattern matching every path below `root`.
+pub fn root_pattern(root: &str) -> String {
+    let sep = if root.contains('\\') && !root.contains('/') { '\\' } else { '/' };
+    let mut prefix = root.to_string();
+    if !prefix.ends_with(sep) {
+        prefix.push(sep);
+    }
+    format!("{}%", escape_like(&prefix))
+}
+
+// SET clause dropping the full hash of a file that changed; ?2 and ?3 are the new
+// size and mtime (SQLite compares against the old row values).
+const KEEP_FULL_HASH: &str = "full_hash = CASE WHEN file_size IS ?2 AND file_mtime IS ?3 THEN full_hash ELSE NULL END";
+
+struct KnownFile {
+    id: i64,
+    size: Option<i64>,
+    mtime: Option<i64>,
+    library_id: Option<i64>,
+    missing: bool,
+    quick_hash: Option<String>,
+    probed: bool,
+}
+
+// Scans a library root: walks the folder without holding the database lock, then
+// applies all changes in one transaction. New files are inserted, known files get
+// their size/mtime refreshed and rows whose file was not found are flagged `missing`,
+// unless their folder could not be listed.
+// A cancelled scan rolls back and leaves the library untouched.
+pub fn scan_library(
+    db: &Mutex<Connection>,
+    library_id: i64,
+    root: &Path,
+    cancel: &AtomicBool,
+    mut report: impl FnMut(&ScanProgress),
+) -> Result<ScanProgress, String> {
+    if !root.is_dir() {
+        // e.g. an unmounted drive; do not flag the whole library as missing
+        return Err(format!("Library folder {} is not available", root.display()));
+    }
+
+    let mut progress = ScanProgress::new(library_id);
+    report(&progress);
+
+    let mut found: Vec<FileEntry> = Vec::new();
+    let mut last_report = Instant::now();
+    let unreadable = walk(root, cancel, |entry| {
+        match entry {
+            Ok(entry) => {
+                progress.files_seen += 1;
+                if last_report.elapsed() > Duration::from_millis(200) {
+                    progress.current = Some(entry.path.clone());
+                    report(&progress);
+                    last_report = Instant::now();
+                }
+                found.push(entry);
+            }
+            Err(_) => progress.errors += 1,
+        }
+    });
+    if cancel.load(Ordering::SeqCst) {
+        progress.phase = ScanPhase::Cancelled;
+        progress.current = None;
+        report(&progress);
+        return Ok(progress);
+    }
+
+    progress.phase = ScanPhase::Importing;
+    progress.current = None;
+    report(&progress);
+
+    let root_str = root.to_string_lossy().to_string();
+    let known = {
+        let conn = db.lock().map_err(|e| e.to_string())?;
+        load_known_files(&conn, library_id, &root_str).map_err(|e| e.to_string())?
+    };
+
+    // Fingerprint and probe new and changed files before taking the lock for the write phase.
+    for entry in found.iter_mut() {
+        if cancel.load(Ordering::Relaxed) {
+            break;
+        }
+        let changed = match known.get(&entry.path) {
+            Some(k) => k.size != Some(entry.size) || k.mtime != Some(entry.mtime),
+            None => true,
+        };
+        let needs_hash = changed || known.get(&entry.path).is_some_and(|k| k.quick_hash.is_none());
+        let needs_probe = changed || known.get(&entry.path).is_some_and(|k| !k.probed);
+        if needs_hash {
+            entry.quick_hash = hashing::quick_hash(Path::new(&entry.path)).ok();
+        }
+        if needs_probe {
+            entry.media = probe::probe_file(Path::new(&entry.path)).ok().flatten();
+            entry.probed = true;
+        }
+        if (needs_hash || needs_probe) && last_report.elapsed() > Duration::from_millis(200) {
+            progress.current = Some(entry.path.clone());
+            report(&progress);
+            last_report = Instant::now();
+        }
+    }
+    progress.current = None;
+
+    let mut conn = db.lock().map_err(|e| e.to_string())?;
+    let support = playback::codec_support(&conn);
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    {
+        let seen: HashSet<&str> = found.iter().map(|e| e.path.as_str()).collect();
+
+        // Flag vanished files first, so that a file that was only moved inside the
+        // library is relinked to its old row below instead of being added twice. Rows
+        // below a folder that could not be listed are left alone.
+        let mut mark_missing = tx
+            .prepare("UPDATE videos SET missing = 1 WHERE id = ?1")
+            .map_err(|e| e.to_string())?;
+        for (path, k) in known.iter() {
+            let listed = !unreadable.iter().any(|dir| Path::new(path).starts_with(dir));
+            if listed && !seen.contains(path.as_str()) && !k.missing {
+                mark_missing.execute(params![k.id]).map_err(|e| e.to_string())?;
+                progress.missing += 1;
+            }
+        }
+
+        let mut insert = tx
+            .prepare("INSERT OR IGNORE INTO videos (uuid, path, title, library_id, file_size, file_mtime, quick_hash) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)")
+            .map_err(|e| e.to_string())?;
+        let mut update = tx
+            .prepare(&format!(
+                "UPDATE videos SET library_id = ?1, {}, file_size = ?2, file_mtime = ?3, quick_hash = COALESCE(?4, quick_hash), missing = 0 WHERE id = ?5",
+                KEEP_FULL_HASH
+            ))
+            .map_err(|e| e.to_string())?;
+
+        for (i, entry) in found.iter().enumerate() {
+            if i % 500 == 0 && cancel.load(Ordering::SeqCst) {
+                // dropping the transaction rolls back everything done so far
+                progress.phase = ScanPhase::Cancelled;
+                report(&progress);
+                return Ok(progress);
+            }
+            match known.get(&entry.path) {
+                Some(k) => {
+                    let unchanged = k.size == Some(entry.size)
+                        && k.mtime == Some(entry.mtime)
+                        && k.library_id == Some(library_id)
+                        && k.quick_hash.is_some()
+                        && k.probed
+                        && !k.missing;
+                    if unchanged {
+                        progress.skipped += 1;
+                        continue;
+                    }
+                    match update.execute(params![library_id, entry.size, entry.mtime, entry.quick_hash, k.id]) {
+                        Ok(_) => progress.updated += 1,
+                        Err(_) => progress.errors += 1,
+                    }
+                    store_media_info(&tx, entry, &support).map_err(|e| e.to_string())?;
+                }
+                None => {
+                    if let Some(id) = find_moved_row(&tx, entry).map_err(|e| e.to_string())? {
+                        match relink_row(&tx, id, Some(library_id), entry) {
+                            Ok(_) => progress.relinked += 1,
+                            Err(_) => progress.errors += 1,
+                        }
+                        store_media_info(&tx, entry, &support).map_err(|e| e.to_string())?;
+                        continue;
+                    }
+                    let uuid = uuid::Uuid::new_v4().to_string();
+                    let title = default_title(Path::new(&entry.path));
+                    match insert.execute(params![uuid, entry.path, title, library_id, entry.size, entry.mtime, entry.quick_hash]) {
+                        Ok(n) if n > 0 => progress.added += 1,
+                        Ok(_) => progress.skipped += 1,
+                        Err(_) => progress.errors += 1,
+                    }
+                    store_media_info(&tx, entry, &support).map_err(|e| e.to_string())?;
+                }
+            }
+            if i % 500 == 0 {
+                report(&progress);
+            }
+        }
+    }
+    // rows relinked above were counted as missing first
+    progress.missing = progress.missing.saturating_sub(progress.relinked);
+    tx.execute("UPDATE libraries SET last_scanned_at = datetime('now') WHERE id = ?1", params![library_id])
+        .map_err(|e| e.to_string())?;
+    tx.commit().map_err(|e| e.to_string())?;
+
+    progress.phase = ScanPhase::Finished;
+    report(&progress);
+    Ok(progress)
+}
+
+// Rows belonging to the library, plus rows below its folder that were added by hand.
+fn load_known_files(conn: &Connection, library_id: i64, root: &str) -> rusqlite::Result<HashMap<String, KnownFile>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, path, file_size, file_mtime, library_id, missing, quick_hash, probed_at IS NOT NULL FROM videos WHERE library_id = ?1 OR path LIKE ?2 ESCAPE '\\'",
+    )?;
+    let rows = stmt.query_map(params![library_id, root_pattern(root)], |row| {
+        Ok((
+            row.get::<_, String>(1)?,
+            KnownFile {
+                id: row.get(0)?,
+                size: row.get(2)?,
+                mtime: row.get(3)?,
+                library_id: row.get(4)?,
+                missing: row.get::<_, Option<i64>>(5)?.unwrap_or(0) != 0,
+                quick_hash: row.get(6)?,
+                probed: row.get(7)?,
+            },
+        ))
+    })?;
+    rows.collect()
+}
+
+// The single missing row with the same size and fingerprint as `entry`, if any.
+// Several candidates are ambiguous (copies of the same file) and yield None.
+pub fn find_moved_row(conn: &Connection, entry: &FileEntry) -> rusqlite::Result<Option<i64>> {
+    let Some(hash) = entry.quick_hash.as_deref() else {
+        return Ok(None);
+    };
+    let mut stmt = conn.prepare_cached("SELECT id FROM videos WHERE missing = 1 AND file_size = ?1 AND quick_hash = ?2 LIMIT 2")?;
+    let ids: Vec<i64> = stmt
+        .query_map(params![entry.size, hash], |row| row.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(if ids.len() == 1 { Some(ids[0]) } else { None })
+}
+
+// Points an existing row at the file's new location, keeping uuid and history.
+pub fn relink_row(conn: &Connection, id: i64, library_id: Option<i64>, entry: &FileEntry) -> rusqlite::Result<usize> {
+    let old_path: String = conn.query_row("SELECT path FROM videos WHERE id = ?1", params![id], |row| row.get(0))?;
+    conn.execute(
+        "UPDATE videos SET path = ?1, title = CASE WHEN title = ?2 THEN ?3 ELSE title END,
+            library_id = COALESCE(?4, library_id), file_size = ?5, file_mtime = ?6,
+            full_hash = CASE WHEN file_size IS ?5 AND quick_hash IS COALESCE(?7, quick_hash) THEN full_hash ELSE NULL END,
+            quick_hash = COALESCE(?7, quick_hash), missing = 0
+         WHERE id = ?8",
+        params![
+            entry.path,
+            default_title(Path::new(&old_path)),
+            default_title(Path::new(&entry.path)),
+            library_id,
+            entry.size,
+            entry.mtime,
+            entry.quick_hash,
+            id
+        ],
+    )
+}
+
+// Folds the history of `drop_id` into `keep_id` and deletes `drop_id`: watch counts
+// add up, the more recent last_watched/position wins, rating and favorite are kept
+// when either row has them. Tags, playlist entries and the subtitle correction move
+// over; a correction of `keep_id` is kept.
+pub fn merge_rows(conn: &Connection, keep_id: i64, drop_id: i64) -> rusqlite::Result<()> {
+    if keep_id == drop_id {
+        return Ok(());
+    }
+    conn.execute(
+        "UPDATE videos SET
+            watch_count = COALESCE(watch_count, 0) + COALESCE((SELECT watch_count FROM videos WHERE id = ?2), 0),
+            last_watched = MAX(COALESCE(last_watched, ''), COALESCE((SELECT last_watched FROM videos WHERE id = ?2), '')),
+            rating = COALESCE(rating, (SELECT rating FROM videos WHERE id = ?2)),
+            favorite = MAX(COALESCE(favorite, 0), COALESCE((SELECT favorite FROM videos WHERE id = ?2), 0)),
+            duration = COALESCE(duration, (SELECT duration FROM videos WHERE id = ?2)),
+            position = CASE WHEN COALESCE((SELECT position_updated_at FROM videos WHERE id = ?2), '') > COALESCE(position_updated_at, '')
+                THEN (SELECT position FROM videos WHERE id = ?2) ELSE position END,
+            position_updated_at = MAX(COALESCE(position_updated_at, ''), COALESCE((SELECT position_updated_at FROM videos WHERE id = ?2), ''))
+         WHERE id = ?1",
+        params![keep_id, drop_id],
+    )?;
+    // MAX() over the '' placeholders above turns "never" into ''; restore NULL
+    conn.execute(
+        "UPDATE videos SET last_watched = NULLIF(last_watched, ''), position_updated_at = NULLIF(position_updated_at, '') WHERE id = ?1",
+        params![keep_id],
+    )?;
+    conn.execute(
+        "INSERT OR IGNORE INTO video_tags (video_id, tag_id) SELECT ?1, tag_id FROM video_tags WHERE video_id = ?2",
+        params![keep_id, drop_id],
+    )?;
+    // the path trigger only fires when `keep_id` moves, so entries taken over from
+    // `drop_id` get its path here
+    conn.execute(
+        "UPDATE playlist_items SET video_id = ?1, video_path = (SELECT path FROM videos WHERE id = ?1) WHERE video_id = ?2",
+        params![keep_id, drop_id],
+    )?;
+    conn.execute(
+        "INSERT OR IGNORE INTO subtitle_timings (video_id, offset_ms, source_fps, target_fps, updated_at)
+         SELECT ?1, offset_ms, source_fps, target_fps, updated_at FROM subtitle_timings WHERE video_id = ?2",
+        params![keep_id, drop_id],
+    )?;
+    remux::delete_remux(conn, drop_id)?;
+    conn.execute("DELETE FROM videos WHERE id = ?1", params![drop_id])?;
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Upsert {
+    Added,
+    Updated,
+    Relinked,
+}
+
+// Adds a single file to the library or refreshes its row (used by the folder watcher).
+// A new file matching a missing row is taken as that file moved and relinked.
+pub fn upsert_file(conn: &Connection, library_id: Option<i64>, entry: &FileEntry, support: &CodecSupport) -> rusqlite::Result<Upsert> {
+    let changed = conn.execute(
+        &format!(
+            "UPDATE videos SET library_id = COALESCE(?1, library_id), {}, file_size = ?2, file_mtime = ?3, quick_hash = COALESCE(?4, quick_hash), missing = 0 WHERE path = ?5",
+            KEEP_FULL_HASH
+        ),
+        params![library_id, entry.size, entry.mtime, entry.quick_hash, entry.path],
+    )?;
+    let result = if changed > 0 {
+        Upsert::Updated
+    } else if let Some(id) = find_moved_row(conn, entry)? {
+        relink_row(conn, id, library_id, entry)?;
+        Upsert::Relinked
+    } else {
+        let uuid = uuid::Uuid::new_v4().to_string();
+        conn.execute(
+            "INSERT INTO videos (uuid, path, title, library_id, file_size, file_mtime, quick_hash) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![uuid, entry.path, default_title(Path::new(&entry.path)), library_id, entry.size, entry.mtime, entry.quick_hash],
+        )?;
+        Upsert::Added
+    };
+    store_media_info(conn, entry, support)?;
+    Ok(result)
+}
+
+// Writes the probe result of `entry` to the row at its path. A file the probe could
+// not read is recorded as probed too, so the scanner does not retry it every time;
+// a known duration from playback is kept in that case. The ID3 title of an audio file
+// replaces the title only while that is still the file name.
+pub fn store_media_info(conn: &Connection, entry: &FileEntry, support: &CodecSupport) -> rusqlite::Result<()> {
+    if !entry.probed {
+        return Ok(());
+    }
+    let info = entry.media.as_ref();
+    let kind = media_kind(entry);
+    let tag_title = info.and_then(|i| i.title.clone()).filter(|_| kind == MediaKind::Audio);
+    conn.execute(
+        "UPDATE videos SET duration = COALESCE(?1, duration), container = ?2, width = ?3, height = ?4, frame_rate = ?5,
+            video_codec = ?6, audio_codec = ?7, audio_channels = ?8, rotation = ?9, playable_in_webview = ?10, media_info = ?11,
+            media_kind = ?12, title = CASE WHEN ?13 IS NOT NULL AND (title IS NULL OR title = ?14) THEN ?13 ELSE title END,
+            probed_at = datetime('now')
+         WHERE path = ?15",
+        params![
+            info.and_then(|i| i.duration).map(|d| d.round() as i64),
+            info.map(|i| i.container.clone()),
+            info.and_then(|i| i.width),
+            info.and_then(|i| i.height),
+            info.and_then(|i| i.frame_rate),
+            info.and_then(|i| i.video_codec.clone()),
+            info.and_then(|i| i.audio_codec.clone()),
+            info.and_then(|i| i.audio_channels),
+            info.and_then(|i| i.rotation),
+            info.and_then(|i| playback::check(i, support).playable),
+            info.and_then(|i| serde_json::to_string(i).ok()),
+            kind.as_str(),
+            tag_title,
+            default_title(Path::new(&entry.path)),
+            entry.path
+        ],
+    )?;
+    Ok(())
+}
+
+// From the probed streams, or from the extension when the probe did not recognise the file.
+fn media_kind(entry: &FileEntry) -> MediaKind {
+    match &entry.media {
+        Some(info) => info.media_kind(),
+        None => {
+            let lower = entry.path.to_lowercase();
+            if AUDIO_EXTENSIONS.iter().any(|ext| lower.ends_with(ext)) {
+                MediaKind::Audio
+            } else {
+                MediaKind::Video
+            }
+        }
+    }
+}
+
+// Flags the file, or every file below it if it was a folder, as missing.
+pub fn mark_missing(conn: &Connection, path: &str) -> rusqlite::Result<usize> {
+    conn.execute(
+        "UPDATE videos SET missing = 1 WHERE (path = ?1 OR path LIKE ?2 ESCAPE '\\') AND COALESCE(missing, 0) = 0",
+        params![path, root_pattern(path)],
+    )
+}
+
+// Moves rows from `from` to `to` keeping their id, uuid and history. Works for single
+// files and for folders (every path below `from` is rewritten). A title that was just
+// the old file name follows the rename.
+pub fn rename_path(conn: &Connection, from: &str, to: &str) -> rusqlite::Result<usize> {
+    if Path::new(to).is_dir() {
+        return conn.execute(
+            "UPDATE videos SET path = ?1 || substr(path, length(?2) + 1), missing = 0 WHERE path LIKE ?3 ESCAPE '\\'",
+            params![to, from, root_pattern(from)],
+        );
+    }
+    let old_title = default_title(Path::new(from));
+    let new_title = default_title(Path::new(to));
+    conn.execute(
+        "UPDATE videos SET path = ?1, title = CASE WHEN title = ?3 THEN ?4 ELSE title END, missing = 0 WHERE path = ?2",
+        params![to, from, old_title, new_title],
+    )
+}
+
+// Library roots by id, for finding the library a path belongs to.
+pub fn load_roots(conn: &Connection) -> rusqlite::Result<HashMap<i64, PathBuf>> {
+    let mut stmt = conn.prepare("SELECT id, path FROM libraries")?;
+    let rows = stmt.query_map([], |row| Ok((row.get::<_, i64>(0)?, PathBuf::from(row.get::<_, String>(1)?))))?;
+    rows.collect()
+}
+
+// The library a path belongs to; roots are never nested (see `add_library`).
+pub fn library_for(roots: &HashMap<i64, PathBuf>, path: &Path) -> Option<i64> {
+    roots.iter().find(|(_, root)| path.starts_with(root)).map(|(id, _)| *id)
+}
+
+#[derive(Debug, Default)]
+pub struct MissingCheck {
+    pub checked: u64,
+    pub newly_missing: u64,
+    pub found_again: u64,
+}
+
+// Stats the file of every row (or of one library's rows) without holding the lock and
+// updates the `missing` flags. Rows below a library root that is itself unavailable
+// (unmounted drive) are left alone, like `scan_library` does.
+pub fn check_missing(db: &Mutex<Connection>, library_id: Option<i64>) -> Result<MissingCheck, String> {
+    let (rows, roots) = {
+        let conn = db.lock().map_err(|e| e.to_string())?;
+        let mut stmt = conn
+            .prepare("SELECT id, path, COALESCE(missing, 0) FROM videos WHERE ?1 IS NULL OR library_id = ?1")
+            .map_err(|e| e.to_string())?;
+        let rows: Vec<(i64, String, bool)> = stmt
+            .query_map(params![library_id], |row| Ok((row.get(0)?, row.get(1)?, row.get::<_, i64>(2)? != 0)))
+            .map_err(|e| e.to_string())?
+            .collect::<rusqlite::Result<_>>()
+            .map_err(|e| e.to_string())?;
+        (rows, load_roots(&conn).map_err(|e| e.to_string())?)
+    };
+
+    let offline: HashSet<i64> = roots.iter().filter(|(_, root)| !root.is_dir()).map(|(id, _)| *id).collect();
+    let mut result = MissingCheck::default();
+    let mut changes: Vec<(i64, bool)> = Vec::new();
+    for (id, path, was_missing) in rows {
+        let path = Path::new(&path);
+        if library_for(&roots, path).is_some_and(|lib| offline.contains(&lib)) {
+            continue;
+        }
+        result.checked += 1;
+        let exists = path.is_file();
+        if exists == was_missing {
+            changes.push((id, !exists));
+            if exists {
+                result.found_again += 1;
+            } else {
+                result.newly_missing += 1;
+            }
+        }
+    }
+
+    let mut conn = db.lock().map_err(|e| e.to_string())?;
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    {
+        let mut update = tx.prepare("UPDATE videos SET missing = ?1 WHERE id = ?2").map_err(|e| e.to_string())?;
+        for (id, missing) in changes {
+            update.execute(params![missing, id]).map_err(|e| e.to_string())?;
+        }
+    }
+    tx.commit().map_err(|e| e.to_string())?;
+    Ok(result)
+}
+
+struct MissingRow {
+    id: i64,
+    path: String,
+    size: i64,
+    quick_hash: Option<String>,
+}
+
+// Looks for the files of missing rows below `folders` and relinks every row that has
+// exactly one match: same size and fingerprint, or for rows hashed before fingerprints
+// existed, same size and file name. When several files match, one with the old file
+// name wins; otherwise the row is reported as ambiguous. A match that already has a
+// row of its own (re-added by hand) is merged into the missing row, which keeps the
+// older history and uuid.
+pub fn relink_missing(db: &Mutex<Connection>, folders: &[PathBuf]) -> Result<RelinkReport, String> {
+    let missing: Vec<MissingRow> = {
+        let conn = db.lock().map_err(|e| e.to_string())?;
+        let mut stmt = conn
+            .prepare("SELECT id, path, file_size, quick_hash FROM videos WHERE missing = 1 AND file_size IS NOT NULL")
+            .map_err(|e| e.to_string())?;
+        let rows = stmt
+            .query_map([], |row| Ok(MissingRow { id: row.get(0)?, path: row.get(1)?, size: row.get(2)?, quick_hash: row.get(3)? }))
+            .map_err(|e| e.to_string())?;
+        rows.collect::<rusqlite::Result<_>>().map_err(|e| e.to_string())?
+    };
+    let mut report = RelinkReport { relinked: Vec::new(), ambiguous: 0, unmatched: 0 };
+    if missing.is_empty() {
+        return Ok(report);
+    }
+
+    // only files with the size of a missing row can match, so only those get hashed
+    let sizes: HashSet<i64> = missing.iter().map(|m| m.size).collect();
+    let no_cancel = AtomicBool::new(false);
+    let mut candidates: Vec<FileEntry> = Vec::new();
+    for folder in folders {
+        walk(folder, &no_cancel, |entry| {
+            if let Ok(entry) = entry {
+                if sizes.contains(&entry.size) {
+                    candidates.push(entry);
+                }
+            }
+        });
+    }
+    let candidates: Vec<FileEntry> = candidates.into_iter().map(FileEntry::with_quick_hash).collect();
+
+    let file_name = |p: &str| Path::new(p).file_name().map(|n| n.to_os_string());
+    let mut picks: Vec<(&MissingRow, usize)> = Vec::new();
+    for row in &missing {
+        let matches: Vec<usize> = candidates
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| {
+                c.size == row.size
+                    && c.path != row.path
+                    && match &row.quick_hash {
+                        Some(hash) => c.quick_hash.as_ref() == Some(hash),
+                        None => file_name(&c.path) == file_name(&row.path),
+                    }
+            })
+            .map(|(i, _)| i)
+            .collect();
+        let pick = match matches.len() {
+            0 => None,
+            1 => Some(matches[0]),
+            _ => {
+                let same_name: Vec<usize> =
+                    matches.into_iter().filter(|&i| file_name(&candidates[i].path) == file_name(&row.path)).collect();
+                if same_name.len() == 1 {
+                    Some(same_name[0])
+                } else {
+                    report.ambiguous += 1;
+                    continue;
+                }
+            }
+        };
+        match pick {
+            Some(i) => picks.push((row, i)),
+            None => report.unmatched += 1,
+        }
+    }
+
+    // two missing rows claiming the same file are copies of each other; leave both
+    let mut claims: HashMap<usize, u32> = HashMap::new();
+    for (_, i) in &picks {
+        *claims.entry(*i).or_default() += 1;
+    }
+
+    let mut conn = db.lock().map_err(|e| e.to_string())?;
+    let roots = load_roots(&conn).map_err(|e| e.to_string())?;
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    for (row, i) in picks {
+        if claims[&i] > 1 {
+            report.ambiguous += 1;
+            continue;
+        }
+        let entry = &candidates[i];
+        let existing: Option<i64> = tx
+            .query_row("SELECT id FROM videos WHERE path = ?1", params![entry.path], |r| r.get(0))
+            .optional()
+            .map_err(|e| e.to_string())?;
+        if let Some(existing) = existing {
+            merge_rows(&tx, row.id, existing).map_err(|e| e.to_string())?;
+        }
+        relink_row(&tx, row.id, None, entry).map_err(|e| e.to_string())?;
+        tx.execute(
+            "UPDATE videos SET library_id = ?1 WHERE id = ?2",
+            params![library_for(&roots, Path::new(&entry.path)), row.id],
+        )
+        .map_err(|e| e.to_string())?;
+        report.relinked.push(RelinkedVideo { id: row.id, old_path: row.path.clone(), new_path: entry.path.clone() });
+    }
+    tx.commit().map_err(|e| e.to_string())?;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn db_with_library(root: &Path) -> Mutex<Connection> {
+        let mut conn = Connection::open_in_memory().unwrap();
+        crate::db::schema::init_schema(&mut conn).unwrap();
+        conn.execute("INSERT INTO libraries (id, path) VALUES (1, ?1)", params![root.to_string_lossy()]).unwrap();
+        Mutex::new(conn)
+    }
+
+    // (path below the root, missing) of every row
+    fn rows(db: &Mutex<Connection>, root: &Path) -> Vec<(String, bool)> {
+        let conn = db.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT path, missing FROM videos ORDER BY path").unwrap();
+        let rows = stmt
+            .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get(1)?)))
+            .unwrap()
+            .map(|r| r.map(|(path, missing)| (path.strip_prefix(root.to_str().unwrap()).unwrap_or(&path).to_string(), missing)));
+        rows.collect::<rusqlite::Result<_>>().unwrap()
+    }
+
+    fn scan(db: &Mutex<Connection>, root: &Path) -> ScanProgress {
+        scan_library(db, 1, root, &AtomicBool::new(false), |_| {}).unwrap()
+    }
+
+    #[test]
+    fn walk_reports_folders_it_cannot_list() {
+        let dir = temp_dir("library-walk");
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::create_dir_all(dir.join("other")).unwrap();
+        for file in ["a.mp4", "notes.txt", "sub/b.mkv", "other/c.MP3"] {
+            fs::write(dir.join(file), file).unwrap();
+        }
+        let mut found = Vec::new();
+        let mut errors = 0;
+        let unreadable = walk(&dir, &AtomicBool::new(false), |entry| match entry {
+            Ok(entry) => {
+                // the root is listed before any subfolder: swap "sub" for a file before it is read
+                if found.is_empty() {
+                    fs::remove_dir_all(dir.join("sub")).unwrap();
+                    fs::write(dir.join("sub"), "").unwrap();
+                }
+                found.push(entry.path);
+            }
+            Err(_) => errors += 1,
+        });
+        found.sort();
+        assert_eq!(found, vec![dir.join("a.mp4").to_string_lossy(), dir.join("other/c.MP3").to_string_lossy()]);
+        assert_eq!((unreadable, errors), (vec![dir.join("sub")], 1));
+
+        assert_eq!(walk(&dir.join("gone"), &AtomicBool::new(false), |_| {}), vec![dir.join("gone")]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn scan_adds_updates_relinks_and_flags_missing_files() {
+        let dir = temp_dir("library-scan");
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        for file in ["a.mp4", "b.mkv", "sub/c.mp4"] {
+            fs::write(dir.join(file), file.repeat(100)).unwrap();
+        }
+        let db = db_with_library(&dir);
+        let progress = scan(&db, &dir);
+        assert_eq!((progress.phase, progress.files_seen, progress.added, progress.missing), (ScanPhase::Finished, 3, 3, 0));
+        let (title, library_id, probed): (String, i64, bool) = db
+            .lock()
+            .unwrap()
+            .query_row("SELECT title, library_id, probed_at IS NOT NULL FROM videos WHERE path LIKE '%a.mp4'", [], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })
+            .unwrap();
+        assert_eq!((title.as_str(), library_id, probed), ("a.mp4", 1, true));
+
+        let progress = scan(&db, &dir);
+        assert_eq!((progress.added, progress.updated, progress.skipped), (0, 0, 3));
+
+        // a changed file keeps its row but loses the full hash
+        db.lock().unwrap().execute("UPDATE videos SET full_hash = 'old', watch_count = 2", []).unwrap();
+        fs::write(dir.join("a.mp4"), "changed").unwrap();
+        fs::remove_file(dir.join("b.mkv")).unwrap();
+        fs::create_dir_all(dir.join("sub/moved")).unwrap();
+        fs::rename(dir.join("sub/c.mp4"), dir.join("sub/moved/c.mp4")).unwrap();
+        let progress = scan(&db, &dir);
+        assert_eq!((progress.added, progress.updated, progress.relinked, progress.missing), (0, 1, 1, 1));
+        assert_eq!(
+            rows(&db, &dir),
+            vec![("/a.mp4".to_string(), false), ("/b.mkv".to_string(), true), ("/sub/moved/c.mp4".to_string(), false)]
+        );
+        let kept: Vec<(Option<String>, i64)> = {
+            let conn = db.lock().unwrap();
+            let mut stmt = conn.prepare("SELECT full_hash, watch_count FROM videos ORDER BY path").unwrap();
+            let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?))).unwrap();
+            rows.collect::<rusqlite::Result<_>>().unwrap()
+        };
+        assert_eq!(kept, vec![(None, 2), (Some("old".to_string()), 2), (Some("old".to_string()), 2)]);
+
+        // the file coming back clears the flag
+        fs::write(dir.join("b.mkv"), "b.mkv".repeat(100)).unwrap();
+        let progress = scan(&db, &dir);
+        assert_eq!((progress.added, progress.updated, progress.missing), (0, 1, 0));
+        assert!(rows(&db, &dir).iter().all(|(_, missing)| !missing));
+
+        // an unavailable root is an error, not a library full of missing files
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(scan_library(&db, 1, &dir, &AtomicBool::new(false), |_| {}).is_err());
+        assert!(rows(&db, &dir).iter().all(|(_, missing)| !missing));
+    }
+
+    #[test]
+    fn relink_finds_moved_files_by_size_and_hash() {
+        let dir = temp_dir("library-relink");
+        for file in ["a", "b", "c", "e", "f", "g", "h", "i"] {
+            // h and i are copies of each other
+            let content = if file == "i" { "h" } else { file };
+            fs::write(dir.join(format!("{}.mp4", file)), content.repeat(100)).unwrap();
+        }
+        let db = db_with_library(&dir);
+        assert_eq!(scan(&db, &dir).added, 8);
+        db.lock().unwrap().execute("UPDATE videos SET watch_count = 2", []).unwrap();
+        db.lock().unwrap().execute("UPDATE videos SET quick_hash = NULL WHERE path LIKE '%f.mp4'", []).unwrap();
+
+        fs::create_dir_all(dir.join("moved")).unwrap();
+        let moves = [("a", "renamed"), ("b", "b"), ("f", "f"), ("g", "g"), ("h", "h2")];
+        for (from, to) in moves {
+            fs::rename(dir.join(format!("{}.mp4", from)), dir.join(format!("moved/{}.mp4", to))).unwrap();
+        }
+        // copies: a second b picked by name, two of c with other names, a file of f's size
+        fs::write(dir.join("moved/copy.mp4"), "b".repeat(100)).unwrap();
+        fs::write(dir.join("x.mp4"), "c".repeat(100)).unwrap();
+        fs::rename(dir.join("c.mp4"), dir.join("y.mp4")).unwrap();
+        fs::write(dir.join("moved/other.mp4"), "o".repeat(100)).unwrap();
+        for file in ["e.mp4", "i.mp4"] {
+            fs::remove_file(dir.join(file)).unwrap();
+        }
+        {
+            let conn = db.lock().unwrap();
+            for file in ["a", "b", "c", "e", "f", "g", "h", "i"] {
+                mark_missing(&conn, &dir.join(format!("{}.mp4", file)).to_string_lossy()).unwrap();
+            }
+            // g was added again by hand under its new path
+            conn.execute(
+                "INSERT INTO videos (uuid, path, watch_count) VALUES ('again', ?1, 1)",
+                params![dir.join("moved/g.mp4").to_string_lossy()],
+            )
+            .unwrap();
+        }
+
+        let report = relink_missing(&db, std::slice::from_ref(&dir)).unwrap();
+        let mut relinked: Vec<_> = report
+            .relinked
+            .iter()
+            .map(|r| (r.old_path[dir.to_string_lossy().len()..].to_string(), r.new_path[dir.to_string_lossy().len()..].to_string()))
+            .collect();
+        relinked.sort();
+        let expected = [("/a.mp4", "/moved/renamed.mp4"), ("/b.mp4", "/moved/b.mp4"), ("/f.mp4", "/moved/f.mp4"), ("/g.mp4", "/moved/g.mp4")];
+        assert_eq!(relinked, expected.map(|(from, to)| (from.to_string(), to.to_string())));
+        // c has two equal matches, h and i claim the same file; e is gone
+        assert_eq!((report.ambiguous, report.unmatched), (3, 1));
+
+        assert_eq!(
+            rows(&db, &dir),
+            vec![
+                ("/c.mp4".to_string(), true),
+                ("/e.mp4".to_string(), true),
+                ("/h.mp4".to_string(), true),
+                ("/i.mp4".to_string(), true),
+                ("/moved/b.mp4".to_string(), false),
+                ("/moved/f.mp4".to_string(), false),
+                ("/moved/g.mp4".to_string(), false),
+                ("/moved/renamed.mp4".to_string(), false),
+            ]
+        );
+        let (uuid, watch_count): (String, i64) = db
+            .lock()
+            .unwrap()
+            .query_row("SELECT uuid, watch_count FROM videos WHERE path LIKE '%moved/g.mp4'", [], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap();
+        assert_ne!(uuid, "again");
+        assert_eq!(watch_count, 3);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn merging_adds_up_history_and_keeps_what_either_row_has() {
+        let db = db_with_library(Path::new("/lib"));
+        let conn = db.lock().unwrap();
+        conn.execute_batch(
+            "INSERT INTO videos (id, uuid, path, watch_count, last_watched, rating, favorite, position, position_updated_at) VALUES
+                (1, 'keep', '/lib/a.mp4', 2, '2024-01-01', NULL, 0, 10, '2024-01-01'),
+                (2, 'drop', '/lib/b.mp4', 3, '2024-02-01', 4, 1, 50, '2024-02-01'),
+                (3, 'rated', '/lib/c.mp4', NULL, NULL, 5, NULL, NULL, NULL),
+                (4, 'unseen', '/lib/d.mp4', NULL, NULL, 1, NULL, NULL, NULL);
+             INSERT INTO tags (id, name) VALUES (1, 'one'), (2, 'two');
+             INSERT INTO video_tags (video_id, tag_id) VALUES (1, 1), (2, 1), (2, 2);
+             INSERT INTO subtitle_timings (video_id, offset_ms) VALUES (1, 100), (2, 200), (4, 300);",
+        )
+        .unwrap();
+        type History = (i64, Option<String>, Option<f64>, bool, Option<f64>, Option<String>, i64);
+        let history = |id: i64| -> History {
+            conn.query_row(
+                "SELECT COALESCE(watch_count, 0), last_watched, rating, COALESCE(favorite, 0), position, position_updated_at,
+                    (SELECT offset_ms FROM subtitle_timings WHERE video_id = ?1)
+                 FROM videos WHERE id = ?1",
+                params![id],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?, row.get(5)?, row.get(6)?)),
+            )
+            .unwrap()
+        };
+
+        merge_rows(&conn, 1, 2).unwrap();
+        let date = |d: &str| Some(d.to_string());
+        assert_eq!(history(1), (5, date("2024-02-01"), Some(4.0), true, Some(50.0), date("2024-02-01"), 100));
+        let tags: i64 = conn.query_row("SELECT COUNT(*) FROM video_tags WHERE video_id = 1", [], |row| row.get(0)).unwrap();
+        assert_eq!(tags, 2);
+
+        // the kept row's rating wins; never watched stays NULL instead of ''
+        merge_rows(&conn, 3, 4).unwrap();
+        assert_eq!(history(3), (0, None, Some(5.0), false, None, None, 300));
+        let left: i64 = conn.query_row("SELECT COUNT(*) FROM videos", [], |row| row.get(0)).unwrap();
+        assert_eq!(left, 2);
+    }
+
+    #[test]
+    fn merged_playlist_entries_point_at_the_kept_row() {
+        let db = db_with_library(Path::new("/lib"));
+        let conn = db.lock().unwrap();
+        conn.execute_batch(
+            "INSERT INTO videos (id, uuid, path) VALUES (1, 'keep', '/lib/keep.mp4'), (2, 'drop', '/lib/copy.mp4');
+             INSERT INTO playlists (id, name) VALUES (1, 'p');
+             INSERT INTO playlist_items (playlist_id, video_id, video_path, position) VALUES (1, 2, '/lib/copy.mp4', 0), (1, 1, '/lib/keep.mp4', 1);",
+        )
+        .unwrap();
+        merge_rows(&conn, 1, 2).unwrap();
+        let mut stmt = conn.prepare("SELECT video_id, video_path FROM playlist_items ORDER BY position").unwrap();
+        let items: Vec<(i64, String)> =
+            stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?))).unwrap().collect::<rusqlite::Result<_>>().unwrap();
+        assert_eq!(items, vec![(1, "/lib/keep.mp4".to_string()), (1, "/lib/keep.mp4".to_string())]);
+
+        // a path that later shows up again as a new row must not pick the entry back up
+        conn.execute("INSERT INTO videos (uuid, path) VALUES ('new', '/lib/copy.mp4')", []).unwrap();
+        let moved: i64 = conn.query_row("SELECT COUNT(*) FROM playlist_items WHERE video_id = 1", [], |row| row.get(0)).unwrap();
+        assert_eq!(moved, 2);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn scan_leaves_rows_below_unreadable_folders_alone() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = temp_dir("library-scan-unreadable");
+        fs::create_dir_all(dir.join("locked")).unwrap();
+        fs::write(dir.join("a.mp4"), "a").unwrap();
+        fs::write(dir.join("locked/b.mp4"), "b").unwrap();
+        let db = db_with_library(&dir);
+        assert_eq!(scan(&db, &dir).added, 2);
+
+        fs::set_permissions(dir.join("locked"), fs::Permissions::from_mode(0o000)).unwrap();
+        // permissions do not apply to root; `walk_reports_folders_it_cannot_list` covers the walk then
+        if fs::read_dir(dir.join("locked")).is_err() {
+            let progress = scan(&db, &dir);
+            assert_eq!((progress.errors, progress.missing), (1, 0));
+            assert!(rows(&db, &dir).iter().all(|(_, missing)| !missing));
+        }
+        fs::set_permissions(dir.join("locked"), fs::Permissions::from_mode(0o755)).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,425 @@
+// Whether the webview's <video> element can play a file, judged from the probe result
+// against a support matrix of containers and codec families (see `probe::codec_family`).
+// The default matrix is what WebKitGTK decodes with the GStreamer plugins we depend on;
+// systems with more plugins (e.g. gst-libav for HEVC and AC-3) can widen it through
+// `set_codec_support`.
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+use crate::probe::{self, MediaInfo, StreamKind};
+
+// Setting key holding the support matrix as JSON; the default applies while it is unset.
+pub const CODEC_SUPPORT_KEY: &str = "playback_codec_support";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CodecSupport {
+    pub containers: Vec<String>,
+    // codec families or any codec name the probes report ("avc1", "A_AAC")
+    pub video_codecs: Vec<String>,
+    pub audio_codecs: Vec<String>,
+}
+
+impl Default for CodecSupport {
+    fn default() -> Self {
+        let list = |items: &[&str]| items.iter().map(|s| s.to_string()).collect();
+        CodecSupport {
+            containers: list(&["mp4", "mov", "webm", "mp3"]),
+            video_codecs: list(&["h264", "vp8", "vp9", "av1"]),
+            audio_codecs: list(&["aac", "mp3", "opus", "vorbis", "flac"]),
+        }
+    }
+}
+
+impl CodecSupport {
+    fn container(&self, container: &str) -> bool {
+        self.containers.iter().any(|c| c.trim().eq_ignore_ascii_case(container))
+    }
+
+    fn codec(&self, kind: StreamKind, family: &str) -> bool {
+        let list = match kind {
+            StreamKind::Video => &self.video_codecs,
+            StreamKind::Audio => &self.audio_codecs,
+            _ => return false,
+        };
+        list.iter().any(|c| probe::codec_family(c) == family)
+    }
+}
+
+// The stored matrix, or the default when it is unset or cannot be parsed.
+pub fn codec_support(conn: &Connection) -> CodecSupport {
+    let value: Option<String> = conn
+        .query_row("SELECT value FROM settings WHERE key = ?1", params![CODEC_SUPPORT_KEY], |row| row.get(0))
+        .optional()
+        .ok()
+        .flatten();
+    value.and_then(|v| serde_json::from_str(&v).ok()).unwrap_or_default()
+}
+
+// Stores the matrix, or goes back to the default with None.
+pub fn save_codec_support(conn: &Connection, support: Option<&CodecSupport>) -> rusqlite::Result<()> {
+    match support {
+        Some(support) => {
+            let json = serde_json::to_string(support).map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+            conn.execute(
+                "INSERT INTO settings (key, value) VALUES (?1, ?2) ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+                params![CODEC_SUPPORT_KEY, json],
+            )?;
+        }
+        None => {
+            conn.execute("DELETE FROM settings WHERE key = ?1", params![CODEC_SUPPORT_KEY])?;
+        }
+    }
+    Ok(())
+}
+
+// Recomputes `videos.playable_in_webview` for every probed row, after the matrix changed.
+// Returns the number of rows whose flag changed.
+pub fn refresh_playable(conn: &Connection, support: &CodecSupport) -> rusqlite::Result<usize> {
+    let rows: Vec<(i64, String)> = {
+        let mut stmt = conn.prepare("SELECT id, media_info FROM videos WHERE media_info IS NOT NULL")?;
+        let iter = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        iter.collect::<rusqlite::Result<_>>()?
+    };
+    let mut changed = 0;
+    for (id, json) in rows {
+        let Ok(info) = serde_json::from_str::<MediaInfo>(&json) else { continue };
+        let playable = check(&info, support).playable;
+        changed += conn.execute(
+            "UPDATE videos SET playable_in_webview = ?1 WHERE id = ?2 AND playable_in_webview IS NOT ?1",
+            params![playable, id],
+        )?;
+    }
+    Ok(changed)
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum StreamStatus {
+    Supported,
+    Unsupported,
+    // subtitles and data streams, which do not decide whether the file plays
+    Ignored,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StreamPlayability {
+    pub id: u64,
+    pub kind: StreamKind,
+    pub codec: String,
+    // the name the support matrix uses, see `probe::codec_family`
+    pub family: String,
+    pub status: StreamStatus,
+    // the video and audio stream the player picks, see `MediaInfo::selected_streams`
+    pub selected: bool,
+    pub reason: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Playability {
+    // None while the file was not probed or when its format is unknown
+    pub playable: Option<bool>,
+    // one or more sentences for the UI, e.g. "HEVC (H.265) video is not supported by the player."
+    pub reason: String,
+    pub container: Option<String>,
+    pub container_supported: Option<bool>,
+    pub streams: Vec<StreamPlayability>,
+}
+
+impl Playability {
+    fn unknown(reason: &str) -> Playability {
+        Playability { playable: None, reason: reason.to_string(), container: None, container_supported: None, streams: Vec::new() }
+    }
+
+    // What keeps the file from playing as (kind, family) pairs, kind being "container",
+    // "video" or "audio"; used to count the causes in the library report.
+    pub fn blockers(&self) -> Vec<(&'static str, String)> {
+        let mut out = Vec::new();
+        if let (Some(container), Some(false)) = (&self.container, self.container_supported) {
+            out.push(("container", container.clone()));
+        }
+        for stream in self.streams.iter().filter(|s| s.selected && s.status == StreamStatus::Unsupported) {
+            let kind = if stream.kind == StreamKind::Video { "video" } else { "audio" };
+            out.push((kind, stream.family.clone()));
+        }
+        out
+    }
+}
+
+// The file plays when the container and the streams the player picks are supported.
+// Unsupported alternative audio tracks are reported but do not block playback.
+pub fn check(info: &MediaInfo, support: &CodecSupport) -> Playability {
+    let (video, audio) = info.selected_streams();
+    let container_supported = support.container(&info.container);
+    let mut problems = Vec::new();
+    if !container_supported {
+        problems.push(format!("{} files are not supported by the player.", container_name(&info.container)));
+    }
+
+    let mut streams = Vec::new();
+    for stream in &info.streams {
+        let family = probe::codec_family(&stream.codec);
+        let selected = [video, audio].into_iter().flatten().any(|s| std::ptr::eq(s, stream));
+        let kind_name = match stream.kind {
+            StreamKind::Video => "video",
+            StreamKind::Audio => "audio",
+            _ => "",
+        };
+        let (status, reason) = match stream.kind {
+            StreamKind::Video | StreamKind::Audio if support.codec(stream.kind, &family) => {
+                (StreamStatus::Supported, format!("{} {} is supported.", codec_name(&family), kind_name))
+            }
+            StreamKind::Video | StreamKind::Audio => {
+                let sentence = format!("{} {} is not supported by the player.", codec_name(&family), kind_name);
+                if selected {
+                    problems.push(sentence.clone());
+                    (StreamStatus::Unsupported, sentence)
+                } else {
+                    (StreamStatus::Unsupported, format!("{} It is not the track played by default.", sentence))
+                }
+            }
+            StreamKind::Subtitle => (StreamStatus::Ignored, "Embedded subtitles are not shown by the player.".to_string()),
+            StreamKind::Other => (StreamStatus::Ignored, "Not an audio or video stream.".to_string()),
+        };
+        streams.push(StreamPlayability { id: stream.id, kind: stream.kind, codec: stream.codec.clone(), family, status, selected, reason });
+    }
+
+    let playable = problems.is_empty();
+    let reason = if playable {
+        let mut parts: Vec<String> = [video, audio]
+            .into_iter()
+            .flatten()
+            .map(|s| {
+                let kind_name = if s.kind == StreamKind::Video { "video" } else { "audio" };
+                format!("{} {}", codec_name(&probe::codec_family(&s.codec)), kind_name)
+            })
+            .collect();
+        if parts.is_empty() {
+            parts.push("no audio or video stream".to_string());
+        }
+        format!("Plays in the player: {} in {}.", parts.join(" and "), container_name(&info.container))
+    } else {
+        problems.join(" ")
+    };
+    Playability {
+        playable: Some(playable),
+        reason,
+        container: Some(info.container.clone()),
+        container_supported: Some(container_supported),
+        streams,
+    }
+}
+
+// Checks the probe result as stored in `videos.media_info`.
+pub fn check_stored(media_info: Option<&str>, probed: bool, support: &CodecSupport) -> serde_json::Result<Playability> {
+    match media_info {
+        Some(json) => Ok(check(&serde_json::from_str(json)?, support)),
+        None if probed => Ok(Playability::unknown("The file format is not recognised, so it most likely does not play.")),
+        None => Ok(Playability::unknown("The file has not been probed yet.")),
+    }
+}
+
+// Readable name of a codec family; unknown families are shown as they are.
+pub fn codec_name(family: &str) -> String {
+    let name = match family {
+        "h264" => "H.264",
+        "hevc" => "HEVC (H.265)",
+        "vvc" => "VVC (H.266)",
+        "vp8" => "VP8",
+        "vp9" => "VP9",
+        "av1" => "AV1",
+        "mpeg4" => "MPEG-4 Part 2 (DivX, Xvid)",
+        "mpeg1video" => "MPEG-1",
+        "mpeg2video" => "MPEG-2",
+        "wmv" => "WMV",
+        "vc1" => "VC-1",
+        "aac" => "AAC",
+        "mp3" => "MP3",
+        "mp2" => "MPEG Layer II",
+        "mp1" => "MPEG Layer I",
+        "opus" => "Opus",
+        "vorbis" => "Vorbis",
+        "flac" => "FLAC",
+        "alac" => "ALAC",
+        "ac3" => "AC-3 (Dolby Digital)",
+        "eac3" => "E-AC-3 (Dolby Digital Plus)",
+        "dts" => "DTS",
+        "truehd" => "Dolby TrueHD",
+        "wma" => "WMA",
+        "pcm" => "PCM",
+        other => return other.to_string(),
+    };
+    name.to_string()
+}
+
+// Readable name of a container as the probes name it.
+pub fn container_name(container: &str) -> String {
+    let name = match container {
+        "mp4" => "MP4",
+        "mov" => "QuickTime",
+        "mkv" => "Matroska",
+        "webm" => "WebM",
+        "avi" => "AVI",
+        "flv" => "Flash Video",
+        "asf" => "ASF (WMV, WMA)",
+        "ts" => "MPEG transport stream",
+        "m2ts" => "Blu-ray transport stream",
+        "mp3" => "MP3",
+        other => return other.to_string(),
+    };
+    name.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::probe::StreamInfo;
+
+    fn stream(id: u64, kind: StreamKind, codec: &str, default: bool) -> StreamInfo {
+        StreamInfo { id, kind, codec: codec.to_string(), default, ..Default::default() }
+    }
+
+    fn info(container: &str, streams: Vec<StreamInfo>) -> MediaInfo {
+        MediaInfo { container: container.to_string(), streams, ..Default::default() }
+    }
+
+    fn av(container: &str, video: &str, audio: &str) -> MediaInfo {
+        info(container, vec![stream(1, StreamKind::Video, video, true), stream(2, StreamKind::Audio, audio, true)])
+    }
+
+    #[test]
+    fn default_matrix() {
+        let support = CodecSupport::default();
+        let cases = [
+            (av("mp4", "avc1", "mp4a.40.2"), true),
+            (av("mov", "avc1", "mp4a"), true),
+            (av("webm", "V_VP9", "A_OPUS"), true),
+            (av("mp4", "av01", "opus"), true),
+            (info("mp3", vec![stream(0, StreamKind::Audio, "mp3", true)]), true),
+            // Matroska needs the matroskademux plugin, which is not a dependency
+            (av("mkv", "V_MPEG4/ISO/AVC", "A_AAC"), false),
+            (av("mp4", "hvc1", "mp4a"), false),
+            (av("mp4", "avc1", "ac-3"), false),
+            (av("avi", "XVID", "mp3"), false),
+            (av("mp4", "xyz1", "mp4a"), false),
+        ];
+        for (info, playable) in cases {
+            assert_eq!(check(&info, &support).playable, Some(playable), "{:?}", info.streams);
+        }
+    }
+
+    #[test]
+    fn reasons_and_blockers() {
+        let support = CodecSupport::default();
+        let playable = check(&av("mp4", "avc1", "mp4a"), &support);
+        assert_eq!(playable.reason, "Plays in the player: H.264 video and AAC audio in MP4.");
+        assert!(playable.blockers().is_empty());
+        assert_eq!(check(&info("mp4", Vec::new()), &support).reason, "Plays in the player: no audio or video stream in MP4.");
+
+        let blocked = check(&av("mkv", "V_MPEGH/ISO/HEVC", "A_AAC"), &support);
+        assert_eq!(blocked.reason, "Matroska files are not supported by the player. HEVC (H.265) video is not supported by the player.");
+        assert_eq!(blocked.container_supported, Some(false));
+        assert_eq!(blocked.blockers(), vec![("container", "mkv".to_string()), ("video", "hevc".to_string())]);
+
+        // unknown codecs are named as the container names them
+        let unknown = check(&av("mp4", "avc1", "XYZ9"), &support);
+        assert_eq!(unknown.reason, "xyz9 audio is not supported by the player.");
+        assert_eq!(unknown.blockers(), vec![("audio", "xyz9".to_string())]);
+    }
+
+    #[test]
+    fn only_the_selected_tracks_decide() {
+        let support = CodecSupport::default();
+        let mut streams = vec![
+            stream(1, StreamKind::Video, "avc1", true),
+            stream(2, StreamKind::Audio, "ac-3", false),
+            stream(3, StreamKind::Audio, "mp4a", true),
+            stream(4, StreamKind::Subtitle, "tx3g", false),
+        ];
+        let result = check(&info("mp4", streams.clone()), &support);
+        assert_eq!(result.playable, Some(true));
+        let summary: Vec<_> = result.streams.iter().map(|s| (s.family.as_str(), s.status, s.selected)).collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("h264", StreamStatus::Supported, true),
+                ("ac3", StreamStatus::Unsupported, false),
+                ("aac", StreamStatus::Supported, true),
+                ("tx3g", StreamStatus::Ignored, false),
+            ]
+        );
+        assert_eq!(result.streams[1].reason, "AC-3 (Dolby Digital) audio is not supported by the player. It is not the track played by default.");
+        assert!(result.blockers().is_empty());
+
+        // the default track is the one played
+        streams[1].default = true;
+        streams[2].default = false;
+        let result = check(&info("mp4", streams), &support);
+        assert_eq!(result.playable, Some(false));
+        assert_eq!(result.blockers(), vec![("audio", "ac3".to_string())]);
+    }
+
+    #[test]
+    fn matrix_entries_are_matched_by_family() {
+        let support = CodecSupport {
+            containers: vec![" MKV ".to_string()],
+            video_codecs: vec!["hvc1".to_string()],
+            audio_codecs: vec!["ac3".to_string(), "xyz9".to_string()],
+        };
+        assert_eq!(check(&av("mkv", "V_MPEGH/ISO/HEVC", "A_AC3"), &support).playable, Some(true));
+        assert_eq!(check(&av("mkv", "hev1", "XYZ9"), &support).playable, Some(true));
+        assert_eq!(check(&av("mkv", "avc1", "ac-3"), &support).playable, Some(false));
+        assert_eq!(check(&av("mp4", "hvc1", "ac-3"), &support).playable, Some(false));
+    }
+
+    #[test]
+    fn stored_probe_results() {
+        let support = CodecSupport::default();
+        let json = serde_json::to_string(&av("mp4", "avc1", "mp4a")).unwrap();
+        assert_eq!(check_stored(Some(&json), true, &support).unwrap().playable, Some(true));
+        let unknown = check_stored(None, true, &support).unwrap();
+        assert_eq!((unknown.playable, unknown.reason.as_str()), (None, "The file format is not recognised, so it most likely does not play."));
+        assert_eq!(check_stored(None, false, &support).unwrap().reason, "The file has not been probed yet.");
+        assert!(check_stored(Some("{"), true, &support).is_err());
+    }
+
+    #[test]
+    fn refresh_follows_the_stored_matrix() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        crate::db::schema::init_schema(&mut conn).unwrap();
+        let files = [
+            ("/a.mkv", Some(serde_json::to_string(&av("mkv", "V_MPEG4/ISO/AVC", "A_AAC")).unwrap())),
+            ("/b.mp4", Some(serde_json::to_string(&av("mp4", "hvc1", "mp4a")).unwrap())),
+            ("/c.mp4", Some(serde_json::to_string(&av("mp4", "avc1", "mp4a")).unwrap())),
+            ("/d.mp4", Some("not json".to_string())),
+            ("/e.mp4", None),
+        ];
+        for (path, media_info) in &files {
+            conn.execute("INSERT INTO videos (uuid, path, media_info) VALUES (?1, ?1, ?2)", params![path, media_info]).unwrap();
+        }
+        let flags = |conn: &Connection| -> Vec<Option<bool>> {
+            let mut stmt = conn.prepare("SELECT playable_in_webview FROM videos ORDER BY path").unwrap();
+            let rows = stmt.query_map([], |row| row.get(0)).unwrap();
+            rows.collect::<rusqlite::Result<_>>().unwrap()
+        };
+
+        assert_eq!(refresh_playable(&conn, &codec_support(&conn)).unwrap(), 3);
+        assert_eq!(flags(&conn), vec![Some(false), Some(false), Some(true), None, None]);
+        assert_eq!(refresh_playable(&conn, &codec_support(&conn)).unwrap(), 0);
+
+        let mut wider = CodecSupport::default();
+        wider.containers.push("mkv".to_string());
+        wider.video_codecs.push("hevc".to_string());
+        save_codec_support(&conn, Some(&wider)).unwrap();
+        assert_eq!(codec_support(&conn).containers, wider.containers);
+        assert_eq!(refresh_playable(&conn, &codec_support(&conn)).unwrap(), 2);
+        assert_eq!(flags(&conn), vec![Some(true), Some(true), Some(true), None, None]);
+
+        // back to the default, also when the stored matrix cannot be read
+        conn.execute("UPDATE settings SET value = 'nope' WHERE key = ?1", params![CODEC_SUPPORT_KEY]).unwrap();
+        assert_eq!(codec_support(&conn).containers, CodecSupport::default().containers);
+        save_codec_support(&conn, None).unwrap();
+        assert_eq!(refresh_playable(&conn, &codec_support(&conn)).unwrap(), 2);
+        assert_eq!(flags(&conn), vec![Some(false), Some(false), Some(true), None, None]);
+    }
+}
@@ -0,0 +1,307 @@
+// ASF (WMV, WMA). Everything we need is in the Header Object at the start of the file:
+// play duration from the File Properties, one Stream Properties object per stream,
+// the title from the Content Description, and language and frame rate from the
+// Extended Stream Properties inside the Header Extension.
+use std::io::{Read, Seek, SeekFrom};
+
+use super::avi::{parse_bitmap_info, parse_wave_format};
+use super::{ByteReader, MediaInfo, ProbeError, StreamInfo, StreamKind};
+
+// Object GUIDs in file byte order (the first three fields are little-endian).
+const HEADER: [u8; 16] = [0x30, 0x26, 0xb2, 0x75, 0x8e, 0x66, 0xcf, 0x11, 0xa6, 0xd9, 0x00, 0xaa, 0x00, 0x62, 0xce, 0x6c];
+const FILE_PROPERTIES: [u8; 16] = [0xa1, 0xdc, 0xab, 0x8c, 0x47, 0xa9, 0xcf, 0x11, 0x8e, 0xe4, 0x00, 0xc0, 0x0c, 0x20, 0x53, 0x65];
+const STREAM_PROPERTIES: [u8; 16] = [0x91, 0x07, 0xdc, 0xb7, 0xb7, 0xa9, 0xcf, 0x11, 0x8e, 0xe6, 0x00, 0xc0, 0x0c, 0x20, 0x53, 0x65];
+const CONTENT_DESCRIPTION: [u8; 16] = [0x33, 0x26, 0xb2, 0x75, 0x8e, 0x66, 0xcf, 0x11, 0xa6, 0xd9, 0x00, 0xaa, 0x00, 0x62, 0xce, 0x6c];
+const HEADER_EXTENSION: [u8; 16] = [0xb5, 0x03, 0xbf, 0x5f, 0x2e, 0xa9, 0xcf, 0x11, 0x8e, 0xe3, 0x00, 0xc0, 0x0c, 0x20, 0x53, 0x65];
+const EXTENDED_STREAM_PROPERTIES: [u8; 16] = [0xcb, 0xa5, 0xe6, 0x14, 0x72, 0xc6, 0x32, 0x43, 0x83, 0x99, 0xa9, 0x69, 0x52, 0x06, 0x5b, 0x5a];
+const LANGUAGE_LIST: [u8; 16] = [0xa9, 0x46, 0x43, 0x7c, 0xe0, 0xef, 0xfc, 0x4b, 0xb2, 0x29, 0x39, 0x3e, 0xde, 0x41, 0x5c, 0x85];
+const AUDIO_MEDIA: [u8; 16] = [0x40, 0x9e, 0x69, 0xf8, 0x4d, 0x5b, 0xcf, 0x11, 0xa8, 0xfd, 0x00, 0x80, 0x5f, 0x5c, 0x44, 0x2b];
+const VIDEO_MEDIA: [u8; 16] = [0xc0, 0xef, 0x19, 0xbc, 0x4d, 0x5b, 0xcf, 0x11, 0xa8, 0xfd, 0x00, 0x80, 0x5f, 0x5c, 0x44, 0x2b];
+
+// Upper bound for the Header Object we load into memory.
+const MAX_HEADER_SIZE: u64 = 16 * 1024 * 1024;
+
+pub fn is_asf(head: &[u8]) -> bool {
+    head.starts_with(&HEADER)
+}
+
+pub fn probe<R: Read + Seek>(r: &mut R) -> Result<MediaInfo, ProbeError> {
+    r.seek(SeekFrom::Start(0))?;
+    let mut header = [0u8; 30];
+    r.read_exact(&mut header)?;
+    let size = u64::from_le_bytes([header[16], header[17], header[18], header[19], header[20], header[21], header[22], header[23]]);
+    if !(30..=MAX_HEADER_SIZE).contains(&size) {
+        return Err(ProbeError::Malformed("header object size"));
+    }
+    let mut body = vec![0u8; size as usize - 30];
+    r.read_exact(&mut body)?;
+
+    let mut info = MediaInfo { container: "asf".to_string(), ..Default::default() };
+    let mut extended = Vec::new();
+    let mut languages = Vec::new();
+    for (guid, data) in objects(&body) {
+        match guid {
+            FILE_PROPERTIES => info.duration = parse_file_properties(data)?,
+            STREAM_PROPERTIES => {
+                if let Some(stream) = parse_stream_properties(data)? {
+                    info.streams.push(stream);
+                }
+            }
+            CONTENT_DESCRIPTION => info.title = parse_title(data)?,
+            // reserved GUID, reserved u16 and the data size come before the nested objects
+            HEADER_EXTENSION if data.len() >= 22 => {
+                for (guid, data) in objects(&data[22..]) {
+                    match guid {
+                        EXTENDED_STREAM_PROPERTIES => extended.push(data),
+                        LANGUAGE_LIST => languages = parse_languages(data)?,
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    for data in extended {
+        parse_extended_stream_properties(data, &languages, &mut info.streams)?;
+    }
+    Ok(info)
+}
+
+// (GUID, data) pairs of the objects in `data`.
+fn objects(data: &[u8]) -> Vec<([u8; 16], &[u8])> {
+    let mut out = Vec::new();
+    let mut r = ByteReader::new(data);
+    while r.remaining() >= 24 {
+        let Ok(guid) = r.bytes(16) else { break };
+        let Ok(size) = r.u64_le() else { break };
+        if size < 24 {
+            break;
+        }
+        let Ok(body) = r.bytes((size as usize - 24).min(r.remaining())) else { break };
+        let mut id = [0u8; 16];
+        id.copy_from_slice(guid);
+        out.push((id, body));
+    }
+    out
+}
+
+// Play duration minus the preroll, which the timestamps are offset by.
+fn parse_file_properties(data: &[u8]) -> Result<Option<f64>, ProbeError> {
+    let mut r = ByteReader::new(data);
+    // file id, file size, creation date, data packet count
+    r.skip(16 + 8 + 8 + 8)?;
+    let play_duration = r.u64_le()?;
+    r.skip(8)?;
+    let preroll = r.u64_le()?;
+    let flags = r.u32_le()?;
+    // broadcast files leave the durations unset
+    if flags & 0x01 != 0 || play_duration == 0 {
+        return Ok(None);
+    }
+    let seconds = play_duration as f64 / 10_000_000.0 - preroll as f64 / 1000.0;
+    Ok(Some(seconds).filter(|s| *s > 0.0))
+}
+
+fn parse_stream_properties(data: &[u8]) -> Result<Option<StreamInfo>, ProbeError> {
+    let mut r = ByteReader::new(data);
+    let stream_type = r.bytes(16)?;
+    // error correction type, time offset
+    r.skip(16 + 8)?;
+    let type_data_len = r.u32_le()? as usize;
+    r.skip(4)?;
+    let flags = r.u16_le()?;
+    r.skip(4)?;
+    let type_data = r.bytes(type_data_len)?;
+
+    let mut stream = StreamInfo { id: (flags & 0x7f) as u64, default: true, ..Default::default() };
+    if stream_type == AUDIO_MEDIA {
+        stream.kind = StreamKind::Audio;
+        parse_wave_format(type_data, &mut stream);
+    } else if stream_type == VIDEO_MEDIA {
+        stream.kind = StreamKind::Video;
+        // encoded width and height, a reserved byte and the format data size
+        if type_data.len() > 11 {
+            parse_bitmap_info(&type_data[11..], &mut stream);
+        }
+    } else {
+        // command, JFIF, degradable JPEG and binary media streams
+        return Ok(None);
+    }
+    Ok(Some(stream))
+}
+
+fn parse_title(data: &[u8]) -> Result<Option<String>, ProbeError> {
+    let mut r = ByteReader::new(data);
+    let title_len = r.u16_le()? as usize;
+    r.skip(8)?;
+    let title = utf16(r.bytes(title_len)?);
+    Ok(Some(title).filter(|t| !t.is_empty()))
+}
+
+fn parse_languages(data: &[u8]) -> Result<Vec<String>, ProbeError> {
+    let mut r = ByteReader::new(data);
+    let count = r.u16_le()?;
+    let mut out = Vec::new();
+    for _ in 0..count {
+        let len = r.u8()? as usize;
+        out.push(utf16(r.bytes(len)?));
+    }
+    Ok(out)
+}
+
+// Language and average frame duration of a stream, matched by its stream number. Streams
+// added after the header was first written carry their Stream Properties object in here.
+fn parse_extended_stream_properties(data: &[u8], languages: &[String], streams: &mut Vec<StreamInfo>) -> Result<(), ProbeError> {
+    let mut r = ByteReader::new(data);
+    // start and end time, bitrates, buffer sizes, max object size, flags
+    r.skip(8 + 8 + 4 * 6 + 4 + 4)?;
+    let number = r.u16_le()? as u64;
+    let language = r.u16_le()? as usize;
+    let frame_time = r.u64_le()?;
+
+    if !streams.iter().any(|s| s.id == number) {
+        let name_count = r.u16_le()?;
+        let extension_count = r.u16_le()?;
+        for _ in 0..name_count {
+            r.skip(2)?;
+            let len = r.u16_le()? as usize;
+            r.skip(len)?;
+        }
+        for _ in 0..extension_count {
+            r.skip(16 + 2)?;
+            let len = r.u32_le()? as usize;
+            r.skip(len)?;
+        }
+        if let Some((STREAM_PROPERTIES, body)) = objects(r.rest()).into_iter().next() {
+            if let Some(stream) = parse_stream_properties(body)? {
+                streams.push(stream);
+            }
+        }
+    }
+    let Some(stream) = streams.iter_mut().find(|s| s.id == number) else {
+        return Ok(());
+    };
+    // RFC 1766 tags like "en-us"; the unset language is stored as an empty string
+    stream.language = languages.get(language).filter(|l| !l.is_empty()).cloned();
+    if stream.kind == StreamKind::Video && frame_time > 0 {
+        stream.frame_rate = Some(10_000_000.0 / frame_time as f64);
+    }
+    Ok(())
+}
+
+fn utf16(data: &[u8]) -> String {
+    let units: Vec<u16> = data.chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]])).collect();
+    String::from_utf16_lossy(&units).trim_end_matches('\0').trim().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn object(guid: &[u8; 16], body: &[u8]) -> Vec<u8> {
+        [guid.to_vec(), (24 + body.len() as u64).to_le_bytes().to_vec(), body.to_vec()].concat()
+    }
+
+    fn utf16le(s: &str) -> Vec<u8> {
+        s.encode_utf16().chain([0]).flat_map(|u| u.to_le_bytes()).collect()
+    }
+
+    fn stream_properties(kind: &[u8; 16], number: u16, type_data: &[u8]) -> Vec<u8> {
+        let mut body = [kind.to_vec(), vec![0; 16 + 8]].concat();
+        body.extend_from_slice(&(type_data.len() as u32).to_le_bytes());
+        body.extend_from_slice(&[0; 4]);
+        body.extend_from_slice(&number.to_le_bytes());
+        body.extend_from_slice(&[0; 4]);
+        body.extend_from_slice(type_data);
+        object(&STREAM_PROPERTIES, &body)
+    }
+
+    fn wave_format(tag: u16, channels: u16, sample_rate: u32) -> Vec<u8> {
+        [tag.to_le_bytes().to_vec(), channels.to_le_bytes().to_vec(), sample_rate.to_le_bytes().to_vec(), vec![0; 10]].concat()
+    }
+
+    fn extended(number: u16, language: u16, frame_time: u64, stream: &[u8]) -> Vec<u8> {
+        let mut body = vec![0; 48];
+        body.extend_from_slice(&number.to_le_bytes());
+        body.extend_from_slice(&language.to_le_bytes());
+        body.extend_from_slice(&frame_time.to_le_bytes());
+        body.extend_from_slice(&[0; 4]);
+        body.extend_from_slice(stream);
+        object(&EXTENDED_STREAM_PROPERTIES, &body)
+    }
+
+    fn asf(objects: &[Vec<u8>]) -> Vec<u8> {
+        let body = objects.concat();
+        let mut out = [HEADER.to_vec(), (30 + body.len() as u64).to_le_bytes().to_vec()].concat();
+        out.extend_from_slice(&(objects.len() as u32).to_le_bytes());
+        out.extend_from_slice(&[1, 2]);
+        out.extend(body);
+        out
+    }
+
+    #[test]
+    fn reads_the_header_object() {
+        // 10.5 s of play duration with a 3 s preroll, seekable
+        let mut file = vec![0; 40];
+        file.extend_from_slice(&105_000_000u64.to_le_bytes());
+        file.extend_from_slice(&[0; 8]);
+        file.extend_from_slice(&3000u64.to_le_bytes());
+        file.extend_from_slice(&2u32.to_le_bytes());
+        file.extend_from_slice(&[0; 12]);
+
+        let title = utf16le("Concert");
+        let description = [(title.len() as u16).to_le_bytes().to_vec(), vec![0; 8], title].concat();
+        let mut video_type = [1280u32.to_le_bytes(), 720u32.to_le_bytes()].concat();
+        video_type.extend_from_slice(&[0, 40, 0]);
+        video_type.extend([40u32, 1280, 720, 0].iter().flat_map(|v| v.to_le_bytes()));
+        video_type.extend_from_slice(b"WMV3");
+
+        let languages = [vec![2, 0, 2, 0, 0], vec![12], utf16le("en-us")[..12].to_vec()].concat();
+        let late_audio = stream_properties(&AUDIO_MEDIA, 3, &wave_format(0x0162, 6, 48_000));
+        let nested = [object(&LANGUAGE_LIST, &languages), extended(2, 1, 333_667, &[]), extended(1, 0, 0, &[]), extended(3, 1, 0, &late_audio)].concat();
+        let extension = [vec![0; 18], (nested.len() as u32).to_le_bytes().to_vec(), nested].concat();
+        let data = asf(&[
+            object(&FILE_PROPERTIES, &file),
+            stream_properties(&AUDIO_MEDIA, 1, &wave_format(0x0161, 2, 44_100)),
+            stream_properties(&VIDEO_MEDIA, 2, &video_type),
+            object(&CONTENT_DESCRIPTION, &description),
+            object(&HEADER_EXTENSION, &extension),
+        ]);
+        let info = probe(&mut Cursor::new(data)).unwrap();
+
+        assert_eq!(info.container, "asf");
+        assert_eq!((info.duration, info.title.as_deref()), (Some(7.5), Some("Concert")));
+        let streams: Vec<_> = info.streams.iter().map(|s| (s.id, s.kind, s.codec.as_str(), s.language.as_deref())).collect();
+        assert_eq!(
+            streams,
+            vec![
+                (1, StreamKind::Audio, "wmav2", None),
+                (2, StreamKind::Video, "WMV3", Some("en-us")),
+                (3, StreamKind::Audio, "wmapro", Some("en-us")),
+            ]
+        );
+        assert_eq!((info.streams[0].channels, info.streams[0].sample_rate), (Some(2), Some(44_100)));
+        let video = &info.streams[1];
+        assert_eq!((video.width, video.height), (Some(1280), Some(720)));
+        assert!((video.frame_rate.unwrap() - 29.97).abs() < 0.01);
+        assert_eq!(info.streams[2].channels, Some(6));
+    }
+
+    #[test]
+    fn broadcast_files_have_no_duration() {
+        let mut file = vec![0; 40];
+        file.extend_from_slice(&105_000_000u64.to_le_bytes());
+        file.extend_from_slice(&[0; 16]);
+        file.extend_from_slice(&1u32.to_le_bytes());
+        let info = probe(&mut Cursor::new(asf(&[object(&FILE_PROPERTIES, &file)]))).unwrap();
+        assert_eq!(info.duration, None);
+    }
+
+    #[test]
+    fn header_size_is_checked() {
+        let mut data = asf(&[]);
+        data[16..24].copy_from_slice(&12u64.to_le_bytes());
+        assert!(matches!(probe(&mut Cursor::new(data)), Err(ProbeError::Malformed("header object size"))));
+    }
+}
@@ -0,0 +1,312 @@
+// RIFF/AVI. Stream headers live in the `hdrl` list at the start of the file; the
+// `movi` list with the frames is skipped by seeking over it. OpenDML files larger than
+// 1 GB continue in `AVIX` chunks, only their total frame count (`dmlh`) is read.
+use std::io::{Read, Seek, SeekFrom};
+
+use super::{channel_layout, ByteReader, MediaInfo, ProbeError, StreamInfo, StreamKind};
+
+// Upper bound for a header list we load into memory.
+const MAX_LIST_SIZE: u32 = 16 * 1024 * 1024;
+
+pub fn is_avi(head: &[u8]) -> bool {
+    head.len() >= 12 && &head[0..4] == b"RIFF" && matches!(&head[8..12], b"AVI " | b"AVIX")
+}
+
+pub fn probe<R: Read + Seek>(r: &mut R) -> Result<MediaInfo, ProbeError> {
+    let mut riff = [0u8; 12];
+    r.seek(SeekFrom::Start(0))?;
+    r.read_exact(&mut riff)?;
+    let riff_end = 8 + u32::from_le_bytes([riff[4], riff[5], riff[6], riff[7]]) as u64;
+    let file_len = r.seek(SeekFrom::End(0))?;
+    let end = riff_end.min(file_len);
+
+    let mut info = MediaInfo { container: "avi".to_string(), ..Default::default() };
+    let mut pos = 12u64;
+    let mut found_header = false;
+    while pos + 12 <= end {
+        r.seek(SeekFrom::Start(pos))?;
+        let mut header = [0u8; 12];
+        r.read_exact(&mut header)?;
+        let size = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+        if &header[0..4] == b"LIST" && matches!(&header[8..12], b"hdrl" | b"INFO") {
+            if !(4..=MAX_LIST_SIZE).contains(&size) {
+                return Err(ProbeError::Malformed("header list size"));
+            }
+            let mut body = vec![0u8; size as usize - 4];
+            r.read_exact(&mut body)?;
+            if &header[8..12] == b"hdrl" {
+                parse_hdrl(&body, &mut info)?;
+                found_header = true;
+            } else {
+                info.title = chunks(&body).into_iter().find(|(id, _)| id == b"INAM").map(|(_, data)| string(data)).filter(|t| !t.is_empty());
+            }
+        }
+        // chunks are padded to an even size
+        pos += 8 + size as u64 + (size & 1) as u64;
+    }
+    if !found_header {
+        return Err(ProbeError::Malformed("no hdrl list"));
+    }
+    Ok(info)
+}
+
+// (FourCC, data) pairs of a chunk list; nested lists are returned with their list type
+// as the id and their children as the data.
+fn chunks(data: &[u8]) -> Vec<([u8; 4], &[u8])> {
+    let mut out = Vec::new();
+    let mut r = ByteReader::new(data);
+    while r.remaining() >= 8 {
+        let Ok(id) = r.bytes(4) else { break };
+        let Ok(size) = r.u32_le() else { break };
+        let Ok(body) = r.bytes((size as usize).min(r.remaining())) else { break };
+        if id == b"LIST" && body.len() >= 4 {
+            out.push(([body[0], body[1], body[2], body[3]], &body[4..]));
+        } else {
+            out.push(([id[0], id[1], id[2], id[3]], body));
+        }
+        if size & 1 == 1 && r.skip(1).is_err() {
+            break;
+        }
+    }
+    out
+}
+
+fn parse_hdrl(hdrl: &[u8], info: &mut MediaInfo) -> Result<(), ProbeError> {
+    let mut micro_sec_per_frame = 0u32;
+    let mut total_frames = 0u32;
+    for (id, body) in chunks(hdrl) {
+        match &id {
+            b"avih" => {
+                let mut r = ByteReader::new(body);
+                micro_sec_per_frame = r.u32_le()?;
+                r.skip(12)?;
+                total_frames = r.u32_le()?;
+                r.skip(12)?;
+                let width = r.u32_le()?;
+                let height = r.u32_le()?;
+                if width > 0 && height > 0 {
+                    info.width = Some(width);
+                    info.height = Some(height);
+                }
+            }
+            b"strl" => {
+                if let Some(stream) = parse_strl(body, info.streams.len() as u64)? {
+                    info.streams.push(stream);
+                }
+            }
+            // OpenDML: the avih frame count only covers the first RIFF chunk
+            b"odml" => {
+                if let Some((_, dmlh)) = chunks(body).into_iter().find(|(id, _)| id == b"dmlh") {
+                    let frames = ByteReader::new(dmlh).u32_le()?;
+                    total_frames = total_frames.max(frames);
+                }
+            }
+            _ => {}
+        }
+    }
+    if micro_sec_per_frame > 0 && total_frames > 0 {
+        info.duration = Some(total_frames as f64 * micro_sec_per_frame as f64 / 1_000_000.0);
+    }
+    Ok(())
+}
+
+fn parse_strl(strl: &[u8], index: u64) -> Result<Option<StreamInfo>, ProbeError> {
+    let children = chunks(strl);
+    let Some((_, strh)) = children.iter().find(|(id, _)| id == b"strh") else {
+        return Ok(None);
+    };
+    let mut r = ByteReader::new(strh);
+    let kind = match r.bytes(4)? {
+        b"vids" => StreamKind::Video,
+        b"auds" => StreamKind::Audio,
+        b"txts" => StreamKind::Subtitle,
+        _ => StreamKind::Other,
+    };
+    let handler = r.bytes(4)?;
+    r.skip(12)?;
+    let scale = r.u32_le()?;
+    let rate = r.u32_le()?;
+    r.skip(4)?;
+    let length = r.u32_le()?;
+
+    let mut stream = StreamInfo {
+        id: index,
+        kind,
+        codec: fourcc(handler),
+        // AVI has no per-stream default flag; players take the first of each kind
+        default: true,
+        ..Default::default()
+    };
+    if scale > 0 && rate > 0 {
+        if length > 0 {
+            stream.duration = Some(length as f64 * scale as f64 / rate as f64);
+        }
+        if kind == StreamKind::Video {
+            stream.frame_rate = Some(rate as f64 / scale as f64);
+        }
+    }
+    if let Some((_, strf)) = children.iter().find(|(id, _)| id == b"strf") {
+        match kind {
+            StreamKind::Video => parse_bitmap_info(strf, &mut stream),
+            StreamKind::Audio => parse_wave_format(strf, &mut stream),
+            _ => {}
+        }
+    }
+    if let Some((_, strn)) = children.iter().find(|(id, _)| id == b"strn") {
+        stream.name = Some(string(strn)).filter(|n| !n.is_empty());
+    }
+    Ok(Some(stream))
+}
+
+// BITMAPINFOHEADER, shared with ASF: frame size and the compression FourCC.
+pub(super) fn parse_bitmap_info(data: &[u8], stream: &mut StreamInfo) {
+    let mut r = ByteReader::new(data);
+    let (Ok(_), Ok(width), Ok(height), Ok(_), Ok(compression)) = (r.u32_le(), r.u32_le(), r.u32_le(), r.skip(4), r.bytes(4)) else {
+        return;
+    };
+    // negative heights mark top-down bitmaps
+    let height = (height as i32).unsigned_abs();
+    if width > 0 && height > 0 {
+        stream.width = Some(width);
+        stream.height = Some(height);
+    }
+    stream.codec = if compression == [0, 0, 0, 0] { "rawvideo".to_string() } else { fourcc(compression) };
+}
+
+// WAVEFORMATEX, shared with ASF: codec, channel count and sample rate.
+pub(super) fn parse_wave_format(data: &[u8], stream: &mut StreamInfo) {
+    let mut r = ByteReader::new(data);
+    let (Ok(mut format_tag), Ok(channels), Ok(sample_rate)) = (r.u16_le(), r.u16_le(), r.u32_le()) else {
+        return;
+    };
+    // WAVE_FORMAT_EXTENSIBLE keeps the real tag in the first bytes of the sub-format GUID
+    if format_tag == 0xfffe && data.len() >= 26 {
+        format_tag = u16::from_le_bytes([data[24], data[25]]);
+    }
+    stream.codec = wave_format_codec(format_tag);
+    if channels > 0 {
+        stream.channels = Some(channels as u32);
+        stream.channel_layout = channel_layout(channels as u32);
+    }
+    if sample_rate > 0 {
+        stream.sample_rate = Some(sample_rate);
+    }
+}
+
+// Codec name for a WAVE format tag; unknown tags are kept as hex ("0x1234").
+fn wave_format_codec(tag: u16) -> String {
+    let name = match tag {
+        0x0001 => "pcm",
+        0x0002 => "adpcm_ms",
+        0x0003 => "pcm_f32le",
+        0x0006 => "pcm_alaw",
+        0x0007 => "pcm_mulaw",
+        0x0011 => "adpcm_ima_wav",
+        0x0050 => "mp2",
+        0x0055 => "mp3",
+        0x00ff | 0x1610 | 0x706d => "aac",
+        0x0160 => "wmav1",
+        0x0161 => "wmav2",
+        0x0162 => "wmapro",
+        0x0163 => "wmalossless",
+        0x000a => "wmavoice",
+        0x2000 => "ac3",
+        0x2001 => "dts",
+        0x674f..=0x6751 => "vorbis",
+        0xf1ac => "flac",
+        _ => return format!("0x{:04x}", tag),
+    };
+    name.to_string()
+}
+
+fn fourcc(data: &[u8]) -> String {
+    String::from_utf8_lossy(data).trim_end_matches(['\0', ' ']).to_string()
+}
+
+fn string(data: &[u8]) -> String {
+    let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).trim().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn chunk(id: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut out = id.to_vec();
+        out.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        out.extend_from_slice(body);
+        if body.len() % 2 == 1 {
+            out.push(0);
+        }
+        out
+    }
+
+    fn list(kind: &[u8; 4], children: &[Vec<u8>]) -> Vec<u8> {
+        chunk(b"LIST", &[kind.to_vec(), children.concat()].concat())
+    }
+
+    fn le32(values: &[u32]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    fn strh(kind: &[u8; 4], handler: &[u8; 4], scale: u32, rate: u32, length: u32) -> Vec<u8> {
+        let mut body = [kind.to_vec(), handler.to_vec()].concat();
+        body.extend(le32(&[0, 0, 0, scale, rate, 0, length, 0, 0, 0, 0, 0]));
+        chunk(b"strh", &body)
+    }
+
+    fn avi(hdrl: &[Vec<u8>], rest: &[Vec<u8>]) -> Vec<u8> {
+        let body = [b"AVI ".to_vec(), list(b"hdrl", hdrl), rest.concat()].concat();
+        chunk(b"RIFF", &body)
+    }
+
+    #[test]
+    fn reads_streams_from_hdrl() {
+        let avih = chunk(b"avih", &le32(&[40_000, 0, 0, 0, 250, 0, 2, 0, 1280, 720, 0, 0, 0, 0]));
+        // top-down bitmap with a negative height
+        let bitmap = [le32(&[40, 1280, (-720i32) as u32, 0]), b"H264".to_vec()].concat();
+        let video = list(b"strl", &[strh(b"vids", b"H264", 1, 25, 250), chunk(b"strf", &bitmap)]);
+        let wave = [0x55u16.to_le_bytes(), 2u16.to_le_bytes()].concat();
+        let wave = [wave, le32(&[44_100, 16_000]), vec![0; 6]].concat();
+        let audio = list(b"strl", &[strh(b"auds", &[0; 4], 1152, 44_100, 100), chunk(b"strf", &wave), chunk(b"strn", b"Commentary\0")]);
+        // OpenDML counts the frames of every RIFF chunk
+        let odml = list(b"odml", &[chunk(b"dmlh", &le32(&[500]))]);
+        let info_list = list(b"INFO", &[chunk(b"INAM", b"Holiday\0")]);
+        let data = avi(&[avih, video, audio, odml], &[chunk(b"JUNK", &[0; 3]), info_list, list(b"movi", &[chunk(b"00dc", &[0; 16])])]);
+        let info = probe(&mut Cursor::new(data)).unwrap();
+
+        assert_eq!(info.container, "avi");
+        assert_eq!(info.duration, Some(20.0));
+        assert_eq!(info.title.as_deref(), Some("Holiday"));
+        assert_eq!((info.width, info.height), (Some(1280), Some(720)));
+        let video = &info.streams[0];
+        assert_eq!((video.kind, video.codec.as_str(), video.id), (StreamKind::Video, "H264", 0));
+        assert_eq!((video.width, video.height, video.frame_rate, video.duration), (Some(1280), Some(720), Some(25.0), Some(10.0)));
+        let audio = &info.streams[1];
+        assert_eq!((audio.kind, audio.codec.as_str(), audio.id), (StreamKind::Audio, "mp3", 1));
+        assert_eq!((audio.channels, audio.sample_rate, audio.frame_rate), (Some(2), Some(44_100), None));
+        assert_eq!(audio.name.as_deref(), Some("Commentary"));
+    }
+
+    #[test]
+    fn wave_format_tags() {
+        let mut stream = StreamInfo::default();
+        let mut extensible = [0xfeu8, 0xff, 6, 0, 0x80, 0xbb, 0, 0].to_vec();
+        extensible.resize(24, 0);
+        extensible.extend_from_slice(&[0x01, 0x00]);
+        parse_wave_format(&extensible, &mut stream);
+        assert_eq!((stream.codec.as_str(), stream.channels, stream.sample_rate), ("pcm", Some(6), Some(48_000)));
+        assert_eq!(wave_format_codec(0x1234), "0x1234");
+    }
+
+    #[test]
+    fn needs_a_header_list() {
+        let data = chunk(b"RIFF", &[b"AVI ".to_vec(), list(b"movi", &[])].concat());
+        assert!(matches!(probe(&mut Cursor::new(data)), Err(ProbeError::Malformed("no hdrl list"))));
+        let mut oversized = avi(&[], &[]);
+        oversized[16..20].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(probe(&mut Cursor::new(oversized)), Err(ProbeError::Malformed("header list size"))));
+    }
+}
@@ -0,0 +1,380 @@
+// Flash Video. Most files start with an `onMetaData` script tag (AMF0) carrying the
+// duration and codec ids; the first audio and video tags are read as well, since the
+// metadata is optional and sometimes wrong. Without a usable duration in the metadata
+// the timestamp of the last tag is used, found through the trailing PreviousTagSize.
+use std::io::{Read, Seek, SeekFrom};
+
+use super::{channel_layout, read_up_to, ByteReader, MediaInfo, ProbeError, StreamInfo, StreamKind};
+
+const TAG_AUDIO: u8 = 8;
+const TAG_VIDEO: u8 = 9;
+const TAG_SCRIPT: u8 = 18;
+
+// How many tags are read from the start looking for metadata and the first frames.
+const MAX_TAGS: usize = 64;
+const MAX_SCRIPT_SIZE: u32 = 1024 * 1024;
+
+pub fn is_flv(head: &[u8]) -> bool {
+    head.len() >= 9 && &head[0..3] == b"FLV" && head[3] == 1
+}
+
+pub fn probe<R: Read + Seek>(r: &mut R) -> Result<MediaInfo, ProbeError> {
+    let file_len = r.seek(SeekFrom::End(0))?;
+    r.seek(SeekFrom::Start(0))?;
+    let mut header = [0u8; 9];
+    r.read_exact(&mut header)?;
+    let data_offset = u32::from_be_bytes([header[5], header[6], header[7], header[8]]) as u64;
+
+    let mut info = MediaInfo { container: "flv".to_string(), ..Default::default() };
+    let mut meta = Metadata::default();
+    let mut video: Option<StreamInfo> = None;
+    let mut audio: Option<StreamInfo> = None;
+
+    // each tag is preceded by the size of the previous one
+    let mut pos = data_offset + 4;
+    for _ in 0..MAX_TAGS {
+        if pos + 11 > file_len || (video.is_some() && audio.is_some() && meta.read) {
+            break;
+        }
+        r.seek(SeekFrom::Start(pos))?;
+        let mut tag = [0u8; 11];
+        r.read_exact(&mut tag)?;
+        let size = u32::from_be_bytes([0, tag[1], tag[2], tag[3]]);
+        match tag[0] & 0x1f {
+            TAG_SCRIPT if !meta.read && size <= MAX_SCRIPT_SIZE => {
+                let mut body = vec![0u8; size as usize];
+                r.read_exact(&mut body)?;
+                meta.parse(&body);
+            }
+            TAG_VIDEO if video.is_none() && size > 0 => {
+                let mut body = [0u8; 5];
+                let n = read_up_to(r, &mut body)?;
+                video = Some(video_stream(&body[..n]));
+            }
+            TAG_AUDIO if audio.is_none() && size > 0 => {
+                let mut body = [0u8; 1];
+                r.read_exact(&mut body)?;
+                audio = Some(audio_stream(body[0]));
+            }
+            _ => {}
+        }
+        pos += 11 + size as u64 + 4;
+    }
+
+    // the metadata covers files where the first frames come later than we looked
+    if video.is_none() {
+        video = meta.video_codec.as_ref().map(|codec| StreamInfo { id: TAG_VIDEO as u64, kind: StreamKind::Video, codec: codec.clone(), default: true, ..Default::default() });
+    }
+    if audio.is_none() {
+        audio = meta.audio_codec.as_ref().map(|codec| StreamInfo { id: TAG_AUDIO as u64, kind: StreamKind::Audio, codec: codec.clone(), default: true, ..Default::default() });
+    }
+    if let Some(mut v) = video {
+        v.width = v.width.or(meta.width);
+        v.height = v.height.or(meta.height);
+        v.frame_rate = meta.frame_rate;
+        info.streams.push(v);
+    }
+    if let Some(mut a) = audio {
+        // the tag header only knows mono/stereo and four sample rates, AAC carries its own
+        if meta.channels.is_some() {
+            a.channels = meta.channels;
+            a.channel_layout = meta.channels.and_then(channel_layout);
+        }
+        a.sample_rate = meta.sample_rate.or(a.sample_rate);
+        info.streams.push(a);
+    }
+    info.title = meta.title;
+    info.duration = match meta.duration {
+        Some(d) if d > 0.0 => Some(d),
+        _ => last_timestamp(r, file_len)?.map(|ms| ms as f64 / 1000.0),
+    };
+    Ok(info)
+}
+
+// Timestamp in ms of the last tag in the file.
+fn last_timestamp<R: Read + Seek>(r: &mut R, file_len: u64) -> Result<Option<u32>, ProbeError> {
+    if file_len < 4 + 11 {
+        return Ok(None);
+    }
+    r.seek(SeekFrom::Start(file_len - 4))?;
+    let mut prev = [0u8; 4];
+    r.read_exact(&mut prev)?;
+    let prev = u32::from_be_bytes(prev) as u64;
+    if prev < 11 || prev + 4 > file_len {
+        return Ok(None);
+    }
+    r.seek(SeekFrom::Start(file_len - 4 - prev))?;
+    let mut tag = [0u8; 11];
+    r.read_exact(&mut tag)?;
+    if !matches!(tag[0] & 0x1f, TAG_AUDIO | TAG_VIDEO | TAG_SCRIPT) {
+        return Ok(None);
+    }
+    // 24-bit timestamp with the upper 8 bits in the byte after it
+    Ok(Some(u32::from_be_bytes([tag[7], tag[4], tag[5], tag[6]])).filter(|&ms| ms > 0))
+}
+
+fn video_stream(body: &[u8]) -> StreamInfo {
+    let first = body.first().copied().unwrap_or(0);
+    // enhanced RTMP: a FourCC follows instead of the 4-bit codec id
+    let codec = if first & 0x80 != 0 && body.len() >= 5 {
+        match &body[1..5] {
+            b"hvc1" => "hevc".to_string(),
+            b"av01" => "av1".to_string(),
+            b"vp09" => "vp9".to_string(),
+            other => String::from_utf8_lossy(other).to_string(),
+        }
+    } else {
+        video_codec((first & 0x0f) as u32)
+    };
+    StreamInfo { id: TAG_VIDEO as u64, kind: StreamKind::Video, codec, default: true, ..Default::default() }
+}
+
+fn audio_stream(flags: u8) -> StreamInfo {
+    let channels = if flags & 0x01 != 0 { 2 } else { 1 };
+    let sample_rate = [5512, 11025, 22050, 44100][((flags >> 2) & 0x03) as usize];
+    StreamInfo {
+        id: TAG_AUDIO as u64,
+        kind: StreamKind::Audio,
+        codec: audio_codec((flags >> 4) as u32),
+        default: true,
+        channels: Some(channels),
+        channel_layout: channel_layout(channels),
+        sample_rate: Some(sample_rate),
+        ..Default::default()
+    }
+}
+
+fn video_codec(id: u32) -> String {
+    match id {
+        2 => "flv1".to_string(),
+        3 => "flashsv".to_string(),
+        4 => "vp6f".to_string(),
+        5 => "vp6a".to_string(),
+        6 => "flashsv2".to_string(),
+        7 => "h264".to_string(),
+        12 => "hevc".to_string(),
+        _ => format!("flv_video_{}", id),
+    }
+}
+
+fn audio_codec(id: u32) -> String {
+    match id {
+        0 => "pcm".to_string(),
+        1 => "adpcm_swf".to_string(),
+        2 | 14 => "mp3".to_string(),
+        3 => "pcm_s16le".to_string(),
+        4..=6 => "nellymoser".to_string(),
+        7 => "pcm_alaw".to_string(),
+        8 => "pcm_mulaw".to_string(),
+        10 => "aac".to_string(),
+        11 => "speex".to_string(),
+        _ => format!("flv_audio_{}", id),
+    }
+}
+
+#[derive(Default)]
+struct Metadata {
+    read: bool,
+    title: Option<String>,
+    duration: Option<f64>,
+    width: Option<u32>,
+    height: Option<u32>,
+    frame_rate: Option<f64>,
+    video_codec: Option<String>,
+    audio_codec: Option<String>,
+    sample_rate: Option<u32>,
+    channels: Option<u32>,
+}
+
+impl Metadata {
+    fn parse(&mut self, body: &[u8]) {
+        let mut r = ByteReader::new(body);
+        if !matches!(amf_value(&mut r, 0), Some(Amf::String(name)) if name == "onMetaData") {
+            return;
+        }
+        self.read = true;
+        let Some(Amf::Object(entries)) = amf_value(&mut r, 0) else { return };
+        let mut stereo = None;
+        for (key, value) in entries {
+            match (key.as_str(), value) {
+                ("duration", Amf::Number(n)) => self.duration = Some(n),
+                ("width", Amf::Number(n)) if n > 0.0 => self.width = Some(n as u32),
+                ("height", Amf::Number(n)) if n > 0.0 => self.height = Some(n as u32),
+                ("framerate" | "videoframerate", Amf::Number(n)) if n > 0.0 => self.frame_rate = Some(n),
+                ("videocodecid", Amf::Number(n)) => self.video_codec = Some(video_codec(n as u32)),
+                ("videocodecid", Amf::String(s)) => self.video_codec = Some(s),
+                ("audiocodecid", Amf::Number(n)) => self.audio_codec = Some(audio_codec(n as u32)),
+                ("audiocodecid", Amf::String(s)) => self.audio_codec = Some(s),
+                ("audiosamplerate", Amf::Number(n)) if n > 0.0 => self.sample_rate = Some(n as u32),
+                ("audiochannels", Amf::Number(n)) if n > 0.0 => self.channels = Some(n as u32),
+                ("stereo", Amf::Bool(b)) => stereo = Some(b),
+                ("title", Amf::String(s)) if !s.trim().is_empty() => self.title = Some(s.trim().to_string()),
+                _ => {}
+            }
+        }
+        if self.channels.is_none() {
+            self.channels = stereo.map(|s| if s { 2 } else { 1 });
+        }
+    }
+}
+
+// The AMF0 values the metadata uses; anything else is skipped as `Other`.
+enum Amf {
+    Number(f64),
+    Bool(bool),
+    String(String),
+    Object(Vec<(String, Amf)>),
+    Other,
+}
+
+// Nesting limit, so a crafted file cannot overflow the stack.
+const MAX_AMF_DEPTH: usize = 16;
+
+fn amf_value(r: &mut ByteReader, depth: usize) -> Option<Amf> {
+    if depth > MAX_AMF_DEPTH {
+        return None;
+    }
+    let value = match r.u8().ok()? {
+        0x00 => Amf::Number(f64::from_bits(r.u64().ok()?)),
+        0x01 => Amf::Bool(r.u8().ok()? != 0),
+        0x02 => Amf::String(amf_string(r)?),
+        0x03 => Amf::Object(amf_properties(r, depth)),
+        // null, undefined
+        0x05 | 0x06 => Amf::Other,
+        // ECMA array: a count that is not always right, then properties like an object
+        0x08 => {
+            r.skip(4).ok()?;
+            Amf::Object(amf_properties(r, depth))
+        }
+        0x0a => {
+            let count = r.u32().ok()?;
+            for _ in 0..count {
+                amf_value(r, depth + 1)?;
+            }
+            Amf::Other
+        }
+        // date: ms since the epoch and a time zone
+        0x0b => {
+            r.skip(10).ok()?;
+            Amf::Other
+        }
+        0x0c => {
+            let len = r.u32().ok()? as usize;
+            Amf::String(String::from_utf8_lossy(r.bytes(len).ok()?).to_string())
+        }
+        _ => return None,
+    };
+    Some(value)
+}
+
+fn amf_string(r: &mut ByteReader) -> Option<String> {
+    let len = r.u16().ok()? as usize;
+    Some(String::from_utf8_lossy(r.bytes(len).ok()?).to_string())
+}
+
+// Key/value pairs up to the empty key followed by the object end marker. Truncated
+// data keeps the pairs read so far.
+fn amf_properties(r: &mut ByteReader, depth: usize) -> Vec<(String, Amf)> {
+    let mut out = Vec::new();
+    while let Some(key) = amf_string(r) {
+        if key.is_empty() {
+            let _ = r.u8();
+            break;
+        }
+        let Some(value) = amf_value(r, depth + 1) else { break };
+        out.push((key, value));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn tag(kind: u8, ms: u32, body: &[u8]) -> Vec<u8> {
+        let size = body.len() as u32;
+        let mut out = vec![kind];
+        out.extend_from_slice(&size.to_be_bytes()[1..]);
+        out.extend_from_slice(&ms.to_be_bytes()[1..]);
+        out.extend_from_slice(&[(ms >> 24) as u8, 0, 0, 0]);
+        out.extend_from_slice(body);
+        out.extend_from_slice(&(11 + size).to_be_bytes());
+        out
+    }
+
+    fn flv(tags: &[Vec<u8>]) -> Vec<u8> {
+        [b"FLV\x01\x05\0\0\0\x09\0\0\0\0".to_vec(), tags.concat()].concat()
+    }
+
+    fn amf_key(key: &str) -> Vec<u8> {
+        [(key.len() as u16).to_be_bytes().to_vec(), key.as_bytes().to_vec()].concat()
+    }
+
+    fn number(n: f64) -> Vec<u8> {
+        [vec![0x00], n.to_bits().to_be_bytes().to_vec()].concat()
+    }
+
+    fn string(s: &str) -> Vec<u8> {
+        [vec![0x02], amf_key(s)].concat()
+    }
+
+    fn on_meta_data(entries: &[(&str, Vec<u8>)]) -> Vec<u8> {
+        let mut out = string("onMetaData");
+        out.push(0x08);
+        out.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+        for (key, value) in entries {
+            out.extend(amf_key(key));
+            out.extend_from_slice(value);
+        }
+        out.extend_from_slice(&[0, 0, 0x09]);
+        out
+    }
+
+    #[test]
+    fn metadata_fills_in_the_tag_headers() {
+        let meta = on_meta_data(&[
+            ("duration", number(12.5)),
+            ("width", number(640.0)),
+            ("height", number(360.0)),
+            ("framerate", number(30.0)),
+            ("videocodecid", number(7.0)),
+            ("audiosamplerate", number(48_000.0)),
+            ("stereo", vec![0x01, 1]),
+            ("title", string("  Clip ")),
+        ]);
+        // AVC keyframe, AAC stereo
+        let data = flv(&[tag(TAG_SCRIPT, 0, &meta), tag(TAG_VIDEO, 0, &[0x17, 0, 0, 0, 0]), tag(TAG_AUDIO, 0, &[0xaf, 1])]);
+        let info = probe(&mut Cursor::new(data)).unwrap();
+
+        assert_eq!(info.container, "flv");
+        assert_eq!((info.duration, info.title.as_deref()), (Some(12.5), Some("Clip")));
+        let video = &info.streams[0];
+        assert_eq!((video.codec.as_str(), video.width, video.height, video.frame_rate), ("h264", Some(640), Some(360), Some(30.0)));
+        let audio = &info.streams[1];
+        assert_eq!((audio.codec.as_str(), audio.channels, audio.sample_rate), ("aac", Some(2), Some(48_000)));
+    }
+
+    #[test]
+    fn without_metadata_the_last_tag_gives_the_duration() {
+        // enhanced RTMP HEVC, then MP3 mono at 44.1 kHz
+        let data = flv(&[tag(TAG_VIDEO, 0, &[0x90, b'h', b'v', b'c', b'1']), tag(TAG_AUDIO, 0, &[0x2e, 0]), tag(TAG_VIDEO, 90_500, &[0x27, 0, 0, 0, 0])]);
+        let info = probe(&mut Cursor::new(data)).unwrap();
+        assert_eq!(info.duration, Some(90.5));
+        assert_eq!(info.streams[0].codec, "hevc");
+        let audio = &info.streams[1];
+        assert_eq!((audio.codec.as_str(), audio.channels, audio.sample_rate), ("mp3", Some(1), Some(44_100)));
+    }
+
+    #[test]
+    fn deeply_nested_metadata_keeps_what_came_before() {
+        let mut nested = vec![0x05];
+        for _ in 0..=MAX_AMF_DEPTH {
+            nested = [vec![0x03], amf_key("x"), nested, vec![0, 0, 0x09]].concat();
+        }
+        let meta = on_meta_data(&[("duration", number(5.0)), ("nested", nested), ("width", number(320.0))]);
+        let info = probe(&mut Cursor::new(flv(&[tag(TAG_SCRIPT, 0, &meta)]))).unwrap();
+        assert_eq!(info.duration, Some(5.0));
+        assert!(info.streams.is_empty());
+    }
+}
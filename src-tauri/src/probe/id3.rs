@@ -0,0 +1,376 @@
+// ID3 tags: version 2.2 to 2.4 at the start of the file and the fixed 128-byte version 1
+// block at the end. Only the fields shown in the library are read: title, artist, album,
+// track number and the attached pictures.
+use std::io::{Read, Seek, SeekFrom};
+
+use serde::{Deserialize, Serialize};
+
+use super::{read_up_to, ByteReader, ProbeError};
+
+// Upper bound for a tag we load into memory; large cover images stay well below. Larger
+// tags are skipped, the audio after them is still probed.
+const MAX_TAG_SIZE: usize = 32 * 1024 * 1024;
+
+// Picture type of the front cover in APIC frames.
+const FRONT_COVER: u8 = 3;
+
+#[derive(Debug, Clone, Default)]
+pub struct Tags {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub track: Option<u32>,
+    pub pictures: Vec<Picture>,
+}
+
+// An attached picture; `data` is dropped before the probe result is stored.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Picture {
+    pub mime_type: String,
+    // APIC picture type, 3 is the front cover
+    pub picture_type: u8,
+    pub description: Option<String>,
+    pub size: u64,
+    #[serde(skip)]
+    pub data: Vec<u8>,
+}
+
+impl Tags {
+    // Fills fields missing here from `other`, used to fall back to ID3v1.
+    pub fn merge(&mut self, other: Tags) {
+        self.title = self.title.take().or(other.title);
+        self.artist = self.artist.take().or(other.artist);
+        self.album = self.album.take().or(other.album);
+        self.track = self.track.or(other.track);
+    }
+
+    // The front cover, or the first picture when none is marked as such.
+    pub fn cover(&self) -> Option<&Picture> {
+        self.cover_index().map(|i| &self.pictures[i])
+    }
+
+    // Same as `cover`, taking the picture with its bytes out of the tags.
+    pub fn into_cover(mut self) -> Option<Picture> {
+        self.cover_index().map(|i| self.pictures.swap_remove(i))
+    }
+
+    fn cover_index(&self) -> Option<usize> {
+        let front = self.pictures.iter().position(|p| p.picture_type == FRONT_COVER);
+        front.or(if self.pictures.is_empty() { None } else { Some(0) })
+    }
+}
+
+// Size of the ID3v2 tag at the start of `head` including its header and footer, or 0.
+pub fn v2_size(head: &[u8]) -> usize {
+    if head.len() < 10 || &head[0..3] != b"ID3" {
+        return 0;
+    }
+    let footer = if head[5] & 0x10 != 0 { 10 } else { 0 };
+    10 + syncsafe(&head[6..10]) as usize + footer
+}
+
+// Reads the ID3v2 tag at the start of the file, if there is one and it is not too large
+// to load.
+pub fn read_v2<R: Read + Seek>(r: &mut R) -> Result<Option<Tags>, ProbeError> {
+    r.seek(SeekFrom::Start(0))?;
+    let mut header = [0u8; 10];
+    if read_up_to(r, &mut header)? < 10 || &header[0..3] != b"ID3" {
+        return Ok(None);
+    }
+    let size = syncsafe(&header[6..10]) as usize;
+    if size > MAX_TAG_SIZE {
+        return Ok(None);
+    }
+    let mut body = vec![0u8; size];
+    let n = read_up_to(r, &mut body)?;
+    body.truncate(n);
+    Ok(Some(parse_v2(header[3], header[5], &body)))
+}
+
+fn parse_v2(major: u8, flags: u8, body: &[u8]) -> Tags {
+    let mut tags = Tags::default();
+    if !(2..=4).contains(&major) {
+        return tags;
+    }
+    // before 2.4 unsynchronisation applies to the whole tag, in 2.4 to single frames
+    let owned;
+    let mut body = body;
+    if flags & 0x80 != 0 && major < 4 {
+        owned = resync(body);
+        body = &owned;
+    }
+    if flags & 0x40 != 0 && major >= 3 && body.len() >= 4 {
+        let ext_size = match major {
+            3 => 4 + u32::from_be_bytes([body[0], body[1], body[2], body[3]]) as usize,
+            _ => syncsafe(&body[0..4]) as usize,
+        };
+        body = body.get(ext_size..).unwrap_or_default();
+    }
+
+    let mut r = ByteReader::new(body);
+    let id_len = if major == 2 { 3 } else { 4 };
+    while r.remaining() > id_len * 2 {
+        let Ok(id) = r.bytes(id_len) else { break };
+        // padding
+        if id[0] == 0 {
+            break;
+        }
+        let (size, frame_flags) = match major {
+            2 => (r.u24().map(|s| s as usize), 0u16),
+            3 => (r.u32().map(|s| s as usize), r.u16().unwrap_or(0)),
+            _ => (r.bytes(4).map(|b| syncsafe(b) as usize), r.u16().unwrap_or(0)),
+        };
+        let Ok(size) = size else { break };
+        let Ok(data) = r.bytes(size) else { break };
+        let Some(data) = frame_data(major, frame_flags, data) else { continue };
+        match id {
+            b"TIT2" | b"TT2" => tags.title = text(&data),
+            b"TPE1" | b"TP1" => tags.artist = text(&data),
+            b"TALB" | b"TAL" => tags.album = text(&data),
+            b"TRCK" | b"TRK" => tags.track = text(&data).and_then(|t| track_number(&t)),
+            b"APIC" | b"PIC" => {
+                if let Some(picture) = picture(&data, major == 2) {
+                    tags.pictures.push(picture);
+                }
+            }
+            _ => {}
+        }
+    }
+    tags
+}
+
+// Frame payload with the per-frame flags applied; None for compressed or encrypted frames.
+fn frame_data(major: u8, flags: u16, data: &[u8]) -> Option<Vec<u8>> {
+    match major {
+        3 => {
+            if flags & 0x00c0 != 0 {
+                return None;
+            }
+            // grouping identity byte
+            let skip = if flags & 0x0020 != 0 { 1 } else { 0 };
+            data.get(skip..).map(|d| d.to_vec())
+        }
+        4 => {
+            if flags & 0x000c != 0 {
+                return None;
+            }
+            let mut skip = 0;
+            if flags & 0x0040 != 0 {
+                skip += 1;
+            }
+            // data length indicator
+            if flags & 0x0001 != 0 {
+                skip += 4;
+            }
+            let data = data.get(skip..)?;
+            Some(if flags & 0x0002 != 0 { resync(data) } else { data.to_vec() })
+        }
+        _ => Some(data.to_vec()),
+    }
+}
+
+// Removes the 0x00 inserted after every 0xFF by unsynchronisation.
+fn resync(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut prev = 0u8;
+    for &b in data {
+        if !(prev == 0xff && b == 0x00) {
+            out.push(b);
+        }
+        prev = b;
+    }
+    out
+}
+
+fn syncsafe(b: &[u8]) -> u32 {
+    ((b[0] as u32 & 0x7f) << 21) | ((b[1] as u32 & 0x7f) << 14) | ((b[2] as u32 & 0x7f) << 7) | (b[3] as u32 & 0x7f)
+}
+
+// First value of a text frame; 2.4 separates multiple values with NUL.
+fn text(data: &[u8]) -> Option<String> {
+    let (&encoding, rest) = data.split_first()?;
+    let (value, _) = decode(encoding, rest);
+    let value = value.trim().to_string();
+    Some(value).filter(|v| !v.is_empty())
+}
+
+// "3" or "3/12"
+fn track_number(text: &str) -> Option<u32> {
+    text.split('/').next()?.trim().parse().ok().filter(|&n| n > 0)
+}
+
+fn picture(data: &[u8], v22: bool) -> Option<Picture> {
+    let (&encoding, rest) = data.split_first()?;
+    let (mime_type, rest) = if v22 {
+        // three-letter image format instead of a MIME type
+        let format = rest.get(..3)?;
+        let mime = match &format.to_ascii_uppercase()[..] {
+            b"PNG" => "image/png".to_string(),
+            b"JPG" => "image/jpeg".to_string(),
+            other => format!("image/{}", String::from_utf8_lossy(other).to_lowercase()),
+        };
+        (mime, &rest[3..])
+    } else {
+        let (mime, rest) = decode(0, rest);
+        // some taggers write just "jpg" or "png"
+        let mime = match mime.to_lowercase().as_str() {
+            m if m.contains('/') => m.to_string(),
+            "" | "jpg" => "image/jpeg".to_string(),
+            m => format!("image/{}", m),
+        };
+        (mime, rest)
+    };
+    let (&picture_type, rest) = rest.split_first()?;
+    let (description, image) = decode(encoding, rest);
+    Some(Picture {
+        mime_type: mime_type.to_lowercase(),
+        picture_type,
+        description: Some(description).filter(|d| !d.is_empty()),
+        size: image.len() as u64,
+        data: image.to_vec(),
+    })
+}
+
+// Decodes a NUL-terminated string and returns it with the bytes after the terminator.
+// Encodings: 0 ISO-8859-1, 1 UTF-16 with BOM, 2 UTF-16BE, 3 UTF-8.
+fn decode(encoding: u8, data: &[u8]) -> (String, &[u8]) {
+    match encoding {
+        1 | 2 => {
+            let end = data.chunks_exact(2).position(|c| c == [0, 0]).map(|i| i * 2);
+            let (text, rest) = match end {
+                Some(end) => (&data[..end], &data[end + 2..]),
+                None => (data, &data[data.len()..]),
+            };
+            let (big_endian, text) = match text {
+                [0xfe, 0xff, rest @ ..] => (true, rest),
+                [0xff, 0xfe, rest @ ..] => (false, rest),
+                _ => (encoding == 2, text),
+            };
+            let units: Vec<u16> = text
+                .chunks_exact(2)
+                .map(|c| if big_endian { u16::from_be_bytes([c[0], c[1]]) } else { u16::from_le_bytes([c[0], c[1]]) })
+                .collect();
+            (String::from_utf16_lossy(&units), rest)
+        }
+        _ => {
+            let end = data.iter().position(|&b| b == 0);
+            let (text, rest) = match end {
+                Some(end) => (&data[..end], &data[end + 1..]),
+                None => (data, &data[data.len()..]),
+            };
+            let text = if encoding == 3 { String::from_utf8_lossy(text).to_string() } else { text.iter().map(|&b| b as char).collect() };
+            (text, rest)
+        }
+    }
+}
+
+// Reads the ID3v1 block from the last 128 bytes, if there is one.
+pub fn read_v1<R: Read + Seek>(r: &mut R, file_len: u64) -> Result<Option<Tags>, ProbeError> {
+    if file_len < 128 {
+        return Ok(None);
+    }
+    r.seek(SeekFrom::Start(file_len - 128))?;
+    let mut block = [0u8; 128];
+    r.read_exact(&mut block)?;
+    if &block[0..3] != b"TAG" {
+        return Ok(None);
+    }
+    let field = |range: std::ops::Range<usize>| {
+        let (text, _) = decode(0, &block[range]);
+        Some(text.trim().to_string()).filter(|t| !t.is_empty())
+    };
+    Ok(Some(Tags {
+        title: field(3..33),
+        artist: field(33..63),
+        album: field(63..93),
+        // ID3v1.1 keeps the track in the last comment byte after a NUL
+        track: if block[125] == 0 && block[126] != 0 { Some(block[126] as u32) } else { None },
+        pictures: Vec::new(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn tag(major: u8, frames: &[Vec<u8>]) -> Vec<u8> {
+        let body = frames.concat();
+        let size = body.len() as u32;
+        let mut out = vec![b'I', b'D', b'3', major, 0, 0];
+        out.extend_from_slice(&[(size >> 21) as u8 & 0x7f, (size >> 14) as u8 & 0x7f, (size >> 7) as u8 & 0x7f, size as u8 & 0x7f]);
+        out.extend(body);
+        out
+    }
+
+    fn frame(id: &[u8; 4], flags: u16, data: &[u8]) -> Vec<u8> {
+        [id.to_vec(), (data.len() as u32).to_be_bytes().to_vec(), flags.to_be_bytes().to_vec(), data.to_vec()].concat()
+    }
+
+    fn v1_block(title: &str, album: &str, track: u8) -> [u8; 128] {
+        let mut block = [0u8; 128];
+        block[0..3].copy_from_slice(b"TAG");
+        block[3..3 + title.len()].copy_from_slice(title.as_bytes());
+        block[63..63 + album.len()].copy_from_slice(album.as_bytes());
+        block[126] = track;
+        block
+    }
+
+    #[test]
+    fn reads_v23_text_and_pictures() {
+        let artist: Vec<u8> = [1, 0xff, 0xfe].into_iter().chain("Björk".encode_utf16().flat_map(|u| u.to_le_bytes())).collect();
+        let back = [b"\0image/jpeg\0\x04back\0".to_vec(), vec![1; 10]].concat();
+        let front = [b"\0png\0\x03\0".to_vec(), vec![2; 20]].concat();
+        let data = tag(
+            3,
+            &[
+                frame(b"TIT2", 0, b"\0 Army of Me "),
+                frame(b"TPE1", 0, &artist),
+                frame(b"TRCK", 0, b"\x003/12"),
+                // zlib-compressed frames are skipped
+                frame(b"TALB", 0x0080, b"\0Post"),
+                frame(b"APIC", 0, &back),
+                frame(b"APIC", 0, &front),
+                vec![0; 16],
+            ],
+        );
+        let tags = read_v2(&mut Cursor::new(data)).unwrap().unwrap();
+        assert_eq!((tags.title.as_deref(), tags.artist.as_deref(), tags.album.as_deref(), tags.track), (Some("Army of Me"), Some("Björk"), None, Some(3)));
+        assert_eq!(tags.pictures[0].description.as_deref(), Some("back"));
+        let cover = tags.into_cover().unwrap();
+        assert_eq!((cover.mime_type.as_str(), cover.picture_type, cover.size), ("image/png", 3, 20));
+        assert_eq!(cover.data, vec![2; 20]);
+    }
+
+    #[test]
+    fn reads_v22_and_v24_frames() {
+        let v22 = [b"TT2\0\0\x06\0Title".to_vec(), b"PIC\0\0\x09\0JPG\0\0abc".to_vec()].concat();
+        let tags = read_v2(&mut Cursor::new(tag(2, &[v22]))).unwrap().unwrap();
+        assert_eq!(tags.title.as_deref(), Some("Title"));
+        assert_eq!((tags.pictures[0].mime_type.as_str(), tags.pictures[0].size), ("image/jpeg", 3));
+
+        // 2.4 separates values with NUL; this frame is also unsynchronised
+        let album = frame(b"TALB", 0x0002, b"\0Caf\xff\x00!");
+        let tags = read_v2(&mut Cursor::new(tag(4, &[frame(b"TIT2", 0, "\x03Ça va\0Other".as_bytes()), album]))).unwrap().unwrap();
+        assert_eq!(tags.title.as_deref(), Some("Ça va"));
+        assert_eq!(tags.album.as_deref(), Some("Cafÿ!"));
+    }
+
+    #[test]
+    fn v1_fills_missing_fields() {
+        let mut file = tag(3, &[frame(b"TIT2", 0, b"\0From v2")]);
+        file.extend(v1_block("From v1", "Album", 7));
+        let mut tags = read_v2(&mut Cursor::new(&file)).unwrap().unwrap();
+        tags.merge(read_v1(&mut Cursor::new(&file), file.len() as u64).unwrap().unwrap());
+        assert_eq!((tags.title.as_deref(), tags.album.as_deref(), tags.track), (Some("From v2"), Some("Album"), Some(7)));
+        assert!(read_v1(&mut Cursor::new(vec![0; 64]), 64).unwrap().is_none());
+    }
+
+    #[test]
+    fn oversized_v2_tag_is_skipped() {
+        // syncsafe size 0x7f7f7f7f, about 256 MiB, with no body behind it
+        let header = b"ID3\x04\x00\x00\x7f\x7f\x7f\x7f".to_vec();
+        assert!(read_v2(&mut Cursor::new(header)).unwrap().is_none());
+    }
+}
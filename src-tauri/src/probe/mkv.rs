@@ -0,0 +1,610 @@
+// Matroska and WebM. The file is read element by element: clusters with the media
+// data are never loaded, metadata that sits behind them (common for chapters and
+// attachments written by muxers after the fact) is found through the SeekHead.
+use std::io::{Read, Seek, SeekFrom};
+
+use serde::{Deserialize, Serialize};
+
+use super::{channel_layout, MediaInfo, ProbeError, StreamInfo, StreamKind};
+
+pub(crate) const EBML: u32 = 0x1A45_DFA3;
+const DOC_TYPE: u32 = 0x4282;
+pub(crate) const SEGMENT: u32 = 0x1853_8067;
+pub(crate) const SEEK_HEAD: u32 = 0x114D_9B74;
+const SEEK: u32 = 0x4DBB;
+const SEEK_ID: u32 = 0x53AB;
+const SEEK_POSITION: u32 = 0x53AC;
+pub(crate) const INFO: u32 = 0x1549_A966;
+pub(crate) const TIMESTAMP_SCALE: u32 = 0x2A_D7B1;
+pub(crate) const DURATION: u32 = 0x4489;
+const TITLE: u32 = 0x7BA9;
+pub(crate) const TRACKS: u32 = 0x1654_AE6B;
+pub(crate) const TRACK_ENTRY: u32 = 0xAE;
+pub(crate) const TRACK_NUMBER: u32 = 0xD7;
+pub(crate) const TRACK_TYPE: u32 = 0x83;
+pub(crate) const FLAG_DEFAULT: u32 = 0x88;
+pub(crate) const FLAG_FORCED: u32 = 0x55AA;
+pub(crate) const NAME: u32 = 0x536E;
+pub(crate) const LANGUAGE: u32 = 0x22_B59C;
+pub(crate) const LANGUAGE_BCP47: u32 = 0x22_B59D;
+pub(crate) const CODEC_ID: u32 = 0x86;
+pub(crate) const DEFAULT_DURATION: u32 = 0x23_E383;
+pub(crate) const VIDEO: u32 = 0xE0;
+pub(crate) const PIXEL_WIDTH: u32 = 0xB0;
+pub(crate) const PIXEL_HEIGHT: u32 = 0xBA;
+pub(crate) const AUDIO: u32 = 0xE1;
+pub(crate) const SAMPLING_FREQUENCY: u32 = 0xB5;
+pub(crate) const CHANNELS: u32 = 0x9F;
+pub(crate) const ATTACHMENTS: u32 = 0x1941_A469;
+const ATTACHED_FILE: u32 = 0x61A7;
+const FILE_DESCRIPTION: u32 = 0x467E;
+const FILE_NAME: u32 = 0x466E;
+const FILE_MIME_TYPE: u32 = 0x4660;
+const FILE_DATA: u32 = 0x465C;
+const FILE_UID: u32 = 0x46AE;
+pub(crate) const CHAPTERS: u32 = 0x1043_A770;
+const EDITION_ENTRY: u32 = 0x45B9;
+const EDITION_UID: u32 = 0x45BC;
+const EDITION_FLAG_HIDDEN: u32 = 0x45BD;
+const EDITION_FLAG_DEFAULT: u32 = 0x45DB;
+const EDITION_FLAG_ORDERED: u32 = 0x45DD;
+const CHAPTER_ATOM: u32 = 0xB6;
+const CHAPTER_UID: u32 = 0x73C4;
+const CHAPTER_TIME_START: u32 = 0x91;
+const CHAPTER_TIME_END: u32 = 0x92;
+const CHAPTER_FLAG_HIDDEN: u32 = 0x98;
+const CHAPTER_DISPLAY: u32 = 0x80;
+const CHAP_STRING: u32 = 0x85;
+const CHAP_LANGUAGE: u32 = 0x437C;
+pub(crate) const CLUSTER: u32 = 0x1F43_B675;
+
+// Upper bound for a metadata element loaded into memory (Info, Tracks, Chapters).
+const MAX_ELEMENT_SIZE: u64 = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Attachment {
+    pub uid: u64,
+    pub file_name: String,
+    pub mime_type: String,
+    pub description: Option<String>,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChapterEdition {
+    pub uid: u64,
+    pub default: bool,
+    pub hidden: bool,
+    pub ordered: bool,
+    pub chapters: Vec<Chapter>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Chapter {
+    pub uid: u64,
+    // seconds
+    pub start: f64,
+    pub end: Option<f64>,
+    pub title: Option<String>,
+    pub language: Option<String>,
+    pub hidden: bool,
+    // nested chapters
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub chapters: Vec<Chapter>,
+}
+
+pub fn is_mkv(head: &[u8]) -> bool {
+    head.starts_with(&EBML.to_be_bytes())
+}
+
+pub(crate) struct Header {
+    pub start: u64,
+    pub id: u32,
+    // None for elements of unknown size (live streams)
+    pub size: Option<u64>,
+    pub data_start: u64,
+}
+
+pub fn probe<R: Read + Seek>(r: &mut R) -> Result<MediaInfo, ProbeError> {
+    let file_len = r.seek(SeekFrom::End(0))?;
+    r.seek(SeekFrom::Start(0))?;
+
+    let header = read_header(r)?;
+    if header.id != EBML {
+        return Err(ProbeError::Malformed("no EBML header"));
+    }
+    let ebml = read_body(r, &header)?;
+    let doc_type = children(&ebml).into_iter().find(|(id, _)| *id == DOC_TYPE).map(|(_, v)| string(v));
+    let mut info = MediaInfo {
+        container: if doc_type.as_deref() == Some("webm") { "webm" } else { "mkv" }.to_string(),
+        ..Default::default()
+    };
+
+    let mut pos = header.data_start + header.size.unwrap_or(0);
+    let segment = loop {
+        if pos >= file_len {
+            return Err(ProbeError::Malformed("no Segment"));
+        }
+        r.seek(SeekFrom::Start(pos))?;
+        let h = read_header(r)?;
+        if h.id == SEGMENT {
+            break h;
+        }
+        // Void or other top-level elements before the segment
+        pos = h.data_start + h.size.ok_or(ProbeError::Malformed("element size"))?;
+    };
+    let segment_start = segment.data_start;
+    let segment_end = segment.size.map(|s| segment_start + s).unwrap_or(file_len).min(file_len);
+
+    let mut state = SegmentState::default();
+    let mut pos = segment_start;
+    while pos < segment_end {
+        r.seek(SeekFrom::Start(pos))?;
+        let Ok(h) = read_header(r) else { break };
+        if h.id == CLUSTER {
+            // media data from here on; anything else is reached through the SeekHead
+            break;
+        }
+        parse_top_level(r, &h, &mut info, &mut state)?;
+        match h.size {
+            Some(size) => pos = h.data_start + size,
+            None => break,
+        }
+    }
+
+    // a SeekHead may point to a second one, so the list can grow while we follow it
+    let mut i = 0;
+    while i < state.seek.len() {
+        let (id, offset) = state.seek[i];
+        i += 1;
+        let wanted = match id {
+            SEEK_HEAD => !state.seek_heads.contains(&(segment_start + offset)),
+            INFO | TRACKS | ATTACHMENTS | CHAPTERS => !state.done.contains(&id),
+            _ => false,
+        };
+        if !wanted {
+            continue;
+        }
+        r.seek(SeekFrom::Start(segment_start + offset))?;
+        let Ok(h) = read_header(r) else { continue };
+        if h.id == id {
+            parse_top_level(r, &h, &mut info, &mut state)?;
+        }
+    }
+
+    // Duration counts in timestamp ticks, by default 1 ms
+    if let Some(duration) = state.duration {
+        let scale = state.timestamp_scale.unwrap_or(1_000_000);
+        info.duration = Some(duration * scale as f64 / 1e9).filter(|d| *d > 0.0);
+    }
+    Ok(info)
+}
+
+#[derive(Default)]
+struct SegmentState {
+    // (element id, position relative to the segment data)
+    seek: Vec<(u32, u64)>,
+    // absolute positions of the SeekHeads already read
+    seek_heads: Vec<u64>,
+    done: Vec<u32>,
+    duration: Option<f64>,
+    timestamp_scale: Option<u64>,
+}
+
+fn parse_top_level<R: Read + Seek>(r: &mut R, h: &Header, info: &mut MediaInfo, state: &mut SegmentState) -> Result<(), ProbeError> {
+    match h.id {
+        SEEK_HEAD => {
+            let body = read_body(r, h)?;
+            for (id, seek) in children(&body) {
+                if id != SEEK {
+                    continue;
+                }
+                let fields = children(seek);
+                let target = fields.iter().find(|(id, _)| *id == SEEK_ID).map(|(_, v)| uint(v) as u32);
+                let position = fields.iter().find(|(id, _)| *id == SEEK_POSITION).map(|(_, v)| uint(v));
+                if let (Some(target), Some(position)) = (target, position) {
+                    state.seek.push((target, position));
+                }
+            }
+            state.seek_heads.push(h.start);
+            return Ok(());
+        }
+        INFO => {
+            let body = read_body(r, h)?;
+            for (id, value) in children(&body) {
+                match id {
+                    TIMESTAMP_SCALE => state.timestamp_scale = Some(uint(value)),
+                    DURATION => state.duration = float(value),
+                    TITLE => info.title = Some(string(value)).filter(|t| !t.is_empty()),
+                    _ => {}
+                }
+            }
+        }
+        TRACKS => {
+            let body = read_body(r, h)?;
+            for (id, entry) in children(&body) {
+                if id == TRACK_ENTRY {
+                    if let Some(stream) = parse_track(entry) {
+                        info.streams.push(stream);
+                    }
+                }
+            }
+        }
+        CHAPTERS => {
+            let body = read_body(r, h)?;
+            for (id, edition) in children(&body) {
+                if id == EDITION_ENTRY {
+                    info.chapters.push(parse_edition(edition));
+                }
+            }
+        }
+        ATTACHMENTS => {
+            if let Some(size) = h.size {
+                info.attachments = parse_attachments(r, h.data_start, h.data_start + size)?;
+            }
+        }
+        _ => return Ok(()),
+    }
+    state.done.push(h.id);
+    Ok(())
+}
+
+fn parse_track(entry: &[u8]) -> Option<StreamInfo> {
+    let mut stream = StreamInfo {
+        default: true,
+        // the Matroska default when a track has no Language element
+        language: Some("eng".to_string()),
+        ..Default::default()
+    };
+    let mut track_type = 0;
+    let mut bcp47 = None;
+    for (id, value) in children(entry) {
+        match id {
+            TRACK_NUMBER => stream.id = uint(value),
+            TRACK_TYPE => track_type = uint(value),
+            FLAG_DEFAULT => stream.default = uint(value) != 0,
+            FLAG_FORCED => stream.forced = uint(value) != 0,
+            NAME => stream.name = Some(string(value)).filter(|n| !n.is_empty()),
+            LANGUAGE => stream.language = Some(string(value)),
+            LANGUAGE_BCP47 => bcp47 = Some(string(value)),
+            CODEC_ID => stream.codec = string(value),
+            DEFAULT_DURATION => {
+                let ns = uint(value);
+                if ns > 0 {
+                    stream.frame_rate = Some(1e9 / ns as f64);
+                }
+            }
+            VIDEO => {
+                for (id, value) in children(value) {
+                    match id {
+                        PIXEL_WIDTH => stream.width = Some(uint(value) as u32),
+                        PIXEL_HEIGHT => stream.height = Some(uint(value) as u32),
+                        _ => {}
+                    }
+                }
+            }
+            AUDIO => {
+                // Channels defaults to 1 when absent
+                let mut channels = 1;
+                for (id, value) in children(value) {
+                    match id {
+                        SAMPLING_FREQUENCY => stream.sample_rate = float(value).map(|f| f as u32),
+                        CHANNELS => channels = uint(value) as u32,
+                        _ => {}
+                    }
+                }
+                stream.channels = Some(channels);
+                stream.channel_layout = channel_layout(channels);
+            }
+            _ => {}
+        }
+    }
+    // LanguageBCP47 takes precedence over the ISO 639-2 element when both are set
+    if bcp47.is_some() {
+        stream.language = bcp47;
+    }
+    if stream.language.as_deref() == Some("und") {
+        stream.language = None;
+    }
+    stream.kind = match track_type {
+        1 => StreamKind::Video,
+        2 => StreamKind::Audio,
+        17 => StreamKind::Subtitle,
+        _ => StreamKind::Other,
+    };
+    if stream.kind != StreamKind::Video {
+        stream.frame_rate = None;
+    }
+    Some(stream)
+}
+
+fn parse_edition(edition: &[u8]) -> ChapterEdition {
+    let mut out = ChapterEdition::default();
+    for (id, value) in children(edition) {
+        match id {
+            EDITION_UID => out.uid = uint(value),
+            EDITION_FLAG_HIDDEN => out.hidden = uint(value) != 0,
+            EDITION_FLAG_DEFAULT => out.default = uint(value) != 0,
+            EDITION_FLAG_ORDERED => out.ordered = uint(value) != 0,
+            CHAPTER_ATOM => out.chapters.push(parse_chapter(value)),
+            _ => {}
+        }
+    }
+    out
+}
+
+fn parse_chapter(atom: &[u8]) -> Chapter {
+    let mut out = Chapter::default();
+    for (id, value) in children(atom) {
+        match id {
+            CHAPTER_UID => out.uid = uint(value),
+            // nanoseconds, independent of the timestamp scale
+            CHAPTER_TIME_START => out.start = uint(value) as f64 / 1e9,
+            CHAPTER_TIME_END => out.end = Some(uint(value) as f64 / 1e9),
+            CHAPTER_FLAG_HIDDEN => out.hidden = uint(value) != 0,
+            // the first display is the primary title
+            CHAPTER_DISPLAY if out.title.is_none() => {
+                for (id, value) in children(value) {
+                    match id {
+                        CHAP_STRING => out.title = Some(string(value)),
+                        CHAP_LANGUAGE => out.language = Some(string(value)),
+                        _ => {}
+                    }
+                }
+            }
+            CHAPTER_ATOM => out.chapters.push(parse_chapter(value)),
+            _ => {}
+        }
+    }
+    out
+}
+
+// Attachments are walked on disk so that the file data (fonts, cover images) is
+// skipped instead of read.
+fn parse_attachments<R: Read + Seek>(r: &mut R, start: u64, end: u64) -> Result<Vec<Attachment>, ProbeError> {
+    let mut out = Vec::new();
+    let mut pos = start;
+    while pos < end {
+        r.seek(SeekFrom::Start(pos))?;
+        let h = read_header(r)?;
+        let size = h.size.ok_or(ProbeError::Malformed("attachment size"))?;
+        if h.id == ATTACHED_FILE {
+            let mut attachment = Attachment::default();
+            let mut child_pos = h.data_start;
+            while child_pos < h.data_start + size {
+                r.seek(SeekFrom::Start(child_pos))?;
+                let c = read_header(r)?;
+                let child_size = c.size.ok_or(ProbeError::Malformed("attachment size"))?;
+                if c.id == FILE_DATA {
+                    attachment.size = child_size;
+                } else if child_size <= 64 * 1024 {
+                    let value = read_body(r, &c)?;
+                    match c.id {
+                        FILE_NAME => attachment.file_name = string(&value),
+                        FILE_MIME_TYPE => attachment.mime_type = string(&value),
+                        FILE_DESCRIPTION => attachment.description = Some(string(&value)),
+                        FILE_UID => attachment.uid = uint(&value),
+                        _ => {}
+                    }
+                }
+                child_pos = c.data_start + child_size;
+            }
+            out.push(attachment);
+        }
+        pos = h.data_start + size;
+    }
+    Ok(out)
+}
+
+pub(crate) fn read_header<R: Read + Seek>(r: &mut R) -> Result<Header, ProbeError> {
+    let start = r.stream_position()?;
+    let mut first = [0u8; 1];
+    r.read_exact(&mut first)?;
+    let id_len = first[0].leading_zeros() as usize + 1;
+    if id_len > 4 {
+        return Err(ProbeError::Malformed("element id"));
+    }
+    let mut id = first[0] as u32;
+    for _ in 1..id_len {
+        r.read_exact(&mut first)?;
+        id = (id << 8) | first[0] as u32;
+    }
+
+    r.read_exact(&mut first)?;
+    let size_len = first[0].leading_zeros() as usize + 1;
+    if size_len > 8 {
+        return Err(ProbeError::Malformed("element size"));
+    }
+    let mut size = (first[0] as u64) & (0xFF >> size_len);
+    let mut all_ones = size == (0xFF >> size_len);
+    for _ in 1..size_len {
+        r.read_exact(&mut first)?;
+        size = (size << 8) | first[0] as u64;
+        all_ones &= first[0] == 0xFF;
+    }
+    let data_start = r.stream_position()?;
+    Ok(Header { start, id, size: if all_ones { None } else { Some(size) }, data_start })
+}
+
+pub(crate) fn read_body<R: Read>(r: &mut R, h: &Header) -> Result<Vec<u8>, ProbeError> {
+    let size = h.size.ok_or(ProbeError::Malformed("element size"))?;
+    if size > MAX_ELEMENT_SIZE {
+        return Err(ProbeError::Malformed("element too large"));
+    }
+    let mut body = vec![0u8; size as usize];
+    r.read_exact(&mut body)?;
+    Ok(body)
+}
+
+// Child elements of an in-memory master element. Stops at the first element that
+// does not fit; unknown sizes are not allowed inside metadata elements.
+pub(crate) fn children(data: &[u8]) -> Vec<(u32, &[u8])> {
+    let mut out = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let Some((id, id_len)) = vint(&data[pos..], true) else { break };
+        let Some((size, size_len)) = vint(&data[pos + id_len..], false) else { break };
+        let start = pos + id_len + size_len;
+        let Some(end) = start.checked_add(size as usize).filter(|end| *end <= data.len()) else { break };
+        out.push((id as u32, &data[start..end]));
+        pos = end;
+    }
+    out
+}
+
+// Variable length integer; IDs keep their length marker bit, sizes do not.
+pub(crate) fn vint(data: &[u8], keep_marker: bool) -> Option<(u64, usize)> {
+    let first = *data.first()?;
+    let len = first.leading_zeros() as usize + 1;
+    if len > 8 || data.len() < len {
+        return None;
+    }
+    let mut value = if keep_marker { first as u64 } else { (first as u64) & (0xFF >> len) };
+    for b in &data[1..len] {
+        value = (value << 8) | *b as u64;
+    }
+    Some((value, len))
+}
+
+pub(crate) fn uint(data: &[u8]) -> u64 {
+    data.iter().take(8).fold(0, |acc, b| (acc << 8) | *b as u64)
+}
+
+pub(crate) fn float(data: &[u8]) -> Option<f64> {
+    match data.len() {
+        4 => Some(f32::from_be_bytes([data[0], data[1], data[2], data[3]]) as f64),
+        8 => Some(f64::from_be_bytes([data[0], data[1], data[2], data[3], data[4], data[5], data[6], data[7]])),
+        _ => None,
+    }
+}
+
+pub(crate) fn string(data: &[u8]) -> String {
+    let end = data.iter().position(|b| *b == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn element(id: u32, body: &[u8]) -> Vec<u8> {
+        let mut out: Vec<u8> = id.to_be_bytes().into_iter().skip_while(|b| *b == 0).collect();
+        out.push(0x01);
+        out.extend_from_slice(&(body.len() as u64).to_be_bytes()[1..]);
+        out.extend_from_slice(body);
+        out
+    }
+
+    fn uint_element(id: u32, value: u64) -> Vec<u8> {
+        element(id, &value.to_be_bytes())
+    }
+
+    fn file(doc_type: &str, segment: &[u8]) -> Vec<u8> {
+        [element(EBML, &element(DOC_TYPE, doc_type.as_bytes())), element(SEGMENT, segment)].concat()
+    }
+
+    #[test]
+    fn reads_info_and_tracks() {
+        let info = [uint_element(TIMESTAMP_SCALE, 1_000_000), element(DURATION, &5000f64.to_be_bytes()), element(TITLE, b"Trailer")].concat();
+        let video = [
+            uint_element(TRACK_NUMBER, 1),
+            uint_element(TRACK_TYPE, 1),
+            element(CODEC_ID, b"V_VP9"),
+            uint_element(DEFAULT_DURATION, 40_000_000),
+            element(VIDEO, &[uint_element(PIXEL_WIDTH, 1280), uint_element(PIXEL_HEIGHT, 720)].concat()),
+        ]
+        .concat();
+        let audio = [
+            uint_element(TRACK_NUMBER, 2),
+            uint_element(TRACK_TYPE, 2),
+            uint_element(FLAG_DEFAULT, 0),
+            element(CODEC_ID, b"A_OPUS"),
+            element(LANGUAGE, b"und"),
+            uint_element(DEFAULT_DURATION, 20_000_000),
+            element(AUDIO, &element(SAMPLING_FREQUENCY, &48_000f64.to_be_bytes())),
+        ]
+        .concat();
+        let subtitle = [
+            uint_element(TRACK_NUMBER, 3),
+            uint_element(TRACK_TYPE, 17),
+            uint_element(FLAG_FORCED, 1),
+            element(CODEC_ID, b"S_TEXT/WEBVTT"),
+            element(LANGUAGE, b"por"),
+            element(LANGUAGE_BCP47, b"pt-BR"),
+        ]
+        .concat();
+        let tracks = [element(TRACK_ENTRY, &video), element(TRACK_ENTRY, &audio), element(TRACK_ENTRY, &subtitle)].concat();
+        let data = file("webm", &[element(INFO, &info), element(TRACKS, &tracks)].concat());
+        let info = probe(&mut Cursor::new(data)).unwrap();
+
+        assert_eq!(info.container, "webm");
+        assert_eq!(info.duration, Some(5.0));
+        assert_eq!(info.title.as_deref(), Some("Trailer"));
+        let [video, audio, subtitle] = &info.streams[..] else { panic!("expected three streams") };
+        assert_eq!((video.kind, video.codec.as_str(), video.id), (StreamKind::Video, "V_VP9", 1));
+        assert_eq!((video.width, video.height, video.frame_rate), (Some(1280), Some(720), Some(25.0)));
+        assert_eq!(video.language.as_deref(), Some("eng"));
+        assert!(video.default);
+        assert_eq!((audio.kind, audio.language.as_deref(), audio.default), (StreamKind::Audio, None, false));
+        assert_eq!((audio.sample_rate, audio.channels, audio.frame_rate), (Some(48_000), Some(1), None));
+        assert_eq!((subtitle.kind, subtitle.language.as_deref(), subtitle.forced), (StreamKind::Subtitle, Some("pt-BR"), true));
+    }
+
+    #[test]
+    fn follows_the_seek_head_past_the_clusters() {
+        let display = [element(CHAP_STRING, b"Intro"), element(CHAP_LANGUAGE, b"eng")].concat();
+        let nested = [uint_element(CHAPTER_UID, 3), uint_element(CHAPTER_TIME_START, 10_000_000_000)].concat();
+        let atom = [
+            uint_element(CHAPTER_UID, 2),
+            uint_element(CHAPTER_TIME_START, 0),
+            uint_element(CHAPTER_TIME_END, 60_000_000_000),
+            element(CHAPTER_DISPLAY, &display),
+            element(CHAPTER_ATOM, &nested),
+        ]
+        .concat();
+        let edition = [uint_element(EDITION_UID, 1), uint_element(EDITION_FLAG_DEFAULT, 1), element(CHAPTER_ATOM, &atom)].concat();
+        let chapters = element(CHAPTERS, &element(EDITION_ENTRY, &edition));
+        let attached = [element(FILE_NAME, b"cover.jpg"), element(FILE_MIME_TYPE, b"image/jpeg"), uint_element(FILE_UID, 7), element(FILE_DATA, &[0xff; 1000])].concat();
+        let attachments = element(ATTACHMENTS, &element(ATTACHED_FILE, &attached));
+        let cluster = element(CLUSTER, &[0; 256]);
+
+        let seek = |id: u32, position: u64| element(SEEK, &[element(SEEK_ID, &id.to_be_bytes()), uint_element(SEEK_POSITION, position)].concat());
+        let seek_head_len = element(SEEK_HEAD, &[seek(CHAPTERS, 0), seek(ATTACHMENTS, 0)].concat()).len();
+        let chapters_at = (seek_head_len + cluster.len()) as u64;
+        let attachments_at = chapters_at + chapters.len() as u64;
+        let seek_head = element(SEEK_HEAD, &[seek(CHAPTERS, chapters_at), seek(ATTACHMENTS, attachments_at)].concat());
+        let info = probe(&mut Cursor::new(file("matroska", &[seek_head, cluster, chapters, attachments].concat()))).unwrap();
+
+        assert_eq!(info.container, "mkv");
+        let edition = &info.chapters[0];
+        assert_eq!((edition.uid, edition.default, edition.hidden), (1, true, false));
+        let chapter = &edition.chapters[0];
+        assert_eq!((chapter.uid, chapter.start, chapter.end), (2, 0.0, Some(60.0)));
+        assert_eq!((chapter.title.as_deref(), chapter.language.as_deref()), (Some("Intro"), Some("eng")));
+        assert_eq!((chapter.chapters[0].uid, chapter.chapters[0].start), (3, 10.0));
+        let attachment = &info.attachments[0];
+        assert_eq!((attachment.file_name.as_str(), attachment.mime_type.as_str()), ("cover.jpg", "image/jpeg"));
+        assert_eq!((attachment.uid, attachment.size), (7, 1000));
+    }
+
+    #[test]
+    fn needs_an_ebml_header_and_a_segment() {
+        let not_ebml = element(SEGMENT, &[]);
+        assert!(matches!(probe(&mut Cursor::new(not_ebml)), Err(ProbeError::Malformed("no EBML header"))));
+        let no_segment = element(EBML, &element(DOC_TYPE, b"matroska"));
+        assert!(matches!(probe(&mut Cursor::new(no_segment)), Err(ProbeError::Malformed("no Segment"))));
+    }
+
+    #[test]
+    fn children_stop_at_an_element_that_does_not_fit() {
+        let mut data = [uint_element(TRACK_NUMBER, 1), element(CODEC_ID, b"V_VP9")].concat();
+        data.truncate(data.len() - 1);
+        let found = children(&data);
+        assert_eq!(found.len(), 1);
+        assert_eq!((found[0].0, uint(found[0].1)), (TRACK_NUMBER, 1));
+    }
+}
@@ -0,0 +1,352 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+pub mod asf;
+pub mod avi;
+pub mod flv;
+pub mod id3;
+pub mod mkv;
+pub mod mp3;
+pub mod mp4;
+pub mod ts;
+
+use id3::Picture;
+use mkv::{Attachment, ChapterEdition};
+use ts::Program;
+
+// What the container probes found out about a file. The summary fields mirror the
+// columns on `videos`; the whole struct is stored as JSON in `videos.media_info`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MediaInfo {
+    pub container: String,
+    // title stored in the container itself, if any
+    pub title: Option<String>,
+    // ID3 tags of audio files
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub artist: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub album: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub track: Option<u32>,
+    // embedded cover image, the bytes are read on demand by `get_cover_art`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cover: Option<Picture>,
+    // seconds
+    pub duration: Option<f64>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub frame_rate: Option<f64>,
+    pub video_codec: Option<String>,
+    pub audio_codec: Option<String>,
+    pub audio_channels: Option<u32>,
+    // clockwise degrees the video has to be turned for display (0, 90, 180, 270)
+    pub rotation: Option<i32>,
+    pub streams: Vec<StreamInfo>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<Attachment>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub chapters: Vec<ChapterEdition>,
+    // MPEG-TS only: programs from the PAT/PMT, each listing the PIDs of its streams
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub programs: Vec<Program>,
+}
+
+// Whether a library entry is watched or listened to; stored in `videos.media_kind`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MediaKind {
+    Video,
+    Audio,
+}
+
+impl MediaKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            MediaKind::Video => "video",
+            MediaKind::Audio => "audio",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum StreamKind {
+    Video,
+    Audio,
+    Subtitle,
+    #[default]
+    Other,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StreamInfo {
+    // track id as stored in the container (the PID for MPEG-TS)
+    pub id: u64,
+    pub kind: StreamKind,
+    // codec as the container names it: a FourCC for MP4 ("avc1", "mp4a.40.2"), the
+    // codec ID for Matroska ("V_MPEG4/ISO/AVC", "A_OPUS"), a name derived from the
+    // PMT stream type for MPEG-TS ("h264", "ac3"), the compression FourCC for AVI and
+    // ASF video ("XVID", "WMV3") with the WAVE format tag mapped to a name for their
+    // audio ("mp3", "wmav2"), and the codec id mapped to a name for FLV ("flv1", "aac")
+    pub codec: String,
+    pub language: Option<String>,
+    pub name: Option<String>,
+    pub default: bool,
+    pub forced: bool,
+    pub duration: Option<f64>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub frame_rate: Option<f64>,
+    pub rotation: Option<i32>,
+    pub channels: Option<u32>,
+    pub channel_layout: Option<String>,
+    pub sample_rate: Option<u32>,
+}
+
+#[derive(Debug)]
+pub enum ProbeError {
+    Io(io::Error),
+    // the file looks like the format but its structure is broken
+    Malformed(&'static str),
+}
+
+impl fmt::Display for ProbeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProbeError::Io(e) => write!(f, "{}", e),
+            ProbeError::Malformed(what) => write!(f, "malformed file: {}", what),
+        }
+    }
+}
+
+impl std::error::Error for ProbeError {}
+
+impl From<io::Error> for ProbeError {
+    fn from(e: io::Error) -> Self {
+        ProbeError::Io(e)
+    }
+}
+
+// Probes the file with the parser matching its first bytes. `Ok(None)` means the
+// format is not one we understand.
+pub fn probe_file(path: &Path) -> Result<Option<MediaInfo>, ProbeError> {
+    let mut reader = BufReader::new(File::open(path)?);
+    // long enough to see several transport stream packets
+    let mut head = [0u8; 1024];
+    let n = read_up_to(&mut reader, &mut head)?;
+    let head = &head[..n];
+    reader.seek(SeekFrom::Start(0))?;
+
+    let mut info = if mp4::is_mp4(head) {
+        mp4::probe(&mut reader)?
+    } else if mkv::is_mkv(head) {
+        mkv::probe(&mut reader)?
+    } else if avi::is_avi(head) {
+        avi::probe(&mut reader)?
+    } else if flv::is_flv(head) {
+        flv::probe(&mut reader)?
+    } else if asf::is_asf(head) {
+        asf::probe(&mut reader)?
+    } else if mp3::is_mp3(head) {
+        mp3::probe(&mut reader)?
+    } else if ts::is_ts(head) {
+        ts::probe(&mut reader)?
+    } else {
+        return Ok(None);
+    };
+    info.summarize();
+    Ok(Some(info))
+}
+
+impl MediaInfo {
+    // Fills the summary fields from the streams the player picks, see `selected_streams`.
+    pub fn summarize(&mut self) {
+        let (video, audio) = self.selected_streams();
+        let (video, audio) = (video.cloned(), audio.cloned());
+        if let Some(v) = video {
+            self.width = self.width.or(v.width);
+            self.height = self.height.or(v.height);
+            self.frame_rate = self.frame_rate.or(v.frame_rate);
+            self.rotation = self.rotation.or(v.rotation);
+            if self.video_codec.is_none() {
+                self.video_codec = Some(v.codec);
+            }
+        }
+        if let Some(a) = audio {
+            self.audio_channels = self.audio_channels.or(a.channels);
+            if self.audio_codec.is_none() {
+                self.audio_codec = Some(a.codec);
+            }
+        }
+        if self.duration.is_none() {
+            self.duration = self.streams.iter().filter_map(|s| s.duration).reduce(f64::max);
+        }
+    }
+
+    // The first video stream and the default (or first) audio stream.
+    pub fn selected_streams(&self) -> (Option<&StreamInfo>, Option<&StreamInfo>) {
+        let video = self.streams.iter().find(|s| s.kind == StreamKind::Video);
+        let audio = self
+            .streams
+            .iter()
+            .find(|s| s.kind == StreamKind::Audio && s.default)
+            .or_else(|| self.streams.iter().find(|s| s.kind == StreamKind::Audio));
+        (video, audio)
+    }
+
+    // Audio when there is sound but no picture; cover art is not a video stream.
+    pub fn media_kind(&self) -> MediaKind {
+        let has = |kind| self.streams.iter().any(|s| s.kind == kind);
+        if !has(StreamKind::Video) && has(StreamKind::Audio) {
+            MediaKind::Audio
+        } else {
+            MediaKind::Video
+        }
+    }
+}
+
+// Codec family of a stream codec however the container names it, so "avc1",
+// "V_MPEG4/ISO/AVC" and "h264" are all "h264". Unknown codecs come back lowercased.
+pub fn codec_family(codec: &str) -> String {
+    let lower = codec.trim().to_ascii_lowercase();
+    let family = match lower.as_str() {
+        "avc1" | "avc3" | "h264" | "x264" | "v_mpeg4/iso/avc" => "h264",
+        "hvc1" | "hev1" | "hevc" | "h265" | "v_mpegh/iso/hevc" => "hevc",
+        "vp08" | "vp8" | "v_vp8" => "vp8",
+        "vp09" | "vp9" | "v_vp9" => "vp9",
+        "av01" | "av1" | "v_av1" => "av1",
+        "mp4v" | "mpeg4" | "xvid" | "divx" | "dx50" | "fmp4" | "v_mpeg4/iso/asp" | "v_mpeg4/iso/sp" => "mpeg4",
+        "mpeg1video" | "v_mpeg1" => "mpeg1video",
+        "mpeg2video" | "mp2v" | "v_mpeg2" => "mpeg2video",
+        "wmv1" | "wmv2" => "wmv",
+        "wmv3" | "wvc1" | "vc1" => "vc1",
+        "aac" | "aac_latm" | "mp4a" => "aac",
+        "mp3" | "mp4a.6b" | "mp4a.69" | "a_mpeg/l3" => "mp3",
+        "mp2" | "a_mpeg/l2" => "mp2",
+        "mp1" | "a_mpeg/l1" => "mp1",
+        "opus" | "a_opus" => "opus",
+        "vorbis" | "a_vorbis" => "vorbis",
+        "flac" | "a_flac" => "flac",
+        "ac-3" | "ac3" | "mp4a.a5" => "ac3",
+        "ec-3" | "eac3" | "mp4a.a6" | "a_eac3" => "eac3",
+        "dts" | "dtsc" | "dtsh" | "dtsl" => "dts",
+        "truehd" | "mlpa" | "a_truehd" => "truehd",
+        "alac" | "a_alac" => "alac",
+        "wmav1" | "wmav2" | "wmapro" | "wmalossless" | "wmavoice" => "wma",
+        "lpcm" | "sowt" | "twos" | "ipcm" | "fpcm" => "pcm",
+        c if c.starts_with("mp4a.40") || c.starts_with("a_aac") => "aac",
+        c if c.starts_with("a_ac3") => "ac3",
+        c if c.starts_with("a_dts") => "dts",
+        c if c.starts_with("pcm") || c.starts_with("a_pcm") => "pcm",
+        _ => return lower,
+    };
+    family.to_string()
+}
+
+// Common speaker layouts by channel count.
+pub fn channel_layout(channels: u32) -> Option<String> {
+    let name = match channels {
+        1 => "mono",
+        2 => "stereo",
+        3 => "2.1",
+        4 => "quad",
+        5 => "5.0",
+        6 => "5.1",
+        7 => "6.1",
+        8 => "7.1",
+        _ => return None,
+    };
+    Some(name.to_string())
+}
+
+fn read_up_to<R: Read>(r: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match r.read(&mut buf[filled..])? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    Ok(filled)
+}
+
+// Big-endian reader over an in-memory buffer, with `_le` variants for RIFF and ASF.
+// Every read fails with `Malformed` instead of panicking when the data is cut short.
+pub struct ByteReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        ByteReader { data, pos: 0 }
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    pub fn skip(&mut self, n: usize) -> Result<(), ProbeError> {
+        self.bytes(n).map(|_| ())
+    }
+
+    pub fn bytes(&mut self, n: usize) -> Result<&'a [u8], ProbeError> {
+        if self.remaining() < n {
+            return Err(ProbeError::Malformed("unexpected end of data"));
+        }
+        let out = &self.data[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(out)
+    }
+
+    pub fn rest(&mut self) -> &'a [u8] {
+        let out = &self.data[self.pos..];
+        self.pos = self.data.len();
+        out
+    }
+
+    pub fn u8(&mut self) -> Result<u8, ProbeError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, ProbeError> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    pub fn u24(&mut self) -> Result<u32, ProbeError> {
+        let b = self.bytes(3)?;
+        Ok(u32::from_be_bytes([0, b[0], b[1], b[2]]))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, ProbeError> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, ProbeError> {
+        let b = self.bytes(8)?;
+        Ok(u64::from_be_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]))
+    }
+
+    pub fn u16_le(&mut self) -> Result<u16, ProbeError> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    pub fn u32_le(&mut self) -> Result<u32, ProbeError> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    pub fn u64_le(&mut self) -> Result<u64, ProbeError> {
+        let b = self.bytes(8)?;
+        Ok(u64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]))
+    }
+}
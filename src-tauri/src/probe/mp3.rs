@@ -0,0 +1,349 @@
+// MPEG audio (MP3, and MP1/MP2 which share the frame format). The duration comes from
+// the Xing/Info or VBRI header in the first frame when there is one, minus the encoder
+// delay and padding from a LAME tag. Without one the file is usually CBR and the size
+// over the bitrate gives the duration; when the first frames change bitrate, it is VBR
+// after all and every frame is walked.
+use std::io::{Read, Seek, SeekFrom};
+
+use super::id3;
+use super::{channel_layout, read_up_to, MediaInfo, ProbeError, StreamInfo, StreamKind};
+
+// How far past the ID3 tag we look for the first frame.
+const MAX_JUNK: usize = 64 * 1024;
+// Chunk size for the frame walk.
+const SCAN_CHUNK: usize = 1024 * 1024;
+// Frames compared with the first one before a file without a VBR header counts as CBR.
+const CBR_CHECK_FRAMES: usize = 32;
+
+const BITRATES_V1: [[u32; 15]; 3] = [
+    [0, 32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448],
+    [0, 32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384],
+    [0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320],
+];
+const BITRATES_V2: [[u32; 15]; 3] = [
+    [0, 32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256],
+    [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160],
+    [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160],
+];
+
+pub fn is_mp3(head: &[u8]) -> bool {
+    head.starts_with(b"ID3") || (head.len() >= 4 && Frame::parse(&head[0..4]).is_some())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Version {
+    V1,
+    V2,
+    V25,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Frame {
+    version: Version,
+    // 1, 2 or 3
+    layer: u8,
+    // bits per second
+    bitrate: u32,
+    sample_rate: u32,
+    channels: u32,
+    samples: u32,
+    length: usize,
+}
+
+impl Frame {
+    fn parse(h: &[u8]) -> Option<Frame> {
+        if h[0] != 0xff || h[1] & 0xe0 != 0xe0 {
+            return None;
+        }
+        let version = match (h[1] >> 3) & 0x03 {
+            0 => Version::V25,
+            2 => Version::V2,
+            3 => Version::V1,
+            _ => return None,
+        };
+        let layer = match (h[1] >> 1) & 0x03 {
+            1 => 3,
+            2 => 2,
+            3 => 1,
+            _ => return None,
+        };
+        // 0 is the free format, whose frame size we cannot know from the header
+        let bitrate_index = (h[2] >> 4) as usize;
+        let rate_index = ((h[2] >> 2) & 0x03) as usize;
+        if bitrate_index == 0 || bitrate_index == 15 || rate_index == 3 {
+            return None;
+        }
+        let table = if version == Version::V1 { &BITRATES_V1 } else { &BITRATES_V2 };
+        let bitrate = table[layer as usize - 1][bitrate_index] * 1000;
+        let sample_rate = match version {
+            Version::V1 => [44100, 48000, 32000][rate_index],
+            Version::V2 => [22050, 24000, 16000][rate_index],
+            Version::V25 => [11025, 12000, 8000][rate_index],
+        };
+        let padding = ((h[2] >> 1) & 0x01) as usize;
+        let samples = match (layer, version) {
+            (1, _) => 384,
+            (3, Version::V2 | Version::V25) => 576,
+            _ => 1152,
+        };
+        let length = if layer == 1 {
+            (12 * bitrate as usize / sample_rate as usize + padding) * 4
+        } else {
+            samples as usize / 8 * bitrate as usize / sample_rate as usize + padding
+        };
+        Some(Frame { version, layer, bitrate, sample_rate, channels: if h[3] >> 6 == 3 { 1 } else { 2 }, samples, length })
+    }
+
+    // Frames following this one have to agree on these to count as the same stream.
+    fn same_stream(&self, other: &Frame) -> bool {
+        self.version == other.version && self.layer == other.layer && self.sample_rate == other.sample_rate
+    }
+
+    // Offset of the Xing/Info header, right after the side information.
+    fn xing_offset(&self) -> usize {
+        match (self.version, self.channels) {
+            (Version::V1, 1) => 4 + 17,
+            (Version::V1, _) => 4 + 32,
+            (_, 1) => 4 + 9,
+            _ => 4 + 17,
+        }
+    }
+}
+
+pub fn probe<R: Read + Seek>(r: &mut R) -> Result<MediaInfo, ProbeError> {
+    let file_len = r.seek(SeekFrom::End(0))?;
+    let mut tags = id3::read_v2(r)?.unwrap_or_default();
+    let v1 = id3::read_v1(r, file_len)?;
+    if let Some(v1) = v1.clone() {
+        tags.merge(v1);
+    }
+    // the audio ends before an ID3v1 block
+    let audio_end = if v1.is_some() { file_len - 128 } else { file_len };
+
+    let mut head = [0u8; 10];
+    r.seek(SeekFrom::Start(0))?;
+    let n = read_up_to(r, &mut head)?;
+    let tag_size = id3::v2_size(&head[..n]) as u64;
+
+    let (start, frame, first) = find_first_frame(r, tag_size, audio_end)?;
+    let total_samples = match vbr_header(&frame, &first) {
+        Some(samples) => samples,
+        None if constant_bitrate(r, start, audio_end, &frame)? => {
+            audio_end.saturating_sub(start) * 8 * frame.sample_rate as u64 / frame.bitrate as u64
+        }
+        None => scan_frames(r, start, audio_end, &frame)?,
+    };
+
+    let duration = Some(total_samples as f64 / frame.sample_rate as f64).filter(|d| *d > 0.0);
+    let stream = StreamInfo {
+        id: 0,
+        kind: StreamKind::Audio,
+        codec: format!("mp{}", frame.layer),
+        default: true,
+        duration,
+        channels: Some(frame.channels),
+        channel_layout: channel_layout(frame.channels),
+        sample_rate: Some(frame.sample_rate),
+        ..Default::default()
+    };
+    let cover = tags.cover().map(|p| id3::Picture { data: Vec::new(), ..p.clone() });
+    Ok(MediaInfo {
+        container: "mp3".to_string(),
+        title: tags.title,
+        artist: tags.artist,
+        album: tags.album,
+        track: tags.track,
+        cover,
+        duration,
+        streams: vec![stream],
+        ..Default::default()
+    })
+}
+
+// Position, header and bytes of the first frame: the first sync whose successor is a
+// matching frame too, so a stray 0xFF in leftover junk is not taken for audio.
+fn find_first_frame<R: Read + Seek>(r: &mut R, from: u64, end: u64) -> Result<(u64, Frame, Vec<u8>), ProbeError> {
+    r.seek(SeekFrom::Start(from))?;
+    let mut buf = vec![0u8; (MAX_JUNK + 2 * 4096).min(end.saturating_sub(from) as usize)];
+    let n = read_up_to(r, &mut buf)?;
+    buf.truncate(n);
+    let mut pos = 0;
+    while pos + 4 <= buf.len().min(MAX_JUNK + 4) {
+        if let Some(frame) = Frame::parse(&buf[pos..pos + 4]) {
+            let next = pos + frame.length;
+            let confirmed = match buf.get(next..next + 4) {
+                Some(h) => Frame::parse(h).is_some_and(|f| f.same_stream(&frame)),
+                // a file holding a single frame
+                None => from + next as u64 >= end,
+            };
+            if confirmed {
+                let bytes = buf[pos..(pos + frame.length).min(buf.len())].to_vec();
+                return Ok((from + pos as u64, frame, bytes));
+            }
+        }
+        pos += 1;
+    }
+    Err(ProbeError::Malformed("no MPEG audio frame"))
+}
+
+// Sample count from a Xing/Info or VBRI header in the first frame.
+fn vbr_header(frame: &Frame, data: &[u8]) -> Option<u64> {
+    let offset = frame.xing_offset();
+    if let Some(xing) = data.get(offset..).filter(|d| d.starts_with(b"Xing") || d.starts_with(b"Info")) {
+        let flags = u32::from_be_bytes(xing.get(4..8)?.try_into().ok()?);
+        if flags & 0x01 == 0 {
+            return None;
+        }
+        let frames = u32::from_be_bytes(xing.get(8..12)?.try_into().ok()?) as u64;
+        let mut pos = 12;
+        for (flag, len) in [(0x02, 4), (0x04, 100), (0x08, 4)] {
+            if flags & flag != 0 {
+                pos += len;
+            }
+        }
+        let samples = frames * frame.samples as u64;
+        return Some(samples.saturating_sub(encoder_delay(xing.get(pos..).unwrap_or_default())));
+    }
+    // VBRI sits at a fixed offset regardless of the channel mode
+    let vbri = data.get(4 + 32..).filter(|d| d.starts_with(b"VBRI"))?;
+    let frames = u32::from_be_bytes(vbri.get(14..18)?.try_into().ok()?) as u64;
+    Some(frames * frame.samples as u64)
+}
+
+// Encoder delay plus padding in samples from the LAME tag that follows the Xing header.
+fn encoder_delay(tag: &[u8]) -> u64 {
+    if !(tag.starts_with(b"LAME") || tag.starts_with(b"Lavf") || tag.starts_with(b"Lavc")) || tag.len() < 24 {
+        return 0;
+    }
+    let b = &tag[21..24];
+    let delay = ((b[0] as u64) << 4) | (b[1] as u64 >> 4);
+    let padding = (((b[1] & 0x0f) as u64) << 8) | b[2] as u64;
+    delay + padding
+}
+
+// Whether the frames after `first` keep its bitrate, as far as CBR_CHECK_FRAMES of them.
+fn constant_bitrate<R: Read + Seek>(r: &mut R, start: u64, end: u64, first: &Frame) -> Result<bool, ProbeError> {
+    r.seek(SeekFrom::Start(start))?;
+    let mut buf = vec![0u8; ((first.length + 4) * CBR_CHECK_FRAMES).min(end.saturating_sub(start) as usize)];
+    let n = read_up_to(r, &mut buf)?;
+    buf.truncate(n);
+    let mut pos = 0;
+    for _ in 0..CBR_CHECK_FRAMES {
+        let Some(frame) = buf.get(pos..pos + 4).and_then(Frame::parse).filter(|f| f.same_stream(first)) else {
+            break;
+        };
+        if frame.bitrate != first.bitrate {
+            return Ok(false);
+        }
+        pos += frame.length;
+    }
+    Ok(true)
+}
+
+// Counts the samples of every frame from `start` to `end`, resyncing over garbage.
+fn scan_frames<R: Read + Seek>(r: &mut R, start: u64, end: u64, first: &Frame) -> Result<u64, ProbeError> {
+    r.seek(SeekFrom::Start(start))?;
+    let mut remaining = end.saturating_sub(start);
+    let mut chunk = vec![0u8; SCAN_CHUNK];
+    let mut buf: Vec<u8> = Vec::new();
+    // bytes of the last frame that lie beyond the buffer
+    let mut skip = 0usize;
+    let mut samples = 0u64;
+    while remaining > 0 {
+        let n = read_up_to(r, &mut chunk[..SCAN_CHUNK.min(remaining as usize)])?;
+        if n == 0 {
+            break;
+        }
+        remaining -= n as u64;
+        let data = &chunk[skip.min(n)..n];
+        skip -= skip.min(n);
+        buf.extend_from_slice(data);
+
+        let mut pos = 0;
+        while pos + 4 <= buf.len() {
+            match Frame::parse(&buf[pos..pos + 4]).filter(|f| f.same_stream(first)) {
+                Some(frame) => {
+                    samples += frame.samples as u64;
+                    pos += frame.length;
+                }
+                None => pos += 1,
+            }
+        }
+        skip += pos.saturating_sub(buf.len());
+        buf.drain(..pos.min(buf.len()));
+    }
+    Ok(samples)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    // MPEG-1 layer III frames at 44.1 kHz, stereo, without padding; `rates` are bitrate
+    // indexes (9 = 128 kbit/s, 10 = 160 kbit/s).
+    fn frames(rates: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for &rate in rates {
+            let header = [0xff, 0xfb, rate << 4, 0x00];
+            let length = Frame::parse(&header).unwrap().length;
+            out.extend_from_slice(&header);
+            out.resize(out.len() + length - 4, 0);
+        }
+        out
+    }
+
+    #[test]
+    fn cbr_duration_is_estimated_from_the_bitrate() {
+        let info = probe(&mut Cursor::new(frames(&[9; 100]))).unwrap();
+        // 100 frames of 417 bytes at 128 kbit/s; each frame holds 1152 samples (2.612 s)
+        assert!((info.duration.unwrap() - 41700.0 * 8.0 / 128000.0).abs() < 0.001);
+        assert_eq!(info.streams[0].codec, "mp3");
+        assert_eq!(info.streams[0].channels, Some(2));
+    }
+
+    #[test]
+    fn vbr_without_header_counts_every_frame() {
+        let rates: Vec<u8> = (0..100).map(|i| if i % 2 == 0 { 9 } else { 10 }).collect();
+        let info = probe(&mut Cursor::new(frames(&rates))).unwrap();
+        assert!((info.duration.unwrap() - 100.0 * 1152.0 / 44100.0).abs() < 0.001);
+    }
+
+    #[test]
+    fn xing_header_gives_the_frame_count_minus_the_encoder_delay() {
+        let mut data = frames(&[9; 2]);
+        // stereo MPEG-1: the Xing header follows 32 bytes of side information
+        let xing = [b"Xing".as_slice(), &[0, 0, 0, 1], &1000u32.to_be_bytes()].concat();
+        data[36..36 + xing.len()].copy_from_slice(&xing);
+        // LAME tag with 576 samples of delay and 1152 of padding
+        let mut lame = b"LAME3.100".to_vec();
+        lame.resize(21, 0);
+        lame.extend_from_slice(&[0x24, 0x04, 0x80]);
+        data[48..48 + lame.len()].copy_from_slice(&lame);
+        let info = probe(&mut Cursor::new(data)).unwrap();
+        assert!((info.duration.unwrap() - (1_152_000.0 - 1728.0) / 44100.0).abs() < 0.0001);
+    }
+
+    #[test]
+    fn tags_and_junk_around_the_frames() {
+        let title = b"TIT2\0\0\0\x06\0\0\0Title";
+        let mut data = [b"ID3\x03\0\0\0\0\0\x10".as_slice(), title].concat();
+        // a stray sync byte in the junk before the first frame
+        data.extend_from_slice(&[0xff, 0xfb, 0x90, 0, 0x12, 0x34]);
+        data.extend(frames(&[9; 10]));
+        let mut v1 = [0u8; 128];
+        v1[0..3].copy_from_slice(b"TAG");
+        v1[63..68].copy_from_slice(b"Album");
+        data.extend_from_slice(&v1);
+        let info = probe(&mut Cursor::new(data)).unwrap();
+
+        assert_eq!((info.title.as_deref(), info.album.as_deref()), (Some("Title"), Some("Album")));
+        // the ID3v1 block is not audio
+        assert!((info.duration.unwrap() - 4170.0 * 8.0 / 128000.0).abs() < 0.001);
+    }
+
+    #[test]
+    fn junk_without_frames_is_malformed() {
+        assert!(matches!(probe(&mut Cursor::new(vec![0x55; 4096])), Err(ProbeError::Malformed("no MPEG audio frame"))));
+    }
+}
@@ -0,0 +1,565 @@
+// ISO base media file format (MP4, M4V, MOV). Only the `moov` box is read; `mdat`
+// with the actual media is skipped by seeking over it, so probing costs a few reads
+// even for large files with the index at the end.
+use std::io::{Read, Seek, SeekFrom};
+
+use super::{channel_layout, ByteReader, MediaInfo, ProbeError, StreamInfo, StreamKind};
+
+// Upper bound for the `moov` box we load into memory; real files stay far below.
+const MAX_MOOV_SIZE: u64 = 64 * 1024 * 1024;
+
+pub fn is_mp4(head: &[u8]) -> bool {
+    head.len() >= 8 && matches!(&head[4..8], b"ftyp" | b"moov" | b"mdat" | b"free" | b"wide" | b"skip" | b"pnot")
+}
+
+pub fn probe<R: Read + Seek>(r: &mut R) -> Result<MediaInfo, ProbeError> {
+    let file_len = r.seek(SeekFrom::End(0))?;
+    let mut pos = 0u64;
+    let mut brand = None;
+    let mut moov = None;
+
+    while file_len.saturating_sub(pos) >= 8 {
+        r.seek(SeekFrom::Start(pos))?;
+        let mut header = [0u8; 8];
+        r.read_exact(&mut header)?;
+        let mut size = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as u64;
+        let mut header_len = 8;
+        if size == 1 {
+            let mut large = [0u8; 8];
+            r.read_exact(&mut large)?;
+            size = u64::from_be_bytes(large);
+            header_len = 16;
+        } else if size == 0 {
+            // box runs to the end of the file
+            size = file_len - pos;
+        }
+        if size < header_len {
+            return Err(ProbeError::Malformed("box size"));
+        }
+        let body_len = size - header_len;
+        match &header[4..8] {
+            b"ftyp" if body_len >= 4 => {
+                let mut major = [0u8; 4];
+                r.read_exact(&mut major)?;
+                brand = Some(major);
+            }
+            b"moov" => {
+                if body_len > MAX_MOOV_SIZE {
+                    return Err(ProbeError::Malformed("moov box too large"));
+                }
+                let mut body = vec![0u8; body_len as usize];
+                r.read_exact(&mut body)?;
+                moov = Some(body);
+                break;
+            }
+            _ => {}
+        }
+        pos = pos.checked_add(size).ok_or(ProbeError::Malformed("box size"))?;
+    }
+
+    let moov = moov.ok_or(ProbeError::Malformed("no moov box"))?;
+    let container = if brand == Some(*b"qt  ") { "mov" } else { "mp4" };
+    parse_moov(&moov, container)
+}
+
+// Child boxes of a container box. Stops at the first box that does not fit.
+fn boxes(data: &[u8]) -> Vec<([u8; 4], &[u8])> {
+    let mut out = Vec::new();
+    let mut r = ByteReader::new(data);
+    while r.remaining() >= 8 {
+        let Ok(size) = r.u32() else { break };
+        let Ok(kind) = r.bytes(4) else { break };
+        let kind = [kind[0], kind[1], kind[2], kind[3]];
+        let body_len = match size {
+            0 => r.remaining(),
+            1 => match r.u64() {
+                Ok(large) if large >= 16 => (large - 16) as usize,
+                _ => break,
+            },
+            n if n >= 8 => n as usize - 8,
+            _ => break,
+        };
+        match r.bytes(body_len) {
+            Ok(body) => out.push((kind, body)),
+            Err(_) => break,
+        }
+    }
+    out
+}
+
+fn child<'a>(data: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    boxes(data).into_iter().find(|(k, _)| k == kind).map(|(_, body)| body)
+}
+
+fn parse_moov(moov: &[u8], container: &str) -> Result<MediaInfo, ProbeError> {
+    let mut info = MediaInfo { container: container.to_string(), ..Default::default() };
+    let mut timescale = 0u32;
+
+    for (kind, body) in boxes(moov) {
+        match &kind {
+            b"mvhd" => {
+                let mut r = ByteReader::new(body);
+                let version = r.u8()?;
+                r.skip(3)?;
+                let duration = if version == 1 {
+                    r.skip(16)?;
+                    timescale = r.u32()?;
+                    r.u64()?
+                } else {
+                    r.skip(8)?;
+                    timescale = r.u32()?;
+                    r.u32()? as u64
+                };
+                info.duration = seconds(duration, timescale);
+            }
+            b"trak" => {
+                if let Some(stream) = parse_trak(body)? {
+                    info.streams.push(stream);
+                }
+            }
+            // fragmented files usually leave the mvhd duration at zero
+            b"mvex" if info.duration.is_none() => {
+                if let Some(mehd) = child(body, b"mehd") {
+                    let mut r = ByteReader::new(mehd);
+                    let version = r.u8()?;
+                    r.skip(3)?;
+                    let duration = if version == 1 { r.u64()? } else { r.u32()? as u64 };
+                    info.duration = seconds(duration, timescale);
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(info)
+}
+
+fn seconds(duration: u64, timescale: u32) -> Option<f64> {
+    // all ones means "unknown" in both header versions
+    if timescale == 0 || duration == 0 || duration == u32::MAX as u64 || duration == u64::MAX {
+        None
+    } else {
+        Some(duration as f64 / timescale as f64)
+    }
+}
+
+fn parse_trak(trak: &[u8]) -> Result<Option<StreamInfo>, ProbeError> {
+    let mut stream = StreamInfo::default();
+    let mut handler = [0u8; 4];
+    let mut sample_count = 0u64;
+    let mut stsd = None;
+
+    for (kind, body) in boxes(trak) {
+        match &kind {
+            b"tkhd" => parse_tkhd(body, &mut stream)?,
+            b"mdia" => {
+                for (kind, body) in boxes(body) {
+                    match &kind {
+                        b"mdhd" => parse_mdhd(body, &mut stream)?,
+                        b"hdlr" => {
+                            let mut r = ByteReader::new(body);
+                            r.skip(8)?;
+                            handler.copy_from_slice(r.bytes(4)?);
+                        }
+                        b"minf" => {
+                            let Some(stbl) = child(body, b"stbl") else { continue };
+                            stsd = child(stbl, b"stsd");
+                            if let Some(stts) = child(stbl, b"stts") {
+                                sample_count = count_samples(stts)?;
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    stream.kind = match &handler {
+        b"vide" => StreamKind::Video,
+        b"soun" => StreamKind::Audio,
+        b"sbtl" | b"subt" | b"text" | b"clcp" => StreamKind::Subtitle,
+        // hint, metadata and timecode tracks are not interesting for the library
+        _ => return Ok(None),
+    };
+    if let Some(stsd) = stsd {
+        parse_stsd(stsd, &mut stream)?;
+    }
+    match stream.kind {
+        StreamKind::Video => {
+            if let Some(duration) = stream.duration.filter(|d| *d > 0.0) {
+                if sample_count > 0 {
+                    stream.frame_rate = Some(sample_count as f64 / duration);
+                }
+            }
+        }
+        // tkhd sizes of audio tracks are zero; nothing to show
+        _ => {
+            stream.width = None;
+            stream.height = None;
+            stream.rotation = None;
+        }
+    }
+    Ok(Some(stream))
+}
+
+fn parse_tkhd(body: &[u8], stream: &mut StreamInfo) -> Result<(), ProbeError> {
+    let mut r = ByteReader::new(body);
+    let version = r.u8()?;
+    let flags = r.u24()?;
+    if version == 1 {
+        r.skip(16)?;
+        stream.id = r.u32()? as u64;
+        r.skip(4 + 8)?;
+    } else {
+        r.skip(8)?;
+        stream.id = r.u32()? as u64;
+        r.skip(4 + 4)?;
+    }
+    // reserved, layer, alternate group, volume, reserved
+    r.skip(8 + 2 + 2 + 2 + 2)?;
+    let mut matrix = [0i32; 9];
+    for m in matrix.iter_mut() {
+        *m = r.u32()? as i32;
+    }
+    let width = r.u32()? >> 16;
+    let height = r.u32()? >> 16;
+
+    // bit 0: track enabled; MP4 has no separate "default" flag
+    stream.default = flags & 1 != 0;
+    if width > 0 && height > 0 {
+        stream.width = Some(width);
+        stream.height = Some(height);
+    }
+    stream.rotation = Some(rotation(&matrix));
+    Ok(())
+}
+
+// Rotation encoded in the 16.16 fixed point display matrix [a b u; c d v; x y w].
+fn rotation(matrix: &[i32; 9]) -> i32 {
+    let a = matrix[0] as f64 / 65536.0;
+    let b = matrix[1] as f64 / 65536.0;
+    let degrees = b.atan2(a).to_degrees().round() as i32;
+    // snap to quarter turns, other angles do not occur in practice
+    (((degrees + 45).rem_euclid(360)) / 90 * 90) % 360
+}
+
+fn parse_mdhd(body: &[u8], stream: &mut StreamInfo) -> Result<(), ProbeError> {
+    let mut r = ByteReader::new(body);
+    let version = r.u8()?;
+    r.skip(3)?;
+    let (timescale, duration) = if version == 1 {
+        r.skip(16)?;
+        let timescale = r.u32()?;
+        (timescale, r.u64()?)
+    } else {
+        r.skip(8)?;
+        let timescale = r.u32()?;
+        (timescale, r.u32()? as u64)
+    };
+    stream.duration = seconds(duration, timescale);
+
+    // ISO 639-2 code packed as three 5-bit letters
+    let packed = r.u16()?;
+    let language: String = [(packed >> 10) & 0x1f, (packed >> 5) & 0x1f, packed & 0x1f]
+        .iter()
+        .map(|c| (*c as u8 + 0x60) as char)
+        .collect();
+    if language.chars().all(|c| c.is_ascii_lowercase()) && language != "und" {
+        stream.language = Some(language);
+    }
+    Ok(())
+}
+
+fn count_samples(stts: &[u8]) -> Result<u64, ProbeError> {
+    let mut r = ByteReader::new(stts);
+    r.skip(4)?;
+    let entries = r.u32()?;
+    let mut total = 0u64;
+    for _ in 0..entries {
+        total += r.u32()? as u64;
+        r.skip(4)?;
+    }
+    Ok(total)
+}
+
+fn parse_stsd(stsd: &[u8], stream: &mut StreamInfo) -> Result<(), ProbeError> {
+    let mut r = ByteReader::new(stsd);
+    r.skip(8)?;
+    // only the first sample entry; more than one is rare and describes the same codec
+    let Some((format, entry)) = boxes(r.rest()).into_iter().next() else {
+        return Ok(());
+    };
+    stream.codec = String::from_utf8_lossy(&format).trim_end().to_string();
+
+    let mut r = ByteReader::new(entry);
+    // reserved and data reference index
+    r.skip(8)?;
+    match stream.kind {
+        StreamKind::Video => {
+            r.skip(16)?;
+            let width = r.u16()? as u32;
+            let height = r.u16()? as u32;
+            if stream.width.is_none() && width > 0 && height > 0 {
+                stream.width = Some(width);
+                stream.height = Some(height);
+            }
+        }
+        StreamKind::Audio if entry.len() >= 28 => {
+            // sound sample entry; QuickTime version 2 moves the fields
+            let version = r.u16()?;
+            r.skip(6)?;
+            let mut channels = r.u16()? as u32;
+            r.skip(6)?;
+            let mut sample_rate = r.u32()? >> 16;
+            let mut extensions = &entry[28..];
+            match version {
+                1 if entry.len() >= 44 => extensions = &entry[44..],
+                2 if entry.len() >= 64 => {
+                    let mut v2 = ByteReader::new(&entry[28..]);
+                    v2.skip(4)?;
+                    sample_rate = f64::from_bits(v2.u64()?) as u32;
+                    channels = v2.u32()?;
+                    extensions = &entry[64..];
+                }
+                _ => {}
+            }
+            if &format == b"mp4a" {
+                // the sample entry always claims stereo for AAC; the decoder config is right
+                if let Some(esds) = find_esds(extensions) {
+                    if let Some((codec, config_channels)) = parse_esds(esds) {
+                        stream.codec = codec;
+                        channels = config_channels.unwrap_or(channels);
+                    }
+                }
+            }
+            if channels > 0 {
+                stream.channels = Some(channels);
+                stream.channel_layout = channel_layout(channels);
+            }
+            if sample_rate > 0 {
+                stream.sample_rate = Some(sample_rate);
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+// QuickTime nests the esds box inside a `wave` box.
+fn find_esds(data: &[u8]) -> Option<&[u8]> {
+    for (kind, body) in boxes(data) {
+        match &kind {
+            b"esds" => return Some(body),
+            b"wave" => {
+                if let Some(esds) = find_esds(body) {
+                    return Some(esds);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+// Codec string in RFC 6381 form ("mp4a.40.2" for AAC-LC, "mp4a.6B" for MP3) and the
+// channel count from the AAC AudioSpecificConfig, if present.
+fn parse_esds(esds: &[u8]) -> Option<(String, Option<u32>)> {
+    let mut r = ByteReader::new(esds);
+    r.skip(4).ok()?;
+    let (tag, _) = descriptor(&mut r)?;
+    if tag != 0x03 {
+        return None;
+    }
+    r.skip(2).ok()?;
+    let flags = r.u8().ok()?;
+    if flags & 0x80 != 0 {
+        r.skip(2).ok()?;
+    }
+    if flags & 0x40 != 0 {
+        let len = r.u8().ok()? as usize;
+        r.skip(len).ok()?;
+    }
+    if flags & 0x20 != 0 {
+        r.skip(2).ok()?;
+    }
+    let (tag, _) = descriptor(&mut r)?;
+    if tag != 0x04 {
+        return None;
+    }
+    let object_type = r.u8().ok()?;
+    r.skip(12).ok()?;
+    if object_type != 0x40 {
+        return Some((format!("mp4a.{:02X}", object_type), None));
+    }
+
+    let (tag, len) = descriptor(&mut r)?;
+    if tag != 0x05 || len < 2 {
+        return Some(("mp4a.40".to_string(), None));
+    }
+    let config = r.bytes(len.min(r.remaining())).ok()?;
+    if config.len() < 2 {
+        return Some(("mp4a.40".to_string(), None));
+    }
+    let bits = u32::from_be_bytes([config[0], config[1], *config.get(2).unwrap_or(&0), *config.get(3).unwrap_or(&0)]);
+    let mut audio_object_type = bits >> 27;
+    let mut shift = 27;
+    if audio_object_type == 31 {
+        shift -= 6;
+        audio_object_type = 32 + ((bits >> shift) & 0x3f);
+    }
+    shift -= 4;
+    let frequency_index = (bits >> shift) & 0x0f;
+    if frequency_index == 15 {
+        // explicit 24-bit sample rate; the channel config is past the bytes we read
+        return Some((format!("mp4a.40.{}", audio_object_type), None));
+    }
+    shift -= 4;
+    let channel_config = (bits >> shift) & 0x0f;
+    let channels = match channel_config {
+        1..=6 => Some(channel_config),
+        7 => Some(8),
+        _ => None,
+    };
+    Some((format!("mp4a.40.{}", audio_object_type), channels))
+}
+
+// Descriptor tag and length; the length uses 7 bits per byte with a continuation bit.
+fn descriptor(r: &mut ByteReader) -> Option<(u8, usize)> {
+    let tag = r.u8().ok()?;
+    let mut len = 0usize;
+    for _ in 0..4 {
+        let b = r.u8().ok()?;
+        len = (len << 7) | (b & 0x7f) as usize;
+        if b & 0x80 == 0 {
+            break;
+        }
+    }
+    Some((tag, len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn mp4_box(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut out = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+        out.extend_from_slice(kind);
+        out.extend_from_slice(body);
+        out
+    }
+
+    fn full_box(kind: &[u8; 4], flags: u32, body: &[u8]) -> Vec<u8> {
+        let mut data = flags.to_be_bytes().to_vec();
+        data.extend_from_slice(body);
+        mp4_box(kind, &data)
+    }
+
+    fn be32(values: &[u32]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_be_bytes()).collect()
+    }
+
+    fn trak(tkhd_flags: u32, width: u32, height: u32, matrix: [u32; 9], handler: &[u8; 4], sample_entry: Vec<u8>, samples: u32) -> Vec<u8> {
+        let mut tkhd = be32(&[0, 0, 1, 0, 0, 0, 0, 0, 0]);
+        tkhd.extend(be32(&matrix));
+        tkhd.extend(be32(&[width << 16, height << 16]));
+        // 10 s at 1000 Hz, language "eng" packed as three 5-bit letters
+        let mut mdhd = be32(&[0, 0, 1000, 10_000]);
+        mdhd.extend_from_slice(&((5u16 << 10) | (14 << 5) | 7).to_be_bytes());
+        mdhd.extend_from_slice(&[0, 0]);
+        let mut hdlr = vec![0; 4];
+        hdlr.extend_from_slice(handler);
+        hdlr.extend_from_slice(&[0; 12]);
+
+        let mut stsd = be32(&[1]);
+        stsd.extend(sample_entry);
+        let stbl = [full_box(b"stsd", 0, &stsd), full_box(b"stts", 0, &be32(&[1, samples, 40]))].concat();
+        let minf = mp4_box(b"minf", &mp4_box(b"stbl", &stbl));
+        let mdia = [full_box(b"mdhd", 0, &mdhd), full_box(b"hdlr", 0, &hdlr), minf].concat();
+        mp4_box(b"trak", &[full_box(b"tkhd", tkhd_flags, &tkhd), mp4_box(b"mdia", &mdia)].concat())
+    }
+
+    fn file(brand: &[u8; 4], traks: &[Vec<u8>]) -> Vec<u8> {
+        let mut moov = full_box(b"mvhd", 0, &be32(&[0, 0, 1000, 12_500]));
+        for t in traks {
+            moov.extend_from_slice(t);
+        }
+        let mut ftyp = brand.to_vec();
+        ftyp.extend_from_slice(&[0; 4]);
+        [mp4_box(b"ftyp", &ftyp), mp4_box(b"mdat", &[0; 64]), mp4_box(b"moov", &moov)].concat()
+    }
+
+    const IDENTITY: [u32; 9] = [0x10000, 0, 0, 0, 0x10000, 0, 0, 0, 0x4000_0000];
+
+    fn avc1(width: u16, height: u16) -> Vec<u8> {
+        let mut entry = vec![0; 8 + 16];
+        entry.extend_from_slice(&width.to_be_bytes());
+        entry.extend_from_slice(&height.to_be_bytes());
+        entry.extend_from_slice(&[0; 50]);
+        mp4_box(b"avc1", &entry)
+    }
+
+    // AAC LC at 48 kHz; the sample entry claims stereo, the decoder config 5.1.
+    fn mp4a() -> Vec<u8> {
+        let mut entry = vec![0; 8];
+        entry.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0, 16, 0, 0, 0, 0]);
+        entry.extend_from_slice(&(48_000u32 << 16).to_be_bytes());
+        let config = [0x05, 2, 0x11, 0xb0];
+        let mut decoder = vec![0x04, 13 + config.len() as u8, 0x40, 0x15];
+        decoder.extend_from_slice(&[0; 11]);
+        decoder.extend_from_slice(&config);
+        let mut es = vec![0x03, 3 + decoder.len() as u8, 0, 1, 0];
+        es.extend_from_slice(&decoder);
+        entry.extend(full_box(b"esds", 0, &es));
+        mp4_box(b"mp4a", &entry)
+    }
+
+    #[test]
+    fn reads_tracks_from_moov_after_mdat() {
+        let rotated = [0, 0x10000, 0, 0xffff_0000, 0, 0, 0, 0, 0x4000_0000];
+        let video = trak(1, 1920, 1080, rotated, b"vide", avc1(1920, 1080), 250);
+        let audio = trak(1, 0, 0, IDENTITY, b"soun", mp4a(), 469);
+        let info = probe(&mut Cursor::new(file(b"isom", &[video, audio]))).unwrap();
+
+        assert_eq!(info.container, "mp4");
+        assert_eq!(info.duration, Some(12.5));
+        let video = &info.streams[0];
+        assert_eq!((video.kind, video.codec.as_str()), (StreamKind::Video, "avc1"));
+        assert_eq!((video.width, video.height, video.rotation), (Some(1920), Some(1080), Some(90)));
+        assert_eq!(video.frame_rate, Some(25.0));
+        assert_eq!(video.language.as_deref(), Some("eng"));
+        assert!(video.default);
+        let audio = &info.streams[1];
+        assert_eq!((audio.kind, audio.codec.as_str()), (StreamKind::Audio, "mp4a.40.2"));
+        assert_eq!((audio.channels, audio.sample_rate), (Some(6), Some(48_000)));
+        assert_eq!((audio.width, audio.rotation), (None, None));
+    }
+
+    #[test]
+    fn sample_entry_size_fills_in_for_an_empty_tkhd() {
+        let video = trak(0, 0, 0, IDENTITY, b"vide", avc1(640, 360), 10);
+        let info = probe(&mut Cursor::new(file(b"qt  ", &[video]))).unwrap();
+        assert_eq!(info.container, "mov");
+        assert_eq!((info.streams[0].width, info.streams[0].height), (Some(640), Some(360)));
+        assert!(!info.streams[0].default);
+    }
+
+    #[test]
+    fn skips_hint_tracks_and_needs_a_moov() {
+        let hint = trak(1, 0, 0, IDENTITY, b"hint", Vec::new(), 0);
+        assert!(probe(&mut Cursor::new(file(b"isom", &[hint]))).unwrap().streams.is_empty());
+        let no_moov = [mp4_box(b"ftyp", b"isom\0\0\0\0"), mp4_box(b"mdat", &[0; 16])].concat();
+        assert!(matches!(probe(&mut Cursor::new(no_moov)), Err(ProbeError::Malformed("no moov box"))));
+    }
+
+    #[test]
+    fn box_size_past_the_end_is_malformed() {
+        let mut file = mp4_box(b"ftyp", b"isom\0\0\0\0");
+        // a 64-bit size that overflows the file position
+        file.extend_from_slice(&1u32.to_be_bytes());
+        file.extend_from_slice(b"free");
+        file.extend_from_slice(&(u64::MAX - 8).to_be_bytes());
+        assert!(matches!(probe(&mut Cursor::new(file)), Err(ProbeError::Malformed("box size"))));
+    }
+}
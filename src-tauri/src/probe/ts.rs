@@ -0,0 +1,395 @@
+// MPEG transport streams: broadcast .ts and the Blu-ray/AVCHD flavour (.m2ts, .mts)
+// whose 192-byte packets carry a 4-byte arrival timestamp in front of each 188-byte
+// packet. Programs and streams come from the PAT/PMT at the start of the file, the
+// duration from the first and last PCR of the first program.
+use std::collections::HashMap;
+use std::io::{Read, Seek, SeekFrom};
+
+use serde::{Deserialize, Serialize};
+
+use super::{read_up_to, MediaInfo, ProbeError, StreamInfo, StreamKind};
+
+const SYNC: u8 = 0x47;
+const TS_PACKET: usize = 188;
+const M2TS_PACKET: usize = 192;
+
+// How much of the start and of the end of the file is searched for tables and PCRs.
+const SCAN_WINDOW: usize = 4 * 1024 * 1024;
+
+// PCR runs at 27 MHz and wraps at 2^33 * 300 (about 26.5 hours).
+const PCR_HZ: f64 = 27_000_000.0;
+const PCR_WRAP: u64 = (1 << 33) * 300;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Program {
+    pub number: u16,
+    pub pmt_pid: u16,
+    pub pcr_pid: u16,
+    // PIDs of the elementary streams, matching `StreamInfo::id`
+    pub streams: Vec<u64>,
+}
+
+// Packet size when the head of the file lines up with 188- or 192-byte packets.
+fn packet_size(head: &[u8]) -> Option<usize> {
+    [TS_PACKET, M2TS_PACKET].into_iter().find(|&size| {
+        let offset = size - TS_PACKET;
+        let packets = (head.len().saturating_sub(offset) / size).min(5);
+        packets >= 2 && (0..packets).all(|i| head[offset + i * size] == SYNC)
+    })
+}
+
+pub fn is_ts(head: &[u8]) -> bool {
+    packet_size(head).is_some()
+}
+
+pub fn probe<R: Read + Seek>(r: &mut R) -> Result<MediaInfo, ProbeError> {
+    let file_len = r.seek(SeekFrom::End(0))?;
+    r.seek(SeekFrom::Start(0))?;
+    let mut head = vec![0u8; SCAN_WINDOW.min(file_len as usize)];
+    let n = read_up_to(r, &mut head)?;
+    head.truncate(n);
+    let size = packet_size(&head).ok_or(ProbeError::Malformed("no transport stream sync"))?;
+    let container = if size == M2TS_PACKET { "m2ts" } else { "ts" };
+
+    let mut tables = Tables::default();
+    let mut first_pcr = None;
+    for packet in packets(&head, size) {
+        tables.feed(packet);
+        if first_pcr.is_none() {
+            if let Some(pcr_pid) = tables.pcr_pid() {
+                first_pcr = pcr(packet, pcr_pid);
+            }
+        }
+        if tables.complete() && first_pcr.is_some() {
+            break;
+        }
+    }
+    let pcr_pid = tables.pcr_pid().ok_or(ProbeError::Malformed("no program map table"))?;
+
+    // search the tail backwards for the last PCR
+    let tail_start = file_len.saturating_sub(SCAN_WINDOW as u64);
+    r.seek(SeekFrom::Start(tail_start))?;
+    let mut tail = vec![0u8; (file_len - tail_start) as usize];
+    let n = read_up_to(r, &mut tail)?;
+    tail.truncate(n);
+    let last_pcr = resync(&tail, size).and_then(|offset| packets(&tail[offset..], size).filter_map(|p| pcr(p, pcr_pid)).last());
+
+    let mut info = MediaInfo { container: container.to_string(), ..Default::default() };
+    if let (Some(first), Some(last)) = (first_pcr, last_pcr) {
+        let elapsed = if last >= first { last - first } else { last + PCR_WRAP - first };
+        if elapsed > 0 {
+            info.duration = Some(elapsed as f64 / PCR_HZ);
+        }
+    }
+    info.streams = tables.streams;
+    info.programs = tables.programs;
+    Ok(info)
+}
+
+// The 188-byte packets in `data`, without the M2TS timestamp. Packets that lost sync are skipped.
+fn packets(data: &[u8], size: usize) -> impl Iterator<Item = &[u8]> {
+    data.chunks_exact(size).map(move |chunk| &chunk[size - TS_PACKET..]).filter(|p| p[0] == SYNC)
+}
+
+// First offset in `data` where three packets in a row start with the sync byte.
+fn resync(data: &[u8], size: usize) -> Option<usize> {
+    let offset = size - TS_PACKET;
+    (0..size).find(|&start| (0..3).all(|i| data.get(start + offset + i * size) == Some(&SYNC)))
+}
+
+fn pid(packet: &[u8]) -> u16 {
+    (((packet[1] & 0x1f) as u16) << 8) | packet[2] as u16
+}
+
+// Program clock reference of the packet, in 27 MHz ticks.
+fn pcr(packet: &[u8], pcr_pid: u16) -> Option<u64> {
+    if pid(packet) != pcr_pid || packet[3] & 0x20 == 0 {
+        return None;
+    }
+    let adaptation_len = packet[4] as usize;
+    if adaptation_len < 7 || packet[5] & 0x10 == 0 {
+        return None;
+    }
+    let b = &packet[6..12];
+    let base = ((b[0] as u64) << 25) | ((b[1] as u64) << 17) | ((b[2] as u64) << 9) | ((b[3] as u64) << 1) | (b[4] as u64 >> 7);
+    let extension = (((b[4] & 0x01) as u64) << 8) | b[5] as u64;
+    Some(base * 300 + extension)
+}
+
+// Payload of a packet, after the header and the adaptation field.
+fn payload(packet: &[u8]) -> Option<&[u8]> {
+    let control = (packet[3] >> 4) & 0x03;
+    match control {
+        1 => Some(&packet[4..]),
+        3 => {
+            let start = 5 + packet[4] as usize;
+            packet.get(start..)
+        }
+        _ => None,
+    }
+}
+
+#[derive(Default)]
+struct Tables {
+    // PMT PID -> program number, from the PAT
+    pmt_pids: HashMap<u16, u16>,
+    // partially received sections by PID
+    pending: HashMap<u16, Vec<u8>>,
+    programs: Vec<Program>,
+    streams: Vec<StreamInfo>,
+}
+
+impl Tables {
+    fn complete(&self) -> bool {
+        !self.pmt_pids.is_empty() && self.programs.len() == self.pmt_pids.len()
+    }
+
+    fn pcr_pid(&self) -> Option<u16> {
+        self.programs.first().map(|p| p.pcr_pid)
+    }
+
+    fn feed(&mut self, packet: &[u8]) {
+        let pid = pid(packet);
+        let is_pat = pid == 0;
+        let is_pmt = self.pmt_pids.contains_key(&pid) && !self.programs.iter().any(|p| p.pmt_pid == pid);
+        if !is_pat && !is_pmt {
+            return;
+        }
+        let Some(payload) = payload(packet) else { return };
+        let unit_start = packet[1] & 0x40 != 0;
+
+        if unit_start {
+            let pointer = *payload.first().unwrap_or(&0) as usize;
+            let Some(data) = payload.get(1 + pointer..) else { return };
+            self.pending.insert(pid, data.to_vec());
+        } else if let Some(buf) = self.pending.get_mut(&pid) {
+            buf.extend_from_slice(payload);
+        }
+        let Some(section) = self.pending.get(&pid) else { return };
+        if section.len() < 3 {
+            return;
+        }
+        let section_len = 3 + ((((section[1] & 0x0f) as usize) << 8) | section[2] as usize);
+        if section.len() < section_len {
+            return;
+        }
+        let section = self.pending.remove(&pid).unwrap_or_default();
+        // without the 4-byte CRC
+        let Some(body) = section.get(..section_len.saturating_sub(4)) else { return };
+        if is_pat {
+            self.parse_pat(body);
+        } else {
+            self.parse_pmt(pid, body);
+        }
+    }
+
+    fn parse_pat(&mut self, section: &[u8]) {
+        if section.first() != Some(&0x00) || !self.pmt_pids.is_empty() {
+            return;
+        }
+        for entry in section.get(8..).unwrap_or_default().chunks_exact(4) {
+            let number = u16::from_be_bytes([entry[0], entry[1]]);
+            let pmt_pid = (((entry[2] & 0x1f) as u16) << 8) | entry[3] as u16;
+            // program 0 points to the network information table
+            if number != 0 {
+                self.pmt_pids.insert(pmt_pid, number);
+            }
+        }
+    }
+
+    fn parse_pmt(&mut self, pmt_pid: u16, section: &[u8]) {
+        if section.first() != Some(&0x02) || section.len() < 12 {
+            return;
+        }
+        let number = u16::from_be_bytes([section[3], section[4]]);
+        let pcr_pid = (((section[8] & 0x1f) as u16) << 8) | section[9] as u16;
+        let info_len = (((section[10] & 0x0f) as usize) << 8) | section[11] as usize;
+        let mut program = Program { number, pmt_pid, pcr_pid, streams: Vec::new() };
+
+        let mut pos = 12 + info_len;
+        while pos + 5 <= section.len() {
+            let stream_type = section[pos];
+            let pid = (((section[pos + 1] & 0x1f) as u16) << 8) | section[pos + 2] as u16;
+            let es_info_len = (((section[pos + 3] & 0x0f) as usize) << 8) | section[pos + 4] as usize;
+            let descriptors = section.get(pos + 5..pos + 5 + es_info_len).unwrap_or_default();
+            pos += 5 + es_info_len;
+
+            let (kind, codec) = classify(stream_type, descriptors);
+            program.streams.push(pid as u64);
+            self.streams.push(StreamInfo {
+                id: pid as u64,
+                kind,
+                codec: codec.to_string(),
+                language: language(descriptors),
+                // the first stream of each kind is what players pick by default
+                default: !self.streams.iter().any(|s| s.kind == kind),
+                ..Default::default()
+            });
+        }
+        self.programs.push(program);
+    }
+}
+
+// Descriptors as (tag, data) pairs.
+fn descriptors(data: &[u8]) -> Vec<(u8, &[u8])> {
+    let mut out = Vec::new();
+    let mut pos = 0;
+    while pos + 2 <= data.len() {
+        let len = data[pos + 1] as usize;
+        let Some(body) = data.get(pos + 2..pos + 2 + len) else { break };
+        out.push((data[pos], body));
+        pos += 2 + len;
+    }
+    out
+}
+
+// ISO 639 language descriptor, or the language of the first DVB subtitle/teletext entry.
+fn language(data: &[u8]) -> Option<String> {
+    descriptors(data)
+        .into_iter()
+        .find(|(tag, body)| matches!(tag, 0x0a | 0x56 | 0x59) && body.len() >= 3)
+        .map(|(_, body)| String::from_utf8_lossy(&body[..3]).to_string())
+        .filter(|l| l.chars().all(|c| c.is_ascii_alphabetic()) && l != "und")
+}
+
+// Stream type from the PMT, refined by descriptors for the private types used by
+// DVB (0x06) and Blu-ray (0x80 and up).
+fn classify(stream_type: u8, data: &[u8]) -> (StreamKind, &'static str) {
+    use StreamKind::{Audio, Other, Subtitle, Video};
+    match stream_type {
+        0x01 => (Video, "mpeg1video"),
+        0x02 => (Video, "mpeg2video"),
+        0x10 => (Video, "mpeg4"),
+        0x1b => (Video, "h264"),
+        0x20 => (Video, "h264"),
+        0x24 => (Video, "hevc"),
+        0x33 => (Video, "vvc"),
+        0xea => (Video, "vc1"),
+        0x03 => (Audio, "mp2"),
+        0x04 => (Audio, "mp2"),
+        0x0f => (Audio, "aac"),
+        0x11 => (Audio, "aac_latm"),
+        0x80 => (Audio, "pcm_bluray"),
+        0x81 => (Audio, "ac3"),
+        0x82 | 0x85 | 0x86 | 0xa2 => (Audio, "dts"),
+        0x83 => (Audio, "truehd"),
+        0x84 | 0x87 | 0xa1 => (Audio, "eac3"),
+        0x90 => (Subtitle, "hdmv_pgs_subtitle"),
+        0x92 => (Subtitle, "hdmv_text_subtitle"),
+        0x06 => {
+            for (tag, body) in descriptors(data) {
+                match tag {
+                    0x6a => return (Audio, "ac3"),
+                    0x7a => return (Audio, "eac3"),
+                    0x7b => return (Audio, "dts"),
+                    0x59 => return (Subtitle, "dvb_subtitle"),
+                    0x56 => return (Subtitle, "dvb_teletext"),
+                    0x05 if body.starts_with(b"Opus") => return (Audio, "opus"),
+                    0x05 if body.starts_with(b"AC-3") => return (Audio, "ac3"),
+                    _ => {}
+                }
+            }
+            (Other, "private")
+        }
+        _ => (Other, "unknown"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn packet(pid: u16, unit_start: bool, payload: &[u8]) -> Vec<u8> {
+        let mut out = vec![SYNC, ((unit_start as u8) << 6) | (pid >> 8) as u8, pid as u8, 0x10];
+        out.extend_from_slice(payload);
+        out.resize(TS_PACKET, 0xff);
+        out
+    }
+
+    // An adaptation-field-only packet carrying a PCR of `base` 90 kHz ticks.
+    fn pcr_packet(pid: u16, base: u64) -> Vec<u8> {
+        let mut out = vec![SYNC, (pid >> 8) as u8, pid as u8, 0x20, 183, 0x10];
+        out.extend_from_slice(&[(base >> 25) as u8, (base >> 17) as u8, (base >> 9) as u8, (base >> 1) as u8, ((base & 1) << 7) as u8 | 0x7e, 0]);
+        out.resize(TS_PACKET, 0xff);
+        out
+    }
+
+    // A PSI section behind a zero pointer field, with a dummy CRC.
+    fn section(table_id: u8, id: u16, body: &[u8]) -> Vec<u8> {
+        let len = 5 + body.len() + 4;
+        let mut out = vec![0, table_id, 0xb0 | (len >> 8) as u8, len as u8];
+        out.extend_from_slice(&id.to_be_bytes());
+        out.extend_from_slice(&[0xc1, 0, 0]);
+        out.extend_from_slice(body);
+        out.extend_from_slice(&[0; 4]);
+        out
+    }
+
+    fn pat(program: u16, pmt_pid: u16) -> Vec<u8> {
+        // program 0 (the NIT) comes first and is ignored
+        packet(0, true, &section(0x00, 1, &[0, 0, 0xe0, 0x10, (program >> 8) as u8, program as u8, 0xe0 | (pmt_pid >> 8) as u8, pmt_pid as u8]))
+    }
+
+    fn pmt(pmt_pid: u16, program: u16, pcr_pid: u16, streams: &[(u8, u16, &[u8])]) -> Vec<u8> {
+        let mut body = vec![0xe0 | (pcr_pid >> 8) as u8, pcr_pid as u8, 0xf0, 0];
+        for (stream_type, pid, descriptors) in streams {
+            body.extend_from_slice(&[*stream_type, 0xe0 | (pid >> 8) as u8, *pid as u8, 0xf0, descriptors.len() as u8]);
+            body.extend_from_slice(descriptors);
+        }
+        packet(pmt_pid, true, &section(0x02, program, &body))
+    }
+
+    fn m2ts(packets: &[Vec<u8>]) -> Vec<u8> {
+        packets.iter().flat_map(|p| [&[0u8; 4][..], p].concat()).collect()
+    }
+
+    #[test]
+    fn reads_the_program_and_pcr_duration() {
+        let streams: &[(u8, u16, &[u8])] = &[
+            (0x1b, 0x100, &[]),
+            (0x0f, 0x101, &[0x0a, 4, b'd', b'e', b'u', 0]),
+            (0x06, 0x102, &[0x59, 8, b'e', b'n', b'g', 0x10, 0, 1, 0, 1]),
+            (0x06, 0x103, &[0x6a, 1, 0]),
+        ];
+        let mut packets = vec![pat(1, 0x1000), pmt(0x1000, 1, 0x100, streams), pcr_packet(0x100, 90_000)];
+        packets.extend((0..10).map(|_| packet(0x100, false, &[])));
+        packets.push(pcr_packet(0x100, 11 * 90_000));
+        let info = probe(&mut Cursor::new(packets.concat())).unwrap();
+
+        assert_eq!(info.container, "ts");
+        assert_eq!(info.duration, Some(10.0));
+        let program = &info.programs[0];
+        assert_eq!((program.number, program.pmt_pid, program.pcr_pid), (1, 0x1000, 0x100));
+        assert_eq!(program.streams, vec![0x100, 0x101, 0x102, 0x103]);
+        let streams: Vec<_> = info.streams.iter().map(|s| (s.id, s.kind, s.codec.as_str(), s.language.as_deref(), s.default)).collect();
+        assert_eq!(
+            streams,
+            vec![
+                (0x100, StreamKind::Video, "h264", None, true),
+                (0x101, StreamKind::Audio, "aac", Some("deu"), true),
+                (0x102, StreamKind::Subtitle, "dvb_subtitle", Some("eng"), true),
+                (0x103, StreamKind::Audio, "ac3", None, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn m2ts_duration_survives_a_pcr_wrap() {
+        let first = (1 << 33) - 90_000;
+        let packets = [pat(1, 0x100), pmt(0x100, 1, 0x1011, &[(0x1b, 0x1011, &[])]), pcr_packet(0x1011, first), pcr_packet(0x1011, 2 * 90_000)];
+        let info = probe(&mut Cursor::new(m2ts(&packets))).unwrap();
+        assert_eq!(info.container, "m2ts");
+        assert_eq!(info.duration, Some(3.0));
+    }
+
+    #[test]
+    fn needs_sync_bytes_and_a_program_map() {
+        assert!(!is_ts(&[0; TS_PACKET * 3]));
+        assert!(matches!(probe(&mut Cursor::new(vec![0; TS_PACKET * 3])), Err(ProbeError::Malformed("no transport stream sync"))));
+        let no_tables = [packet(0x100, true, &[]), packet(0x100, false, &[]), packet(0x100, false, &[])].concat();
+        assert!(is_ts(&no_tables));
+        assert!(matches!(probe(&mut Cursor::new(no_tables)), Err(ProbeError::Malformed("no program map table"))));
+    }
+}
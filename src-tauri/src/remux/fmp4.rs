@@ -0,0 +1,461 @@
+// Fragmented MP4 (ISO/IEC 14496-12) writer: `ftyp` and an empty `moov` up front, then
+// one `moof` + `mdat` pair per fragment, and an `mfra` index of the keyframe fragments
+// at the end so players can seek without reading every `moof`.
+use std::io::{self, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Handler {
+    Video,
+    Audio,
+}
+
+#[derive(Clone)]
+pub struct TrackConfig {
+    pub handler: Handler,
+    // media timescale in ticks per second
+    pub timescale: u32,
+    // complete sample entry box for `stsd`, see the `*_entry` functions
+    pub sample_entry: Vec<u8>,
+    // ISO 639-2 code, "und" when unknown
+    pub language: String,
+    // display size for video
+    pub width: u32,
+    pub height: u32,
+    // tracks of the same group are alternatives; only the enabled one plays
+    pub enabled: bool,
+    pub alternate_group: u16,
+}
+
+pub struct Sample {
+    pub duration: u32,
+    // presentation minus decode time, may be negative (version 1 `trun`)
+    pub composition_offset: i32,
+    pub keyframe: bool,
+    pub data: Vec<u8>,
+}
+
+// Samples of one track in one fragment; `decode_time` is that of the first sample.
+pub struct Run {
+    pub track: usize,
+    pub decode_time: u64,
+    pub samples: Vec<Sample>,
+}
+
+pub struct Writer<W: Write> {
+    w: W,
+    tracks: Vec<TrackConfig>,
+    sequence: u32,
+    written: u64,
+    // (track index, time, moof offset, traf number) of fragments starting with a keyframe
+    random_access: Vec<(usize, u64, u64, u8)>,
+}
+
+impl<W: Write> Writer<W> {
+    // Writes the header. `duration_ms` goes into `mehd` so players know the length up front.
+    pub fn new(w: W, tracks: Vec<TrackConfig>, duration_ms: Option<u64>) -> io::Result<Writer<W>> {
+        let mut writer = Writer { w, tracks, sequence: 0, written: 0, random_access: Vec::new() };
+        let mut ftyp = Vec::new();
+        ftyp.extend_from_slice(b"isom");
+        ftyp.extend_from_slice(&0x200u32.to_be_bytes());
+        for brand in [b"isom", b"iso6", b"mp41"] {
+            ftyp.extend_from_slice(brand);
+        }
+        let header = [make_box(b"ftyp", &ftyp), writer.moov(duration_ms.unwrap_or(0))].concat();
+        writer.write(&header)?;
+        Ok(writer)
+    }
+
+    // Writes one fragment holding the given runs, at most one per track.
+    pub fn write_fragment(&mut self, runs: &[Run]) -> io::Result<()> {
+        let runs: Vec<&Run> = runs.iter().filter(|r| !r.samples.is_empty()).collect();
+        if runs.is_empty() {
+            return Ok(());
+        }
+        self.sequence += 1;
+        // the data offsets depend on the moof size, which does not depend on them
+        let moof_size = self.moof(&runs, 0).len() as u64;
+        let moof = self.moof(&runs, moof_size + 8);
+        let mdat_size: u64 = runs.iter().flat_map(|r| &r.samples).map(|s| s.data.len() as u64).sum::<u64>() + 8;
+        if mdat_size > u32::MAX as u64 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "fragment too large"));
+        }
+
+        let moof_offset = self.written;
+        for (i, run) in runs.iter().enumerate() {
+            if run.samples[0].keyframe {
+                self.random_access.push((run.track, run.decode_time, moof_offset, (i + 1) as u8));
+            }
+        }
+        self.write(&moof)?;
+        self.write(&(mdat_size as u32).to_be_bytes())?;
+        self.write(b"mdat")?;
+        for sample in runs.iter().flat_map(|r| &r.samples) {
+            self.write(&sample.data)?;
+        }
+        Ok(())
+    }
+
+    // Writes the `mfra` index and returns the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        let mut mfra = Vec::new();
+        for (index, _) in self.tracks.iter().enumerate() {
+            let entries: Vec<_> = self.random_access.iter().filter(|e| e.0 == index).collect();
+            if entries.is_empty() {
+                continue;
+            }
+            let mut tfra = Vec::new();
+            tfra.extend_from_slice(&(index as u32 + 1).to_be_bytes());
+            // one byte each for the traf, trun and sample numbers
+            tfra.extend_from_slice(&0u32.to_be_bytes());
+            tfra.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+            for &&(_, time, offset, traf) in &entries {
+                tfra.extend_from_slice(&time.to_be_bytes());
+                tfra.extend_from_slice(&offset.to_be_bytes());
+                tfra.extend_from_slice(&[traf, 1, 1]);
+            }
+            mfra.extend(full_box(b"tfra", 1, 0, &tfra));
+        }
+        // mfro holds the size of the whole mfra so readers can find it from the end
+        let size = (8 + mfra.len() + 16) as u32;
+        mfra.extend(full_box(b"mfro", 0, 0, &size.to_be_bytes()));
+        let mfra = make_box(b"mfra", &mfra);
+        self.write(&mfra)?;
+        self.w.flush()?;
+        Ok(self.w)
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.w.write_all(data)?;
+        self.written += data.len() as u64;
+        Ok(())
+    }
+
+    fn moov(&self, duration_ms: u64) -> Vec<u8> {
+        let mut mvhd = Vec::new();
+        // creation and modification time
+        mvhd.extend_from_slice(&[0; 8]);
+        mvhd.extend_from_slice(&1000u32.to_be_bytes());
+        // the duration of the fragments is in mehd
+        mvhd.extend_from_slice(&0u32.to_be_bytes());
+        mvhd.extend_from_slice(&0x0001_0000u32.to_be_bytes());
+        mvhd.extend_from_slice(&0x0100u16.to_be_bytes());
+        mvhd.extend_from_slice(&[0; 10]);
+        mvhd.extend_from_slice(&MATRIX);
+        mvhd.extend_from_slice(&[0; 24]);
+        mvhd.extend_from_slice(&(self.tracks.len() as u32 + 1).to_be_bytes());
+
+        let mut moov = full_box(b"mvhd", 0, 0, &mvhd);
+        for (index, track) in self.tracks.iter().enumerate() {
+            moov.extend(trak(index as u32 + 1, track));
+        }
+        let mut mvex = full_box(b"mehd", 1, 0, &duration_ms.to_be_bytes());
+        for index in 0..self.tracks.len() {
+            let mut trex = Vec::new();
+            trex.extend_from_slice(&(index as u32 + 1).to_be_bytes());
+            // sample description index, then default duration, size and flags
+            trex.extend_from_slice(&1u32.to_be_bytes());
+            trex.extend_from_slice(&[0; 12]);
+            mvex.extend(full_box(b"trex", 0, 0, &trex));
+        }
+        moov.extend(make_box(b"mvex", &mvex));
+        make_box(b"moov", &moov)
+    }
+
+    fn moof(&self, runs: &[&Run], data_start: u64) -> Vec<u8> {
+        let mut moof = full_box(b"mfhd", 0, 0, &self.sequence.to_be_bytes());
+        let mut data_offset = data_start;
+        for run in runs {
+            let video = self.tracks[run.track].handler == Handler::Video;
+            // default-base-is-moof
+            let tfhd = full_box(b"tfhd", 0, 0x02_0000, &(run.track as u32 + 1).to_be_bytes());
+            let tfdt = full_box(b"tfdt", 1, 0, &run.decode_time.to_be_bytes());
+            // data offset, sample duration, size, flags and composition offset
+            let flags = if video { 0x0001 | 0x0100 | 0x0200 | 0x0400 | 0x0800 } else { 0x0001 | 0x0100 | 0x0200 };
+            let mut trun = Vec::new();
+            trun.extend_from_slice(&(run.samples.len() as u32).to_be_bytes());
+            trun.extend_from_slice(&(data_offset as u32).to_be_bytes());
+            for sample in &run.samples {
+                trun.extend_from_slice(&sample.duration.to_be_bytes());
+                trun.extend_from_slice(&(sample.data.len() as u32).to_be_bytes());
+                if video {
+                    // keyframes depend on no other sample, others are non-sync
+                    let flags: u32 = if sample.keyframe { 0x0200_0000 } else { 0x0101_0000 };
+                    trun.extend_from_slice(&flags.to_be_bytes());
+                    trun.extend_from_slice(&sample.composition_offset.to_be_bytes());
+                }
+                data_offset += sample.data.len() as u64;
+            }
+            let traf = [tfhd, tfdt, full_box(b"trun", 1, flags, &trun)].concat();
+            moof.extend(make_box(b"traf", &traf));
+        }
+        make_box(b"moof", &moof)
+    }
+}
+
+const MATRIX: [u8; 36] = [
+    0x00, 0x01, 0x00, 0x00, 0, 0, 0, 0, 0, 0, 0, 0, //
+    0, 0, 0, 0, 0x00, 0x01, 0x00, 0x00, 0, 0, 0, 0, //
+    0, 0, 0, 0, 0, 0, 0, 0, 0x40, 0x00, 0x00, 0x00,
+];
+
+fn trak(id: u32, track: &TrackConfig) -> Vec<u8> {
+    let video = track.handler == Handler::Video;
+    let mut tkhd = Vec::new();
+    tkhd.extend_from_slice(&[0; 8]);
+    tkhd.extend_from_slice(&id.to_be_bytes());
+    tkhd.extend_from_slice(&[0; 4]);
+    // duration, then reserved and layer
+    tkhd.extend_from_slice(&[0; 4]);
+    tkhd.extend_from_slice(&[0; 10]);
+    tkhd.extend_from_slice(&track.alternate_group.to_be_bytes());
+    tkhd.extend_from_slice(&(if video { 0u16 } else { 0x0100 }).to_be_bytes());
+    tkhd.extend_from_slice(&[0; 2]);
+    tkhd.extend_from_slice(&MATRIX);
+    tkhd.extend_from_slice(&(track.width << 16).to_be_bytes());
+    tkhd.extend_from_slice(&(track.height << 16).to_be_bytes());
+    // in movie and in preview, plus enabled
+    let flags = if track.enabled { 0x07 } else { 0x06 };
+
+    let mut mdhd = Vec::new();
+    mdhd.extend_from_slice(&[0; 8]);
+    mdhd.extend_from_slice(&track.timescale.to_be_bytes());
+    mdhd.extend_from_slice(&[0; 4]);
+    mdhd.extend_from_slice(&language_code(&track.language).to_be_bytes());
+    mdhd.extend_from_slice(&[0; 2]);
+
+    let mut hdlr = Vec::new();
+    hdlr.extend_from_slice(&[0; 4]);
+    hdlr.extend_from_slice(if video { b"vide" } else { b"soun" });
+    hdlr.extend_from_slice(&[0; 12]);
+    hdlr.extend_from_slice(if video { b"VideoHandler\0" } else { b"SoundHandler\0" });
+
+    let media_header = if video { full_box(b"vmhd", 0, 1, &[0; 8]) } else { full_box(b"smhd", 0, 0, &[0; 4]) };
+    let mut dref = 1u32.to_be_bytes().to_vec();
+    // the media data is in this file
+    dref.extend(full_box(b"url ", 0, 1, &[]));
+    let dinf = make_box(b"dinf", &full_box(b"dref", 0, 0, &dref));
+
+    let mut stsd = 1u32.to_be_bytes().to_vec();
+    stsd.extend_from_slice(&track.sample_entry);
+    // the sample tables are empty, the samples are described in the fragments
+    let stbl = [
+        full_box(b"stsd", 0, 0, &stsd),
+        full_box(b"stts", 0, 0, &[0; 4]),
+        full_box(b"stsc", 0, 0, &[0; 4]),
+        full_box(b"stsz", 0, 0, &[0; 8]),
+        full_box(b"stco", 0, 0, &[0; 4]),
+    ]
+    .concat();
+    let minf = [media_header, dinf, make_box(b"stbl", &stbl)].concat();
+    let mdia = [full_box(b"mdhd", 0, 0, &mdhd), full_box(b"hdlr", 0, 0, &hdlr), make_box(b"minf", &minf)].concat();
+    make_box(b"trak", &[full_box(b"tkhd", 0, flags, &tkhd), make_box(b"mdia", &mdia)].concat())
+}
+
+// Packed ISO 639-2/T code as used by `mdhd`.
+fn language_code(language: &str) -> u16 {
+    let code = language.as_bytes();
+    if code.len() != 3 || !code.iter().all(|c| c.is_ascii_lowercase()) {
+        return language_code("und");
+    }
+    code.iter().fold(0u16, |acc, c| (acc << 5) | (c - 0x60) as u16)
+}
+
+pub fn make_box(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(body.len() + 8);
+    out.extend_from_slice(&(body.len() as u32 + 8).to_be_bytes());
+    out.extend_from_slice(kind);
+    out.extend_from_slice(body);
+    out
+}
+
+pub fn full_box(kind: &[u8; 4], version: u8, flags: u32, body: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(body.len() + 4);
+    data.extend_from_slice(&(((version as u32) << 24) | (flags & 0x00ff_ffff)).to_be_bytes());
+    data.extend_from_slice(body);
+    make_box(kind, &data)
+}
+
+// VisualSampleEntry with its decoder configuration box, e.g. `avc1` with `avcC`.
+pub fn visual_entry(kind: &[u8; 4], width: u32, height: u32, config: Vec<u8>) -> Vec<u8> {
+    let mut entry = Vec::new();
+    // reserved, data reference index
+    entry.extend_from_slice(&[0; 6]);
+    entry.extend_from_slice(&1u16.to_be_bytes());
+    entry.extend_from_slice(&[0; 16]);
+    entry.extend_from_slice(&(width as u16).to_be_bytes());
+    entry.extend_from_slice(&(height as u16).to_be_bytes());
+    // 72 dpi
+    entry.extend_from_slice(&0x0048_0000u32.to_be_bytes());
+    entry.extend_from_slice(&0x0048_0000u32.to_be_bytes());
+    entry.extend_from_slice(&[0; 4]);
+    entry.extend_from_slice(&1u16.to_be_bytes());
+    entry.extend_from_slice(&[0; 32]);
+    entry.extend_from_slice(&0x0018u16.to_be_bytes());
+    entry.extend_from_slice(&(-1i16).to_be_bytes());
+    entry.extend(config);
+    make_box(kind, &entry)
+}
+
+// AudioSampleEntry with its decoder configuration box, e.g. `mp4a` with `esds`.
+pub fn audio_entry(kind: &[u8; 4], channels: u32, sample_rate: u32, config: Vec<u8>) -> Vec<u8> {
+    let mut entry = Vec::new();
+    entry.extend_from_slice(&[0; 6]);
+    entry.extend_from_slice(&1u16.to_be_bytes());
+    entry.extend_from_slice(&[0; 8]);
+    entry.extend_from_slice(&(channels as u16).to_be_bytes());
+    entry.extend_from_slice(&16u16.to_be_bytes());
+    entry.extend_from_slice(&[0; 4]);
+    // 16.16 fixed point; rates above 65535 Hz do not fit and are left to the config
+    let rate = if sample_rate <= 0xffff { sample_rate << 16 } else { 0 };
+    entry.extend_from_slice(&rate.to_be_bytes());
+    entry.extend(config);
+    make_box(kind, &entry)
+}
+
+// `esds` for MPEG-4 audio; `object_type` 0x40 is AAC (with the AudioSpecificConfig as
+// `specific_info`), 0x6B is MP3.
+pub fn esds(track_id: u16, object_type: u8, specific_info: &[u8]) -> Vec<u8> {
+    let mut decoder_config = vec![object_type, 0x15];
+    // buffer size, max and average bitrate
+    decoder_config.extend_from_slice(&[0; 11]);
+    if !specific_info.is_empty() {
+        decoder_config.extend(descriptor(0x05, specific_info));
+    }
+    let mut es = track_id.to_be_bytes().to_vec();
+    es.push(0);
+    es.extend(descriptor(0x04, &decoder_config));
+    // SL config: predefined for MP4 files
+    es.extend(descriptor(0x06, &[0x02]));
+    full_box(b"esds", 0, 0, &descriptor(0x03, &es))
+}
+
+fn descriptor(tag: u8, body: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    // four-byte size form, always valid
+    let len = body.len() as u32;
+    out.extend_from_slice(&[0x80 | ((len >> 21) as u8 & 0x7f), 0x80 | ((len >> 14) as u8 & 0x7f), 0x80 | ((len >> 7) as u8 & 0x7f), len as u8 & 0x7f]);
+    out.extend_from_slice(body);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::probe::{mp4, StreamKind};
+    use std::io::Cursor;
+
+    // (type, offset, box bytes) of the boxes in `data`.
+    fn boxes(data: &[u8]) -> Vec<([u8; 4], usize, &[u8])> {
+        let mut out = Vec::new();
+        let mut pos = 0;
+        while pos + 8 <= data.len() {
+            let size = u32::from_be_bytes(data[pos..pos + 4].try_into().unwrap()) as usize;
+            out.push((data[pos + 4..pos + 8].try_into().unwrap(), pos, &data[pos..pos + size]));
+            pos += size;
+        }
+        out
+    }
+
+    fn tracks() -> Vec<TrackConfig> {
+        let video = TrackConfig {
+            handler: Handler::Video,
+            timescale: 90_000,
+            sample_entry: visual_entry(b"avc1", 1280, 720, make_box(b"avcC", &[1, 0x64, 0, 0x1f])),
+            language: "und".to_string(),
+            width: 1280,
+            height: 720,
+            enabled: true,
+            alternate_group: 0,
+        };
+        let audio = TrackConfig {
+            handler: Handler::Audio,
+            timescale: 48_000,
+            // AAC LC, 48 kHz, 5.1
+            sample_entry: audio_entry(b"mp4a", 6, 48_000, esds(2, 0x40, &[0x11, 0xb0])),
+            language: "fra".to_string(),
+            width: 0,
+            height: 0,
+            enabled: true,
+            alternate_group: 1,
+        };
+        vec![video, audio]
+    }
+
+    fn sample(keyframe: bool, data: &[u8]) -> Sample {
+        Sample { duration: 3000, composition_offset: -1500, keyframe, data: data.to_vec() }
+    }
+
+    fn write() -> Vec<u8> {
+        let mut writer = Writer::new(Vec::new(), tracks(), Some(2500)).unwrap();
+        writer
+            .write_fragment(&[
+                Run { track: 0, decode_time: 0, samples: vec![sample(true, b"key"), sample(false, b"delta")] },
+                Run { track: 1, decode_time: 0, samples: vec![sample(true, b"aac")] },
+            ])
+            .unwrap();
+        writer.write_fragment(&[Run { track: 0, decode_time: 6000, samples: vec![sample(false, b"late")] }]).unwrap();
+        // nothing to write, no fragment
+        writer.write_fragment(&[Run { track: 1, decode_time: 3000, samples: Vec::new() }]).unwrap();
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn output_reads_back_with_the_probe() {
+        let info = mp4::probe(&mut Cursor::new(write())).unwrap();
+        assert_eq!(info.duration, Some(2.5));
+        let video = &info.streams[0];
+        assert_eq!((video.kind, video.codec.as_str(), video.width, video.height), (StreamKind::Video, "avc1", Some(1280), Some(720)));
+        let audio = &info.streams[1];
+        assert_eq!((audio.kind, audio.codec.as_str()), (StreamKind::Audio, "mp4a.40.2"));
+        assert_eq!((audio.channels, audio.sample_rate, audio.language.as_deref()), (Some(6), Some(48_000), Some("fra")));
+    }
+
+    #[test]
+    fn fragments_point_at_their_samples() {
+        let data = write();
+        let top = boxes(&data);
+        let kinds: Vec<&[u8; 4]> = top.iter().map(|(kind, _, _)| kind).collect();
+        assert_eq!(kinds, [b"ftyp", b"moov", b"moof", b"mdat", b"moof", b"mdat", b"mfra"]);
+        assert_eq!(&top[3].2[8..], b"keydeltaaac");
+
+        // the trun data offset counts from the start of the moof
+        let (_, moof_at, moof) = top[2];
+        let trafs: Vec<_> = boxes(&moof[8..]).into_iter().filter(|(kind, _, _)| kind == b"traf").collect();
+        assert_eq!(trafs.len(), 2);
+        let offsets: Vec<usize> = trafs
+            .iter()
+            .map(|(_, _, traf)| {
+                let (_, _, trun) = boxes(&traf[8..]).into_iter().find(|(kind, _, _)| kind == b"trun").unwrap();
+                u32::from_be_bytes(trun[16..20].try_into().unwrap()) as usize
+            })
+            .collect();
+        assert_eq!(&data[moof_at + offsets[0]..moof_at + offsets[0] + 3], b"key");
+        assert_eq!(&data[moof_at + offsets[1]..moof_at + offsets[1] + 3], b"aac");
+    }
+
+    #[test]
+    fn mfra_indexes_the_keyframe_fragments() {
+        let data = write();
+        let (_, _, mfra) = *boxes(&data).last().unwrap();
+        assert_eq!(u32::from_be_bytes(data[data.len() - 4..].try_into().unwrap()) as usize, mfra.len());
+        let tfras: Vec<_> = boxes(&mfra[8..]).into_iter().filter(|(kind, _, _)| kind == b"tfra").collect();
+        let moof_at = boxes(&data)[2].1 as u64;
+        // (track id, entries, first time, first moof offset, traf number); the second video
+        // fragment starts with a delta frame and is left out
+        let entries: Vec<_> = tfras
+            .iter()
+            .map(|(_, _, tfra)| {
+                let field = |at: usize, len: usize| tfra[at..at + len].iter().fold(0u64, |acc, b| (acc << 8) | *b as u64);
+                (field(12, 4), field(20, 4), field(24, 8), field(32, 8), tfra[40])
+            })
+            .collect();
+        assert_eq!(entries, vec![(1, 1, 0, moof_at, 1), (2, 1, 0, moof_at, 2)]);
+    }
+
+    #[test]
+    fn language_codes_are_packed_or_undetermined() {
+        assert_eq!(language_code("eng"), (5 << 10) | (14 << 5) | 7);
+        assert_eq!(language_code("english"), language_code("und"));
+        assert_eq!(language_code("ENG"), language_code("und"));
+    }
+}
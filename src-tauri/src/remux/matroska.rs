@@ -0,0 +1,468 @@
+// Matroska demuxer for the remux: reads the track headers, then hands out the frames of
+// every block in file order. Built on the EBML reader of `probe::mkv`.
+use std::collections::VecDeque;
+use std::io::{self, Read, Seek, SeekFrom};
+
+use crate::probe::mkv::{
+    children, float, read_body, read_header, string, uint, vint, Header, ATTACHMENTS, AUDIO, CHANNELS, CHAPTERS, CLUSTER,
+    CODEC_ID, DEFAULT_DURATION, DURATION, EBML, FLAG_DEFAULT, FLAG_FORCED, INFO, LANGUAGE, LANGUAGE_BCP47, NAME, PIXEL_HEIGHT, PIXEL_WIDTH,
+    SAMPLING_FREQUENCY, SEEK_HEAD, SEGMENT, TIMESTAMP_SCALE, TRACKS, TRACK_ENTRY, TRACK_NUMBER, TRACK_TYPE, VIDEO,
+};
+use crate::probe::{ProbeError, StreamKind};
+
+const CODEC_PRIVATE: u32 = 0x63A2;
+const DISPLAY_WIDTH: u32 = 0x54B0;
+const DISPLAY_HEIGHT: u32 = 0x54BA;
+const CONTENT_ENCODINGS: u32 = 0x6D80;
+const CONTENT_ENCODING: u32 = 0x6240;
+const CONTENT_COMPRESSION: u32 = 0x5034;
+const CONTENT_COMP_ALGO: u32 = 0x4254;
+const CONTENT_COMP_SETTINGS: u32 = 0x4255;
+const CONTENT_ENCRYPTION: u32 = 0x5035;
+const CLUSTER_TIMESTAMP: u32 = 0xE7;
+const SIMPLE_BLOCK: u32 = 0xA3;
+const BLOCK_GROUP: u32 = 0xA0;
+const BLOCK: u32 = 0xA1;
+const BLOCK_DURATION: u32 = 0x9B;
+const REFERENCE_BLOCK: u32 = 0xFB;
+const CUES: u32 = 0x1C53_BB6B;
+const TAGS: u32 = 0x1254_C367;
+const FLAG_HEARING_IMPAIRED: u32 = 0x55AB;
+
+// Header stripping, the only content compression we can undo without a codec library.
+const HEADER_STRIPPING: u64 = 3;
+const ZLIB: u64 = 0;
+
+#[derive(Debug, Clone, Default)]
+pub struct Track {
+    pub number: u64,
+    pub kind: StreamKind,
+    pub codec_id: String,
+    pub codec_private: Vec<u8>,
+    pub default: bool,
+    pub forced: bool,
+    pub hearing_impaired: bool,
+    pub language: Option<String>,
+    pub name: Option<String>,
+    // nanoseconds
+    pub default_duration: Option<u64>,
+    pub width: u32,
+    pub height: u32,
+    pub display_width: Option<u32>,
+    pub display_height: Option<u32>,
+    pub sample_rate: f64,
+    pub channels: u32,
+    // bytes the muxer removed from the start of every frame (header stripping)
+    pub stripped_header: Vec<u8>,
+    // compressed with something else or encrypted; the frames cannot be copied
+    pub encoded: bool,
+    // the frames are zlib-compressed (`encoded` is set too); only subtitles are inflated
+    pub zlib: bool,
+}
+
+#[derive(Debug)]
+pub struct Frame {
+    pub track: u64,
+    // in timestamp ticks, see `Demuxer::timestamp_scale`
+    pub timestamp: i64,
+    pub keyframe: bool,
+    // of the whole block, in ticks, when the file says so
+    pub block_duration: Option<u64>,
+    // position of the frame in a laced block and the number of frames in it
+    pub lace: (usize, usize),
+    pub data: Vec<u8>,
+}
+
+struct Cluster {
+    // None for clusters of unknown size, which end at the next top-level element
+    end: Option<u64>,
+    timestamp: i64,
+}
+
+pub struct Demuxer<R> {
+    r: R,
+    pub tracks: Vec<Track>,
+    // nanoseconds per timestamp tick
+    pub timestamp_scale: u64,
+    // seconds
+    pub duration: Option<f64>,
+    pos: u64,
+    segment_end: u64,
+    cluster: Option<Cluster>,
+    pending: VecDeque<Frame>,
+    // track numbers whose frames are wanted, None for all
+    selected: Option<Vec<u64>>,
+}
+
+impl<R: Read + Seek> Demuxer<R> {
+    // Reads everything up to the first cluster. Tracks have to be in front of it, which
+    // every muxer we know of does.
+    pub fn open(mut r: R) -> Result<Demuxer<R>, ProbeError> {
+        let file_len = r.seek(SeekFrom::End(0))?;
+        r.seek(SeekFrom::Start(0))?;
+        let header = read_header(&mut r)?;
+        if header.id != EBML {
+            return Err(ProbeError::Malformed("no EBML header"));
+        }
+        let mut pos = header.data_start + header.size.unwrap_or(0);
+        let segment = loop {
+            if pos >= file_len {
+                return Err(ProbeError::Malformed("no Segment"));
+            }
+            r.seek(SeekFrom::Start(pos))?;
+            let h = read_header(&mut r)?;
+            if h.id == SEGMENT {
+                break h;
+            }
+            pos = h.data_start + h.size.ok_or(ProbeError::Malformed("element size"))?;
+        };
+
+        let mut demuxer = Demuxer {
+            r,
+            tracks: Vec::new(),
+            timestamp_scale: 1_000_000,
+            duration: None,
+            pos: segment.data_start,
+            segment_end: segment.size.map(|s| segment.data_start + s).unwrap_or(file_len).min(file_len),
+            cluster: None,
+            pending: VecDeque::new(),
+            selected: None,
+        };
+        let mut duration = None;
+        while demuxer.pos < demuxer.segment_end {
+            demuxer.r.seek(SeekFrom::Start(demuxer.pos))?;
+            let h = read_header(&mut demuxer.r)?;
+            if h.id == CLUSTER {
+                break;
+            }
+            match h.id {
+                INFO => {
+                    for (id, value) in children(&read_body(&mut demuxer.r, &h)?) {
+                        match id {
+                            TIMESTAMP_SCALE => demuxer.timestamp_scale = uint(value).max(1),
+                            DURATION => duration = float(value),
+                            _ => {}
+                        }
+                    }
+                }
+                TRACKS => {
+                    for (id, entry) in children(&read_body(&mut demuxer.r, &h)?) {
+                        if id == TRACK_ENTRY {
+                            demuxer.tracks.push(parse_track(entry));
+                        }
+                    }
+                }
+                _ => {}
+            }
+            demuxer.pos = h.data_start + h.size.ok_or(ProbeError::Malformed("element size"))?;
+        }
+        if demuxer.tracks.is_empty() {
+            return Err(ProbeError::Malformed("no tracks before the first cluster"));
+        }
+        demuxer.duration = duration.map(|d| d * demuxer.timestamp_scale as f64 / 1e9).filter(|d| *d > 0.0);
+        Ok(demuxer)
+    }
+
+    // Only hands out frames of these tracks. SimpleBlocks of other tracks are skipped
+    // without reading them, which makes pulling a subtitle track out of a large file fast.
+    pub fn select(&mut self, tracks: &[u64]) {
+        self.selected = Some(tracks.to_vec());
+    }
+
+    // Byte offset of the next element, for progress reporting.
+    pub fn position(&self) -> u64 {
+        self.pos
+    }
+
+    // The next frame in file order, None at the end of the segment. A file cut off in
+    // the middle of a block ends there instead of failing.
+    pub fn next_frame(&mut self) -> Result<Option<Frame>, ProbeError> {
+        loop {
+            if let Some(frame) = self.pending.pop_front() {
+                return Ok(Some(frame));
+            }
+            if let Some(end) = self.cluster.as_ref().and_then(|c| c.end) {
+                if self.pos >= end {
+                    self.cluster = None;
+                }
+            }
+            if self.pos >= self.segment_end {
+                return Ok(None);
+            }
+            self.r.seek(SeekFrom::Start(self.pos))?;
+            let h = match read_header(&mut self.r) {
+                Ok(h) => h,
+                Err(ProbeError::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+                Err(e) => return Err(e),
+            };
+            if self.cluster.is_some() && matches!(h.id, CLUSTER | CUES | TAGS | INFO | TRACKS | SEEK_HEAD | ATTACHMENTS | CHAPTERS) {
+                // only reached for clusters of unknown size
+                self.cluster = None;
+            }
+            if self.cluster.is_none() {
+                if h.id == CLUSTER {
+                    self.cluster = Some(Cluster { end: h.size.map(|s| h.data_start + s), timestamp: 0 });
+                    self.pos = h.data_start;
+                } else {
+                    self.pos = h.data_start + h.size.ok_or(ProbeError::Malformed("element size"))?;
+                }
+                continue;
+            }
+
+            let size = h.size.ok_or(ProbeError::Malformed("element size"))?;
+            if h.id == SIMPLE_BLOCK && !self.wants_block(&h)? {
+                self.pos = h.data_start + size;
+                continue;
+            }
+            match h.id {
+                CLUSTER_TIMESTAMP | SIMPLE_BLOCK | BLOCK_GROUP => {
+                    let body = match read_body(&mut self.r, &h) {
+                        Ok(body) => body,
+                        Err(ProbeError::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+                        Err(e) => return Err(e),
+                    };
+                    self.read_cluster_child(&h, &body)?;
+                }
+                _ => {}
+            }
+            self.pos = h.data_start + size;
+        }
+    }
+
+    // Whether the block the reader stands at belongs to a selected track; reads only its
+    // track number.
+    fn wants_block(&mut self, h: &Header) -> Result<bool, ProbeError> {
+        let Some(selected) = &self.selected else { return Ok(true) };
+        let mut head = vec![0; h.size.unwrap_or(0).min(8) as usize];
+        // a cut-off block is read again by next_frame, which ends there
+        let wanted = self.r.read_exact(&mut head).is_err() || vint(&head, false).map_or(true, |(track, _)| selected.contains(&track));
+        self.r.seek(SeekFrom::Start(h.data_start))?;
+        Ok(wanted)
+    }
+
+    fn read_cluster_child(&mut self, h: &Header, body: &[u8]) -> Result<(), ProbeError> {
+        let cluster_timestamp = self.cluster.as_ref().map_or(0, |c| c.timestamp);
+        match h.id {
+            CLUSTER_TIMESTAMP => {
+                if let Some(cluster) = self.cluster.as_mut() {
+                    cluster.timestamp = uint(body) as i64;
+                }
+            }
+            SIMPLE_BLOCK => self.read_block(body, cluster_timestamp, None, None)?,
+            BLOCK_GROUP => {
+                let mut block = None;
+                let mut duration = None;
+                let mut referenced = false;
+                for (id, value) in children(body) {
+                    match id {
+                        BLOCK => block = Some(value),
+                        BLOCK_DURATION => duration = Some(uint(value)),
+                        REFERENCE_BLOCK => referenced = true,
+                        _ => {}
+                    }
+                }
+                if let Some(block) = block {
+                    // a Block without references is a keyframe
+                    self.read_block(block, cluster_timestamp, Some(!referenced), duration)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // Splits a (Simple)Block into its laced frames. `keyframe` is None for SimpleBlocks,
+    // which carry the flag themselves.
+    fn read_block(&mut self, data: &[u8], cluster_timestamp: i64, keyframe: Option<bool>, duration: Option<u64>) -> Result<(), ProbeError> {
+        let (track, n) = vint(data, false).ok_or(ProbeError::Malformed("block track number"))?;
+        if self.selected.as_ref().is_some_and(|s| !s.contains(&track)) {
+            return Ok(());
+        }
+        if data.len() < n + 3 {
+            return Err(ProbeError::Malformed("block header"));
+        }
+        let relative = i16::from_be_bytes([data[n], data[n + 1]]) as i64;
+        let flags = data[n + 2];
+        let keyframe = keyframe.unwrap_or(flags & 0x80 != 0);
+        let payload = &data[n + 3..];
+        let frames = match (flags >> 1) & 0x03 {
+            0 => vec![payload],
+            1 => xiph_lacing(payload)?,
+            2 => fixed_lacing(payload)?,
+            _ => ebml_lacing(payload)?,
+        };
+        let stripped = self.tracks.iter().find(|t| t.number == track).map(|t| t.stripped_header.as_slice()).unwrap_or_default();
+        let count = frames.len();
+        for (i, frame) in frames.into_iter().enumerate() {
+            let mut bytes = Vec::with_capacity(stripped.len() + frame.len());
+            bytes.extend_from_slice(stripped);
+            bytes.extend_from_slice(frame);
+            self.pending.push_back(Frame {
+                track,
+                timestamp: cluster_timestamp + relative,
+                keyframe,
+                block_duration: duration,
+                lace: (i, count),
+                data: bytes,
+            });
+        }
+        Ok(())
+    }
+}
+
+fn parse_track(entry: &[u8]) -> Track {
+    let mut track = Track { default: true, language: Some("eng".to_string()), channels: 1, sample_rate: 8000.0, ..Default::default() };
+    let mut bcp47 = None;
+    for (id, value) in children(entry) {
+        match id {
+            TRACK_NUMBER => track.number = uint(value),
+            TRACK_TYPE => {
+                track.kind = match uint(value) {
+                    1 => StreamKind::Video,
+                    2 => StreamKind::Audio,
+                    17 => StreamKind::Subtitle,
+                    _ => StreamKind::Other,
+                }
+            }
+            FLAG_DEFAULT => track.default = uint(value) != 0,
+            FLAG_FORCED => track.forced = uint(value) != 0,
+            FLAG_HEARING_IMPAIRED => track.hearing_impaired = uint(value) != 0,
+            NAME => track.name = Some(string(value)).filter(|n| !n.is_empty()),
+            LANGUAGE => track.language = Some(string(value)),
+            LANGUAGE_BCP47 => bcp47 = Some(string(value)),
+            CODEC_ID => track.codec_id = string(value),
+            CODEC_PRIVATE => track.codec_private = value.to_vec(),
+            DEFAULT_DURATION => track.default_duration = Some(uint(value)).filter(|d| *d > 0),
+            VIDEO => {
+                for (id, value) in children(value) {
+                    match id {
+                        PIXEL_WIDTH => track.width = uint(value) as u32,
+                        PIXEL_HEIGHT => track.height = uint(value) as u32,
+                        DISPLAY_WIDTH => track.display_width = Some(uint(value) as u32),
+                        DISPLAY_HEIGHT => track.display_height = Some(uint(value) as u32),
+                        _ => {}
+                    }
+                }
+            }
+            AUDIO => {
+                for (id, value) in children(value) {
+                    match id {
+                        SAMPLING_FREQUENCY => track.sample_rate = float(value).unwrap_or(track.sample_rate),
+                        CHANNELS => track.channels = uint(value) as u32,
+                        _ => {}
+                    }
+                }
+            }
+            CONTENT_ENCODINGS => {
+                for (id, encoding) in children(value) {
+                    if id != CONTENT_ENCODING {
+                        continue;
+                    }
+                    for (id, value) in children(encoding) {
+                        match id {
+                            CONTENT_COMPRESSION => {
+                                let fields = children(value);
+                                // ContentCompAlgo defaults to zlib
+                                let algo = fields.iter().find(|(id, _)| *id == CONTENT_COMP_ALGO).map_or(0, |(_, v)| uint(v));
+                                let settings = fields.iter().find(|(id, _)| *id == CONTENT_COMP_SETTINGS).map(|(_, v)| v.to_vec());
+                                match (algo, settings) {
+                                    (HEADER_STRIPPING, Some(settings)) => track.stripped_header = settings,
+                                    (ZLIB, _) => {
+                                        track.encoded = true;
+                                        track.zlib = true;
+                                    }
+                                    _ => track.encoded = true,
+                                }
+                            }
+                            CONTENT_ENCRYPTION => track.encoded = true,
+                            _ => {}
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    if bcp47.is_some() {
+        track.language = bcp47;
+    }
+    track
+}
+
+fn xiph_lacing(data: &[u8]) -> Result<Vec<&[u8]>, ProbeError> {
+    let (&count, mut rest) = data.split_first().ok_or(ProbeError::Malformed("lacing"))?;
+    let mut sizes = Vec::new();
+    for _ in 0..count {
+        let mut size = 0usize;
+        loop {
+            let (&b, tail) = rest.split_first().ok_or(ProbeError::Malformed("lacing"))?;
+            rest = tail;
+            size += b as usize;
+            if b != 255 {
+                break;
+            }
+        }
+        sizes.push(size);
+    }
+    split_laced(rest, &sizes)
+}
+
+fn fixed_lacing(data: &[u8]) -> Result<Vec<&[u8]>, ProbeError> {
+    let (&count, rest) = data.split_first().ok_or(ProbeError::Malformed("lacing"))?;
+    let frames = count as usize + 1;
+    if rest.is_empty() || rest.len() % frames != 0 {
+        return Err(ProbeError::Malformed("lacing"));
+    }
+    Ok(rest.chunks(rest.len() / frames).collect())
+}
+
+// Sizes after the first are stored as signed differences to the previous one.
+fn ebml_lacing(data: &[u8]) -> Result<Vec<&[u8]>, ProbeError> {
+    let (&count, mut rest) = data.split_first().ok_or(ProbeError::Malformed("lacing"))?;
+    let mut sizes: Vec<usize> = Vec::new();
+    for i in 0..count as usize {
+        let (raw, len) = vint(rest, false).ok_or(ProbeError::Malformed("lacing"))?;
+        rest = &rest[len..];
+        let size = if i == 0 {
+            raw as i64
+        } else {
+            let bias = (1i64 << (7 * len - 1)) - 1;
+            sizes[i - 1] as i64 + raw as i64 - bias
+        };
+        sizes.push(usize::try_from(size).map_err(|_| ProbeError::Malformed("lacing"))?);
+    }
+    split_laced(rest, &sizes)
+}
+
+// Frames of the given sizes followed by one taking the rest.
+fn split_laced<'a>(mut data: &'a [u8], sizes: &[usize]) -> Result<Vec<&'a [u8]>, ProbeError> {
+    let mut out = Vec::with_capacity(sizes.len() + 1);
+    for &size in sizes {
+        if size > data.len() {
+            return Err(ProbeError::Malformed("lacing"));
+        }
+        let (frame, rest) = data.split_at(size);
+        out.push(frame);
+        data = rest;
+    }
+    out.push(data);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixed_lacing_splits_evenly() {
+        let frames = fixed_lacing(&[2, 1, 1, 2, 2, 3, 3]).unwrap();
+        assert_eq!(frames, vec![&[1, 1][..], &[2, 2], &[3, 3]]);
+    }
+
+    #[test]
+    fn fixed_lacing_rejects_empty_and_uneven_data() {
+        assert!(matches!(fixed_lacing(&[1]), Err(ProbeError::Malformed("lacing"))));
+        assert!(matches!(fixed_lacing(&[1, 1, 2, 3]), Err(ProbeError::Malformed("lacing"))));
+        assert!(matches!(fixed_lacing(&[]), Err(ProbeError::Malformed("lacing"))));
+    }
+}
@@ -0,0 +1,897 @@
+// Lossless Matroska to fragmented MP4 remux, so H.264/AAC (and other MP4-compatible)
+// videos in .mkv files play in the webview without re-encoding. The copy goes to the
+// remux cache and is linked to the video through the `remuxes` table.
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+
+use crate::db::database;
+use crate::db::model::Remux;
+use crate::library::FileEntry;
+use crate::probe::StreamKind;
+
+mod fmp4;
+pub(crate) mod matroska;
+
+use fmp4::{Handler, Run, Sample, TrackConfig};
+use matroska::{Demuxer, Frame, Track};
+
+// A fragment is cut at the first video keyframe after this much media.
+const FRAGMENT_SECONDS: f64 = 2.0;
+// Cut anyway once this much sample data is queued, for streams with rare keyframes.
+const MAX_FRAGMENT_BYTES: usize = 32 * 1024 * 1024;
+const VIDEO_TIMESCALE: u32 = 90_000;
+
+// Sample rates with an index in the AudioSpecificConfig.
+const AAC_SAMPLE_RATES: [u32; 13] = [96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350];
+
+pub fn cache_dir() -> PathBuf {
+    database::get_app_dir().join("remux")
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RemuxPhase {
+    Remuxing,
+    Finished,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RemuxProgress {
+    pub video_id: i64,
+    pub phase: RemuxPhase,
+    // bytes of the source read so far, out of its size
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+    // seconds of media written; the duration is None when the file does not say
+    pub seconds_done: f64,
+    pub duration: Option<f64>,
+    // the MP4 file once finished
+    pub path: Option<String>,
+    // tracks left out and why, e.g. "audio track 3 (A_AC3): the codec cannot be copied into MP4"
+    pub skipped_tracks: Vec<String>,
+}
+
+// Cancellation flags of the remuxes currently running, by video id.
+static RUNNING_REMUXES: Lazy<Mutex<HashMap<i64, Arc<AtomicBool>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+// Removes the video from RUNNING_REMUXES when the remux ends, however it ends.
+pub struct RemuxGuard {
+    video_id: i64,
+    pub cancel: Arc<AtomicBool>,
+}
+
+impl Drop for RemuxGuard {
+    fn drop(&mut self) {
+        if let Ok(mut remuxes) = RUNNING_REMUXES.lock() {
+            remuxes.remove(&self.video_id);
+        }
+    }
+}
+
+pub fn begin_remux(video_id: i64) -> Result<RemuxGuard, String> {
+    let mut remuxes = RUNNING_REMUXES.lock().map_err(|e| e.to_string())?;
+    if remuxes.contains_key(&video_id) {
+        return Err("This video is already being remuxed".to_string());
+    }
+    let cancel = Arc::new(AtomicBool::new(false));
+    remuxes.insert(video_id, cancel.clone());
+    Ok(RemuxGuard { video_id, cancel })
+}
+
+// Returns false when no remux of the video is running.
+pub fn cancel_remux(video_id: i64) -> bool {
+    match RUNNING_REMUXES.lock() {
+        Ok(remuxes) => match remuxes.get(&video_id) {
+            Some(flag) => {
+                flag.store(true, Ordering::SeqCst);
+                true
+            }
+            None => false,
+        },
+        Err(_) => false,
+    }
+}
+
+// The cached copy of the video, unless there is none, its file is gone or the source
+// changed since it was made.
+pub fn cached_remux(conn: &Connection, video_id: i64) -> rusqlite::Result<Option<Remux>> {
+    let row = conn
+        .query_row(
+            "SELECT r.video_id, r.path, r.file_size, r.created_at, r.source_size, r.source_mtime, v.path
+             FROM remuxes r JOIN videos v ON v.id = r.video_id WHERE r.video_id = ?1",
+            params![video_id],
+            |row| {
+                let remux = Remux { video_id: row.get(0)?, path: row.get(1)?, file_size: row.get(2)?, created_at: row.get(3)? };
+                Ok((remux, row.get::<_, Option<i64>>(4)?, row.get::<_, Option<i64>>(5)?, row.get::<_, String>(6)?))
+            },
+        )
+        .optional()?;
+    let Some((remux, size, mtime, source)) = row else {
+        return Ok(None);
+    };
+    let fresh = match FileEntry::from_path(Path::new(&source)) {
+        Ok(entry) => Some(entry.size) == size && Some(entry.mtime) == mtime && Path::new(&remux.path).is_file(),
+        Err(_) => false,
+    };
+    Ok(if fresh { Some(remux) } else { None })
+}
+
+// Deletes the cached copy of the video, if there is one.
+pub fn delete_remux(conn: &Connection, video_id: i64) -> rusqlite::Result<bool> {
+    let path: Option<String> = conn
+        .query_row("SELECT path FROM remuxes WHERE video_id = ?1", params![video_id], |row| row.get(0))
+        .optional()?;
+    let Some(path) = path else {
+        return Ok(false);
+    };
+    let _ = fs::remove_file(path);
+    conn.execute("DELETE FROM remuxes WHERE video_id = ?1", params![video_id])?;
+    Ok(true)
+}
+
+// Deletes files in the cache no `remuxes` row points to, left by videos removed while
+// the app was not running or by remuxes that were interrupted. Called at startup, when
+// no remux is running. Returns how many files were deleted.
+pub fn sweep_cache(conn: &Connection) -> rusqlite::Result<usize> {
+    let Ok(entries) = fs::read_dir(cache_dir()) else {
+        return Ok(0);
+    };
+    let mut stmt = conn.prepare("SELECT path FROM remuxes")?;
+    let known = stmt.query_map([], |row| row.get::<_, String>(0))?.collect::<rusqlite::Result<HashSet<String>>>()?;
+    let mut deleted = 0;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_file() && !known.contains(path.to_string_lossy().as_ref()) && fs::remove_file(&path).is_ok() {
+            deleted += 1;
+        }
+    }
+    Ok(deleted)
+}
+
+// Remuxes the video into the cache, replacing an older copy. The database lock is only
+// held to read the row and to record the result. A cancelled or failed remux leaves no
+// file behind.
+pub fn remux_video(
+    db: &Mutex<Connection>,
+    video_id: i64,
+    cancel: &AtomicBool,
+    mut report: impl FnMut(&RemuxProgress),
+) -> Result<RemuxProgress, String> {
+    let (uuid, path, container): (String, String, Option<String>) = {
+        let conn = db.lock().map_err(|e| e.to_string())?;
+        conn.query_row("SELECT uuid, path, container FROM videos WHERE id = ?1", params![video_id], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+        })
+        .map_err(|e| e.to_string())?
+    };
+    if !matches!(container.as_deref(), Some("mkv" | "webm")) {
+        return Err("Only Matroska files can be remuxed".to_string());
+    }
+    let source = FileEntry::from_path(Path::new(&path)).map_err(|e| e.to_string())?;
+    let dir = cache_dir();
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    let target = dir.join(format!("{}.mp4", uuid));
+    let part = dir.join(format!("{}.mp4.part", uuid));
+
+    let mut progress = RemuxProgress {
+        video_id,
+        phase: RemuxPhase::Remuxing,
+        bytes_done: 0,
+        bytes_total: source.size as u64,
+        seconds_done: 0.0,
+        duration: None,
+        path: None,
+        skipped_tracks: Vec::new(),
+    };
+    report(&progress);
+    let mut last_report = Instant::now();
+
+    let result = (|| -> io::Result<Remuxed> {
+        let input = BufReader::new(File::open(&path)?);
+        let output = BufWriter::new(File::create(&part)?);
+        remux(input, output, cancel, |bytes, seconds, duration| {
+            progress.bytes_done = bytes;
+            progress.seconds_done = seconds;
+            progress.duration = duration;
+            if last_report.elapsed() > Duration::from_millis(200) {
+                report(&progress);
+                last_report = Instant::now();
+            }
+        })
+    })();
+    let remuxed = match result {
+        Ok(remuxed) => remuxed,
+        Err(e) => {
+            let _ = fs::remove_file(&part);
+            if e.kind() == io::ErrorKind::Interrupted {
+                progress.phase = RemuxPhase::Cancelled;
+                report(&progress);
+                return Ok(progress);
+            }
+            return Err(e.to_string());
+        }
+    };
+    fs::rename(&part, &target).map_err(|e| e.to_string())?;
+    let file_size = fs::metadata(&target).map_err(|e| e.to_string())?.len();
+
+    let target = target.to_string_lossy().to_string();
+    let conn = db.lock().map_err(|e| e.to_string())?;
+    conn.execute(
+        "INSERT INTO remuxes (video_id, path, file_size, source_size, source_mtime, created_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, datetime('now'))
+         ON CONFLICT(video_id) DO UPDATE SET path = excluded.path, file_size = excluded.file_size,
+            source_size = excluded.source_size, source_mtime = excluded.source_mtime, created_at = excluded.created_at",
+        params![video_id, target, file_size as i64, source.size, source.mtime],
+    )
+    .map_err(|e| e.to_string())?;
+    drop(conn);
+
+    progress.phase = RemuxPhase::Finished;
+    progress.bytes_done = progress.bytes_total;
+    progress.path = Some(target);
+    progress.skipped_tracks = remuxed.skipped_tracks;
+    report(&progress);
+    Ok(progress)
+}
+
+pub struct Remuxed {
+    pub skipped_tracks: Vec<String>,
+}
+
+// Copies the first video track and every audio track MP4 can carry from `input` into
+// fragmented MP4. `on_progress` gets the bytes read, the seconds written and the
+// duration. Fails with `ErrorKind::Interrupted` when `cancel` is set, and with
+// `ErrorKind::Unsupported` when there is nothing to copy.
+pub fn remux<R: Read + Seek, W: Write>(
+    input: R,
+    output: W,
+    cancel: &AtomicBool,
+    mut on_progress: impl FnMut(u64, f64, Option<f64>),
+) -> io::Result<Remuxed> {
+    let mut demuxer = Demuxer::open(input).map_err(invalid_data)?;
+    let scale = demuxer.timestamp_scale;
+    let duration = demuxer.duration;
+
+    let mut skipped_tracks = Vec::new();
+    let mut outputs: Vec<OutputTrack> = Vec::new();
+    let first_video = demuxer.tracks.iter().position(|t| t.kind == StreamKind::Video);
+    // the default audio track comes first so it is the one that plays
+    let mut order: Vec<&Track> = demuxer.tracks.iter().collect();
+    order.sort_by_key(|t| match t.kind {
+        StreamKind::Video => 0,
+        StreamKind::Audio if t.default => 1,
+        _ => 2,
+    });
+    for track in order {
+        let is_first_video = first_video.is_some_and(|i| demuxer.tracks[i].number == track.number);
+        let result = match track.kind {
+            StreamKind::Video if is_first_video => OutputTrack::video(track),
+            StreamKind::Audio => OutputTrack::audio(track),
+            StreamKind::Video => Err("only the first video track is copied".to_string()),
+            _ => Err("only audio and video are copied".to_string()),
+        };
+        match result {
+            Ok(out) => outputs.push(out),
+            // the player would show nothing useful without the picture
+            Err(reason) if is_first_video => return Err(io::Error::new(io::ErrorKind::Unsupported, reason)),
+            Err(reason) => skipped_tracks.push(format!("{} track {} ({}): {}", kind_name(track.kind), track.number, track.codec_id, reason)),
+        }
+    }
+    if outputs.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::Unsupported, "No track can be copied into MP4"));
+    }
+    let mut audio_seen = false;
+    for out in outputs.iter_mut().filter(|o| o.config.handler == Handler::Audio) {
+        out.config.alternate_group = 1;
+        out.config.enabled = !audio_seen;
+        audio_seen = true;
+    }
+
+    let configs = outputs.iter().map(|o| o.config.clone()).collect();
+    let mut writer = fmp4::Writer::new(output, configs, duration.map(|d| (d * 1000.0).round() as u64))?;
+    // the first track (video, else the default audio) decides where fragments are cut
+    let mut frames = 0u64;
+    loop {
+        if frames % 64 == 0 && cancel.load(Ordering::Relaxed) {
+            return Err(io::Error::new(io::ErrorKind::Interrupted, "cancelled"));
+        }
+        frames += 1;
+        let frame = demuxer.next_frame().map_err(invalid_data)?;
+        let Some(frame) = frame else { break };
+        let Some(index) = outputs.iter().position(|o| o.number == frame.track) else { continue };
+        let queued: usize = outputs.iter().map(|o| o.queued_bytes).sum();
+        let cut = index == 0 && frame.keyframe && outputs[0].queued_seconds(&frame, scale) >= FRAGMENT_SECONDS;
+        if cut || queued > MAX_FRAGMENT_BYTES {
+            let next = if index == 0 { Some(frame.timestamp) } else { None };
+            write_fragment(&mut writer, &mut outputs, next, scale)?;
+            let seconds = outputs[0].written_seconds();
+            on_progress(demuxer.position(), seconds, duration);
+        }
+        outputs[index].push(frame, scale);
+    }
+    write_fragment(&mut writer, &mut outputs, None, scale)?;
+    on_progress(demuxer.position(), outputs[0].written_seconds(), duration);
+    writer.finish()?;
+    Ok(Remuxed { skipped_tracks })
+}
+
+fn write_fragment<W: Write>(writer: &mut fmp4::Writer<W>, outputs: &mut [OutputTrack], next: Option<i64>, scale: u64) -> io::Result<()> {
+    let runs: Vec<Run> = outputs.iter_mut().enumerate().filter_map(|(i, o)| o.take_run(i, next, scale)).collect();
+    writer.write_fragment(&runs)
+}
+
+fn invalid_data(e: crate::probe::ProbeError) -> io::Error {
+    match e {
+        crate::probe::ProbeError::Io(e) => e,
+        e => io::Error::new(io::ErrorKind::InvalidData, e.to_string()),
+    }
+}
+
+fn kind_name(kind: StreamKind) -> &'static str {
+    match kind {
+        StreamKind::Video => "video",
+        StreamKind::Audio => "audio",
+        StreamKind::Subtitle => "subtitle",
+        StreamKind::Other => "data",
+    }
+}
+
+// How the duration of an audio frame is found; MP4 needs exact sample counts, the
+// millisecond timestamps of Matroska would make the audio drift.
+#[derive(Clone, Copy)]
+enum AudioFrames {
+    Fixed(u32),
+    Mp3,
+    Opus,
+    Flac,
+}
+
+struct Queued {
+    // presentation time in the track timescale
+    pts: i64,
+    keyframe: bool,
+    duration: Option<u32>,
+    data: Vec<u8>,
+}
+
+struct OutputTrack {
+    number: u64,
+    config: TrackConfig,
+    audio_frames: Option<AudioFrames>,
+    // frame duration from the track header, in the track timescale
+    default_duration: Option<u32>,
+    queue: Vec<Queued>,
+    queued_bytes: usize,
+    // decode time where the next fragment of this track starts
+    next_decode: Option<u64>,
+    last_duration: u32,
+}
+
+impl OutputTrack {
+    fn new(track: &Track, config: TrackConfig, audio_frames: Option<AudioFrames>) -> OutputTrack {
+        let default_duration = track
+            .default_duration
+            .map(|ns| (ns as u128 * config.timescale as u128 / 1_000_000_000) as u32)
+            .filter(|d| *d > 0);
+        OutputTrack {
+            number: track.number,
+            config,
+            audio_frames,
+            default_duration,
+            queue: Vec::new(),
+            queued_bytes: 0,
+            next_decode: None,
+            last_duration: 0,
+        }
+    }
+
+    fn video(track: &Track) -> Result<OutputTrack, String> {
+        if track.encoded {
+            return Err("the frames are compressed or encrypted".to_string());
+        }
+        let (kind, config_kind) = match track.codec_id.as_str() {
+            "V_MPEG4/ISO/AVC" => (b"avc1", b"avcC"),
+            "V_MPEGH/ISO/HEVC" => (b"hvc1", b"hvcC"),
+            "V_AV1" => (b"av01", b"av1C"),
+            _ => return Err("the codec cannot be copied into MP4".to_string()),
+        };
+        if track.codec_private.is_empty() {
+            return Err("the decoder configuration is missing".to_string());
+        }
+        let entry = fmp4::visual_entry(kind, track.width, track.height, fmp4::make_box(config_kind, &track.codec_private));
+        let config = TrackConfig {
+            handler: Handler::Video,
+            timescale: VIDEO_TIMESCALE,
+            sample_entry: entry,
+            language: language(track),
+            width: track.display_width.unwrap_or(track.width),
+            height: track.display_height.unwrap_or(track.height),
+            enabled: true,
+            alternate_group: 0,
+        };
+        Ok(OutputTrack::new(track, config, None))
+    }
+
+    fn audio(track: &Track) -> Result<OutputTrack, String> {
+        if track.encoded {
+            return Err("the frames are compressed or encrypted".to_string());
+        }
+        let rate = track.sample_rate.round() as u32;
+        let channels = track.channels;
+        let (timescale, entry, frames) = match track.codec_id.as_str() {
+            id if id.starts_with("A_AAC") => {
+                let asc = aac_config(track)?;
+                // the frameLengthFlag selects 960 instead of 1024 samples
+                let short = asc.len() >= 2 && asc[0] >> 3 != 31 && asc[1] & 0x04 != 0;
+                let entry = fmp4::audio_entry(b"mp4a", channels, rate, fmp4::esds(0, 0x40, &asc));
+                (rate, entry, AudioFrames::Fixed(if short { 960 } else { 1024 }))
+            }
+            "A_MPEG/L3" => (rate, fmp4::audio_entry(b"mp4a", channels, rate, fmp4::esds(0, 0x6b, &[])), AudioFrames::Mp3),
+            "A_OPUS" => {
+                let dops = opus_config(&track.codec_private).ok_or("the OpusHead is missing")?;
+                (48000, fmp4::audio_entry(b"Opus", channels, 48000, fmp4::make_box(b"dOps", &dops)), AudioFrames::Opus)
+            }
+            "A_FLAC" => {
+                let blocks = track.codec_private.strip_prefix(b"fLaC").ok_or("the FLAC header is missing")?;
+                (rate, fmp4::audio_entry(b"fLaC", channels, rate, fmp4::full_box(b"dfLa", 0, 0, blocks)), AudioFrames::Flac)
+            }
+            _ => return Err("the codec cannot be copied into MP4".to_string()),
+        };
+        if timescale == 0 {
+            return Err("the sample rate is missing".to_string());
+        }
+        let config = TrackConfig {
+            handler: Handler::Audio,
+            timescale,
+            sample_entry: entry,
+            language: language(track),
+            width: 0,
+            height: 0,
+            enabled: true,
+            alternate_group: 0,
+        };
+        Ok(OutputTrack::new(track, config, Some(frames)))
+    }
+
+    fn to_timescale(&self, ticks: i64, scale: u64) -> i64 {
+        (ticks as i128 * scale as i128 * self.config.timescale as i128 / 1_000_000_000) as i64
+    }
+
+    fn push(&mut self, frame: Frame, scale: u64) {
+        let mut pts = self.to_timescale(frame.timestamp, scale);
+        // frames after the first in a laced block share its timestamp
+        if frame.lace.0 > 0 {
+            let step = match frame.block_duration {
+                Some(d) => self.to_timescale(d as i64, scale) / frame.lace.1 as i64,
+                None => self.default_duration.unwrap_or(0) as i64,
+            };
+            pts += step * frame.lace.0 as i64;
+        }
+        let duration = self.audio_frames.and_then(|kind| audio_frame_duration(kind, &frame.data));
+        self.queued_bytes += frame.data.len();
+        let keyframe = frame.keyframe || self.config.handler == Handler::Audio;
+        self.queue.push(Queued { pts, keyframe, duration, data: frame.data });
+    }
+
+    fn queued_seconds(&self, next: &Frame, scale: u64) -> f64 {
+        match self.queue.first() {
+            Some(first) => (self.to_timescale(next.timestamp, scale) - first.pts) as f64 / self.config.timescale as f64,
+            None => 0.0,
+        }
+    }
+
+    fn written_seconds(&self) -> f64 {
+        self.next_decode.unwrap_or(0) as f64 / self.config.timescale as f64
+    }
+
+    // Turns the queue into a run. Matroska only stores presentation times; decode times
+    // are those times in ascending order, which is the order decoders need them in, and
+    // the difference becomes the (possibly negative) composition offset. `next` is the
+    // timestamp of the frame that starts the next fragment, if it belongs to this track.
+    fn take_run(&mut self, index: usize, next: Option<i64>, scale: u64) -> Option<Run> {
+        if self.queue.is_empty() {
+            return None;
+        }
+        let queue = std::mem::take(&mut self.queue);
+        self.queued_bytes = 0;
+        let start = self.next_decode.unwrap_or_else(|| queue[0].pts.max(0) as u64);
+        let next = next.map(|t| self.to_timescale(t, scale));
+
+        let samples = if self.config.handler == Handler::Video {
+            let mut sorted: Vec<i64> = queue.iter().map(|q| q.pts).collect();
+            sorted.sort_unstable();
+            let mut dts = Vec::with_capacity(sorted.len());
+            for (i, &pts) in sorted.iter().enumerate() {
+                let value = if i == 0 { start as i64 } else { pts.max(dts[i - 1] + 1) };
+                dts.push(value);
+            }
+            let fallback = self.default_duration.unwrap_or(VIDEO_TIMESCALE / 25);
+            queue
+                .into_iter()
+                .enumerate()
+                .map(|(i, q)| {
+                    let duration = match dts.get(i + 1) {
+                        Some(following) => (following - dts[i]) as u32,
+                        None => match next {
+                            Some(next) if next > dts[i] => (next - dts[i]) as u32,
+                            _ => if self.last_duration > 0 { self.last_duration } else { fallback },
+                        },
+                    };
+                    self.last_duration = duration;
+                    Sample {
+                        duration,
+                        composition_offset: (q.pts - dts[i]).clamp(i32::MIN as i64, i32::MAX as i64) as i32,
+                        keyframe: q.keyframe,
+                        data: q.data,
+                    }
+                })
+                .collect()
+        } else {
+            let pts: Vec<i64> = queue.iter().map(|q| q.pts).collect();
+            queue
+                .into_iter()
+                .enumerate()
+                .map(|(i, q)| {
+                    let duration = q.duration.unwrap_or_else(|| match pts.get(i + 1) {
+                        Some(following) if *following > pts[i] => (following - pts[i]) as u32,
+                        _ => self.default_duration.unwrap_or(self.last_duration),
+                    });
+                    self.last_duration = duration;
+                    Sample { duration, composition_offset: 0, keyframe: true, data: q.data }
+                })
+                .collect::<Vec<_>>()
+        };
+        let total: u64 = samples.iter().map(|s| s.duration as u64).sum();
+        self.next_decode = Some(start + total);
+        Some(Run { track: index, decode_time: start, samples })
+    }
+}
+
+fn language(track: &Track) -> String {
+    // MP4 takes ISO 639-2 codes; BCP 47 tags are cut to their primary language when that
+    // is three letters long
+    let language = track.language.as_deref().unwrap_or("und");
+    let primary = language.split('-').next().unwrap_or("und").to_ascii_lowercase();
+    if primary.len() == 3 {
+        primary
+    } else {
+        "und".to_string()
+    }
+}
+
+// The AudioSpecificConfig from CodecPrivate, or built from the codec id of old files
+// that name the profile there instead ("A_AAC/MPEG4/LC").
+fn aac_config(track: &Track) -> Result<Vec<u8>, String> {
+    if !track.codec_private.is_empty() {
+        return Ok(track.codec_private.clone());
+    }
+    let profile: u16 = match track.codec_id.as_str() {
+        id if id.ends_with("/MAIN") => 1,
+        id if id.ends_with("/SSR") => 3,
+        id if id.ends_with("/LTP") => 4,
+        _ => 2,
+    };
+    let rate = track.sample_rate.round() as u32;
+    let index = AAC_SAMPLE_RATES.iter().position(|r| *r == rate).ok_or("the AAC configuration is missing")? as u16;
+    let config = (profile << 11) | (index << 7) | ((track.channels as u16 & 0x0f) << 3);
+    Ok(config.to_be_bytes().to_vec())
+}
+
+// `dOps` body from the OpusHead in CodecPrivate; MP4 stores the fields big-endian.
+fn opus_config(head: &[u8]) -> Option<Vec<u8>> {
+    if head.len() < 19 || !head.starts_with(b"OpusHead") {
+        return None;
+    }
+    let mut dops = vec![0, head[9]];
+    dops.extend_from_slice(&u16::from_le_bytes([head[10], head[11]]).to_be_bytes());
+    dops.extend_from_slice(&u32::from_le_bytes([head[12], head[13], head[14], head[15]]).to_be_bytes());
+    dops.extend_from_slice(&i16::from_le_bytes([head[16], head[17]]).to_be_bytes());
+    dops.push(head[18]);
+    if head[18] != 0 {
+        // stream count, coupled count and the channel mapping
+        dops.extend_from_slice(head.get(19..21 + head[9] as usize)?);
+    }
+    Some(dops)
+}
+
+// Samples in one audio frame, read from the frame itself.
+fn audio_frame_duration(kind: AudioFrames, data: &[u8]) -> Option<u32> {
+    match kind {
+        AudioFrames::Fixed(samples) => Some(samples),
+        AudioFrames::Mp3 => {
+            if data.len() < 4 || data[0] != 0xff || data[1] & 0xe0 != 0xe0 {
+                return None;
+            }
+            let mpeg1 = (data[1] >> 3) & 0x03 == 3;
+            match (data[1] >> 1) & 0x03 {
+                3 => Some(384),
+                2 => Some(1152),
+                1 => Some(if mpeg1 { 1152 } else { 576 }),
+                _ => None,
+            }
+        }
+        AudioFrames::Opus => {
+            // the TOC byte gives the frame size (RFC 6716, 3.1) and the code the frame count
+            let toc = *data.first()?;
+            let config = (toc >> 3) as usize;
+            let size = match config {
+                0..=11 => [480, 960, 1920, 2880][config % 4],
+                12..=15 => [480, 960][config % 2],
+                _ => [120, 240, 480, 960][config % 4],
+            };
+            let count = match toc & 0x03 {
+                0 => 1,
+                1 | 2 => 2,
+                _ => (*data.get(1)? & 0x3f) as u32,
+            };
+            Some(size * count)
+        }
+        AudioFrames::Flac => {
+            if data.len() < 5 || data[0] != 0xff || data[1] & 0xfe != 0xf8 {
+                return None;
+            }
+            let code = data[2] >> 4;
+            match code {
+                1 => Some(192),
+                2..=5 => Some(576 << (code - 2)),
+                8..=15 => Some(256 << (code - 8)),
+                6 | 7 => {
+                    // the block size follows the UTF-8 coded frame number
+                    let at = 4 + (data[4].leading_ones() as usize).max(1);
+                    if code == 6 {
+                        Some(*data.get(at)? as u32 + 1)
+                    } else {
+                        Some(u16::from_be_bytes([*data.get(at)?, *data.get(at + 1)?]) as u32 + 1)
+                    }
+                }
+                _ => None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // millisecond timestamps, as most Matroska files use
+    const MS: u64 = 1_000_000;
+
+    fn frame(timestamp: i64, keyframe: bool, data: &[u8]) -> Frame {
+        Frame { track: 1, timestamp, keyframe, block_duration: None, lace: (0, 1), data: data.to_vec() }
+    }
+
+    fn video_track() -> OutputTrack {
+        let track = Track {
+            number: 1,
+            kind: StreamKind::Video,
+            codec_id: "V_MPEG4/ISO/AVC".to_string(),
+            codec_private: vec![1, 0x64, 0, 0x1f],
+            default_duration: Some(40_000_000),
+            width: 1280,
+            height: 720,
+            ..Default::default()
+        };
+        OutputTrack::video(&track).unwrap()
+    }
+
+    fn audio_track(codec_id: &str, codec_private: &[u8]) -> Result<OutputTrack, String> {
+        let track = Track {
+            number: 2,
+            kind: StreamKind::Audio,
+            codec_id: codec_id.to_string(),
+            codec_private: codec_private.to_vec(),
+            sample_rate: 48000.0,
+            channels: 2,
+            ..Default::default()
+        };
+        OutputTrack::audio(&track)
+    }
+
+    // (duration, composition offset) of every sample
+    fn timing(run: &Run) -> Vec<(u32, i32)> {
+        run.samples.iter().map(|s| (s.duration, s.composition_offset)).collect()
+    }
+
+    #[test]
+    fn mp3_frame_durations() {
+        let cases: [(&[u8], Option<u32>); 7] = [
+            (&[0xff, 0xfb, 0x90, 0x00], Some(1152)), // MPEG-1 Layer III
+            (&[0xff, 0xf3, 0x90, 0x00], Some(576)),  // MPEG-2 Layer III
+            (&[0xff, 0xfd, 0x90, 0x00], Some(1152)), // Layer II
+            (&[0xff, 0xff, 0x90, 0x00], Some(384)),  // Layer I
+            (&[0xff, 0xf9, 0x90, 0x00], None),       // reserved layer
+            (&[0x49, 0x44, 0x33, 0x04], None),
+            (&[0xff, 0xfb], None),
+        ];
+        for (data, expected) in cases {
+            assert_eq!(audio_frame_duration(AudioFrames::Mp3, data), expected, "{:02x?}", data);
+        }
+        assert_eq!(audio_frame_duration(AudioFrames::Fixed(960), &[]), Some(960));
+    }
+
+    #[test]
+    fn opus_frame_durations() {
+        let toc = |config: u8, code: u8| config << 3 | code;
+        let cases: [(Vec<u8>, Option<u32>); 10] = [
+            (vec![toc(0, 0)], Some(480)),   // SILK 10 ms
+            (vec![toc(3, 0)], Some(2880)),  // SILK 60 ms
+            (vec![toc(12, 0)], Some(480)),  // hybrid 10 ms
+            (vec![toc(13, 0)], Some(960)),  // hybrid 20 ms
+            (vec![toc(16, 0)], Some(120)),  // CELT 2.5 ms
+            (vec![toc(31, 0)], Some(960)),  // CELT 20 ms
+            (vec![toc(1, 1)], Some(1920)),  // two frames of equal size
+            (vec![toc(1, 2), 5], Some(1920)),
+            (vec![toc(16, 3), 0x85], Some(600)), // five frames, padding flag set
+            (vec![toc(16, 3)], None),
+        ];
+        for (data, expected) in cases {
+            assert_eq!(audio_frame_duration(AudioFrames::Opus, &data), expected, "{:02x?}", data);
+        }
+        assert_eq!(audio_frame_duration(AudioFrames::Opus, &[]), None);
+    }
+
+    #[test]
+    fn flac_block_sizes() {
+        let header = |code: u8, rest: &[u8]| [&[0xff, 0xf8, code << 4 | 0x09, 0x08][..], rest].concat();
+        let cases = [
+            (header(1, &[0]), Some(192)),
+            (header(2, &[0]), Some(576)),
+            (header(5, &[0]), Some(4608)),
+            (header(8, &[0]), Some(256)),
+            (header(12, &[0]), Some(4096)),
+            (header(15, &[0]), Some(32768)),
+            // 8 and 16 bit sizes minus one after the frame number, here one and two bytes long
+            (header(6, &[0x05, 0x0f]), Some(16)),
+            (header(7, &[0xc2, 0x80, 0x10, 0x00]), Some(4097)),
+            (header(7, &[0xc2, 0x80, 0x10]), None),
+            (header(0, &[0]), None),
+        ];
+        for (data, expected) in cases {
+            assert_eq!(audio_frame_duration(AudioFrames::Flac, &data), expected, "{:02x?}", data);
+        }
+        // variable block size streams use 0xfff9
+        assert_eq!(audio_frame_duration(AudioFrames::Flac, &[0xff, 0xf9, 0x19, 0x08, 0]), Some(192));
+        assert_eq!(audio_frame_duration(AudioFrames::Flac, &[0xff, 0xfa, 0x19, 0x08, 0]), None);
+    }
+
+    #[test]
+    fn b_frames_get_decode_times_and_negative_offsets() {
+        let mut track = video_track();
+        // I P B B P B B at 25 fps, in decode order with presentation timestamps
+        for (pts, key) in [(0, true), (120, false), (40, false), (80, false), (240, false), (160, false), (200, false)] {
+            track.push(frame(pts, key, &[0]), MS);
+        }
+        let run = track.take_run(0, Some(280), MS).unwrap();
+        assert_eq!(run.decode_time, 0);
+        assert_eq!(
+            timing(&run),
+            vec![(3600, 0), (3600, 7200), (3600, -3600), (3600, -3600), (3600, 7200), (3600, -3600), (3600, -3600)]
+        );
+        assert_eq!(run.samples.iter().map(|s| s.keyframe).collect::<Vec<_>>(), vec![true, false, false, false, false, false, false]);
+
+        // the next run carries on where this one ended; without a following frame the
+        // last sample keeps the previous duration
+        for (pts, key) in [(280, true), (360, false), (320, false)] {
+            track.push(frame(pts, key, &[0]), MS);
+        }
+        let run = track.take_run(0, None, MS).unwrap();
+        assert_eq!(run.decode_time, 25200);
+        assert_eq!(timing(&run), vec![(3600, 0), (3600, 3600), (3600, -3600)]);
+        assert!(track.take_run(0, None, MS).is_none());
+    }
+
+    #[test]
+    fn decode_times_are_strictly_increasing() {
+        let mut track = video_track();
+        // a stream that starts late, with two frames on the same timestamp
+        for (pts, key) in [(80, true), (80, false), (120, false)] {
+            track.push(frame(pts, key, &[0]), MS);
+        }
+        let run = track.take_run(0, None, MS).unwrap();
+        assert_eq!(run.decode_time, 7200);
+        // without a following frame the last sample repeats the one before
+        assert_eq!(timing(&run), vec![(1, 0), (3599, -1), (3599, 0)]);
+
+        // and a lone frame gets the track's default duration
+        let mut track = video_track();
+        track.push(frame(0, true, &[0]), MS);
+        assert_eq!(timing(&track.take_run(0, None, MS).unwrap()), vec![(3600, 0)]);
+
+        // a frame before zero starts the first fragment at zero, the offset keeps its time
+        let mut track = video_track();
+        for (pts, key) in [(-40, true), (0, false)] {
+            track.push(frame(pts, key, &[0]), MS);
+        }
+        let run = track.take_run(0, Some(40), MS).unwrap();
+        assert_eq!(run.decode_time, 0);
+        assert_eq!(timing(&run), vec![(1, -3600), (3599, -1)]);
+    }
+
+    #[test]
+    fn audio_durations_come_from_the_frames() {
+        let mut aac = audio_track("A_AAC", &[0x11, 0x90]).unwrap();
+        for pts in [0, 21, 42] {
+            aac.push(frame(pts, false, &[0]), MS);
+        }
+        let run = aac.take_run(1, None, MS).unwrap();
+        assert_eq!(timing(&run), vec![(1024, 0), (1024, 0), (1024, 0)]);
+        assert!(run.samples.iter().all(|s| s.keyframe));
+
+        // frames that cannot be read fall back to the timestamps
+        let mut mp3 = audio_track("A_MPEG/L3", &[]).unwrap();
+        for (pts, data) in [(0, &[0xff, 0xfb, 0x90, 0x00][..]), (24, &[0][..]), (48, &[0][..])] {
+            mp3.push(frame(pts, false, data), MS);
+        }
+        let run = mp3.take_run(1, None, MS).unwrap();
+        assert_eq!((run.decode_time, timing(&run)), (0, vec![(1152, 0), (1152, 0), (1152, 0)]));
+
+        // laced frames share the block timestamp
+        let mut opus = audio_track("A_OPUS", &opus_head(2, 0, &[])).unwrap();
+        for lace in 0..3 {
+            let mut laced = frame(100, false, &[13 << 3]);
+            laced.block_duration = Some(60);
+            laced.lace = (lace, 3);
+            opus.push(laced, MS);
+        }
+        let pts: Vec<i64> = opus.queue.iter().map(|q| q.pts).collect();
+        assert_eq!(pts, vec![4800, 5760, 6720]);
+        let run = opus.take_run(1, None, MS).unwrap();
+        assert_eq!((run.decode_time, timing(&run)), (4800, vec![(960, 0), (960, 0), (960, 0)]));
+    }
+
+    fn opus_head(channels: u8, mapping: u8, table: &[u8]) -> Vec<u8> {
+        let mut head = b"OpusHead".to_vec();
+        head.extend_from_slice(&[1, channels]);
+        head.extend_from_slice(&312u16.to_le_bytes());
+        head.extend_from_slice(&48000u32.to_le_bytes());
+        head.extend_from_slice(&(-256i16).to_le_bytes());
+        head.push(mapping);
+        head.extend_from_slice(table);
+        head
+    }
+
+    #[test]
+    fn opus_head_becomes_dops() {
+        assert_eq!(opus_config(&opus_head(2, 0, &[])), Some(vec![0, 2, 0x01, 0x38, 0, 0, 0xbb, 0x80, 0xff, 0x00, 0]));
+        // surround: stream count, coupled count and one mapping entry per channel
+        let table = [4, 2, 0, 4, 1, 2, 3, 5];
+        let dops = opus_config(&opus_head(6, 1, &table)).unwrap();
+        assert_eq!((&dops[..11], &dops[11..]), (&[0, 6, 0x01, 0x38, 0, 0, 0xbb, 0x80, 0xff, 0x00, 1][..], &table[..]));
+        assert_eq!(opus_config(&opus_head(6, 1, &table[..5])), None);
+        assert_eq!(opus_config(&opus_head(2, 0, &[])[..18]), None);
+        assert_eq!(opus_config(b"OpusTagsxxxxxxxxxxxxxxx"), None);
+        assert!(audio_track("A_OPUS", b"").is_err());
+    }
+
+    #[test]
+    fn aac_config_from_the_codec_id() {
+        let track = |codec_id: &str, private: &[u8], sample_rate: f64, channels: u32| Track {
+            codec_id: codec_id.to_string(),
+            codec_private: private.to_vec(),
+            sample_rate,
+            channels,
+            ..Default::default()
+        };
+        assert_eq!(aac_config(&track("A_AAC", &[0x12, 0x10], 44100.0, 2)), Ok(vec![0x12, 0x10]));
+        assert_eq!(aac_config(&track("A_AAC/MPEG4/LC", &[], 48000.0, 2)), Ok(vec![0x11, 0x90]));
+        assert_eq!(aac_config(&track("A_AAC/MPEG2/MAIN", &[], 44100.0, 1)), Ok(vec![0x0a, 0x08]));
+        assert_eq!(aac_config(&track("A_AAC/MPEG4/LTP", &[], 8000.0, 2)), Ok(vec![0x25, 0x90]));
+        assert!(aac_config(&track("A_AAC/MPEG4/LC", &[], 12345.0, 2)).is_err());
+
+        // the frameLengthFlag picks 960 sample frames
+        let mut short = audio_track("A_AAC", &[0x11, 0x94]).unwrap();
+        short.push(frame(0, false, &[0]), MS);
+        assert_eq!(timing(&short.take_run(1, None, MS).unwrap()), vec![(960, 0)]);
+    }
+}
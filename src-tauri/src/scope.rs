@@ -0,0 +1,162 @@
+// What the webview may read from disk through the `asset:` protocol: the library roots
+// and the app's caches, following the `libraries` table like the watcher does. They are
+// allowed in a Tauri filesystem scope that `stream::handle_asset` checks. The app's own
+// asset scope cannot drop a folder once allowed (forbidding one is permanent), so the
+// scope is created again whenever a library is added or removed: a removed root is
+// unreadable at once and readable again if it is added back. Videos themselves are
+// served by the `video://` protocol (see `stream`), which checks the `videos` table.
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+use once_cell::sync::Lazy;
+use rusqlite::Connection;
+use tauri::scope::fs::Scope;
+use tauri::utils::config::FsScope;
+use tauri::AppHandle;
+
+use crate::db::database;
+use crate::library;
+use crate::remux;
+use crate::subtitles;
+
+// The current scope; None, so nothing is readable, until `sync_roots` first ran.
+static SCOPE: Lazy<RwLock<Option<Scope>>> = Lazy::new(|| RwLock::new(None));
+
+// Folders the app writes files into that the frontend loads, created on first use.
+pub fn cache_dirs() -> Vec<PathBuf> {
+    let app_dir = database::get_app_dir();
+    vec![remux::cache_dir(), subtitles::cache_dir(), app_dir.join("thumbnails")]
+}
+
+// Replaces the scope with one allowing the current library roots and the caches. Called
+// at startup and whenever a library is added or removed. Roots that cannot be resolved
+// (an unplugged drive) are left out until the next call.
+pub fn sync_roots(app: &AppHandle) -> Result<(), String> {
+    let roots = {
+        let conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+        canonical_roots(&conn)?
+    };
+    let scope = Scope::new(app, &FsScope::default()).map_err(|e| e.to_string())?;
+    let mut allowed: Vec<PathBuf> = roots.into_values().collect();
+    for dir in cache_dirs() {
+        let _ = fs::create_dir_all(&dir);
+        allowed.extend(fs::canonicalize(&dir).ok());
+    }
+    for dir in allowed {
+        scope.allow_directory(&dir, true).map_err(|e| format!("Could not allow {}: {}", dir.display(), e))?;
+    }
+    *SCOPE.write().map_err(|e| e.to_string())? = Some(scope);
+    Ok(())
+}
+
+// Whether `path` lies inside an allowed folder once symlinks are resolved.
+pub fn is_allowed(path: &Path) -> bool {
+    SCOPE.read().is_ok_and(|scope| scope.as_ref().is_some_and(|scope| scope.is_allowed(path)))
+}
+
+// The canonical form of `path` when it lies inside a library root, for commands that
+// read folders the frontend names.
+pub fn check_in_library(path: &Path) -> Result<PathBuf, String> {
+    let roots = {
+        let conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+        canonical_roots(&conn)?
+    };
+    in_library(&roots, path)
+}
+
+fn in_library(roots: &HashMap<i64, PathBuf>, path: &Path) -> Result<PathBuf, String> {
+    let path = fs::canonicalize(path).map_err(|e| e.to_string())?;
+    match library::library_for(roots, &path) {
+        Some(_) => Ok(path),
+        None => Err(format!("{} is not inside a library folder", path.display())),
+    }
+}
+
+// Library roots with symlinks resolved; a root may have become a link since it was added.
+fn canonical_roots(conn: &Connection) -> Result<HashMap<i64, PathBuf>, String> {
+    let roots = library::load_roots(conn).map_err(|e| e.to_string())?;
+    Ok(roots.into_iter().filter_map(|(id, root)| fs::canonicalize(root).ok().map(|root| (id, root))).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::canonicalize(dir).unwrap()
+    }
+
+    fn db_with_roots(roots: &[PathBuf]) -> Connection {
+        let mut conn = Connection::open_in_memory().unwrap();
+        crate::db::schema::init_schema(&mut conn).unwrap();
+        for root in roots {
+            conn.execute("INSERT INTO libraries (path) VALUES (?1)", [root.to_string_lossy()]).unwrap();
+        }
+        conn
+    }
+
+    fn check(conn: &Connection, path: &Path) -> Result<PathBuf, String> {
+        in_library(&canonical_roots(conn)?, path)
+    }
+
+    #[test]
+    fn dot_dot_and_sibling_prefixes_stay_outside() {
+        let dir = temp_dir("scope-dots");
+        for sub in ["lib/sub", "library2"] {
+            fs::create_dir_all(dir.join(sub)).unwrap();
+        }
+        let conn = db_with_roots(&[dir.join("lib")]);
+        assert_eq!(check(&conn, &dir.join("lib/sub")), Ok(dir.join("lib/sub")));
+        assert_eq!(check(&conn, &dir.join("lib/sub/..")), Ok(dir.join("lib")));
+        // "/lib" is not a prefix of "/library2"
+        assert!(check(&conn, &dir.join("library2")).is_err());
+        assert!(check(&conn, &dir.join("lib/sub/../../library2")).is_err());
+        assert!(check(&conn, &dir.join("lib/..")).is_err());
+        assert!(check(&conn, &dir.join("lib/missing")).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn links_are_followed_to_where_they_point() {
+        use std::os::unix::fs::symlink;
+
+        let dir = temp_dir("scope-links");
+        for sub in ["lib/sub", "outside"] {
+            fs::create_dir_all(dir.join(sub)).unwrap();
+        }
+        fs::write(dir.join("lib/sub/a.mp4"), "a").unwrap();
+        symlink(dir.join("outside"), dir.join("lib/escape")).unwrap();
+        symlink(dir.join("lib/sub"), dir.join("outside/into")).unwrap();
+        let conn = db_with_roots(&[dir.join("lib")]);
+        assert!(check(&conn, &dir.join("lib/escape")).is_err());
+        assert_eq!(check(&conn, &dir.join("outside/into/a.mp4")), Ok(dir.join("lib/sub/a.mp4")));
+
+        // a root that is a link itself is compared by its target
+        symlink(dir.join("lib"), dir.join("linked")).unwrap();
+        let conn = db_with_roots(&[dir.join("linked")]);
+        assert_eq!(check(&conn, &dir.join("lib/sub/a.mp4")), Ok(dir.join("lib/sub/a.mp4")));
+        assert_eq!(check(&conn, &dir.join("linked/sub")), Ok(dir.join("lib/sub")));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn removed_and_unavailable_roots_allow_nothing() {
+        let dir = temp_dir("scope-removed");
+        for sub in ["one", "two"] {
+            fs::create_dir_all(dir.join(sub)).unwrap();
+        }
+        let conn = db_with_roots(&[dir.join("one"), dir.join("two"), dir.join("unplugged")]);
+        assert_eq!(canonical_roots(&conn).unwrap().len(), 2);
+        assert!(check(&conn, &dir.join("two")).is_ok());
+        conn.execute("DELETE FROM libraries WHERE path = ?1", [dir.join("two").to_string_lossy()]).unwrap();
+        assert!(check(&conn, &dir.join("two")).is_err());
+        assert!(check(&conn, &dir.join("one")).is_ok());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
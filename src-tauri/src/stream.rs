@@ -0,0 +1,327 @@
+// `video://` protocol serving library files to the webview by uuid, in place of the
+// `asset:` protocol which can read any path. Only files with a row in `videos` are
+// served. Range requests, which the player makes, are answered in chunks so seeking
+// works without loading whole files into memory; a request without one gets the file
+// when it fits in a chunk and the first chunk as a partial response otherwise.
+// The URL is `video://localhost/<uuid>` (`http://video.localhost/<uuid>` on Windows, see
+// `convertFileSrc(uuid, "video")`); `?remux` serves the cached MP4 copy instead and
+// `<uuid>/subtitles/<id>.vtt` a subtitle of the video as WebVTT. The `asset:` protocol
+// is served here too, for files inside the folders `scope` allows.
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use percent_encoding::percent_decode_str;
+use rusqlite::{params, Connection, OptionalExtension};
+use tauri::http::{header, Method, Request, Response, StatusCode};
+
+use crate::db::database;
+use crate::remux;
+use crate::scope;
+use crate::subtitles::{self, timing};
+
+pub const SCHEME: &str = "video";
+pub const ASSET_SCHEME: &str = "asset";
+
+// Most bytes sent for one request; open-ended ranges ("bytes=0-") get this much and the
+// player asks for the rest as it plays.
+const MAX_CHUNK: u64 = 4 * 1024 * 1024;
+
+// URL of `path` on this protocol as the webview loads it; WebView2 and Android only
+// take custom schemes in the form http://<scheme>.localhost.
+pub fn url(path: &str) -> String {
+    if cfg!(any(windows, target_os = "android")) {
+        format!("http://{}.localhost/{}", SCHEME, path)
+    } else {
+        format!("{}://localhost/{}", SCHEME, path)
+    }
+}
+
+pub fn handle(request: &Request<Vec<u8>>) -> Response<Vec<u8>> {
+    respond(serve(request))
+}
+
+// `asset://localhost/<path>` as made by `convertFileSrc(path)`, with the path
+// percent-encoded.
+pub fn handle_asset(request: &Request<Vec<u8>>) -> Response<Vec<u8>> {
+    respond(serve_asset(request))
+}
+
+fn respond(result: Result<Response<Vec<u8>>, (StatusCode, String)>) -> Response<Vec<u8>> {
+    match result {
+        Ok(response) => response,
+        Err((status, message)) => Response::builder()
+            .status(status)
+            .header(header::CONTENT_TYPE, "text/plain")
+            .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+            .body(message.into_bytes())
+            .unwrap_or_default(),
+    }
+}
+
+fn serve(request: &Request<Vec<u8>>) -> Result<Response<Vec<u8>>, (StatusCode, String)> {
+    if request.method() != Method::GET && request.method() != Method::HEAD {
+        return Err((StatusCode::METHOD_NOT_ALLOWED, "Only GET and HEAD are supported".to_string()));
+    }
+    let uri = request.uri();
+    // "video://localhost/<uuid>", or "video://<uuid>" where the uuid ends up as the host
+    let mut segments: Vec<&str> = uri.path().split('/').filter(|s| !s.is_empty()).collect();
+    if segments.is_empty() {
+        segments.push(uri.host().unwrap_or_default());
+    }
+    let uuid = segments[0];
+    if let ["subtitles", file] = segments[1..] {
+        return serve_subtitle(uuid, file.strip_suffix(".vtt").unwrap_or(file));
+    }
+    if segments.len() > 1 {
+        return Err((StatusCode::NOT_FOUND, format!("Nothing at {}", uri.path())));
+    }
+    let remux = uri.query().is_some_and(|q| q.split('&').any(|p| p == "remux" || p.starts_with("remux=")));
+
+    let path = {
+        let conn = database::get_connection().lock().map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        resolve(&conn, uuid, remux).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+    };
+    let Some(path) = path else {
+        return Err((StatusCode::NOT_FOUND, format!("No video with uuid {}", uuid)));
+    };
+    serve_file(request, &path)
+}
+
+fn serve_asset(request: &Request<Vec<u8>>) -> Result<Response<Vec<u8>>, (StatusCode, String)> {
+    if request.method() != Method::GET && request.method() != Method::HEAD {
+        return Err((StatusCode::METHOD_NOT_ALLOWED, "Only GET and HEAD are supported".to_string()));
+    }
+    let encoded = request.uri().path().trim_start_matches('/');
+    let path = percent_decode_str(encoded).decode_utf8().map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+    let path = PathBuf::from(path.as_ref());
+    if !scope::is_allowed(&path) {
+        return Err((StatusCode::FORBIDDEN, format!("{} is outside the library folders", path.display())));
+    }
+    serve_file(request, &path)
+}
+
+// The file, or the part of it a Range header asks for; never more than MAX_CHUNK.
+fn serve_file(request: &Request<Vec<u8>>, path: &Path) -> Result<Response<Vec<u8>>, (StatusCode, String)> {
+    let mut file = File::open(path).map_err(|e| (StatusCode::NOT_FOUND, e.to_string()))?;
+    let size = file.metadata().map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?.len();
+    let range = request.headers().get(header::RANGE).and_then(|v| v.to_str().ok());
+    let (start, end, ranged) = match range.and_then(|r| parse_range(r, size)) {
+        Some(Ok((start, end))) => (start, end, true),
+        Some(Err(())) => {
+            return Response::builder()
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .header(header::CONTENT_RANGE, format!("bytes */{}", size))
+                .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+                .body(Vec::new())
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()));
+        }
+        // no Range header, or one we do not understand: the whole file if it is small,
+        // otherwise the first chunk, which tells the client it can ask for the rest
+        None if size > MAX_CHUNK => (0, MAX_CHUNK - 1, true),
+        None => (0, size.saturating_sub(1), false),
+    };
+    let length = if size == 0 { 0 } else { end - start + 1 };
+
+    let mut builder = Response::builder()
+        .status(if ranged { StatusCode::PARTIAL_CONTENT } else { StatusCode::OK })
+        .header(header::CONTENT_TYPE, mime_type(path))
+        .header(header::CONTENT_LENGTH, length)
+        .header(header::ACCEPT_RANGES, "bytes")
+        // the player loads subtitles with crossorigin set, which makes this a CORS request too
+        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*");
+    if ranged {
+        builder = builder.header(header::CONTENT_RANGE, format!("bytes {}-{}/{}", start, end, size));
+    }
+    let mut body = Vec::new();
+    if request.method() == Method::GET && length > 0 {
+        body.reserve_exact(length as usize);
+        file.seek(SeekFrom::Start(start)).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        file.take(length).read_to_end(&mut body).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    }
+    builder.body(body).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+fn serve_subtitle(uuid: &str, id: &str) -> Result<Response<Vec<u8>>, (StatusCode, String)> {
+    let (path, correction) = {
+        let conn = database::get_connection().lock().map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        let internal = |e: rusqlite::Error| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+        let video_id: Option<i64> =
+            conn.query_row("SELECT id FROM videos WHERE uuid = ?1", params![uuid], |row| row.get(0)).optional().map_err(internal)?;
+        let correction = match video_id {
+            Some(id) => Some(timing::load(&conn, id).map_err(internal)?),
+            None => None,
+        };
+        (resolve(&conn, uuid, false).map_err(internal)?, correction)
+    };
+    let (Some(path), Some(correction)) = (path, correction) else {
+        return Err((StatusCode::NOT_FOUND, format!("No video with uuid {}", uuid)));
+    };
+    let vtt = subtitles::load_vtt(&path, uuid, id, &correction).map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, e))?;
+    let Some(vtt) = vtt else {
+        return Err((StatusCode::NOT_FOUND, format!("No subtitle {} for video {}", id, uuid)));
+    };
+    Response::builder()
+        .header(header::CONTENT_TYPE, "text/vtt; charset=utf-8")
+        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        // the timing correction can change between loads of the same URL
+        .header(header::CACHE_CONTROL, "no-store")
+        .body(vtt.into_bytes())
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+// The file behind a uuid; None for unknown uuids, missing files and, with `remux`, videos
+// without an up-to-date cached copy.
+fn resolve(conn: &Connection, uuid: &str, remux: bool) -> rusqlite::Result<Option<PathBuf>> {
+    let row: Option<(i64, String, bool)> = conn
+        .query_row("SELECT id, path, missing FROM videos WHERE uuid = ?1", params![uuid], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+        })
+        .optional()?;
+    match row {
+        Some((id, _, _)) if remux => Ok(remux::cached_remux(conn, id)?.map(|r| PathBuf::from(r.path))),
+        Some((_, path, false)) => Ok(Some(PathBuf::from(path))),
+        _ => Ok(None),
+    }
+}
+
+// First range of a "bytes=" Range header as inclusive offsets, shortened to MAX_CHUNK.
+// None when the header is malformed (it is then ignored), Err when the range lies
+// outside the file.
+fn parse_range(header: &str, size: u64) -> Option<Result<(u64, u64), ()>> {
+    let spec = header.trim().strip_prefix("bytes=")?.split(',').next()?.trim();
+    let (first, last) = spec.split_once('-')?;
+    let (start, end) = match (first.trim(), last.trim()) {
+        // "bytes=-500": the last 500 bytes
+        ("", suffix) => {
+            let suffix: u64 = suffix.parse().ok()?;
+            if suffix == 0 {
+                return Some(Err(()));
+            }
+            (size.saturating_sub(suffix), size.saturating_sub(1))
+        }
+        (first, "") => (first.parse().ok()?, size.saturating_sub(1)),
+        (first, last) => {
+            let (start, end): (u64, u64) = (first.parse().ok()?, last.parse().ok()?);
+            if end < start {
+                return None;
+            }
+            (start, end.min(size.saturating_sub(1)))
+        }
+    };
+    if start >= size {
+        return Some(Err(()));
+    }
+    Some(Ok((start, end.min(start + MAX_CHUNK - 1))))
+}
+
+fn mime_type(path: &Path) -> &'static str {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or_default().to_ascii_lowercase();
+    match extension.as_str() {
+        "mp4" | "m4v" => "video/mp4",
+        "m4a" => "audio/mp4",
+        "mov" => "video/quicktime",
+        "mkv" => "video/x-matroska",
+        "mka" => "audio/x-matroska",
+        "webm" => "video/webm",
+        "avi" => "video/x-msvideo",
+        "flv" => "video/x-flv",
+        "wmv" | "asf" => "video/x-ms-asf",
+        "wma" => "audio/x-ms-wma",
+        "ts" | "m2ts" | "mts" => "video/mp2t",
+        "mpg" | "mpeg" => "video/mpeg",
+        "ogv" => "video/ogg",
+        "ogg" | "oga" | "opus" => "audio/ogg",
+        "mp3" => "audio/mpeg",
+        "flac" => "audio/flac",
+        "wav" => "audio/wav",
+        "vtt" => "text/vtt; charset=utf-8",
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "webp" => "image/webp",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    const MB: u64 = 1024 * 1024;
+
+    fn get(path: &Path, range: Option<&str>) -> Response<Vec<u8>> {
+        let mut builder = Request::builder().uri("video://localhost/x");
+        if let Some(range) = range {
+            builder = builder.header(header::RANGE, range);
+        }
+        respond(serve_file(&builder.body(Vec::new()).unwrap(), path))
+    }
+
+    #[test]
+    fn ranges_are_clamped_to_the_file_and_the_chunk_size() {
+        assert_eq!(parse_range("bytes=0-99", 1000), Some(Ok((0, 99))));
+        assert_eq!(parse_range("bytes=900-2000", 1000), Some(Ok((900, 999))));
+        assert_eq!(parse_range("bytes=-100", 1000), Some(Ok((900, 999))));
+        assert_eq!(parse_range("bytes=-5000", 1000), Some(Ok((0, 999))));
+        // only the first of several ranges is served
+        assert_eq!(parse_range(" bytes=10-19, 50-59", 1000), Some(Ok((10, 19))));
+        assert_eq!(parse_range("bytes=0-", 100 * MB), Some(Ok((0, MAX_CHUNK - 1))));
+        assert_eq!(parse_range("bytes=-20971520", 100 * MB), Some(Ok((80 * MB, 80 * MB + MAX_CHUNK - 1))));
+    }
+
+    #[test]
+    fn unsatisfiable_and_malformed_ranges() {
+        assert_eq!(parse_range("bytes=1000-", 1000), Some(Err(())));
+        assert_eq!(parse_range("bytes=-0", 1000), Some(Err(())));
+        assert_eq!(parse_range("bytes=0-", 0), Some(Err(())));
+        assert_eq!(parse_range("bytes=20-10", 1000), None);
+        assert_eq!(parse_range("bytes=a-b", 1000), None);
+        assert_eq!(parse_range("bytes=100", 1000), None);
+        assert_eq!(parse_range("items=0-10", 1000), None);
+    }
+
+    #[test]
+    fn whole_file_without_a_range_and_part_of_it_with_one() {
+        let path = std::env::temp_dir().join(format!("stream-test-{}.mp4", std::process::id()));
+        fs::write(&path, b"0123456789").unwrap();
+        let request = |range: Option<&str>| get(&path, range);
+
+        let whole = request(None);
+        assert_eq!(whole.status(), StatusCode::OK);
+        assert_eq!(whole.body(), b"0123456789");
+        assert_eq!(whole.headers()[header::CONTENT_TYPE], "video/mp4");
+        assert!(whole.headers().get(header::CONTENT_RANGE).is_none());
+
+        let part = request(Some("bytes=2-4"));
+        assert_eq!(part.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(part.body(), b"234");
+        assert_eq!(part.headers()[header::CONTENT_RANGE], "bytes 2-4/10");
+
+        let outside = request(Some("bytes=10-"));
+        assert_eq!(outside.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(outside.headers()[header::CONTENT_RANGE], "bytes */10");
+        assert_eq!(outside.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn large_files_are_never_read_whole() {
+        let path = std::env::temp_dir().join(format!("stream-test-large-{}.mp4", std::process::id()));
+        let size = MAX_CHUNK + MB;
+        fs::write(&path, (0..size).map(|i| (i % 251) as u8).collect::<Vec<_>>()).unwrap();
+        let request = |range: Option<&str>| get(&path, range);
+
+        for range in [None, Some("bytes=a-b"), Some("items=0-")] {
+            let response = request(range);
+            assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+            assert_eq!(response.body().len() as u64, MAX_CHUNK);
+            assert_eq!(response.headers()[header::CONTENT_LENGTH], MAX_CHUNK.to_string().as_str());
+            assert_eq!(response.headers()[header::CONTENT_RANGE], format!("bytes 0-{}/{}", MAX_CHUNK - 1, size).as_str());
+        }
+        let rest = request(Some(&format!("bytes={}-", MAX_CHUNK)));
+        assert_eq!(rest.body().len() as u64, MB);
+        assert_eq!(rest.body()[0], (MAX_CHUNK % 251) as u8);
+        fs::remove_file(&path).unwrap();
+    }
+}
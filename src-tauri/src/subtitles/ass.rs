@@ -0,0 +1,447 @@
+// Advanced SubStation Alpha (.ass) and SubStation Alpha (.ssa) reader. Dialogue events
+// become WebVTT cues keeping what WebVTT can show: italic, bold, underline, the primary
+// colour (as `::cue` classes), alignment (`\an`, `\a`) and `\pos`. Everything else
+// (karaoke, fades, movement, fonts, drawings, ...) is left out and named in `dropped`.
+use super::{parse_time, rewrite_lines, vtt, Converted, Cue};
+
+// Script resolution when the header does not give one, as in the ASS specification.
+const DEFAULT_PLAY_RES: (f64, f64) = (384.0, 288.0);
+// The player draws text in white unless told otherwise.
+const WHITE: u32 = 0xffffff;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Format {
+    bold: bool,
+    italic: bool,
+    underline: bool,
+    // primary colour as 0xRRGGBB
+    color: u32,
+}
+
+#[derive(Debug, Clone)]
+struct Style {
+    name: String,
+    format: Format,
+    // numpad layout, 2 is bottom centre
+    alignment: u8,
+}
+
+impl Default for Style {
+    fn default() -> Style {
+        Style { name: "Default".to_string(), format: Format { bold: false, italic: false, underline: false, color: WHITE }, alignment: 2 }
+    }
+}
+
+pub fn parse(text: &str) -> Converted {
+    let mut section = String::new();
+    let mut legacy = false;
+    let mut play_res = (None, None);
+    let mut style_fields: Vec<String> = Vec::new();
+    let mut event_fields: Vec<String> = Vec::new();
+    let mut styles: Vec<Style> = Vec::new();
+    let mut converted = Converted::default();
+    let mut classes: Vec<u32> = Vec::new();
+
+    for line in text.lines().map(str::trim) {
+        if line.starts_with('[') && line.ends_with(']') {
+            section = line[1..line.len() - 1].to_ascii_lowercase();
+            // SSA styles use the older alignment numbers
+            if section == "v4 styles" {
+                legacy = true;
+            }
+            continue;
+        }
+        let Some((key, value)) = line.split_once(':') else { continue };
+        let value = value.trim();
+        match (section.as_str(), key.trim()) {
+            ("script info", "ScriptType") if value.eq_ignore_ascii_case("v4.00") => legacy = true,
+            ("script info", "PlayResX") => play_res.0 = value.parse::<f64>().ok().filter(|v| *v > 0.0),
+            ("script info", "PlayResY") => play_res.1 = value.parse::<f64>().ok().filter(|v| *v > 0.0),
+            (_, "Format") if section.ends_with("styles") => style_fields = field_names(value),
+            (_, "Style") if section.ends_with("styles") => {
+                let fields = split_fields(value, style_fields.len());
+                styles.push(parse_style(&style_fields, &fields, legacy));
+            }
+            ("events", "Format") => event_fields = field_names(value),
+            ("events", "Dialogue") => {
+                let fields = split_fields(value, event_fields.len());
+                let get = |name: &str| event_fields.iter().position(|f| f == name).and_then(|i| fields.get(i)).copied().unwrap_or_default();
+                let (Some(start), Some(end)) = (parse_time(get("start").trim()), parse_time(get("end").trim())) else { continue };
+                let style_name = get("style").trim().trim_start_matches('*');
+                let style = styles
+                    .iter()
+                    .find(|s| s.name == style_name)
+                    .or_else(|| styles.iter().find(|s| s.name.eq_ignore_ascii_case("default")))
+                    .cloned()
+                    .unwrap_or_default();
+                let effect = get("effect").trim().to_ascii_lowercase();
+                if effect.starts_with("scroll") || effect.starts_with("banner") {
+                    drop_feature(&mut converted.dropped, "scrolling and banner effects");
+                }
+                let play_res = (play_res.0.unwrap_or(DEFAULT_PLAY_RES.0), play_res.1.unwrap_or(DEFAULT_PLAY_RES.1));
+                let event = convert_text(get("text"), &style, &styles, &mut converted.dropped);
+                if event.text.trim().is_empty() {
+                    continue;
+                }
+                for color in &event.colors {
+                    if !classes.contains(color) {
+                        classes.push(*color);
+                    }
+                }
+                let settings = match event.position {
+                    Some(position) => position_settings(position, event.alignment, play_res),
+                    None => vtt::alignment_settings(event.alignment),
+                };
+                converted.cues.push(Cue { start, end, settings, text: event.text });
+            }
+            _ => {}
+        }
+    }
+
+    converted.cues.sort_by_key(|c| c.start);
+    converted.css = classes.iter().map(|c| format!("::cue(.{}) {{ color: #{:06x}; }}", class_name(*c), c)).collect::<Vec<_>>().join("\n");
+    converted
+}
+
+// `text` with the start and end of every event passed through `correct`; the rest of
+// the script is kept as it is.
+pub fn retime(text: &str, correct: impl Fn(i64) -> i64) -> String {
+    let mut section = String::new();
+    let mut event_fields: Vec<String> = Vec::new();
+    rewrite_lines(text, |line| {
+        let line = line.trim();
+        if line.starts_with('[') && line.ends_with(']') {
+            section = line[1..line.len() - 1].to_ascii_lowercase();
+            return None;
+        }
+        if section != "events" {
+            return None;
+        }
+        let (key, value) = line.split_once(':')?;
+        match key.trim() {
+            "Format" => {
+                event_fields = field_names(value);
+                None
+            }
+            "Dialogue" | "Comment" => {
+                let mut fields: Vec<String> = split_fields(value.trim_start(), event_fields.len()).into_iter().map(str::to_string).collect();
+                for name in ["start", "end"] {
+                    let i = event_fields.iter().position(|f| f == name)?;
+                    let ms = parse_time(fields.get(i)?.trim())?;
+                    fields[i] = timestamp(correct(ms));
+                }
+                Some(format!("{}: {}", key.trim(), fields.join(",")))
+            }
+            _ => None,
+        }
+    })
+}
+
+// "h:mm:ss.cc" as ASS writes times, clamped at zero.
+fn timestamp(ms: i64) -> String {
+    let cs = (ms.max(0) + 5) / 10;
+    format!("{}:{:02}:{:02}.{:02}", cs / 360_000, cs / 6000 % 60, cs / 100 % 60, cs % 100)
+}
+
+fn field_names(value: &str) -> Vec<String> {
+    value.split(',').map(|f| f.trim().to_ascii_lowercase()).collect()
+}
+
+// Splits a Style or Dialogue line; the last field (the text) may contain commas.
+fn split_fields(value: &str, count: usize) -> Vec<&str> {
+    value.splitn(count.max(1), ',').collect()
+}
+
+fn parse_style(names: &[String], fields: &[&str], legacy: bool) -> Style {
+    let get = |name: &str| names.iter().position(|f| f == name).and_then(|i| fields.get(i)).map(|f| f.trim()).unwrap_or_default();
+    // -1 is true in both formats; SSA also writes font weights
+    let flag = |name: &str| get(name).parse::<i32>().is_ok_and(|v| v == -1 || v == 1 || v >= 600);
+    let alignment = get("alignment").parse::<u8>().unwrap_or(2);
+    Style {
+        name: get("name").trim_start_matches('*').to_string(),
+        format: Format {
+            bold: flag("bold"),
+            italic: flag("italic"),
+            underline: flag("underline"),
+            color: parse_color(get("primarycolour")).unwrap_or(WHITE),
+        },
+        alignment: if legacy { legacy_alignment(alignment) } else { alignment.clamp(1, 9) },
+    }
+}
+
+// SSA numbers alignments 1-3 (bottom), 5-7 (top) and 9-11 (middle).
+fn legacy_alignment(value: u8) -> u8 {
+    match value {
+        5..=7 => value + 2,
+        9..=11 => value - 5,
+        1..=3 => value,
+        _ => 2,
+    }
+}
+
+// "&H00BBGGRR", "&HBBGGRR&" or a decimal number (SSA); returns 0xRRGGBB.
+fn parse_color(value: &str) -> Option<u32> {
+    let value = value.trim().trim_end_matches('&');
+    let bgr = match value.strip_prefix("&H").or_else(|| value.strip_prefix("&h")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+        None => value.parse::<i64>().ok()? as u32,
+    };
+    Some(((bgr & 0xff) << 16) | (bgr & 0xff00) | ((bgr >> 16) & 0xff))
+}
+
+fn class_name(color: u32) -> String {
+    format!("c{:06x}", color)
+}
+
+fn drop_feature(dropped: &mut Vec<String>, feature: &str) {
+    if !dropped.iter().any(|d| d == feature) {
+        dropped.push(feature.to_string());
+    }
+}
+
+struct Event {
+    text: String,
+    alignment: u8,
+    position: Option<(f64, f64)>,
+    // colours used through classes
+    colors: Vec<u32>,
+}
+
+// Override tags by name, longest first so `\bord` is not read as `\b` with "ord".
+const TAGS: &[&str] = &[
+    "iclip", "xbord", "ybord", "xshad", "yshad", "alpha", "clip", "move", "fade", "fscx", "fscy", "bord", "shad", "blur", "fad",
+    "fsp", "frx", "fry", "frz", "fax", "fay", "pos", "org", "pbo", "fn", "fs", "fr", "fe", "be", "an", "1c", "2c", "3c", "4c",
+    "1a", "2a", "3a", "4a", "kf", "ko", "k", "K", "c", "a", "i", "b", "u", "s", "t", "q", "r", "p",
+];
+
+fn convert_text(text: &str, style: &Style, styles: &[Style], dropped: &mut Vec<String>) -> Event {
+    let mut format = style.format;
+    let mut runs: Vec<(Format, String)> = Vec::new();
+    let mut alignment = None;
+    let mut position = None;
+    let mut drawing = false;
+    let mut rest = text;
+
+    while !rest.is_empty() {
+        if rest.starts_with('{') {
+            let close = rest.find('}').unwrap_or(rest.len());
+            for tag in override_tags(&rest[1..close]) {
+                let Some(name) = TAGS.iter().find(|name| tag.starts_with(**name)) else { continue };
+                let arg = tag[name.len()..].trim();
+                let args = || arg.trim_start_matches('(').trim_end_matches(')').split(',').map(|a| a.trim().parse::<f64>().ok()).collect::<Vec<_>>();
+                match *name {
+                    "i" => format.italic = if arg.is_empty() { style.format.italic } else { arg != "0" },
+                    "b" => format.bold = if arg.is_empty() { style.format.bold } else { arg == "1" || arg.parse::<i32>().is_ok_and(|w| w >= 600) },
+                    "u" => format.underline = if arg.is_empty() { style.format.underline } else { arg != "0" },
+                    "c" | "1c" => format.color = if arg.is_empty() { style.format.color } else { parse_color(arg).unwrap_or(format.color) },
+                    // only the first alignment and position of a line count
+                    "an" => alignment = alignment.or(arg.parse::<u8>().ok().filter(|a| (1..=9).contains(a))),
+                    "a" => alignment = alignment.or(arg.parse::<u8>().ok().map(legacy_alignment)),
+                    "pos" => {
+                        if let [Some(x), Some(y)] = args()[..] {
+                            position = position.or(Some((x, y)));
+                        }
+                    }
+                    "move" => {
+                        // shown where the movement starts
+                        if let [Some(x), Some(y), ..] = args()[..] {
+                            position = position.or(Some((x, y)));
+                        }
+                        drop_feature(dropped, "movement (\\move)");
+                    }
+                    "r" => {
+                        format = styles.iter().find(|s| s.name == arg).map(|s| s.format).unwrap_or(style.format);
+                    }
+                    "p" => {
+                        drawing = arg.parse::<i32>().is_ok_and(|p| p > 0);
+                        if drawing {
+                            drop_feature(dropped, "drawings (\\p)");
+                        }
+                    }
+                    "s" if arg != "0" => drop_feature(dropped, "strikeout (\\s)"),
+                    "k" | "K" | "kf" | "ko" => drop_feature(dropped, "karaoke (\\k)"),
+                    "fad" | "fade" => drop_feature(dropped, "fades (\\fad)"),
+                    "t" => drop_feature(dropped, "animated tags (\\t)"),
+                    "clip" | "iclip" => drop_feature(dropped, "clipping (\\clip)"),
+                    "frx" | "fry" | "frz" | "fr" | "fax" | "fay" | "org" => drop_feature(dropped, "rotation and shearing"),
+                    "fn" | "fs" | "fscx" | "fscy" | "fsp" => drop_feature(dropped, "font, size and spacing changes"),
+                    "bord" | "xbord" | "ybord" | "shad" | "xshad" | "yshad" | "blur" | "be" => drop_feature(dropped, "outline, shadow and blur"),
+                    "2c" | "3c" | "4c" => drop_feature(dropped, "outline and shadow colours"),
+                    "alpha" | "1a" | "2a" | "3a" | "4a" => drop_feature(dropped, "transparency"),
+                    _ => {}
+                }
+            }
+            rest = &rest[(close + 1).min(rest.len())..];
+            continue;
+        }
+        let end = rest.find('{').unwrap_or(rest.len());
+        if !drawing {
+            let plain = rest[..end].replace("\\N", "\n").replace("\\n", " ").replace("\\h", "\u{a0}");
+            match runs.last_mut() {
+                Some((last, text)) if *last == format => text.push_str(&plain),
+                _ => runs.push((format, plain)),
+            }
+        }
+        rest = &rest[end..];
+    }
+
+    let alignment = alignment.unwrap_or(style.alignment);
+    let mut colors = Vec::new();
+    let mut out = String::new();
+    for (format, text) in runs.iter().filter(|(_, t)| !t.is_empty()) {
+        let mut open = String::new();
+        let mut close = String::new();
+        if format.color != WHITE {
+            open.push_str(&format!("<c.{}>", class_name(format.color)));
+            close.insert_str(0, "</c>");
+            if !colors.contains(&format.color) {
+                colors.push(format.color);
+            }
+        }
+        for (on, tag) in [(format.bold, "b"), (format.italic, "i"), (format.underline, "u")] {
+            if on {
+                open.push_str(&format!("<{}>", tag));
+                close.insert_str(0, &format!("</{}>", tag));
+            }
+        }
+        out.push_str(&open);
+        out.push_str(&vtt::escape(text));
+        out.push_str(&close);
+    }
+    // blank lines would end the cue
+    let text = out.lines().map(|l| l.trim_matches(' ')).filter(|l| !l.trim().is_empty()).collect::<Vec<_>>().join("\n");
+    Event { text, alignment, position, colors }
+}
+
+// The tags of an override block, split at backslashes outside parentheses so that
+// `\t(\fs20)` stays one tag. Text without a backslash is a comment.
+fn override_tags(block: &str) -> Vec<&str> {
+    let mut tags = Vec::new();
+    let mut depth = 0;
+    let mut start = None;
+    for (i, c) in block.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            '\\' if depth <= 0 => {
+                if let Some(s) = start {
+                    tags.push(&block[s..i]);
+                }
+                start = Some(i + 1);
+            }
+            _ => {}
+        }
+    }
+    if let Some(s) = start {
+        tags.push(&block[s..]);
+    }
+    tags
+}
+
+// `\pos` as cue settings: the point is where the text's anchor (given by the alignment)
+// sits, in percent of the script resolution.
+fn position_settings((x, y): (f64, f64), alignment: u8, (width, height): (f64, f64)) -> String {
+    let x = (x / width * 100.0).clamp(0.0, 100.0);
+    let y = (y / height * 100.0).clamp(0.0, 100.0);
+    let (anchor, align) = match alignment % 3 {
+        1 => ("line-left", "left"),
+        0 => ("line-right", "right"),
+        _ => ("center", "center"),
+    };
+    let line_align = match alignment {
+        7..=9 => "start",
+        4..=6 => "center",
+        _ => "end",
+    };
+    format!("position:{:.1}%,{} line:{:.1}%,{} align:{}", x, anchor, y, line_align, align)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCRIPT: &str = r"[Script Info]
+ScriptType: v4.00+
+PlayResX: 1920
+PlayResY: 1080
+
+[V4+ Styles]
+Format: Name, Fontname, Fontsize, PrimaryColour, SecondaryColour, OutlineColour, BackColour, Bold, Italic, Underline, StrikeOut, ScaleX, ScaleY, Spacing, Angle, BorderStyle, Outline, Shadow, Alignment, MarginL, MarginR, MarginV, Encoding
+Style: Default,Arial,20,&H00FFFFFF,&H000000FF,&H00000000,&H00000000,0,0,0,0,100,100,0,0,1,2,2,2,10,10,10,1
+Style: Sign,Arial,20,&H0000FFFF,&H000000FF,&H00000000,&H00000000,-1,0,0,0,100,100,0,0,1,2,2,8,10,10,10,1
+
+[Events]
+Format: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text
+Dialogue: 0,0:00:05.00,0:00:07.50,Default,,0,0,0,,Second, with a comma
+Dialogue: 0,0:00:01.00,0:00:03.00,Default,,0,0,0,,{\i1}Hello{\i0} world\NNew line
+Comment: 0,0:00:01.50,0:00:02.00,Default,,0,0,0,,not shown
+Dialogue: 0,0:00:02.00,0:00:04.00,Sign,,0,0,0,,Exit
+Dialogue: 0,0:00:03.00,0:00:04.00,Default,,0,0,0,,{\an8\c&H0000FF&\bord2}Red <top>
+Dialogue: 0,0:00:04.00,0:00:05.00,Default,,0,0,0,,{\pos(960,540)\fad(200,200)\k20}Centre
+Dialogue: 0,0:00:06.00,0:00:07.00,Default,,0,0,0,,{\p1}m 0 0 l 100 0{\p0}
+";
+
+    #[test]
+    fn dialogue_becomes_styled_cues() {
+        let converted = parse(SCRIPT);
+        let cues: Vec<_> = converted.cues.iter().map(|c| (c.start, c.end, c.settings.as_str(), c.text.as_str())).collect();
+        assert_eq!(
+            cues,
+            vec![
+                (1000, 3000, "", "<i>Hello</i> world\nNew line"),
+                (2000, 4000, "line:0", "<c.cffff00><b>Exit</b></c>"),
+                (3000, 4000, "line:0", "<c.cff0000>Red &lt;top&gt;</c>"),
+                (4000, 5000, "position:50.0%,center line:50.0%,end align:center", "Centre"),
+                (5000, 7500, "", "Second, with a comma"),
+            ]
+        );
+        assert_eq!(converted.css, "::cue(.cffff00) { color: #ffff00; }\n::cue(.cff0000) { color: #ff0000; }");
+        assert_eq!(converted.dropped, ["outline, shadow and blur", "fades (\\fad)", "karaoke (\\k)", "drawings (\\p)"]);
+    }
+
+    #[test]
+    fn ssa_styles_use_legacy_alignment_and_decimal_colours() {
+        let script = "[Script Info]\nScriptType: v4.00\n\n[V4 Styles]\nFormat: Name, PrimaryColour, Bold, Alignment\nStyle: Default,65535,700,6\n\n\
+            [Events]\nFormat: Marked, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text\n\
+            Dialogue: Marked=0,0:00:01.00,0:00:02.00,*Default,,0,0,0,,Top\n\
+            Dialogue: Marked=0,0:00:02.00,0:00:03.00,Missing,,0,0,0,,{\\a9}Middle\n";
+        let cues = parse(script).cues;
+        assert_eq!((cues[0].settings.as_str(), cues[0].text.as_str()), ("line:0", "<c.cffff00><b>Top</b></c>"));
+        assert_eq!(cues[1].settings, "line:50% align:left");
+    }
+
+    #[test]
+    fn broken_override_blocks_and_defaults() {
+        let script = "[Events]\nFormat: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text\n\
+            Dialogue: 0,0:00:01.00,0:00:02.00,Default,,0,0,0,,Text {\\i1 never closed\n\
+            Dialogue: 0,0:00:02.00,0:00:03.00,Default,,0,0,0,,{\\pos(192,144)\\an7}Corner\n\
+            Dialogue: 0,bad,0:00:03.00,Default,,0,0,0,,No start\n\
+            Dialogue: 0,0:00:04.00,0:00:05.00,Default,,0,0,0,,{a comment}Shown\n";
+        let cues = parse(script).cues;
+        let texts: Vec<_> = cues.iter().map(|c| c.text.as_str()).collect();
+        assert_eq!(texts, ["Text", "Corner", "Shown"]);
+        // without PlayResX/Y positions are relative to 384x288
+        assert_eq!(cues[1].settings, "position:50.0%,line-left line:50.0%,start align:left");
+    }
+
+    #[test]
+    fn retime_rewrites_event_times_only() {
+        let retimed = retime(SCRIPT, |ms| ms + 1234);
+        assert!(retimed.contains("Dialogue: 0,0:00:02.23,0:00:04.23,Default,,0,0,0,,{\\i1}Hello{\\i0} world\\NNew line\n"));
+        // comments keep their place in the timeline too
+        assert!(retimed.contains("Comment: 0,0:00:02.73,0:00:03.23,Default,,0,0,0,,not shown\n"));
+        assert!(retimed.contains("Dialogue: 0,0:00:06.23,0:00:08.73,Default,,0,0,0,,Second, with a comma\n"));
+        assert!(retimed.starts_with("[Script Info]\nScriptType: v4.00+\nPlayResX: 1920\n"));
+        assert_eq!(timestamp(-10), "0:00:00.00");
+        assert_eq!(timestamp(3_723_456), "1:02:03.46");
+    }
+
+    #[test]
+    fn override_tags_keep_parentheses_together() {
+        assert_eq!(override_tags("\\t(\\fs20)\\b1"), ["t(\\fs20)", "b1"]);
+        assert!(override_tags("just a note").is_empty());
+        assert_eq!(parse_color("&HFF0000&"), Some(0x0000ff));
+        assert_eq!(parse_color("&H80FF8000"), Some(0x0080ff));
+        assert_eq!(parse_color("blue"), None);
+    }
+}
@@ -0,0 +1,246 @@
+// Text subtitle tracks inside Matroska files, which the webview cannot reach. A track is
+// demuxed on request into `<cache>/<uuid>/track<N>.vtt` and then served like a sidecar
+// file; a copy older than the video counts as missing.
+use std::fs::{self, File};
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+
+use flate2::read::ZlibDecoder;
+
+use super::{ass, cache_dir, label, language_tag, srt, vtt, Converted, Cue, SubtitleFormat, SubtitleSource, SubtitleTrack};
+use crate::probe::StreamKind;
+use crate::remux::matroska::{Demuxer, Track};
+use crate::stream;
+
+const MATROSKA_EXTENSIONS: &[&str] = &["mkv", "mk3d", "mka", "webm"];
+
+// Subtitle ids are "track<N>" with the Matroska track number; sidecar ids are hex.
+const ID_PREFIX: &str = "track";
+
+// Length of a cue the file gives no duration for and no other cue follows.
+const FALLBACK_DURATION_MS: i64 = 5000;
+
+const ASS_EVENTS_HEADER: &str = "[Events]\nFormat: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text\n";
+
+// Text subtitle tracks of the video, with a URL once extracted. Bitmap subtitles
+// (VobSub, PGS) are left out.
+pub fn list(video_path: &Path, video_uuid: &str) -> Result<Vec<SubtitleTrack>, String> {
+    if !is_matroska(video_path) {
+        return Ok(Vec::new());
+    }
+    let file = File::open(video_path).map_err(|e| e.to_string())?;
+    let demuxer = Demuxer::open(BufReader::new(file)).map_err(|e| e.to_string())?;
+    Ok(demuxer.tracks.iter().filter_map(|t| embedded_track(video_path, video_uuid, t)).collect())
+}
+
+// Demuxes track `number` into the cache, replacing an older copy, and returns it with
+// its URL set.
+pub fn extract(video_path: &Path, video_uuid: &str, number: u64) -> Result<SubtitleTrack, String> {
+    let file = File::open(video_path).map_err(|e| e.to_string())?;
+    let mut demuxer = Demuxer::open(BufReader::new(file)).map_err(|e| e.to_string())?;
+    let track = demuxer.tracks.iter().find(|t| t.number == number).cloned().ok_or_else(|| format!("The video has no track {}", number))?;
+    let mut subtitle = embedded_track(video_path, video_uuid, &track).ok_or_else(|| format!("Track {} is not a text subtitle track", number))?;
+    if track.encoded && !track.zlib {
+        return Err(format!("Track {} is compressed or encrypted in a way that cannot be read", number));
+    }
+
+    demuxer.select(&[number]);
+    let scale = demuxer.timestamp_scale as i64;
+    let ms = |ticks: i64| ticks * scale / 1_000_000;
+    // (start, end, text) in milliseconds
+    let mut events: Vec<(i64, Option<i64>, String)> = Vec::new();
+    while let Some(frame) = demuxer.next_frame().map_err(|e| e.to_string())? {
+        let data = if track.zlib { inflate(&frame.data)? } else { frame.data };
+        let start = ms(frame.timestamp);
+        let duration = frame.block_duration.map(|d| ms(d as i64)).or(track.default_duration.map(|ns| ns as i64 / 1_000_000));
+        let text = String::from_utf8_lossy(&data).trim_end_matches(['\0', '\r', '\n']).to_string();
+        events.push((start, duration.map(|d| start + d), text));
+    }
+    events.sort_by_key(|e| e.0);
+    let starts: Vec<i64> = events.iter().map(|e| e.0).collect();
+    let events: Vec<(i64, i64, String)> = events
+        .into_iter()
+        .enumerate()
+        .map(|(i, (start, end, text))| {
+            let next = starts[i + 1..].iter().copied().find(|s| *s > start);
+            (start, end.unwrap_or_else(|| next.unwrap_or(start + FALLBACK_DURATION_MS)), text)
+        })
+        .collect();
+
+    let converted = match subtitle.format {
+        SubtitleFormat::Srt => Converted { cues: events.into_iter().filter_map(|(start, end, text)| srt_cue(start, end, &text)).collect(), ..Default::default() },
+        SubtitleFormat::Vtt => Converted {
+            cues: events.into_iter().map(|(start, end, text)| Cue { start, end, settings: String::new(), text }).collect(),
+            ..Default::default()
+        },
+        SubtitleFormat::Ass => ass::parse(&ass_document(&track.codec_private, &events)),
+    };
+
+    let target = cached_path(video_uuid, number);
+    let part = target.with_extension("vtt.part");
+    if let Some(dir) = target.parent() {
+        fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    fs::write(&part, vtt::write(&converted.cues, &converted.css)).map_err(|e| e.to_string())?;
+    fs::rename(&part, &target).map_err(|e| e.to_string())?;
+
+    subtitle.url = Some(url(video_uuid, number));
+    subtitle.dropped_features = converted.dropped;
+    Ok(subtitle)
+}
+
+// The extracted track `id` as WebVTT; None for other ids and tracks not extracted yet.
+pub fn load(video_path: &Path, video_uuid: &str, id: &str) -> Result<Option<String>, String> {
+    let Some(number) = id.strip_prefix(ID_PREFIX).and_then(|n| n.parse::<u64>().ok()) else {
+        return Ok(None);
+    };
+    let path = cached_path(video_uuid, number);
+    if !is_fresh(&path, video_path) {
+        return Ok(None);
+    }
+    fs::read_to_string(path).map(Some).map_err(|e| e.to_string())
+}
+
+fn embedded_track(video_path: &Path, video_uuid: &str, track: &Track) -> Option<SubtitleTrack> {
+    if track.kind != StreamKind::Subtitle {
+        return None;
+    }
+    let format = text_format(&track.codec_id)?;
+    let mut subtitle = SubtitleTrack {
+        id: format!("{}{}", ID_PREFIX, track.number),
+        source: SubtitleSource::Embedded,
+        path: video_path.to_string_lossy().to_string(),
+        track_number: Some(track.number),
+        format,
+        language: track.language.as_deref().and_then(|l| language_tag(&l.to_lowercase())),
+        label: String::new(),
+        forced: track.forced,
+        hearing_impaired: track.hearing_impaired,
+        default: track.default,
+        synced: false,
+        url: is_fresh(&cached_path(video_uuid, track.number), video_path).then(|| url(video_uuid, track.number)),
+        encoding: None,
+        encoding_overridden: false,
+        dropped_features: Vec::new(),
+    };
+    subtitle.label = track.name.clone().or_else(|| label(&subtitle)).unwrap_or_else(|| format!("Track {}", track.number));
+    Some(subtitle)
+}
+
+fn text_format(codec_id: &str) -> Option<SubtitleFormat> {
+    match codec_id {
+        "S_TEXT/UTF8" | "S_TEXT/ASCII" => Some(SubtitleFormat::Srt),
+        "S_TEXT/ASS" | "S_TEXT/SSA" | "S_ASS" | "S_SSA" => Some(SubtitleFormat::Ass),
+        "S_TEXT/WEBVTT" | "D_WEBVTT/SUBTITLES" => Some(SubtitleFormat::Vtt),
+        _ => None,
+    }
+}
+
+fn srt_cue(start: i64, end: i64, text: &str) -> Option<Cue> {
+    if text.trim().is_empty() {
+        return None;
+    }
+    let (text, alignment) = srt::markup(text.trim());
+    Some(Cue { start, end, settings: alignment.map(vtt::alignment_settings).unwrap_or_default(), text })
+}
+
+// An ASS script from the track's CodecPrivate (the script header and styles) and its
+// blocks, which hold "ReadOrder,Layer,Style,Name,MarginL,MarginR,MarginV,Effect,Text".
+fn ass_document(header: &[u8], events: &[(i64, i64, String)]) -> String {
+    let mut doc = String::from_utf8_lossy(header).trim_end_matches('\0').to_string();
+    if !doc.ends_with('\n') {
+        doc.push('\n');
+    }
+    if !doc.to_ascii_lowercase().contains("[events]") {
+        doc.push_str(ASS_EVENTS_HEADER);
+    }
+    for (start, end, block) in events {
+        let mut fields = block.splitn(3, ',');
+        let (_, layer, rest) = (fields.next(), fields.next().unwrap_or("0"), fields.next().unwrap_or_default());
+        doc.push_str(&format!("Dialogue: {},{},{},{}\n", layer, ass_time(*start), ass_time(*end), rest.replace('\n', "\\N")));
+    }
+    doc
+}
+
+// "h:mm:ss.mmm", which `parse_time` reads like ASS's centiseconds.
+fn ass_time(ms: i64) -> String {
+    let ms = ms.max(0);
+    format!("{}:{:02}:{:02}.{:03}", ms / 3_600_000, ms / 60_000 % 60, ms / 1000 % 60, ms % 1000)
+}
+
+fn inflate(data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+    ZlibDecoder::new(data).read_to_end(&mut out).map_err(|e| format!("Could not decompress a subtitle block: {}", e))?;
+    Ok(out)
+}
+
+fn cached_path(video_uuid: &str, number: u64) -> PathBuf {
+    cache_dir().join(video_uuid).join(format!("{}{}.vtt", ID_PREFIX, number))
+}
+
+fn url(video_uuid: &str, number: u64) -> String {
+    stream::url(&format!("{}/subtitles/{}{}.vtt", video_uuid, ID_PREFIX, number))
+}
+
+fn is_fresh(cached: &Path, video_path: &Path) -> bool {
+    let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+    match (modified(cached), modified(video_path)) {
+        (Some(cached), Some(video)) => cached >= video,
+        _ => false,
+    }
+}
+
+fn is_matroska(path: &Path) -> bool {
+    path.extension().and_then(|e| e.to_str()).is_some_and(|e| MATROSKA_EXTENSIONS.contains(&e.to_lowercase().as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    #[test]
+    fn text_codecs_only() {
+        assert_eq!(text_format("S_TEXT/UTF8"), Some(SubtitleFormat::Srt));
+        assert_eq!(text_format("S_TEXT/SSA"), Some(SubtitleFormat::Ass));
+        assert_eq!(text_format("D_WEBVTT/SUBTITLES"), Some(SubtitleFormat::Vtt));
+        assert_eq!(text_format("S_HDMV/PGS"), None);
+        assert_eq!(text_format("S_VOBSUB"), None);
+        let audio = Track { number: 2, kind: StreamKind::Audio, codec_id: "S_TEXT/UTF8".to_string(), ..Default::default() };
+        assert!(embedded_track(Path::new("/videos/movie.mkv"), "uuid", &audio).is_none());
+        assert!(is_matroska(Path::new("/videos/movie.MKV")) && !is_matroska(Path::new("/videos/movie.mp4")));
+    }
+
+    #[test]
+    fn srt_blocks_become_cues() {
+        assert!(srt_cue(0, 1000, " \n ").is_none());
+        let cue = srt_cue(0, 1000, "{\\an8}<i>Hi</i> & bye\n").unwrap();
+        assert_eq!((cue.settings.as_str(), cue.text.as_str()), ("line:0", "<i>Hi</i> &amp; bye"));
+    }
+
+    #[test]
+    fn ass_blocks_become_a_script() {
+        assert_eq!(ass_time(3_723_450), "1:02:03.450");
+        assert_eq!(ass_time(-20), "0:00:00.000");
+
+        let header = b"[Script Info]\nScriptType: v4.00+\n\n[V4+ Styles]\nFormat: Name, Italic\nStyle: Default,-1\0\0";
+        let events = [(1000, 2000, "3,0,Default,,0,0,0,,Hi, you\nthere".to_string()), (2500, 3000, "4,1,Default,,0,0,0,,Bye".to_string())];
+        let doc = ass_document(header, &events);
+        assert!(doc.starts_with("[Script Info]\n"));
+        assert!(doc.ends_with(&format!("{}Dialogue: 0,0:00:01.000,0:00:02.000,Default,,0,0,0,,Hi, you\\Nthere\nDialogue: 1,0:00:02.500,0:00:03.000,Default,,0,0,0,,Bye\n", ASS_EVENTS_HEADER)));
+
+        let cues = ass::parse(&doc).cues;
+        let cues: Vec<_> = cues.iter().map(|c| (c.start, c.end, c.text.as_str())).collect();
+        assert_eq!(cues, vec![(1000, 2000, "<i>Hi, you\nthere</i>"), (2500, 3000, "<i>Bye</i>")]);
+    }
+
+    #[test]
+    fn zlib_blocks_are_inflated() {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"compressed line").unwrap();
+        assert_eq!(inflate(&encoder.finish().unwrap()).unwrap(), b"compressed line");
+        assert!(inflate(b"not zlib").is_err());
+    }
+}
@@ -0,0 +1,157 @@
+// Character encodings of subtitle files, which are decoded to UTF-8 before anything else
+// reads them. A BOM decides first, then text that is valid UTF-8 is taken as such, and
+// otherwise chardetng guesses, weighted by the subtitle's language. Wrong guesses can be
+// overridden per file; the choice is stored by path.
+use chardetng::EncodingDetector;
+use encoding_rs::{Encoding, UTF_8};
+use rusqlite::{params, Connection, OptionalExtension};
+
+pub struct Decoded {
+    pub text: String,
+    pub encoding: &'static Encoding,
+    // the encoding was chosen by hand
+    pub overridden: bool,
+}
+
+// Decodes a subtitle file with `forced` when given, detecting the encoding otherwise.
+// `language` is the subtitle's language tag ("pl", "zh-TW") when known.
+pub fn decode(bytes: &[u8], forced: Option<&'static Encoding>, language: Option<&str>) -> Decoded {
+    let bom = Encoding::for_bom(bytes);
+    let encoding = forced.or(bom.map(|(e, _)| e)).unwrap_or_else(|| detect(bytes, language));
+    let body = match bom {
+        Some((e, length)) if e == encoding => &bytes[length..],
+        _ => bytes,
+    };
+    let (text, _) = encoding.decode_without_bom_handling(body);
+    Decoded { text: text.into_owned(), encoding, overridden: forced.is_some() }
+}
+
+fn detect(bytes: &[u8], language: Option<&str>) -> &'static Encoding {
+    if std::str::from_utf8(bytes).is_ok() {
+        return UTF_8;
+    }
+    let mut detector = EncodingDetector::new();
+    detector.feed(bytes, true);
+    let tld = language.map(country_domain);
+    detector.guess(tld.as_deref().map(str::as_bytes), true)
+}
+
+// The top-level domain chardetng weighs its guess with: the region of the tag, or the
+// country most associated with the language.
+fn country_domain(language: &str) -> String {
+    let (primary, region) = language.split_once('-').unwrap_or((language, ""));
+    if !region.is_empty() {
+        return region.to_ascii_lowercase();
+    }
+    let domain = match primary {
+        "cs" => "cz",
+        "da" => "dk",
+        "el" => "gr",
+        "et" => "ee",
+        "fa" => "ir",
+        "he" => "il",
+        "hi" => "in",
+        "ja" => "jp",
+        "ko" => "kr",
+        "sl" => "si",
+        "sr" => "rs",
+        "sv" => "se",
+        "uk" => "ua",
+        "vi" => "vn",
+        "zh" => "cn",
+        other => other,
+    };
+    domain.to_string()
+}
+
+// The encoding chosen for the subtitle file at `path`, if any.
+pub fn load_override(conn: &Connection, path: &str) -> rusqlite::Result<Option<&'static Encoding>> {
+    let label: Option<String> = conn
+        .query_row("SELECT encoding FROM subtitle_encodings WHERE path = ?1", params![path], |row| row.get(0))
+        .optional()?;
+    Ok(label.and_then(|l| Encoding::for_label(l.as_bytes())))
+}
+
+// Stores the encoding for the file under any WHATWG label ("latin2", "sjis", ...); None
+// goes back to detection.
+pub fn save_override(conn: &Connection, path: &str, label: Option<&str>) -> Result<(), String> {
+    let Some(label) = label else {
+        conn.execute("DELETE FROM subtitle_encodings WHERE path = ?1", params![path]).map_err(|e| e.to_string())?;
+        return Ok(());
+    };
+    let encoding = Encoding::for_label(label.trim().as_bytes()).ok_or_else(|| format!("Unknown encoding {}", label))?;
+    conn.execute(
+        "INSERT INTO subtitle_encodings (path, encoding, updated_at) VALUES (?1, ?2, datetime('now'))
+         ON CONFLICT(path) DO UPDATE SET encoding = excluded.encoding, updated_at = excluded.updated_at",
+        params![path, encoding.name()],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding_rs::{SHIFT_JIS, UTF_16LE, WINDOWS_1250, WINDOWS_1251, WINDOWS_1252};
+    use rusqlite::Connection;
+
+    const SRT: &str = "1\n00:00:01,000 --> 00:00:02,000\n";
+
+    fn encoded(encoding: &'static Encoding, text: &str) -> Vec<u8> {
+        encoding.encode(&format!("{}{}\n", SRT, text)).0.into_owned()
+    }
+
+    #[test]
+    fn bom_and_utf8_come_first() {
+        let decoded = decode("\u{feff}Zażółć".as_bytes(), None, Some("pl"));
+        assert_eq!((decoded.encoding, decoded.text.as_str(), decoded.overridden), (UTF_8, "Zażółć", false));
+        let utf16: Vec<u8> = [0xff, 0xfe].into_iter().chain("Hé".encode_utf16().flat_map(|u| u.to_le_bytes())).collect();
+        assert_eq!((decode(&utf16, None, None).encoding, decode(&utf16, None, None).text.as_str()), (UTF_16LE, "Hé"));
+        assert_eq!(decode(&encoded(UTF_8, "Ça a été très élégant"), None, Some("ja")).encoding, UTF_8);
+    }
+
+    #[test]
+    fn legacy_encodings_are_detected_with_the_language() {
+        let cases = [
+            (WINDOWS_1252, "fr", "Ça a été très élégant, où êtes-vous allés à Noël ? Déjà fini."),
+            (WINDOWS_1250, "pl", "Zażółć gęślą jaźń. Czy możesz mi pomóc? Dziękuję bardzo, to było świetne."),
+            (WINDOWS_1251, "ru", "Привет, как дела? Я не знаю, что сказать. Это было очень хорошо."),
+            (SHIFT_JIS, "ja", "こんにちは、元気ですか？ 今日はとても良い天気ですね。また明日会いましょう。"),
+        ];
+        for (encoding, language, text) in cases {
+            let decoded = decode(&encoded(encoding, text), None, Some(language));
+            assert_eq!(decoded.encoding, encoding, "{}", language);
+            assert!(decoded.text.ends_with(&format!("{}\n", text)));
+        }
+    }
+
+    #[test]
+    fn a_forced_encoding_wins() {
+        let bytes = encoded(WINDOWS_1252, "Déjà vu");
+        let decoded = decode(&bytes, Some(WINDOWS_1251), Some("fr"));
+        assert_eq!((decoded.encoding, decoded.overridden), (WINDOWS_1251, true));
+        assert!(!decoded.text.contains("Déjà"));
+        // a BOM of another encoding is kept as text
+        assert!(decode(&[0xef, 0xbb, 0xbf, b'a'], Some(WINDOWS_1252), None).text.starts_with('ï'));
+    }
+
+    #[test]
+    fn country_domains() {
+        assert_eq!(country_domain("pt-BR"), "br");
+        assert_eq!(country_domain("zh"), "cn");
+        assert_eq!(country_domain("pl"), "pl");
+    }
+
+    #[test]
+    fn overrides_are_stored_by_path() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        crate::db::schema::init_schema(&mut conn).unwrap();
+        save_override(&conn, "/s/movie.srt", Some(" latin2 ")).unwrap();
+        assert_eq!(load_override(&conn, "/s/movie.srt").unwrap().map(Encoding::name), Some("ISO-8859-2"));
+        save_override(&conn, "/s/movie.srt", Some("sjis")).unwrap();
+        assert_eq!(load_override(&conn, "/s/movie.srt").unwrap(), Some(SHIFT_JIS));
+        assert!(save_override(&conn, "/s/movie.srt", Some("klingon")).is_err());
+        save_override(&conn, "/s/movie.srt", None).unwrap();
+        assert_eq!(load_override(&conn, "/s/movie.srt").unwrap(), None);
+    }
+}
@@ -0,0 +1,547 @@
+// Subtitles for the player, which only reads WebVTT. Sidecar files next to a video are
+// found by naming convention and converted when the player asks for them; text tracks
+// inside Matroska files are extracted into a cache first (see `embedded`). `stream`
+// serves the result at `video://localhost/<uuid>/subtitles/<id>.vtt`.
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::db::database;
+use crate::db::model::SubtitleTiming;
+use crate::library;
+use crate::stream;
+
+pub mod ass;
+pub mod embedded;
+pub mod encoding;
+pub mod srt;
+pub mod timing;
+pub mod vtt;
+
+const SUBTITLE_EXTENSIONS: &[&str] = &["srt", "vtt", "ass", "ssa"];
+
+// Folders next to a video that hold subtitles, matched case-insensitively.
+const SUBTITLE_FOLDERS: &[&str] = &["subs", "subtitles", "sub"];
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SubtitleFormat {
+    Srt,
+    Vtt,
+    // also .ssa
+    Ass,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SubtitleSource {
+    Sidecar,
+    Embedded,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SubtitleTrack {
+    // stable across calls, part of the URL
+    pub id: String,
+    pub source: SubtitleSource,
+    // the subtitle file, or the video for embedded tracks
+    pub path: String,
+    // Matroska track number of embedded tracks
+    pub track_number: Option<u64>,
+    pub format: SubtitleFormat,
+    // ISO 639-1 code with an optional region ("pt-BR") for the track's `srclang`
+    pub language: Option<String>,
+    // for the track menu, e.g. "English (forced)"
+    pub label: String,
+    pub forced: bool,
+    pub hearing_impaired: bool,
+    pub default: bool,
+    // a copy written by `save_corrected` ("movie.en.synced.srt"), served as it is since
+    // its times are already corrected
+    pub synced: bool,
+    // WebVTT URL for the `<track>` element; None for embedded tracks not extracted yet
+    pub url: Option<String>,
+    // encoding a sidecar file is read with ("windows-1252"), detected unless
+    // `encoding_overridden`; filled by `list`
+    pub encoding: Option<String>,
+    pub encoding_overridden: bool,
+    // styling of ASS files the player cannot show, e.g. "karaoke (\k)"; filled by `list`
+    pub dropped_features: Vec<String>,
+}
+
+// One subtitle in the WebVTT model. Times are in milliseconds; `text` is WebVTT cue
+// text, already escaped, and `settings` the cue settings ("line:0").
+#[derive(Debug, Clone)]
+pub struct Cue {
+    pub start: i64,
+    pub end: i64,
+    pub settings: String,
+    pub text: String,
+}
+
+// A subtitle file in the WebVTT model: its cues, CSS for a STYLE block (the colour
+// classes ASS cues use) and what could not be carried over.
+#[derive(Debug, Clone, Default)]
+pub struct Converted {
+    pub cues: Vec<Cue>,
+    pub css: String,
+    pub dropped: Vec<String>,
+}
+
+pub fn cache_dir() -> PathBuf {
+    database::get_app_dir().join("subtitles")
+}
+
+// Sidecar subtitles of the video at `video_path`, sorted by path:
+// - next to it, named after it: `movie.srt`, `movie.en.srt`, `movie.forced.de.srt`
+// - the same in a `Subs/` folder next to it
+// - every file in `Subs/movie/`
+// - every file in `Subs/` when the video is the only one in its folder
+pub fn discover(video_path: &Path, video_uuid: &str) -> Vec<SubtitleTrack> {
+    let (Some(dir), Some(stem)) = (video_path.parent(), video_path.file_stem().and_then(|s| s.to_str())) else {
+        return Vec::new();
+    };
+    let stem = stem.to_lowercase();
+    // (file, the part of its name that holds language and flags)
+    let mut found: Vec<(PathBuf, String)> = Vec::new();
+    let named_after = |path: &Path| -> Option<String> {
+        let lower = path.file_stem()?.to_str()?.to_lowercase();
+        if lower == stem {
+            Some(String::new())
+        } else {
+            lower.strip_prefix(&stem)?.strip_prefix('.').map(str::to_string)
+        }
+    };
+
+    let entries = read_dir(dir);
+    for path in entries.iter().filter(|p| is_subtitle_file(p)) {
+        if let Some(tags) = named_after(path) {
+            found.push((path.clone(), tags));
+        }
+    }
+    let only_video = entries.iter().filter(|p| p.is_file() && library::is_media_file(p)).count() == 1;
+    for folder in entries.iter().filter(|p| p.is_dir() && is_subtitle_folder(p)) {
+        let inner = read_dir(folder);
+        for path in inner.iter().filter(|p| is_subtitle_file(p)) {
+            match named_after(path) {
+                Some(tags) => found.push((path.clone(), tags)),
+                None if only_video => found.push((path.clone(), file_stem(path))),
+                None => {}
+            }
+        }
+        let own = inner.iter().filter(|p| p.is_dir()).find(|p| p.file_name().and_then(|n| n.to_str()).is_some_and(|n| n.to_lowercase() == stem));
+        if let Some(own) = own {
+            for path in read_dir(own).into_iter().filter(|p| is_subtitle_file(p)) {
+                let tags = file_stem(&path);
+                found.push((path, tags));
+            }
+        }
+    }
+
+    found.sort_by(|a, b| a.0.cmp(&b.0));
+    found.dedup_by(|a, b| a.0 == b.0);
+    found.into_iter().filter_map(|(path, tags)| sidecar_track(&path, &tags, video_uuid)).collect()
+}
+
+// `discover` for the track menu: sidecar files are read once for their encoding, and
+// ASS files converted to list the styling the player will not show. Embedded tracks
+// follow the sidecar files.
+pub fn list(video_path: &Path, video_uuid: &str) -> Vec<SubtitleTrack> {
+    let mut tracks = discover(video_path, video_uuid);
+    for track in tracks.iter_mut() {
+        let Ok(decoded) = read_text(track) else { continue };
+        track.encoding = Some(decoded.encoding.name().to_string());
+        track.encoding_overridden = decoded.overridden;
+        if track.format == SubtitleFormat::Ass {
+            track.dropped_features = ass::parse(&decoded.text).dropped;
+        }
+    }
+    tracks.extend(embedded::list(video_path, video_uuid).unwrap_or_default());
+    tracks
+}
+
+// The subtitle `id` of the video as WebVTT with the video's timing correction applied,
+// except to synced copies, which carry it already; None when the video has no such
+// subtitle.
+pub fn load_vtt(video_path: &Path, video_uuid: &str, id: &str, correction: &SubtitleTiming) -> Result<Option<String>, String> {
+    let mut converted = if let Some(text) = embedded::load(video_path, video_uuid, id)? {
+        Converted { cues: vtt::parse(&text), css: vtt::styles(&text), ..Default::default() }
+    } else {
+        let Some(track) = discover(video_path, video_uuid).into_iter().find(|t| t.id == id) else {
+            return Ok(None);
+        };
+        let converted = convert(&track)?;
+        if track.synced {
+            return Ok(Some(vtt::write(&converted.cues, &converted.css)));
+        }
+        converted
+    };
+    timing::apply(correction, &mut converted.cues);
+    Ok(Some(vtt::write(&converted.cues, &converted.css)))
+}
+
+// Writes the subtitle `id` with the timing correction applied next to the video, named
+// "<video>.<language>[.forced][.sdh].synced.<ext>" (".synced.2.<ext>" and up when taken),
+// and returns the path. Sidecar files keep their format and everything but the times;
+// embedded tracks are written as the extracted WebVTT. None when the video has no such
+// subtitle (or it is an embedded track not extracted yet). Synced copies are refused,
+// their times are corrected already.
+pub fn save_corrected(video_path: &Path, video_uuid: &str, id: &str, correction: &SubtitleTiming) -> Result<Option<PathBuf>, String> {
+    let correct = |ms| timing::correct(correction, ms);
+    let (track, text) = if let Some(text) = embedded::load(video_path, video_uuid, id)? {
+        let Some(track) = embedded::list(video_path, video_uuid)?.into_iter().find(|t| t.id == id) else {
+            return Ok(None);
+        };
+        (track, vtt::retime(&text, correct))
+    } else {
+        let Some(track) = discover(video_path, video_uuid).into_iter().find(|t| t.id == id) else {
+            return Ok(None);
+        };
+        if track.synced {
+            return Err(format!("{} is already a corrected copy", track.path));
+        }
+        let text = read_text(&track)?.text;
+        let text = match track.format {
+            SubtitleFormat::Srt => srt::retime(&text, correct),
+            SubtitleFormat::Vtt => vtt::retime(&text, correct),
+            SubtitleFormat::Ass => ass::retime(&text, correct),
+        };
+        (track, text)
+    };
+
+    let (Some(dir), Some(stem)) = (video_path.parent(), video_path.file_stem()) else {
+        return Err(format!("{} has no folder", video_path.display()));
+    };
+    let extension = match track.source {
+        SubtitleSource::Sidecar => Path::new(&track.path).extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default(),
+        SubtitleSource::Embedded => "vtt".to_string(),
+    };
+    let mut parts = vec![stem.to_string_lossy().to_string()];
+    parts.extend(track.language.clone());
+    if track.forced {
+        parts.push("forced".to_string());
+    }
+    if track.hearing_impaired {
+        parts.push("sdh".to_string());
+    }
+    parts.push("synced".to_string());
+    write_new(dir, &parts.join("."), &extension, &text).map(Some)
+}
+
+// Writes `text` to "<base>.<ext>" in `dir`, or "<base>.2.<ext>" and up when that is
+// taken; existing files are never overwritten.
+fn write_new(dir: &Path, base: &str, extension: &str, text: &str) -> Result<PathBuf, String> {
+    let mut n = 1;
+    loop {
+        let name = if n == 1 { format!("{}.{}", base, extension) } else { format!("{}.{}.{}", base, n, extension) };
+        let path = dir.join(name);
+        match fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut file) => {
+                file.write_all(text.as_bytes()).map_err(|e| e.to_string())?;
+                return Ok(path);
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => n += 1,
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+}
+
+fn convert(track: &SubtitleTrack) -> Result<Converted, String> {
+    let text = read_text(track)?.text;
+    Ok(match track.format {
+        SubtitleFormat::Srt => Converted { cues: srt::parse(&text), ..Default::default() },
+        SubtitleFormat::Vtt => Converted { cues: vtt::parse(&text), css: vtt::styles(&text), ..Default::default() },
+        SubtitleFormat::Ass => ass::parse(&text),
+    })
+}
+
+// Text of a sidecar file as UTF-8, in the encoding chosen for it or the detected one.
+fn read_text(track: &SubtitleTrack) -> Result<encoding::Decoded, String> {
+    let forced = {
+        let conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+        encoding::load_override(&conn, &track.path).map_err(|e| e.to_string())?
+    };
+    let bytes = fs::read(&track.path).map_err(|e| e.to_string())?;
+    Ok(encoding::decode(&bytes, forced, track.language.as_deref()))
+}
+
+fn sidecar_track(path: &Path, tags: &str, video_uuid: &str) -> Option<SubtitleTrack> {
+    let extension = path.extension()?.to_str()?.to_lowercase();
+    let format = match extension.as_str() {
+        "srt" => SubtitleFormat::Srt,
+        "vtt" => SubtitleFormat::Vtt,
+        "ass" | "ssa" => SubtitleFormat::Ass,
+        _ => return None,
+    };
+    let path_str = path.to_string_lossy().to_string();
+    let id = blake3::hash(path_str.as_bytes()).to_hex()[..16].to_string();
+    let mut track = SubtitleTrack {
+        url: Some(stream::url(&format!("{}/subtitles/{}.vtt", video_uuid, id))),
+        id,
+        source: SubtitleSource::Sidecar,
+        path: path_str,
+        track_number: None,
+        format,
+        language: None,
+        label: String::new(),
+        forced: false,
+        hearing_impaired: false,
+        default: false,
+        synced: false,
+        encoding: None,
+        encoding_overridden: false,
+        dropped_features: Vec::new(),
+    };
+    apply_tags(&mut track, tags);
+    track.label = match label(&track) {
+        Some(label) => label,
+        None => path.file_name()?.to_string_lossy().to_string(),
+    };
+    Some(track)
+}
+
+// Reads language and flags from the name parts after the video name, e.g. "en.forced",
+// "German.SDH" or "2_English".
+fn apply_tags(track: &mut SubtitleTrack, tags: &str) {
+    for token in tags.split(['.', '_', ' ']).map(|t| t.trim().to_lowercase()).filter(|t| !t.is_empty()) {
+        match token.as_str() {
+            "forced" | "foreign" => track.forced = true,
+            "sdh" | "cc" => track.hearing_impaired = true,
+            // Hindi unless a language came before it
+            "hi" if track.language.is_some() => track.hearing_impaired = true,
+            "default" => track.default = true,
+            "synced" => track.synced = true,
+            _ if track.language.is_none() => track.language = language_tag(&token),
+            _ => {}
+        }
+    }
+}
+
+// "English (forced)", "Portuguese (BR)"; None when the language is unknown.
+fn label(track: &SubtitleTrack) -> Option<String> {
+    let tag = track.language.as_deref()?;
+    let mut label = language_name(tag)?.to_string();
+    if let Some((_, region)) = tag.split_once('-') {
+        label.push_str(&format!(" ({})", region));
+    }
+    if track.forced {
+        label.push_str(" (forced)");
+    }
+    if track.hearing_impaired {
+        label.push_str(" (SDH)");
+    }
+    if track.synced {
+        label.push_str(" (synced)");
+    }
+    Some(label)
+}
+
+// ISO 639-1 code, English name, and the other ways file names spell the language
+// (ISO 639-2 codes, native names), lowercase.
+const LANGUAGES: &[(&str, &str, &[&str])] = &[
+    ("ar", "Arabic", &["ara"]),
+    ("bg", "Bulgarian", &["bul"]),
+    ("cs", "Czech", &["cze", "ces", "cesky"]),
+    ("da", "Danish", &["dan", "dansk"]),
+    ("de", "German", &["ger", "deu", "deutsch"]),
+    ("el", "Greek", &["gre", "ell"]),
+    ("en", "English", &["eng"]),
+    ("es", "Spanish", &["spa", "espanol", "español", "castellano"]),
+    ("et", "Estonian", &["est"]),
+    ("fa", "Persian", &["per", "fas", "farsi"]),
+    ("fi", "Finnish", &["fin", "suomi"]),
+    ("fr", "French", &["fre", "fra", "francais", "français"]),
+    ("he", "Hebrew", &["heb"]),
+    ("hi", "Hindi", &["hin"]),
+    ("hr", "Croatian", &["hrv", "hrvatski"]),
+    ("hu", "Hungarian", &["hun", "magyar"]),
+    ("id", "Indonesian", &["ind"]),
+    ("it", "Italian", &["ita", "italiano"]),
+    ("ja", "Japanese", &["jpn"]),
+    ("ko", "Korean", &["kor"]),
+    ("lt", "Lithuanian", &["lit"]),
+    ("lv", "Latvian", &["lav"]),
+    ("ms", "Malay", &["may", "msa"]),
+    ("nl", "Dutch", &["dut", "nld", "nederlands"]),
+    ("no", "Norwegian", &["nor", "nob", "nb", "norsk"]),
+    ("pl", "Polish", &["pol", "polski"]),
+    ("pt", "Portuguese", &["por", "portugues", "português"]),
+    ("ro", "Romanian", &["rum", "ron"]),
+    ("ru", "Russian", &["rus"]),
+    ("sk", "Slovak", &["slo", "slk"]),
+    ("sl", "Slovenian", &["slv"]),
+    ("sr", "Serbian", &["srp"]),
+    ("sv", "Swedish", &["swe", "svenska"]),
+    ("th", "Thai", &["tha"]),
+    ("tr", "Turkish", &["tur", "turkce", "türkçe"]),
+    ("uk", "Ukrainian", &["ukr"]),
+    ("vi", "Vietnamese", &["vie"]),
+    ("zh", "Chinese", &["chi", "zho"]),
+];
+
+// ISO 639-1 code for a language code or name, keeping a region ("pt-br" -> "pt-BR").
+fn language_tag(token: &str) -> Option<String> {
+    let (primary, region) = match token.split_once('-') {
+        Some((primary, region)) if region.len() == 2 => (primary, Some(region)),
+        _ => (token, None),
+    };
+    let (code, _, _) = LANGUAGES
+        .iter()
+        .find(|(code, name, other)| *code == primary || name.eq_ignore_ascii_case(primary) || other.contains(&primary))?;
+    Some(match region {
+        Some(region) => format!("{}-{}", code, region.to_uppercase()),
+        None => code.to_string(),
+    })
+}
+
+fn language_name(tag: &str) -> Option<&'static str> {
+    let primary = tag.split('-').next()?;
+    LANGUAGES.iter().find(|(code, _, _)| *code == primary).map(|(_, name, _)| *name)
+}
+
+fn is_subtitle_file(path: &Path) -> bool {
+    path.is_file()
+        && path.extension().and_then(|e| e.to_str()).is_some_and(|e| SUBTITLE_EXTENSIONS.contains(&e.to_lowercase().as_str()))
+}
+
+fn is_subtitle_folder(path: &Path) -> bool {
+    path.file_name().and_then(|n| n.to_str()).is_some_and(|n| SUBTITLE_FOLDERS.contains(&n.to_lowercase().as_str()))
+}
+
+fn file_stem(path: &Path) -> String {
+    path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default()
+}
+
+fn read_dir(dir: &Path) -> Vec<PathBuf> {
+    match fs::read_dir(dir) {
+        Ok(entries) => entries.filter_map(|e| e.ok().map(|e| e.path())).collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+// Replaces the lines `rewrite` returns a new text for, keeping every other line and the
+// line endings as they are.
+pub(crate) fn rewrite_lines(text: &str, mut rewrite: impl FnMut(&str) -> Option<String>) -> String {
+    let mut out = String::with_capacity(text.len());
+    for line in text.split_inclusive('\n') {
+        let content = line.trim_end_matches(['\r', '\n']);
+        match rewrite(content) {
+            Some(new) => {
+                out.push_str(&new);
+                out.push_str(&line[content.len()..]);
+            }
+            None => out.push_str(line),
+        }
+    }
+    out
+}
+
+// "00:01:02,500 --> 00:01:04,000 X1:..." as SRT and WebVTT write it: start and end in
+// milliseconds and what follows the end time (cue settings in WebVTT).
+pub(crate) fn parse_timing(line: &str) -> Option<(i64, i64, &str)> {
+    let (start, rest) = line.split_once("-->")?;
+    let rest = rest.trim();
+    let (end, settings) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+    Some((parse_time(start.trim())?, parse_time(end)?, settings.trim()))
+}
+
+// "h:mm:ss,mmm", "mm:ss.mmm" or with fewer fraction digits ("0:01:02.5", as ASS writes).
+fn parse_time(text: &str) -> Option<i64> {
+    let (clock, fraction) = match text.split_once([',', '.']) {
+        Some((clock, fraction)) => (clock, fraction),
+        None => (text, "0"),
+    };
+    let mut seconds = 0i64;
+    let parts: Vec<&str> = clock.split(':').collect();
+    if parts.len() < 2 || parts.len() > 3 {
+        return None;
+    }
+    for part in parts {
+        seconds = seconds * 60 + part.trim().parse::<i64>().ok()?;
+    }
+    if fraction.is_empty() || fraction.len() > 3 || !fraction.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let millis: i64 = format!("{:0<3}", fraction).parse().ok()?;
+    Some(seconds * 1000 + millis)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn time_formats() {
+        assert_eq!(parse_time("1:02:03,5"), Some(3_723_500));
+        assert_eq!(parse_time("02:03.25"), Some(123_250));
+        assert_eq!(parse_time("00:00:01"), Some(1000));
+        assert_eq!(parse_time("1:2:3:4,000"), None);
+        assert_eq!(parse_time("00:00:01,0000"), None);
+        assert_eq!(parse_time("00:00:01,"), None);
+        assert_eq!(parse_timing("00:00:01,000 --> 00:00:02,000  line:0 align:left"), Some((1000, 2000, "line:0 align:left")));
+        assert_eq!(parse_timing("00:00:01,000 -> 00:00:02,000"), None);
+    }
+
+    #[test]
+    fn language_and_flags_from_the_file_name() {
+        let track = |tags: &str| sidecar_track(Path::new("/videos/Movie.x.srt"), tags, "uuid").unwrap();
+        let summary = |t: SubtitleTrack| (t.language, t.forced, t.hearing_impaired, t.default, t.synced, t.label);
+        let some = |s: &str| Some(s.to_string());
+        assert_eq!(summary(track("en.forced")), (some("en"), true, false, false, false, "English (forced)".to_string()));
+        assert_eq!(summary(track("German.SDH")), (some("de"), false, true, false, false, "German (SDH)".to_string()));
+        assert_eq!(summary(track("2_English")), (some("en"), false, false, false, false, "English".to_string()));
+        assert_eq!(summary(track("en.hi.default")), (some("en"), false, true, true, false, "English (SDH)".to_string()));
+        assert_eq!(summary(track("hi")), (some("hi"), false, false, false, false, "Hindi".to_string()));
+        assert_eq!(summary(track("pt-br")), (some("pt-BR"), false, false, false, false, "Portuguese (BR)".to_string()));
+        assert_eq!(summary(track("fre.synced")), (some("fr"), false, false, false, true, "French (synced)".to_string()));
+        assert_eq!(summary(track("director")), (None, false, false, false, false, "Movie.x.srt".to_string()));
+        assert_eq!(track("en").format, SubtitleFormat::Srt);
+        assert!(sidecar_track(Path::new("/videos/Movie.en.txt"), "en", "uuid").is_none());
+    }
+
+    #[test]
+    fn corrected_copies_never_overwrite() {
+        let dir = std::env::temp_dir().join(format!("subtitle-write-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let first = write_new(&dir, "Movie.en.synced", "srt", "first").unwrap();
+        let second = write_new(&dir, "Movie.en.synced", "srt", "second").unwrap();
+        assert_eq!((first.file_name().unwrap(), second.file_name().unwrap()), ("Movie.en.synced.srt".as_ref(), "Movie.en.synced.2.srt".as_ref()));
+        assert_eq!(fs::read_to_string(&first).unwrap(), "first");
+        assert_eq!(fs::read_to_string(&second).unwrap(), "second");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn discovers_sidecars_by_naming_convention() {
+        let dir = std::env::temp_dir().join(format!("subtitle-discover-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("Subs").join("movie")).unwrap();
+        for file in ["Movie.mkv", "Movie.srt", "Movie.en.srt", "Movie.fr.forced.ASS", "Movies.en.srt", "notes.txt", "Subs/Movie.de.vtt", "Subs/spanish.srt", "Subs/movie/2_English.srt"] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+        let video = dir.join("Movie.mkv");
+        let found = |video: &Path| -> Vec<(String, Option<String>)> {
+            discover(video, "uuid").into_iter().map(|t| (t.path.strip_prefix(dir.to_str().unwrap()).unwrap().to_string(), t.language)).collect()
+        };
+        let some = |s: &str| Some(s.to_string());
+        assert_eq!(
+            found(&video),
+            vec![
+                ("/Movie.en.srt".to_string(), some("en")),
+                ("/Movie.fr.forced.ASS".to_string(), some("fr")),
+                ("/Movie.srt".to_string(), None),
+                ("/Subs/Movie.de.vtt".to_string(), some("de")),
+                ("/Subs/movie/2_English.srt".to_string(), some("en")),
+                ("/Subs/spanish.srt".to_string(), some("es")),
+            ]
+        );
+        // with a second video in the folder, files in Subs/ need the video's name
+        fs::write(dir.join("Other.mp4"), "").unwrap();
+        assert!(!found(&video).iter().any(|(path, _)| path == "/Subs/spanish.srt"));
+        assert!(found(&dir.join("Other.mp4")).is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
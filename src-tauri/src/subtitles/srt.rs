@@ -0,0 +1,154 @@
+// SubRip (.srt) reader. Real files are sloppy: counters missing or wrong, dots instead
+// of commas, hours left out, coordinates after the timing and blank lines inside cues
+// all occur, so cues are cut at timing lines rather than at blank lines.
+use super::{parse_timing, rewrite_lines, vtt, Cue};
+
+pub fn parse(text: &str) -> Vec<Cue> {
+    let mut cues: Vec<Cue> = Vec::new();
+    let mut body: Vec<&str> = Vec::new();
+    let mut current: Option<(i64, i64)> = None;
+    for line in text.lines() {
+        if let Some((start, end, _)) = parse_timing(line) {
+            if let Some((start, end)) = current {
+                push_cue(&mut cues, start, end, &mut body);
+            }
+            current = Some((start, end));
+            body.clear();
+        } else if current.is_some() {
+            body.push(line.trim_end());
+        }
+    }
+    if let Some((start, end)) = current {
+        push_cue(&mut cues, start, end, &mut body);
+    }
+    cues
+}
+
+// `text` with every timing line passed through `correct`, for a corrected copy; the rest
+// of the file is kept as it is.
+pub fn retime(text: &str, correct: impl Fn(i64) -> i64) -> String {
+    rewrite_lines(text, |line| {
+        let (start, end, rest) = parse_timing(line)?;
+        let mut timing = format!("{} --> {}", timestamp(correct(start)), timestamp(correct(end)));
+        if !rest.is_empty() {
+            timing.push(' ');
+            timing.push_str(rest);
+        }
+        Some(timing)
+    })
+}
+
+// "hh:mm:ss,mmm", clamped at zero.
+fn timestamp(ms: i64) -> String {
+    let ms = ms.max(0);
+    format!("{:02}:{:02}:{:02},{:03}", ms / 3_600_000, ms / 60_000 % 60, ms / 1000 % 60, ms % 1000)
+}
+
+fn push_cue(cues: &mut Vec<Cue>, start: i64, end: i64, body: &mut Vec<&str>) {
+    // the counter of the next cue ends up at the bottom of this one
+    while body.last().is_some_and(|l| l.trim().is_empty()) {
+        body.pop();
+    }
+    if body.len() > 1 && body.last().is_some_and(|l| l.trim().chars().all(|c| c.is_ascii_digit())) && body[body.len() - 2].trim().is_empty() {
+        body.pop();
+    }
+    let lines: Vec<&str> = body.iter().copied().filter(|l| !l.trim().is_empty()).collect();
+    if lines.is_empty() {
+        return;
+    }
+    let (text, alignment) = markup(&lines.join("\n"));
+    let settings = alignment.map(vtt::alignment_settings).unwrap_or_default();
+    cues.push(Cue { start, end, settings, text });
+}
+
+// Turns SRT markup into WebVTT cue text: <i>, <b> and <u> are kept, <font> and unknown
+// tags are dropped, `{\anN}` becomes the returned alignment and other `{\...}` override
+// blocks are removed.
+pub(super) fn markup(text: &str) -> (String, Option<u8>) {
+    let mut out = String::with_capacity(text.len());
+    let mut alignment = None;
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        if c == '{' && rest.starts_with("{\\") {
+            if let Some(close) = rest.find('}') {
+                let block = &rest[2..close];
+                if let Some(n) = block.strip_prefix("an").and_then(|n| n.trim().parse::<u8>().ok()).filter(|n| (1..=9).contains(n)) {
+                    alignment = Some(n);
+                }
+                rest = &rest[close + 1..];
+                continue;
+            }
+        }
+        if c == '<' {
+            if let Some(close) = rest.find('>') {
+                let tag = rest[1..close].trim().to_ascii_lowercase();
+                let name = tag.trim_start_matches('/').split_whitespace().next().unwrap_or_default();
+                if matches!(name, "i" | "b" | "u") {
+                    out.push('<');
+                    if tag.starts_with('/') {
+                        out.push('/');
+                    }
+                    out.push_str(name);
+                    out.push('>');
+                    rest = &rest[close + 1..];
+                    continue;
+                }
+                // <font color=...> and the like; "I <3 you" is text
+                if name.starts_with(|c: char| c.is_ascii_alphabetic()) && name.chars().all(|c| c.is_ascii_alphanumeric()) {
+                    rest = &rest[close + 1..];
+                    continue;
+                }
+            }
+        }
+        out.push_str(&vtt::escape(&rest[..c.len_utf8()]));
+        rest = &rest[c.len_utf8()..];
+    }
+    (out, alignment)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SLOPPY: &str = "1\n00:00:01,000 --> 00:00:02,500\n<i>Hello</i> & <font color=\"red\">world</font>\n\n\
+        2\n00:00:03.000 --> 00:00:04,000 X1:100 X2:200 Y1:1 Y2:2\n{\\an8}{\\b1}Top line\nI <3 you\n\n\
+        00:05,000 --> 00:06,000\nFirst part\n\nafter a blank line\n\n4\n\
+        00:00:07,000 --> 00:00:08,000\n   \n";
+
+    #[test]
+    fn cues_are_cut_at_timing_lines() {
+        let cues = parse(SLOPPY);
+        let summary: Vec<_> = cues.iter().map(|c| (c.start, c.end, c.settings.as_str(), c.text.as_str())).collect();
+        assert_eq!(
+            summary,
+            vec![
+                (1000, 2500, "", "<i>Hello</i> &amp; world"),
+                (3000, 4000, "line:0", "Top line\nI &lt;3 you"),
+                (5000, 6000, "", "First part\nafter a blank line"),
+            ]
+        );
+    }
+
+    #[test]
+    fn crlf_files_and_missing_counters() {
+        let cues = parse("00:00:01,000 --> 00:00:02,000\r\nOne\r\n\r\n00:00:03,000 --> 00:00:04,000\r\nTwo\r\n");
+        assert_eq!(cues.iter().map(|c| c.text.as_str()).collect::<Vec<_>>(), ["One", "Two"]);
+        assert!(parse("no timing here\n\njust text").is_empty());
+    }
+
+    #[test]
+    fn retime_only_touches_timing_lines() {
+        let text = "1\r\n00:00:01,600 --> 00:00:02,000 X1:10\r\n00:00:01,600 is not a timing\r\n\r\n2\r\n00:00:00,200 --> 00:00:01,000\r\nEarly\r\n";
+        let retimed = retime(text, |ms| ms - 500);
+        assert_eq!(retimed, "1\r\n00:00:01,100 --> 00:00:01,500 X1:10\r\n00:00:01,600 is not a timing\r\n\r\n2\r\n00:00:00,000 --> 00:00:00,500\r\nEarly\r\n");
+    }
+
+    #[test]
+    fn markup_keeps_basic_tags_only() {
+        assert_eq!(markup("<B>bold</B> <u>under</u>"), ("<b>bold</b> <u>under</u>".to_string(), None));
+        assert_eq!(markup("{\\an1}left {\\i1}x"), ("left x".to_string(), Some(1)));
+        // an unclosed brace or an out-of-range alignment is left alone
+        assert_eq!(markup("{\\an0} {not a tag"), (" {not a tag".to_string(), None));
+        assert_eq!(markup("a > b"), ("a &gt; b".to_string(), None));
+    }
+}
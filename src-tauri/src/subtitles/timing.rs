@@ -0,0 +1,107 @@
+// Per-video subtitle sync correction: a frame-rate stretch, then an offset, applied to cue
+// times whenever a subtitle of the video is served or saved as a corrected copy.
+use rusqlite::{params, Connection, OptionalExtension};
+
+use super::Cue;
+use crate::db::model::SubtitleTiming;
+
+// The correction of the video; the neutral one when none was saved.
+pub fn load(conn: &Connection, video_id: i64) -> rusqlite::Result<SubtitleTiming> {
+    let timing = conn
+        .query_row(
+            "SELECT video_id, offset_ms, source_fps, target_fps, updated_at FROM subtitle_timings WHERE video_id = ?1",
+            params![video_id],
+            |row| {
+                Ok(SubtitleTiming {
+                    video_id: row.get(0)?,
+                    offset_ms: row.get(1)?,
+                    source_fps: row.get(2)?,
+                    target_fps: row.get(3)?,
+                    updated_at: row.get(4)?,
+                })
+            },
+        )
+        .optional()?;
+    Ok(timing.unwrap_or(SubtitleTiming { video_id, offset_ms: 0, source_fps: None, target_fps: None, updated_at: None }))
+}
+
+// Stores the correction; a neutral one removes the row. The frame rates come as a pair.
+pub fn save(conn: &Connection, video_id: i64, offset_ms: i64, source_fps: Option<f64>, target_fps: Option<f64>) -> Result<SubtitleTiming, String> {
+    let valid = |fps: f64| fps.is_finite() && fps > 0.0 && fps <= 1000.0;
+    let (source_fps, target_fps) = match (source_fps, target_fps) {
+        (Some(source), Some(target)) if valid(source) && valid(target) => (Some(source), Some(target)),
+        (None, None) => (None, None),
+        _ => return Err("Give both frame rates, as positive numbers, or neither".to_string()),
+    };
+    if offset_ms == 0 && source_fps == target_fps {
+        conn.execute("DELETE FROM subtitle_timings WHERE video_id = ?1", params![video_id]).map_err(|e| e.to_string())?;
+    } else {
+        conn.execute(
+            "INSERT INTO subtitle_timings (video_id, offset_ms, source_fps, target_fps, updated_at)
+             VALUES (?1, ?2, ?3, ?4, datetime('now'))
+             ON CONFLICT(video_id) DO UPDATE SET offset_ms = excluded.offset_ms, source_fps = excluded.source_fps,
+                target_fps = excluded.target_fps, updated_at = excluded.updated_at",
+            params![video_id, offset_ms, source_fps, target_fps],
+        )
+        .map_err(|e| e.to_string())?;
+    }
+    load(conn, video_id).map_err(|e| e.to_string())
+}
+
+// A subtitle time (ms) as corrected. Subtitles timed for 23.976 fps on a 25 fps video
+// run 23.976/25 as long.
+pub fn correct(timing: &SubtitleTiming, ms: i64) -> i64 {
+    let ratio = match (timing.source_fps, timing.target_fps) {
+        (Some(source), Some(target)) => source / target,
+        _ => 1.0,
+    };
+    (ms as f64 * ratio).round() as i64 + timing.offset_ms
+}
+
+pub fn apply(timing: &SubtitleTiming, cues: &mut [Cue]) {
+    for cue in cues {
+        cue.start = correct(timing, cue.start);
+        cue.end = correct(timing, cue.end);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timing(offset_ms: i64, fps: Option<(f64, f64)>) -> SubtitleTiming {
+        SubtitleTiming { video_id: 1, offset_ms, source_fps: fps.map(|f| f.0), target_fps: fps.map(|f| f.1), updated_at: None }
+    }
+
+    #[test]
+    fn stretch_then_offset() {
+        assert_eq!(correct(&timing(0, None), 1600), 1600);
+        assert_eq!(correct(&timing(-2000, None), 1600), -400);
+        assert_eq!(correct(&timing(1500, Some((23.976, 25.0))), 1600), 3034);
+        let mut cues = vec![Cue { start: 1000, end: 2000, settings: String::new(), text: "x".to_string() }];
+        apply(&timing(250, Some((25.0, 25.0))), &mut cues);
+        assert_eq!((cues[0].start, cues[0].end), (1250, 2250));
+    }
+
+    #[test]
+    fn save_validates_and_removes_neutral_corrections() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        crate::db::schema::init_schema(&mut conn).unwrap();
+        conn.execute("INSERT INTO videos (id, uuid, path) VALUES (1, 'u', '/v.mp4')", []).unwrap();
+        let rows = |conn: &Connection| conn.query_row("SELECT COUNT(*) FROM subtitle_timings", [], |row| row.get::<_, i64>(0)).unwrap();
+
+        let saved = save(&conn, 1, 1500, Some(23.976), Some(25.0)).unwrap();
+        assert_eq!((saved.offset_ms, saved.source_fps, saved.target_fps), (1500, Some(23.976), Some(25.0)));
+        assert!(saved.updated_at.is_some());
+        assert_eq!(save(&conn, 1, -300, None, None).unwrap().source_fps, None);
+        assert_eq!(load(&conn, 1).unwrap().offset_ms, -300);
+
+        for (source, target) in [(Some(25.0), None), (Some(0.0), Some(25.0)), (Some(f64::NAN), Some(25.0)), (Some(25.0), Some(5000.0))] {
+            assert!(save(&conn, 1, 0, source, target).is_err());
+        }
+        assert_eq!(rows(&conn), 1);
+        save(&conn, 1, 0, Some(25.0), Some(25.0)).unwrap();
+        assert_eq!(rows(&conn), 0);
+        assert_eq!(load(&conn, 1).unwrap().offset_ms, 0);
+    }
+}
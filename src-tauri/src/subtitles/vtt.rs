@@ -0,0 +1,157 @@
+// WebVTT writer, and a reader for sidecar .vtt files so they go through the same cue
+// model (and timing corrections) as the formats converted to it.
+use super::{parse_timing, rewrite_lines, Cue};
+
+// `css` goes into a STYLE block ahead of the cues when not empty.
+pub fn write(cues: &[Cue], css: &str) -> String {
+    let mut out = String::from("WEBVTT\n\n");
+    if !css.is_empty() {
+        out.push_str("STYLE\n");
+        out.push_str(css);
+        out.push_str("\n\n");
+    }
+    for cue in cues {
+        out.push_str(&timestamp(cue.start));
+        out.push_str(" --> ");
+        out.push_str(&timestamp(cue.end.max(cue.start)));
+        if !cue.settings.is_empty() {
+            out.push(' ');
+            out.push_str(&cue.settings);
+        }
+        out.push('\n');
+        out.push_str(&cue.text);
+        out.push_str("\n\n");
+    }
+    out
+}
+
+// "hh:mm:ss.mmm"; cues moved before the start are clamped to it.
+fn timestamp(ms: i64) -> String {
+    let ms = ms.max(0);
+    format!("{:02}:{:02}:{:02}.{:03}", ms / 3_600_000, ms / 60_000 % 60, ms / 1000 % 60, ms % 1000)
+}
+
+// Cue text is a block of lines without blank ones; NOTE and REGION blocks and cue
+// identifiers are dropped, STYLE blocks are read by `styles`.
+pub fn parse(text: &str) -> Vec<Cue> {
+    let mut cues = Vec::new();
+    let mut lines = text.lines().peekable();
+    while let Some(line) = lines.next() {
+        let Some((start, end, settings)) = parse_timing(line) else { continue };
+        let mut body = Vec::new();
+        while let Some(next) = lines.peek() {
+            if next.trim().is_empty() {
+                break;
+            }
+            body.push(lines.next().unwrap_or_default().trim_end());
+        }
+        cues.push(Cue { start, end, settings: settings.to_string(), text: body.join("\n") });
+    }
+    cues
+}
+
+// The CSS of the STYLE blocks, which come before the first cue.
+pub fn styles(text: &str) -> String {
+    let mut css = Vec::new();
+    let mut lines = text.lines().map(str::trim_end).peekable();
+    while let Some(line) = lines.next() {
+        if parse_timing(line).is_some() {
+            break;
+        }
+        if line.trim() != "STYLE" {
+            continue;
+        }
+        while let Some(next) = lines.peek() {
+            if next.trim().is_empty() {
+                break;
+            }
+            css.push(lines.next().unwrap_or_default());
+        }
+    }
+    css.join("\n")
+}
+
+// `text` with every timing line passed through `correct`, keeping cue settings.
+pub fn retime(text: &str, correct: impl Fn(i64) -> i64) -> String {
+    rewrite_lines(text, |line| {
+        let (start, end, settings) = parse_timing(line)?;
+        let mut timing = format!("{} --> {}", timestamp(correct(start)), timestamp(correct(end)));
+        if !settings.is_empty() {
+            timing.push(' ');
+            timing.push_str(settings);
+        }
+        Some(timing)
+    })
+}
+
+// Cue settings for a numpad alignment as used by ASS and the `{\anN}` tags in SRT files:
+// 7-9 at the top, 4-6 in the middle, 1-3 at the bottom (the default).
+pub fn alignment_settings(alignment: u8) -> String {
+    let line = match alignment {
+        7..=9 => Some("line:0"),
+        4..=6 => Some("line:50%"),
+        _ => None,
+    };
+    let align = match alignment % 3 {
+        1 => Some("align:left"),
+        0 => Some("align:right"),
+        _ => None,
+    };
+    [line, align].into_iter().flatten().collect::<Vec<_>>().join(" ")
+}
+
+// Escapes text for a cue; `<`, `&` and `>` would otherwise start tags and entities or
+// form the "-->" that ends a timing line.
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIDECAR: &str = "WEBVTT\n\nSTYLE\n::cue(.yellow) { color: yellow }\n\nNOTE a comment\n\nintro\n\
+        00:00:01.000 --> 00:00:02.000 align:left\nLine one\nLine two\n\n00:01:00.000 --> 00:01:01.500\n<c.yellow>Yellow</c>\n";
+
+    #[test]
+    fn reads_cues_and_styles() {
+        let cues = parse(SIDECAR);
+        let summary: Vec<_> = cues.iter().map(|c| (c.start, c.end, c.settings.as_str(), c.text.as_str())).collect();
+        assert_eq!(summary, vec![(1000, 2000, "align:left", "Line one\nLine two"), (60_000, 61_500, "", "<c.yellow>Yellow</c>")]);
+        assert_eq!(styles(SIDECAR), "::cue(.yellow) { color: yellow }");
+    }
+
+    #[test]
+    fn writes_what_it_reads() {
+        let written = write(&parse(SIDECAR), &styles(SIDECAR));
+        assert_eq!(
+            written,
+            "WEBVTT\n\nSTYLE\n::cue(.yellow) { color: yellow }\n\n00:00:01.000 --> 00:00:02.000 align:left\nLine one\nLine two\n\n\
+             00:01:00.000 --> 00:01:01.500\n<c.yellow>Yellow</c>\n\n"
+        );
+    }
+
+    #[test]
+    fn negative_and_inverted_times_are_clamped() {
+        let cues = [Cue { start: -500, end: -100, settings: String::new(), text: "x".to_string() }, Cue { start: 5000, end: 4000, settings: String::new(), text: "y".to_string() }];
+        assert_eq!(write(&cues, ""), "WEBVTT\n\n00:00:00.000 --> 00:00:00.000\nx\n\n00:00:05.000 --> 00:00:05.000\ny\n\n");
+    }
+
+    #[test]
+    fn retime_keeps_settings_and_styles() {
+        let retimed = retime(SIDECAR, |ms| ms * 2);
+        assert!(retimed.contains("00:00:02.000 --> 00:00:04.000 align:left\nLine one"));
+        assert!(retimed.contains("00:02:00.000 --> 00:02:03.000\n"));
+        assert_eq!(styles(&retimed), styles(SIDECAR));
+        assert_eq!(retimed.len(), SIDECAR.len());
+    }
+
+    #[test]
+    fn numpad_alignment() {
+        assert_eq!(alignment_settings(2), "");
+        assert_eq!(alignment_settings(7), "line:0 align:left");
+        assert_eq!(alignment_settings(8), "line:0");
+        assert_eq!(alignment_settings(6), "line:50% align:right");
+        assert_eq!(escape("a<b>&c"), "a&lt;b&gt;&amp;c");
+    }
+}
@@ -0,0 +1,472 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use notify_debouncer_full::notify::event::{ModifyKind, RenameMode};
+use notify_debouncer_full::notify::{EventKind, RecommendedWatcher, RecursiveMode};
+use notify_debouncer_full::{new_debouncer, DebounceEventResult, DebouncedEvent, Debouncer, RecommendedCache};
+use once_cell::sync::OnceCell;
+use rusqlite::Connection;
+use serde::Serialize;
+use tauri::{AppHandle, Emitter};
+
+use crate::db::database;
+use crate::library::{self, FileEntry, Upsert};
+use crate::playback::{self, CodecSupport};
+
+// Event the frontend listens to for refreshing library views.
+pub const LIBRARY_CHANGED_EVENT: &str = "library-changed";
+
+// Event with a message when a library folder cannot be watched or a change in it
+// cannot be recorded.
+pub const LIBRARY_ERROR_EVENT: &str = "library-error";
+
+// Bursts of events (copying a large file, extracting an archive) are folded into one batch.
+const DEBOUNCE: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct LibraryChange {
+    pub added: u64,
+    pub updated: u64,
+    pub renamed: u64,
+    pub missing: u64,
+    // library ids touched by this batch
+    pub libraries: Vec<i64>,
+}
+
+struct WatcherState {
+    debouncer: Debouncer<RecommendedWatcher, RecommendedCache>,
+    roots: HashMap<i64, PathBuf>,
+}
+
+// Global singleton like the DB connection; created once in `setup`.
+static WATCHER: OnceCell<Mutex<WatcherState>> = OnceCell::new();
+
+pub fn start(app: AppHandle) -> Result<(), String> {
+    let debouncer = new_debouncer(DEBOUNCE, None, move |result: DebounceEventResult| match result {
+        Ok(events) => handle_events(&app, events),
+        Err(errors) => {
+            for e in errors {
+                report_error(&app, e.to_string());
+            }
+        }
+    })
+    .map_err(|e| e.to_string())?;
+
+    WATCHER
+        .set(Mutex::new(WatcherState { debouncer, roots: HashMap::new() }))
+        .map_err(|_| "Library watcher already started".to_string())?;
+    sync_roots()
+}
+
+// Watches exactly the folders in the `libraries` table. Called at startup and whenever
+// a library is added or removed. A root that cannot be watched (e.g. an unplugged
+// drive) is skipped and retried on the next call.
+pub fn sync_roots() -> Result<(), String> {
+    let Some(state) = WATCHER.get() else {
+        return Ok(());
+    };
+    let wanted = load_roots()?;
+    let mut state = state.lock().map_err(|e| e.to_string())?;
+
+    let stale: Vec<(i64, PathBuf)> = state
+        .roots
+        .iter()
+        .filter(|(id, path)| wanted.get(id) != Some(path))
+        .map(|(id, path)| (*id, path.clone()))
+        .collect();
+    for (id, path) in stale {
+        let _ = state.debouncer.unwatch(&path);
+        state.roots.remove(&id);
+    }
+
+    let mut failed = Vec::new();
+    for (id, path) in wanted {
+        if state.roots.contains_key(&id) {
+            continue;
+        }
+        match state.debouncer.watch(&path, RecursiveMode::Recursive) {
+            Ok(()) => {
+                state.roots.insert(id, path);
+            }
+            Err(e) => failed.push(format!("{}: {}", path.display(), e)),
+        }
+    }
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("Could not watch {}", failed.join(", ")))
+    }
+}
+
+fn load_roots() -> Result<HashMap<i64, PathBuf>, String> {
+    let conn = database::get_connection().lock().map_err(|e| e.to_string())?;
+    library::load_roots(&conn).map_err(|e| e.to_string())
+}
+
+fn handle_events(app: &AppHandle, events: Vec<DebouncedEvent>) {
+    let roots = match load_roots() {
+        Ok(roots) => roots,
+        Err(e) => {
+            report_error(app, e);
+            return;
+        }
+    };
+    let entries = read_new_files(&roots, &events);
+    let mut change = LibraryChange::default();
+    {
+        let conn = match database::get_connection().lock() {
+            Ok(conn) => conn,
+            Err(_) => return,
+        };
+        let support = playback::codec_support(&conn);
+        for event in &events {
+            let paths = &event.paths;
+            let result = match event.kind {
+                EventKind::Create(_) => paths.iter().try_for_each(|p| on_created(&conn, &roots, &entries, &support, p, &mut change)),
+                EventKind::Modify(ModifyKind::Name(RenameMode::Both)) if paths.len() == 2 => {
+                    on_renamed(&conn, &roots, &entries, &support, &paths[0], &paths[1], &mut change)
+                }
+                // moved out of / into the watched tree
+                EventKind::Modify(ModifyKind::Name(RenameMode::From)) => {
+                    paths.iter().try_for_each(|p| on_removed(&conn, &roots, p, &mut change))
+                }
+                EventKind::Modify(ModifyKind::Name(RenameMode::To)) => {
+                    paths.iter().try_for_each(|p| on_created(&conn, &roots, &entries, &support, p, &mut change))
+                }
+                EventKind::Modify(ModifyKind::Name(_)) => paths.iter().try_for_each(|p| {
+                    if p.exists() {
+                        on_created(&conn, &roots, &entries, &support, p, &mut change)
+                    } else {
+                        on_removed(&conn, &roots, p, &mut change)
+                    }
+                }),
+                EventKind::Modify(_) => paths.iter().try_for_each(|p| on_modified(&conn, &roots, &entries, &support, p, &mut change)),
+                EventKind::Remove(_) => paths.iter().try_for_each(|p| on_removed(&conn, &roots, p, &mut change)),
+                _ => Ok(()),
+            };
+            if let Err(e) = result {
+                report_error(app, e.to_string());
+            }
+        }
+    }
+
+    if change.added + change.updated + change.renamed + change.missing > 0 {
+        change.libraries.sort_unstable();
+        change.libraries.dedup();
+        let _ = app.emit(LIBRARY_CHANGED_EVENT, change);
+    }
+}
+
+// The files each event path may add to the library, read (walking new folders, hashing,
+// probing) before the database is locked, like `relink_video` does. Paths outside the
+// roots and files that are gone again or not media are left out.
+fn read_new_files(roots: &HashMap<i64, PathBuf>, events: &[DebouncedEvent]) -> HashMap<PathBuf, Vec<FileEntry>> {
+    let mut entries = HashMap::new();
+    for event in events {
+        let paths: &[PathBuf] = match event.kind {
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)) if event.paths.len() == 2 => &event.paths[1..],
+            EventKind::Modify(ModifyKind::Name(RenameMode::From)) | EventKind::Remove(_) => &[],
+            EventKind::Create(_) | EventKind::Modify(_) => &event.paths,
+            _ => &[],
+        };
+        for path in paths {
+            if entries.contains_key(path) || library::library_for(roots, path).is_none() {
+                continue;
+            }
+            let found = if path.is_dir() {
+                // a folder moved or copied in at once only produces an event for the folder
+                let no_cancel = AtomicBool::new(false);
+                let mut found = Vec::new();
+                library::walk(path, &no_cancel, |entry| {
+                    if let Ok(entry) = entry {
+                        found.push(entry);
+                    }
+                });
+                found
+            } else if library::is_media_file(path) {
+                match FileEntry::from_path(path) {
+                    Ok(entry) => vec![entry],
+                    // already gone again, e.g. a temporary file
+                    Err(_) => continue,
+                }
+            } else {
+                continue;
+            };
+            entries.insert(path.clone(), found.into_iter().map(|e| e.with_quick_hash().with_media_info()).collect());
+        }
+    }
+    entries
+}
+
+pub fn report_error(app: &AppHandle, message: String) {
+    let _ = app.emit(LIBRARY_ERROR_EVENT, message);
+}
+
+fn touch(change: &mut LibraryChange, library_id: Option<i64>) {
+    if let Some(id) = library_id {
+        change.libraries.push(id);
+    }
+}
+
+fn on_created(
+    conn: &Connection,
+    roots: &HashMap<i64, PathBuf>,
+    entries: &HashMap<PathBuf, Vec<FileEntry>>,
+    support: &CodecSupport,
+    path: &Path,
+    change: &mut LibraryChange,
+) -> rusqlite::Result<()> {
+    let library_id = library::library_for(roots, path);
+    let Some(entries) = entries.get(path) else {
+        return Ok(());
+    };
+    for entry in entries {
+        count_upsert(library::upsert_file(conn, library_id, entry, support)?, change);
+    }
+    touch(change, library_id);
+    Ok(())
+}
+
+fn count_upsert(result: Upsert, change: &mut LibraryChange) {
+    match result {
+        Upsert::Added => change.added += 1,
+        Upsert::Updated => change.updated += 1,
+        Upsert::Relinked => change.renamed += 1,
+    }
+}
+
+fn on_modified(
+    conn: &Connection,
+    roots: &HashMap<i64, PathBuf>,
+    entries: &HashMap<PathBuf, Vec<FileEntry>>,
+    support: &CodecSupport,
+    path: &Path,
+    change: &mut LibraryChange,
+) -> rusqlite::Result<()> {
+    if !library::is_media_file(path) || !path.is_file() {
+        return Ok(());
+    }
+    let library_id = library::library_for(roots, path);
+    if let Some([entry]) = entries.get(path).map(Vec::as_slice) {
+        count_upsert(library::upsert_file(conn, library_id, entry, support)?, change);
+        touch(change, library_id);
+    }
+    Ok(())
+}
+
+fn on_removed(conn: &Connection, roots: &HashMap<i64, PathBuf>, path: &Path, change: &mut LibraryChange) -> rusqlite::Result<()> {
+    // rows are kept (with their history) and only flagged, so a relink can restore them
+    let n = library::mark_missing(conn, &path.to_string_lossy())?;
+    if n > 0 {
+        change.missing += n as u64;
+        touch(change, library::library_for(roots, path));
+    }
+    Ok(())
+}
+
+fn on_renamed(
+    conn: &Connection,
+    roots: &HashMap<i64, PathBuf>,
+    entries: &HashMap<PathBuf, Vec<FileEntry>>,
+    support: &CodecSupport,
+    from: &Path,
+    to: &Path,
+    change: &mut LibraryChange,
+) -> rusqlite::Result<()> {
+    // renamed to something we do not index (e.g. "clip.mp4.bak")
+    if to.is_file() && !library::is_media_file(to) {
+        return on_removed(conn, roots, from, change);
+    }
+    let from_str = from.to_string_lossy();
+    let to_str = to.to_string_lossy();
+    let renamed = match library::rename_path(conn, &from_str, &to_str) {
+        Ok(n) => n,
+        // the target path is already a row of its own; keep both and just flag the old one
+        Err(rusqlite::Error::SqliteFailure(e, _)) if e.code == rusqlite::ErrorCode::ConstraintViolation => {
+            on_removed(conn, roots, from, change)?;
+            0
+        }
+        Err(e) => return Err(e),
+    };
+    if renamed > 0 {
+        change.renamed += renamed as u64;
+        if let Some(library_id) = library::library_for(roots, to) {
+            conn.execute(
+                "UPDATE videos SET library_id = ?1 WHERE path = ?2 OR path LIKE ?3 ESCAPE '\\'",
+                rusqlite::params![library_id, to_str, library::root_pattern(&to_str)],
+            )?;
+        }
+        touch(change, library::library_for(roots, to));
+        Ok(())
+    } else {
+        // not known under the old name: treat it as a new file
+        on_created(conn, roots, entries, support, to, change)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::time::Instant;
+
+    use notify_debouncer_full::notify::event::CreateKind;
+    use notify_debouncer_full::notify::Event;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn db_with_library(root: &Path) -> (Connection, HashMap<i64, PathBuf>) {
+        let mut conn = Connection::open_in_memory().unwrap();
+        crate::db::schema::init_schema(&mut conn).unwrap();
+        conn.execute("INSERT INTO libraries (id, path) VALUES (1, ?1)", [root.to_string_lossy()]).unwrap();
+        (conn, HashMap::from([(1, root.to_path_buf())]))
+    }
+
+    fn event(kind: EventKind, paths: &[&Path]) -> DebouncedEvent {
+        let event = paths.iter().fold(Event::new(kind), |event, path| event.add_path(path.to_path_buf()));
+        DebouncedEvent::new(event, Instant::now())
+    }
+
+    // (path below the root, missing) of every row
+    fn rows(conn: &Connection, root: &Path) -> Vec<(String, bool)> {
+        let mut stmt = conn.prepare("SELECT path, missing FROM videos ORDER BY path").unwrap();
+        let rows = stmt
+            .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get(1)?)))
+            .unwrap()
+            .map(|row| row.unwrap());
+        let root = root.to_string_lossy();
+        rows.map(|(path, missing)| (path[root.len()..].to_string(), missing)).collect()
+    }
+
+    #[test]
+    fn new_files_are_read_once_and_only_inside_a_library() {
+        let dir = temp_dir("watcher-read");
+        let outside = temp_dir("watcher-read-outside");
+        fs::create_dir_all(dir.join("copied")).unwrap();
+        for file in ["a.mp4", "notes.txt", "copied/b.mkv", "copied/c.txt"] {
+            fs::write(dir.join(file), file).unwrap();
+        }
+        fs::write(outside.join("d.mp4"), "d").unwrap();
+        let (_, roots) = db_with_library(&dir);
+        let events = [
+            event(EventKind::Create(CreateKind::File), &[&dir.join("a.mp4")]),
+            event(EventKind::Modify(ModifyKind::Any), &[&dir.join("a.mp4")]),
+            event(EventKind::Create(CreateKind::File), &[&dir.join("notes.txt")]),
+            event(EventKind::Create(CreateKind::Folder), &[&dir.join("copied")]),
+            event(EventKind::Create(CreateKind::File), &[&outside.join("d.mp4")]),
+            event(EventKind::Create(CreateKind::File), &[&dir.join("gone.mp4")]),
+            event(EventKind::Modify(ModifyKind::Name(RenameMode::Both)), &[&dir.join("old.mp4"), &dir.join("a.mp4")]),
+        ];
+        let entries = read_new_files(&roots, &events);
+        let mut found: Vec<_> = entries.iter().map(|(path, entries)| (path.clone(), entries.len())).collect();
+        found.sort();
+        assert_eq!(found, vec![(dir.join("a.mp4"), 1), (dir.join("copied"), 1)]);
+        assert!(entries[&dir.join("a.mp4")][0].quick_hash.is_some());
+    }
+
+    #[test]
+    fn created_and_modified_files_are_upserted() {
+        let dir = temp_dir("watcher-upsert");
+        fs::write(dir.join("a.mp4"), "a".repeat(100)).unwrap();
+        let (conn, roots) = db_with_library(&dir);
+        let support = playback::codec_support(&conn);
+        let path = dir.join("a.mp4");
+        let events = [event(EventKind::Create(CreateKind::File), &[&path])];
+
+        let mut change = LibraryChange::default();
+        on_created(&conn, &roots, &read_new_files(&roots, &events), &support, &path, &mut change).unwrap();
+        assert_eq!((change.added, change.updated, change.libraries.clone()), (1, 0, vec![1]));
+        // the same file reported again does not add a second row
+        on_created(&conn, &roots, &read_new_files(&roots, &events), &support, &path, &mut change).unwrap();
+        assert_eq!((change.added, change.updated), (1, 1));
+
+        // a rewritten file keeps its row and history but loses the full hash
+        conn.execute("UPDATE videos SET full_hash = 'old', watch_count = 2", []).unwrap();
+        fs::write(&path, "changed").unwrap();
+        let mut change = LibraryChange::default();
+        on_modified(&conn, &roots, &read_new_files(&roots, &events), &support, &path, &mut change).unwrap();
+        assert_eq!(change.updated, 1);
+        let (size, full_hash, watch_count): (i64, Option<String>, i64) = conn
+            .query_row("SELECT file_size, full_hash, watch_count FROM videos", [], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap();
+        assert_eq!((size, full_hash, watch_count), (7, None, 2));
+        assert_eq!(rows(&conn, &dir), vec![("/a.mp4".to_string(), false)]);
+    }
+
+    #[test]
+    fn removed_files_are_flagged_and_renames_keep_their_rows() {
+        let dir = temp_dir("watcher-rename");
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        for file in ["a.mp4", "b.mp4", "sub/c.mp4"] {
+            fs::write(dir.join(file), file.repeat(100)).unwrap();
+        }
+        let (conn, roots) = db_with_library(&dir);
+        let support = playback::codec_support(&conn);
+        let events: Vec<_> = ["a.mp4", "b.mp4", "sub"]
+            .iter()
+            .map(|file| event(EventKind::Create(CreateKind::Any), &[&dir.join(file)]))
+            .collect();
+        let entries = read_new_files(&roots, &events);
+        let mut change = LibraryChange::default();
+        for file in ["a.mp4", "b.mp4", "sub"] {
+            on_created(&conn, &roots, &entries, &support, &dir.join(file), &mut change).unwrap();
+        }
+        assert_eq!(change.added, 3);
+        conn.execute("UPDATE videos SET watch_count = 3", []).unwrap();
+        let ids = |conn: &Connection| -> Vec<i64> {
+            let mut stmt = conn.prepare("SELECT id FROM videos ORDER BY id").unwrap();
+            let rows = stmt.query_map([], |row| row.get(0)).unwrap();
+            rows.collect::<rusqlite::Result<_>>().unwrap()
+        };
+        let before = ids(&conn);
+
+        let mut change = LibraryChange::default();
+        // a file and a folder renamed in place
+        fs::rename(dir.join("b.mp4"), dir.join("renamed.mp4")).unwrap();
+        on_renamed(&conn, &roots, &entries, &support, &dir.join("b.mp4"), &dir.join("renamed.mp4"), &mut change).unwrap();
+        fs::rename(dir.join("sub"), dir.join("moved")).unwrap();
+        on_renamed(&conn, &roots, &entries, &support, &dir.join("sub"), &dir.join("moved"), &mut change).unwrap();
+        // a removed file keeps its row
+        fs::remove_file(dir.join("a.mp4")).unwrap();
+        on_removed(&conn, &roots, &dir.join("a.mp4"), &mut change).unwrap();
+        assert_eq!((change.renamed, change.missing), (2, 1));
+        assert_eq!(
+            rows(&conn, &dir),
+            vec![("/a.mp4".to_string(), true), ("/moved/c.mp4".to_string(), false), ("/renamed.mp4".to_string(), false)]
+        );
+
+        // the removed file showing up elsewhere is relinked by size and hash
+        fs::write(dir.join("back.mp4"), "a.mp4".repeat(100)).unwrap();
+        let entries = read_new_files(&roots, &[event(EventKind::Create(CreateKind::File), &[&dir.join("back.mp4")])]);
+        let mut change = LibraryChange::default();
+        on_created(&conn, &roots, &entries, &support, &dir.join("back.mp4"), &mut change).unwrap();
+        assert_eq!((change.added, change.renamed), (0, 1));
+
+        // renamed to something that is not indexed counts as removed
+        fs::rename(dir.join("renamed.mp4"), dir.join("renamed.mp4.bak")).unwrap();
+        let mut change = LibraryChange::default();
+        on_renamed(&conn, &roots, &entries, &support, &dir.join("renamed.mp4"), &dir.join("renamed.mp4.bak"), &mut change).unwrap();
+        assert_eq!((change.renamed, change.missing), (0, 1));
+
+        // renamed onto a path that already has a row: both rows stay, the old one flagged
+        fs::rename(dir.join("moved/c.mp4"), dir.join("back.mp4")).unwrap();
+        let mut change = LibraryChange::default();
+        on_renamed(&conn, &roots, &entries, &support, &dir.join("moved/c.mp4"), &dir.join("back.mp4"), &mut change).unwrap();
+        assert_eq!((change.renamed, change.missing), (0, 1));
+        assert_eq!(
+            rows(&conn, &dir),
+            vec![("/back.mp4".to_string(), false), ("/moved/c.mp4".to_string(), true), ("/renamed.mp4".to_string(), true)]
+        );
+        assert_eq!(ids(&conn), before);
+        let history: i64 = conn.query_row("SELECT MIN(watch_count) FROM videos", [], |row| row.get(0)).unwrap();
+        assert_eq!(history, 3);
+    }
+}
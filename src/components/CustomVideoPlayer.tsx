@@ -13,6 +13,7 @@ import { Button } from "./ui/button";
 import { Slider } from "./ui/slider";
 import { cn } from "../lib/utils";
 import useApi from '../hooks/useApi';
+import type { SubtitleTrack } from '../services/api';
 
 interface CustomVideoPlayerProps {
   src: string;
@@ -20,9 +21,10 @@ interface CustomVideoPlayerProps {
   autoPlay?: boolean;
   onBack?: () => void;
   filePath?: string;
+  subtitles?: SubtitleTrack[];
 }
 
-export function CustomVideoPlayer({ src, title, autoPlay = false, onBack, filePath }: CustomVideoPlayerProps) {
+export function CustomVideoPlayer({ src, title, autoPlay = false, onBack, filePath, subtitles = [] }: CustomVideoPlayerProps) {
   const api = useApi();
   const _onBack = onBack;
   const [viewIncremented, setViewIncremented] = useState(false);
@@ -90,12 +92,7 @@ export function CustomVideoPlayer({ src, title, autoPlay = false, onBack, filePa
         return;
       }
       try {
-        const vids = await api.listVideos();
-        let vid = vids.find(v => v.path === filePath);
-        if (!vid) {
-          const dur = duration && duration > 0 ? Math.round(duration) : null;
-          vid = await api.addVideo(filePath, title || undefined, dur);
-        }
+        const vid = await api.getVideoByPath(filePath);
         if (vid && typeof (vid as any).id === 'number') {
           await api.incrementView((vid as any).id);
         }
@@ -112,7 +109,7 @@ export function CustomVideoPlayer({ src, title, autoPlay = false, onBack, filePa
       mounted = false;
       video.removeEventListener('play', handleFirstPlay);
     };
-  }, [filePath, api, viewIncremented, duration, title]);
+  }, [filePath, api, viewIncremented]);
 
   useEffect(() => {
     if (durationUpdated) return;
@@ -261,9 +258,22 @@ export function CustomVideoPlayer({ src, title, autoPlay = false, onBack, filePa
       <video
         ref={videoRef}
         src={src}
+        // subtitles come from the video:// protocol, a different origin than the page
+        crossOrigin="anonymous"
         className="w-full h-full object-contain"
         onClick={togglePlay}
-      />
+      >
+        {subtitles.filter(s => s.url).map(s => (
+          <track
+            key={s.id}
+            kind="subtitles"
+            src={s.url!}
+            srcLang={s.language ?? undefined}
+            label={s.label}
+            default={s.default}
+          />
+        ))}
+      </video>
 
       {!isPlaying && !isBuffering && (
         <div className="absolute inset-0 flex items-center justify-center z-10 pointer-events-none">
@@ -5,14 +5,28 @@ import { Play, FolderOpen, ArrowLeft } from 'lucide-react';
 import { toast } from 'sonner';
 import { useNavigate } from 'react-router';
 
-const VIDEO_EXTS = ['.mp4', '.mkv', '.mov', '.webm', '.avi', '.flv', '.m4v', '.ts', '.m2ts', '.wmv', '.mp3'];
+const VIDEO_EXTS = ['.mp4', '.mkv', '.mov', '.webm', '.avi', '.flv', '.m4v', '.ts', '.m2ts', '.mts', '.wmv', '.mp3'];
 
 export default function Browse() {
   const api = useApi();
   const [files, setFiles] = useState<string[]>([]);
   const [dir, setDir] = useState<string | null>(null);
+  const [error, setError] = useState<string | null>(null);
   const navigate = useNavigate();
 
+  // only folders inside a library can be listed; the error says so for other folders
+  const readFolder = useCallback(async (p: string) => {
+    try {
+      const paths = await api.readDirRecursive(p);
+      setFiles(paths || []);
+      setError(null);
+    } catch (err) {
+      console.error('readDirRecursive failed', err);
+      setFiles([]);
+      setError(String(err));
+    }
+  }, [api]);
+
   const pickFolder = useCallback(async () => {
     try {
       const p = await api.pickFolder();
@@ -21,18 +35,24 @@ export default function Browse() {
         return;
       }
       setDir(p);
-      try {
-        const paths = await api.readDirRecursive(p);
-        setFiles(paths || []);
-      } catch (err) {
-        console.error('readDirRecursive failed', err);
-        toast.error('Failed to read folder contents');
-      }
+      await readFolder(p);
     } catch (e) {
       console.error('Folder pick cancelled or failed', e);
       toast.error('Failed to pick folder');
     }
-  }, [api]);
+  }, [api, readFolder]);
+
+  const addFolderAsLibrary = useCallback(async () => {
+    if (!dir) return;
+    try {
+      await api.addLibrary(dir);
+      toast.success('Folder added as a library');
+      await readFolder(dir);
+    } catch (e) {
+      console.error('addLibrary failed', e);
+      toast.error('Failed to add folder as a library');
+    }
+  }, [api, dir, readFolder]);
 
   const addToLibrary = useCallback(async (path: string) => {
     try {
@@ -82,8 +102,14 @@ export default function Browse() {
       {dir && (
         <div>
           <p className="text-sm text-muted-foreground mb-4">{dir}</p>
+          {error && (
+            <div className="flex items-center gap-4 mb-4 text-sm">
+              <span className="text-destructive">{error}</span>
+              <Button onClick={addFolderAsLibrary} variant="outline" size="sm">Add folder as library</Button>
+            </div>
+          )}
           <div className="flex flex-col gap-3">
-            {files.length === 0 && <div className="text-sm text-muted-foreground">No video files found in this folder.</div>}
+            {!error && files.length === 0 && <div className="text-sm text-muted-foreground">No video files found in this folder.</div>}
             {files.map((f) => (
               <div key={f} className="flex items-center gap-4 p-3 rounded-2xl bg-card/40 border border-white/5">
                 <div className="w-24 h-16 rounded-lg bg-gradient-to-br from-slate-600 to-slate-400 flex items-center justify-center">
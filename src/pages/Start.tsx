@@ -113,6 +113,8 @@ export default function Start() {
                  await api.addVideo(file, title, null);
              } catch (e) {
                  console.warn('Failed to add video to DB', e);
+                 toast.error(String(e));
+                 return;
              }
              navigate("/videoplayer?file=" + encodeURIComponent(file));
          }
@@ -129,6 +131,8 @@ export default function Start() {
                     await api.addVideo(filePath, title, null);
                 } catch (e) {
                     console.warn('Failed to add dropped video to DB', e);
+                    toast.error(String(e));
+                    return;
                 }
                 navigate("/videoplayer?file=" + encodeURIComponent(filePath));
                  return;
@@ -4,29 +4,40 @@ import { convertFileSrc } from '@tauri-apps/api/core';
 import { CustomVideoPlayer } from "../components/CustomVideoPlayer";
 import { Button } from "../components/ui/button";
 import { HelpCircle } from "lucide-react";
+import { useApi } from "../hooks/useApi";
+import type { SubtitleTrack } from "../services/api";
 
 export default function VideoPlayer() {
     const [searchParams] = useSearchParams();
     const navigate = useNavigate();
+    const api = useApi();
     const [videoSource, setVideoSource] = useState<string | null>(null);
     const [fileName, setFileName] = useState<string>("");
     const [rawPath, setRawPath] = useState<string | null>(null);
+    const [subtitles, setSubtitles] = useState<SubtitleTrack[]>([]);
+    const [error, setError] = useState<string | null>(null);
 
     useEffect(() => {
         async function fetchVideo() {
             const file = searchParams.get("file");
             if (file) {
-                const src = convertFileSrc(file);
-                setVideoSource(src);
-                setRawPath(file);
-
                 // Extract filename from path
                 const name = file.split(/[\\/]/).pop() || "Unknown Video";
                 setFileName(name);
+
+                // the video:// protocol only serves files known to the library, by uuid
+                const video = await api.getVideoByPath(file);
+                if (!video) {
+                    setError("This file is not in your library. Add its folder as a library to play it.");
+                    return;
+                }
+                setVideoSource(convertFileSrc(video.uuid, "video"));
+                setRawPath(file);
+                setSubtitles(await api.listSubtitles(video.id).catch(() => []));
             }
         }
         fetchVideo();
-    }, [searchParams]);
+    }, [searchParams, api]);
 
     return (
         <div className="p-6 flex-1 flex flex-col relative">
@@ -37,10 +48,11 @@ export default function VideoPlayer() {
                         title={fileName}
                         autoPlay={true}
                         filePath={rawPath || undefined}
+                        subtitles={subtitles}
                     />
                 ) : (
                     <div className="flex items-center justify-center h-full text-muted-foreground">
-                        Loading video...
+                        {error ?? "Loading video..."}
                     </div>
                 )}
             </div>
@@ -11,6 +11,38 @@ export interface Video {
     favorite?: number | null;
 }
 
+export interface Library {
+    id: number;
+    path: string;
+    name?: string | null;
+}
+
+export interface SubtitleTrack {
+    id: string;
+    source: 'sidecar' | 'embedded';
+    path: string;
+    track_number?: number | null;
+    format: 'srt' | 'vtt' | 'ass';
+    language?: string | null;
+    label: string;
+    forced: boolean;
+    hearing_impaired: boolean;
+    default: boolean;
+    synced: boolean;
+    url?: string | null;
+    encoding?: string | null;
+    encoding_overridden: boolean;
+    dropped_features: string[];
+}
+
+export interface SubtitleTiming {
+    video_id: number;
+    offset_ms: number;
+    source_fps?: number | null;
+    target_fps?: number | null;
+    updated_at?: string | null;
+}
+
 export interface Setting {
     key: string;
     value: string;
@@ -101,4 +133,38 @@ export class Api {
     listSettings(): Promise<Setting[]> {
         return invoke('list_settings') as Promise<Setting[]>;
     }
+
+    // Library commands
+    addLibrary(path: string, name?: string | null): Promise<Library> {
+        return invoke('add_library', { path, name: name ?? null }) as Promise<Library>;
+    }
+
+    // Subtitle commands
+    listSubtitles(id: number): Promise<SubtitleTrack[]> {
+        return invoke('list_subtitles', { id }) as Promise<SubtitleTrack[]>;
+    }
+
+    listEmbeddedSubtitles(id: number): Promise<SubtitleTrack[]> {
+        return invoke('list_embedded_subtitles', { id }) as Promise<SubtitleTrack[]>;
+    }
+
+    extractSubtitle(id: number, track: number): Promise<SubtitleTrack> {
+        return invoke('extract_subtitle', { id, track }) as Promise<SubtitleTrack>;
+    }
+
+    setSubtitleEncoding(id: number, subtitleId: string, encoding?: string | null): Promise<SubtitleTrack> {
+        return invoke('set_subtitle_encoding', { id, subtitleId, encoding: encoding ?? null }) as Promise<SubtitleTrack>;
+    }
+
+    getSubtitleTiming(id: number): Promise<SubtitleTiming> {
+        return invoke('get_subtitle_timing', { id }) as Promise<SubtitleTiming>;
+    }
+
+    setSubtitleTiming(id: number, offsetMs: number, sourceFps?: number | null, targetFps?: number | null): Promise<SubtitleTiming> {
+        return invoke('set_subtitle_timing', { id, offsetMs, sourceFps: sourceFps ?? null, targetFps: targetFps ?? null }) as Promise<SubtitleTiming>;
+    }
+
+    saveCorrectedSubtitle(id: number, subtitleId: string): Promise<string> {
+        return invoke('save_corrected_subtitle', { id, subtitleId }) as Promise<string>;
+    }
 }